@@ -0,0 +1,185 @@
+// Compares listing folders and playlists item by item with the bulk queries used by the UI,
+// on a generated collection. Run with `cargo bench -p lappi_core --bench collection_listing`.
+
+use std::time::{Duration, Instant};
+
+use amina_core::events::EventEmitter;
+use amina_core::service::Context;
+use anyhow::Result;
+use rusqlite::Connection;
+
+use lappi_core::collection::folders::database_api::FoldersDbApi;
+use lappi_core::collection::folders::{FolderId, FolderType};
+use lappi_core::collection::music::database_api::MusicDbApi;
+use lappi_core::collection::music::MusicItemId;
+use lappi_core::collection::playlists::database_api::PlaylistsDbApi;
+use lappi_core::collection::playlists::types::PlaylistId;
+use lappi_core::collection::tags::database_api::TagsDbApi;
+use lappi_core::collection::tags::{Tag, TagValue};
+use lappi_core::database::sqlite::collection::folders::FoldersDb;
+use lappi_core::database::sqlite::collection::music::MusicDb;
+use lappi_core::database::sqlite::collection::playlists::PlaylistsDb;
+use lappi_core::database::sqlite::collection::tags::TagsDb;
+use lappi_core::database::sqlite::migrations;
+use lappi_core::database::sqlite::utils::DatabaseUtils;
+
+static ARTISTS: usize = 50;
+static ALBUMS_PER_ARTIST: usize = 5;
+static TRACKS_PER_ALBUM: usize = 12;
+// Every n-th track goes to the playlist
+static PLAYLIST_STEP: usize = 3;
+static ITERATIONS: u32 = 5;
+
+struct GeneratedDb {
+    folders: FoldersDb,
+    music: MusicDb,
+    tags: TagsDb,
+    playlists: PlaylistsDb,
+    albums: Vec<FolderId>,
+    playlist_id: PlaylistId,
+}
+
+impl GeneratedDb {
+    fn generate() -> Result<Self> {
+        let context = Context::new();
+        context.init_service::<EventEmitter>();
+        let connection = Connection::open_in_memory()?;
+        migrations::prepare_database(&connection)?;
+        let db_utils = DatabaseUtils::new(&context, connection);
+
+        let mut db = Self {
+            folders: FoldersDb::new(db_utils.clone()),
+            music: MusicDb::new(db_utils.clone()),
+            tags: TagsDb::new(db_utils.clone()),
+            playlists: PlaylistsDb::new(db_utils.clone()),
+            albums: Vec::new(),
+            playlist_id: 0,
+        };
+
+        db_utils.lock().start_batch()?;
+        db.playlist_id = db.playlists.create_playlist("Generated")?;
+        let mut track_index = 0;
+        for artist_index in 0..ARTISTS {
+            let artist_id = db.folders.find_or_add_folder(0, &format!("Artist {}", artist_index), FolderType::Artist)?;
+            db.tags.set_add_folder_tag(artist_id, "genre", &TagValue::String(format!("Genre {}", artist_index % 7)))?;
+            for album_index in 0..ALBUMS_PER_ARTIST {
+                let album_id = db.folders.find_or_add_folder(artist_id, &format!("Album {}", album_index), FolderType::Album)?;
+                db.tags.set_add_folder_tag(album_id, "year", &TagValue::Number(1970 + album_index as i32))?;
+                db.albums.push(album_id);
+                for track in 0..TRACKS_PER_ALBUM {
+                    let item_id = db.music.add_music_item(&format!("Track {}", track), album_id)?;
+                    db.tags.set_add_item_tag(item_id, "title", &TagValue::String(format!("Track {}", track)))?;
+                    db.tags.set_add_item_tag(item_id, "track", &TagValue::Number(track as i32 + 1))?;
+                    if track_index % PLAYLIST_STEP == 0 {
+                        db.playlists.add_item_to_playlist(db.playlist_id, item_id)?;
+                    }
+                    track_index += 1;
+                }
+            }
+        }
+        db_utils.lock().commit_batch()?;
+
+        Ok(db)
+    }
+
+    // The lookup of MusicCollection::get_tag, a few queries per folder of the chain
+    fn get_tag_one_by_one(&self, item_id: MusicItemId, tag_name: &str) -> Result<Option<Tag>> {
+        let root_folder_id = self.folders.get_root_folder();
+        let mut folder_id = self.music.get_music_item_folder(item_id)?;
+
+        let mut tags = self.tags.get_item_tags(item_id)?;
+        tags.extend(self.tags.get_folder_tags(folder_id)?);
+        while folder_id != root_folder_id {
+            let description = FoldersDbApi::get_folder_description(&self.folders, folder_id)?;
+            if let Some(own_tag_name) = description.folder_type.get_own_tag_name() {
+                tags.push(Tag::new_string(own_tag_name.to_string(), description.name));
+            }
+            folder_id = self.folders.get_folder_parent(folder_id)?;
+            if folder_id == root_folder_id {
+                break;
+            }
+            tags.extend(self.tags.get_folder_tags(folder_id)?);
+        }
+        Ok(tags.into_iter().find(|tag| tag.get_key() == tag_name))
+    }
+
+    fn list_folders_one_by_one(&self) -> Result<usize> {
+        let mut count = 0;
+        for album_id in &self.albums {
+            for item_id in self.folders.get_music_items_in_folder(*album_id)? {
+                self.music.get_music_item_description(item_id)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    fn list_folders_bulk(&self) -> Result<usize> {
+        let mut count = 0;
+        for album_id in &self.albums {
+            let items = self.folders.get_music_items_in_folder(*album_id)?;
+            count += self.music.get_music_item_descriptions(&items)?.len();
+        }
+        Ok(count)
+    }
+
+    fn list_playlist_one_by_one(&self) -> Result<Vec<(String, String, String)>> {
+        let get_tag = |item_id: MusicItemId, tag_name: &str| -> Result<String> {
+            Ok(self.get_tag_one_by_one(item_id, tag_name)?.map(|tag| tag.to_string()).unwrap_or_default())
+        };
+
+        let mut result = Vec::new();
+        for (_, item_id) in self.playlists.get_playlist_items(self.playlist_id)? {
+            let title = self.music.get_music_item_description(item_id)?.name;
+            result.push((title, get_tag(item_id, "artist")?, get_tag(item_id, "album")?));
+        }
+        Ok(result)
+    }
+
+    fn list_playlist_bulk(&self) -> Result<Vec<(String, String, String)>> {
+        let items: Vec<MusicItemId> = self.playlists.get_playlist_items(self.playlist_id)?.into_iter()
+            .map(|(_, item_id)| item_id)
+            .collect();
+        let descriptions = self.music.get_music_item_descriptions(&items)?;
+        let effective_tags = self.tags.get_effective_item_tags(&items)?;
+        let get_tag = |item_id: MusicItemId, tag_name: &str| {
+            effective_tags.get(&item_id)
+                .and_then(|tags| tags.iter().find(|tag| tag.get_key() == tag_name))
+                .map(|tag| tag.to_string())
+                .unwrap_or_default()
+        };
+
+        Ok(items.iter().zip(descriptions)
+            .map(|(item_id, description)| (description.name, get_tag(*item_id, "artist"), get_tag(*item_id, "album")))
+            .collect())
+    }
+}
+
+fn measure<T>(f: impl Fn() -> Result<T>) -> Result<(Duration, T)> {
+    let mut result = f()?;
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        result = f()?;
+    }
+    Ok((start.elapsed() / ITERATIONS, result))
+}
+
+fn report(name: &str, one_by_one: Duration, bulk: Duration) {
+    println!("{:<10} one by one {:>8.2} ms, bulk {:>8.2} ms", name, one_by_one.as_secs_f64() * 1000., bulk.as_secs_f64() * 1000.);
+}
+
+fn main() -> Result<()> {
+    let db = GeneratedDb::generate()?;
+
+    let (one_by_one, one_by_one_count) = measure(|| db.list_folders_one_by_one())?;
+    let (bulk, bulk_count) = measure(|| db.list_folders_bulk())?;
+    assert_eq!(one_by_one_count, bulk_count);
+    report("folders", one_by_one, bulk);
+
+    let (one_by_one, one_by_one_items) = measure(|| db.list_playlist_one_by_one())?;
+    let (bulk, bulk_items) = measure(|| db.list_playlist_bulk())?;
+    assert_eq!(one_by_one_items, bulk_items, "Bulk listing of the playlist differs from listing item by item");
+    report("playlist", one_by_one, bulk);
+
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use anyhow::Result;
+use camino::{Utf8Component, Utf8Path};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotFile {
+    // Path relative to the collection root
+    pub path: String,
+    pub hash: String,
+    pub size: u64,
+}
+
+impl SnapshotFile {
+    // Manifests are read from the repository, so a path must not lead out of the collection
+    pub fn get_safe_path(&self) -> Result<&Utf8Path> {
+        let path = Utf8Path::new(&self.path);
+        let is_relative = path.components().all(|component| matches!(component, Utf8Component::Normal(_)));
+        if self.path.is_empty() || !is_relative {
+            anyhow::bail!("Snapshot file has an invalid path {:?}", self.path);
+        }
+        Ok(path)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SnapshotManifest {
+    pub id: String,
+    pub created: String,
+    pub files: Vec<SnapshotFile>,
+}
+
+impl SnapshotManifest {
+    pub fn get_info(&self) -> SnapshotInfo {
+        SnapshotInfo {
+            id: self.id.clone(),
+            created: self.created.clone(),
+            files_count: self.files.len(),
+            total_size: self.files.iter().map(|file| file.size).sum(),
+        }
+    }
+
+    pub fn find_file(&self, path: &str) -> Option<&SnapshotFile> {
+        self.files.iter().find(|file| file.path == path)
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SnapshotInfo {
+    pub id: String,
+    pub created: String,
+    pub files_count: usize,
+    pub total_size: u64,
+}
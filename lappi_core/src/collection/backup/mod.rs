@@ -0,0 +1,158 @@
+pub mod manifest;
+pub mod prune;
+pub mod repository;
+
+#[cfg(test)]
+mod tests;
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use camino::Utf8PathBuf;
+use amina_core::register_rpc_handler;
+use amina_core::rpc::Rpc;
+use amina_core::service::{Context, Service, ServiceApi, ServiceInitializer};
+
+use crate::collection::Collection;
+use crate::collection::jobs::collection_restore::RESTORE_JOB_ID;
+use crate::jobs::Jobs;
+use crate::settings::Settings;
+use crate::storage::remote::RemoteStorage;
+use crate::storage::remote::adapter::file_system::FileSystemFactory;
+use crate::storage::remote::adapter::{RemoteStorageFactory, RemoteStorageSettings};
+
+use manifest::SnapshotInfo;
+use prune::{PrunePolicy, PruneResult};
+use repository::BackupRepository;
+
+static DEFAULT_REMOTE_PATH: &str = "lappi_backup";
+
+// Versioned backups of the collection in a local folder or in the remote storage
+pub struct CollectionBackup {
+    settings: Service<Settings>,
+    collection: Service<Collection>,
+    pending_restore: Mutex<Option<String>>,
+}
+
+impl CollectionBackup {
+    pub fn open_repository(&self) -> Result<BackupRepository> {
+        let repository_type = self.settings.get_string("collection_backup.repository").get();
+        match repository_type.as_str() {
+            "" | "local" => {
+                let local_path = self.settings.get_string("collection_backup.local_path").get();
+                if local_path.is_empty() {
+                    anyhow::bail!("Backup folder is not set");
+                }
+                let adapter = FileSystemFactory::new().connect(&RemoteStorageSettings {
+                    url: local_path,
+                    user: String::new(),
+                    password: String::new(),
+                    path: String::new(),
+                    region: String::new(),
+                    host_key: String::new(),
+                })?;
+                Ok(BackupRepository::new(adapter, Utf8PathBuf::new()))
+            },
+            "remote" => {
+                let adapter = crate::context().get_service::<RemoteStorage>().connect()?;
+                let remote_path = self.settings.get_string("collection_backup.remote_path").get();
+                let remote_path = if remote_path.is_empty() { DEFAULT_REMOTE_PATH.to_string() } else { remote_path };
+                Ok(BackupRepository::new(adapter, Utf8PathBuf::from(remote_path)))
+            },
+            _ => anyhow::bail!("Unknown backup repository type '{}'", repository_type),
+        }
+    }
+
+    pub fn get_prune_policy(&self) -> PrunePolicy {
+        PrunePolicy {
+            keep_last: self.settings.get_string("collection_backup.keep_last").get().parse().ok(),
+            keep_daily: self.settings.get_string("collection_backup.keep_daily").get().parse().ok(),
+        }
+    }
+
+    pub fn get_snapshots(&self) -> Result<Vec<SnapshotInfo>> {
+        self.open_repository()?.get_snapshots()
+    }
+
+    // Restoring takes long, so it runs as a job that shows progress and can be stopped
+    pub fn restore_snapshot(&self, snapshot_id: String) -> Result<()> {
+        *self.pending_restore.lock().unwrap() = Some(snapshot_id);
+        crate::context().get_service::<Jobs>().start_job(RESTORE_JOB_ID.to_string())
+    }
+
+    pub fn take_pending_restore(&self) -> Option<String> {
+        self.pending_restore.lock().unwrap().take()
+    }
+
+    pub fn restore_file(&self, snapshot_id: String, path: String) -> Result<()> {
+        let mut repository = self.open_repository()?;
+        let manifest = repository.read_manifest(&snapshot_id)?;
+        let file = manifest.find_file(&path)
+            .ok_or_else(|| anyhow::anyhow!("Snapshot {} has no file {}", snapshot_id, path))?;
+        log::info!("Restore file {} from snapshot {}", path, snapshot_id);
+        repository.read_object(&file.hash, &self.collection.get_local_path().join(file.get_safe_path()?))
+    }
+
+    pub fn prune(&self) -> Result<PruneResult> {
+        let mut repository = self.open_repository()?;
+        let policy = self.get_prune_policy();
+        repository.with_lock("prune", |repository| self.prune_repository(repository, &policy))
+    }
+
+    // The repository has to be locked, see `BackupRepository::with_lock`
+    pub fn prune_repository(&self, repository: &mut BackupRepository, policy: &PrunePolicy) -> Result<PruneResult> {
+        let mut result = PruneResult::default();
+        if policy.is_empty() {
+            return Ok(result);
+        }
+
+        let snapshots = repository.get_snapshots()?;
+        for snapshot_id in policy.select_for_removal(&snapshots) {
+            log::info!("Remove backup snapshot {}", snapshot_id);
+            repository.remove_manifest(&snapshot_id)?;
+            result.removed_snapshots += 1;
+        }
+
+        // Objects are shared between snapshots, so only the ones no snapshot refers to are removed
+        let referenced_hashes: std::collections::HashSet<String> = repository.read_manifests()?.into_iter()
+            .flat_map(|manifest| manifest.files.into_iter().map(|file| file.hash))
+            .collect();
+        for hash in repository.get_object_hashes()? {
+            if !referenced_hashes.contains(&hash) {
+                repository.remove_object(&hash)?;
+                result.removed_objects += 1;
+            }
+        }
+
+        log::info!("Backup pruned, {} snapshots and {} objects removed", result.removed_snapshots, result.removed_objects);
+        Ok(result)
+    }
+}
+
+impl ServiceApi for CollectionBackup {
+
+}
+
+impl ServiceInitializer for CollectionBackup {
+    fn initialize(context: &Context) -> Arc<Self> {
+        let rpc = context.get_service::<Rpc>();
+
+        let settings = context.get_service::<Settings>();
+        for name in ["repository", "local_path", "remote_path", "keep_last", "keep_daily"] {
+            let _ = settings.get_string(&format!("collection_backup.{}", name));
+        }
+
+        let backup = Arc::new(Self {
+            settings,
+            collection: context.get_service::<Collection>(),
+            pending_restore: Mutex::new(None),
+        });
+
+        register_rpc_handler!(rpc, backup, "lappi.collection.backup.get_snapshots", get_snapshots());
+        register_rpc_handler!(rpc, backup, "lappi.collection.backup.restore_snapshot", restore_snapshot(snapshot_id: String));
+        register_rpc_handler!(rpc, backup, "lappi.collection.backup.restore_file", restore_file(snapshot_id: String, path: String));
+        register_rpc_handler!(rpc, backup, "lappi.collection.backup.prune", prune());
+
+        backup
+    }
+}
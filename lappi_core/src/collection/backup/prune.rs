@@ -0,0 +1,66 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Local, NaiveDate};
+use serde::Serialize;
+
+use super::manifest::SnapshotInfo;
+
+// A snapshot is kept if any of the rules keeps it, with no rules all snapshots are kept
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PrunePolicy {
+    pub keep_last: Option<usize>,
+    pub keep_daily: Option<usize>,
+}
+
+impl PrunePolicy {
+    pub fn is_empty(&self) -> bool {
+        self.keep_last.is_none() && self.keep_daily.is_none()
+    }
+
+    // Returns ids of snapshots to remove
+    pub fn select_for_removal(&self, snapshots: &[SnapshotInfo]) -> Vec<String> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+
+        let mut newest_first: Vec<&SnapshotInfo> = snapshots.iter().collect();
+        newest_first.sort_by(|a, b| b.created.cmp(&a.created));
+
+        let mut keep = HashSet::new();
+        if let Some(keep_last) = self.keep_last {
+            keep.extend(newest_first.iter().take(keep_last).map(|snapshot| snapshot.id.clone()));
+        }
+        if let Some(keep_daily) = self.keep_daily {
+            // The newest snapshot of each day represents that day
+            let mut days = HashSet::new();
+            for snapshot in &newest_first {
+                let day = match get_snapshot_day(snapshot) {
+                    Some(day) => day,
+                    None => continue,
+                };
+                if days.len() == keep_daily && !days.contains(&day) {
+                    break;
+                }
+                if days.insert(day) {
+                    keep.insert(snapshot.id.clone());
+                }
+            }
+        }
+
+        newest_first.into_iter()
+            .filter(|snapshot| !keep.contains(&snapshot.id))
+            .map(|snapshot| snapshot.id.clone())
+            .collect()
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct PruneResult {
+    pub removed_snapshots: usize,
+    pub removed_objects: usize,
+}
+
+fn get_snapshot_day(snapshot: &SnapshotInfo) -> Option<NaiveDate> {
+    DateTime::parse_from_rfc3339(&snapshot.created).ok()
+        .map(|created| created.with_timezone(&Local).date_naive())
+}
@@ -0,0 +1,220 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Cursor;
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::remote::adapter::{self, RemoteStorageAdapter};
+
+use super::manifest::{SnapshotInfo, SnapshotManifest};
+
+static OBJECTS_DIR: &str = "objects";
+static SNAPSHOTS_DIR: &str = "snapshots";
+static PART_SUFFIX: &str = ".lappi-part";
+static LOCK_FILE_NAME: &str = "lock.yaml";
+// A lock left by a client that crashed stops blocking the repository after this time
+static STALE_LOCK_HOURS: i64 = 24;
+// Length of a hex encoded blake3 hash
+static HASH_LENGTH: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RepositoryLock {
+    token: String,
+    purpose: String,
+    created: String,
+}
+
+impl RepositoryLock {
+    fn is_stale(&self) -> bool {
+        match chrono::DateTime::parse_from_rfc3339(&self.created) {
+            Ok(created) => chrono::Local::now().signed_duration_since(created) > chrono::Duration::hours(STALE_LOCK_HOURS),
+            Err(_) => true,
+        }
+    }
+}
+
+// Content-addressed storage of backups: every file is stored once under its blake3 hash,
+// snapshots are manifests that map collection paths to stored objects
+pub struct BackupRepository {
+    adapter: Box<dyn RemoteStorageAdapter>,
+    base_path: Utf8PathBuf,
+}
+
+impl BackupRepository {
+    pub fn new(adapter: Box<dyn RemoteStorageAdapter>, base_path: Utf8PathBuf) -> Self {
+        Self {
+            adapter,
+            base_path,
+        }
+    }
+
+    pub fn get_snapshot_ids(&mut self) -> Result<Vec<String>> {
+        let snapshots_path = self.base_path.join(SNAPSHOTS_DIR);
+        let names = self.list_dir_if_exists(&snapshots_path)?;
+        let mut ids: Vec<String> = names.iter()
+            .filter_map(|name| name.strip_suffix(".yaml"))
+            .map(|id| id.to_string())
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    pub fn read_manifest(&mut self, snapshot_id: &str) -> Result<SnapshotManifest> {
+        let mut data = Vec::new();
+        self.adapter.read_file(&self.get_manifest_path(snapshot_id), &mut data)
+            .context(format!("Failed to read snapshot {}", snapshot_id))?;
+        Ok(serde_yaml::from_slice(&data)?)
+    }
+
+    pub fn read_manifests(&mut self) -> Result<Vec<SnapshotManifest>> {
+        let mut manifests = Vec::new();
+        for snapshot_id in self.get_snapshot_ids()? {
+            manifests.push(self.read_manifest(&snapshot_id)?);
+        }
+        Ok(manifests)
+    }
+
+    pub fn get_snapshots(&mut self) -> Result<Vec<SnapshotInfo>> {
+        Ok(self.read_manifests()?.iter().map(|manifest| manifest.get_info()).collect())
+    }
+
+    // The manifest is written last, so a snapshot is visible only when all its objects are stored
+    pub fn write_manifest(&mut self, manifest: &SnapshotManifest) -> Result<()> {
+        let data = serde_yaml::to_string(manifest)?;
+        let path = self.get_manifest_path(&manifest.id);
+        self.write_atomically(&path, &mut Cursor::new(data.into_bytes()))
+    }
+
+    pub fn remove_manifest(&mut self, snapshot_id: &str) -> Result<()> {
+        let path = self.get_manifest_path(snapshot_id);
+        self.adapter.remove_file(&path)
+    }
+
+    pub fn write_object(&mut self, hash: &str, src_path: &Utf8Path) -> Result<()> {
+        let mut file = File::open(src_path).context(format!("Failed to open file {:?}", src_path))?;
+        let path = self.get_object_path(hash)?;
+        self.write_atomically(&path, &mut file)
+    }
+
+    // The object is verified against its hash before it replaces the destination file
+    pub fn read_object(&mut self, hash: &str, dst_path: &Utf8Path) -> Result<()> {
+        let object_path = self.get_object_path(hash)?;
+        let parent_path = dst_path.parent().ok_or_else(|| anyhow::anyhow!("Failed to get parent directory of {:?}", dst_path))?;
+        std::fs::create_dir_all(parent_path).context(format!("Failed to create directory {:?}", parent_path))?;
+
+        let part_path = Utf8PathBuf::from(format!("{}{}", dst_path, PART_SUFFIX));
+        let mut file = File::create(&part_path)?;
+        let result = self.adapter.read_file(&object_path, &mut file)
+            .and_then(|_| file.sync_all().map_err(Into::into))
+            .and_then(|_| crate::utils::hash::blake3::calc_file_hash(&part_path))
+            .and_then(|actual_hash| {
+                if crate::utils::hash::blake3::to_hex(&actual_hash) != hash {
+                    anyhow::bail!("Backup object {} is corrupted", hash);
+                }
+                Ok(())
+            });
+        if let Err(err) = result {
+            let _ = std::fs::remove_file(&part_path);
+            return Err(err);
+        }
+
+        std::fs::rename(&part_path, dst_path).context(format!("Failed to rename {:?}", part_path))?;
+        Ok(())
+    }
+
+    pub fn get_object_hashes(&mut self) -> Result<HashSet<String>> {
+        let objects_path = self.base_path.join(OBJECTS_DIR);
+        let mut hashes = HashSet::new();
+        for prefix in self.list_dir_if_exists(&objects_path)? {
+            for name in self.list_dir_if_exists(&objects_path.join(&prefix))? {
+                if is_valid_hash(&name) {
+                    hashes.insert(name);
+                }
+            }
+        }
+        Ok(hashes)
+    }
+
+    pub fn remove_object(&mut self, hash: &str) -> Result<()> {
+        let path = self.get_object_path(hash)?;
+        self.adapter.remove_file(&path)
+    }
+
+    // Backups and prunes run with the repository locked, so a prune never removes objects
+    // that a backup stored for a snapshot whose manifest is not written yet
+    pub fn with_lock<T>(&mut self, purpose: &str, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.lock(purpose)?;
+        let result = f(self);
+        if let Err(err) = self.adapter.remove_file(&self.base_path.join(LOCK_FILE_NAME)) {
+            log::error!("Failed to unlock backup repository: {:#}", err);
+        }
+        result
+    }
+
+    fn lock(&mut self, purpose: &str) -> Result<()> {
+        if let Some(lock) = self.read_lock()? {
+            if !lock.is_stale() {
+                anyhow::bail!("Backup repository is locked by {} started at {}", lock.purpose, lock.created);
+            }
+            log::warn!("Take over stale backup repository lock of {} started at {}", lock.purpose, lock.created);
+        }
+
+        let created = chrono::Local::now().to_rfc3339();
+        let token = blake3::hash(format!("{}-{}", std::process::id(), created).as_bytes()).to_hex().to_string();
+        let lock = RepositoryLock {
+            token,
+            purpose: purpose.to_string(),
+            created,
+        };
+        let data = serde_yaml::to_string(&lock)?;
+        self.write_atomically(&self.base_path.join(LOCK_FILE_NAME), &mut Cursor::new(data.into_bytes()))?;
+
+        // Clients that lock the repository at the same time both write the lock, the last write wins
+        match self.read_lock()? {
+            Some(stored_lock) if stored_lock.token == lock.token => Ok(()),
+            _ => anyhow::bail!("Backup repository was locked by another client"),
+        }
+    }
+
+    fn read_lock(&mut self) -> Result<Option<RepositoryLock>> {
+        let mut data = Vec::new();
+        match self.adapter.read_file(&self.base_path.join(LOCK_FILE_NAME), &mut data) {
+            Ok(()) => Ok(Some(serde_yaml::from_slice(&data)?)),
+            Err(err) if adapter::is_not_found(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    // A repository without snapshots may have no folders yet, other listing errors are real
+    fn list_dir_if_exists(&mut self, path: &Utf8Path) -> Result<Vec<String>> {
+        match self.adapter.list_dir(path) {
+            Ok(names) => Ok(names),
+            Err(err) if adapter::is_not_found(&err) => Ok(Vec::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn write_atomically(&mut self, path: &Utf8Path, src: &mut dyn std::io::Read) -> Result<()> {
+        let part_path = Utf8PathBuf::from(format!("{}{}", path, PART_SUFFIX));
+        self.adapter.write_file(&part_path, src)?;
+        self.adapter.rename_file(&part_path, path)
+    }
+
+    fn get_manifest_path(&self, snapshot_id: &str) -> Utf8PathBuf {
+        self.base_path.join(SNAPSHOTS_DIR).join(format!("{}.yaml", snapshot_id))
+    }
+
+    // Objects are spread over folders by the first byte of the hash to keep folders small
+    fn get_object_path(&self, hash: &str) -> Result<Utf8PathBuf> {
+        if !is_valid_hash(hash) {
+            anyhow::bail!("Invalid backup object hash '{}'", hash);
+        }
+        Ok(self.base_path.join(OBJECTS_DIR).join(&hash[..2]).join(hash))
+    }
+}
+
+fn is_valid_hash(hash: &str) -> bool {
+    hash.len() == HASH_LENGTH && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
@@ -0,0 +1,170 @@
+use camino::Utf8PathBuf;
+use chrono::{Local, TimeZone};
+
+use crate::storage::remote::adapter::file_system::FileSystemFactory;
+use crate::storage::remote::adapter::{RemoteStorageFactory, RemoteStorageSettings};
+
+use super::manifest::{SnapshotFile, SnapshotInfo, SnapshotManifest};
+use super::prune::PrunePolicy;
+use super::repository::BackupRepository;
+
+// Days of snapshots are local days, so the times are local too
+fn snapshot(id: &str, day: u32, hour: u32) -> SnapshotInfo {
+    SnapshotInfo {
+        id: id.to_string(),
+        created: Local.with_ymd_and_hms(2024, 3, day, hour, 0, 0).unwrap().to_rfc3339(),
+        files_count: 0,
+        total_size: 0,
+    }
+}
+
+fn get_snapshots() -> Vec<SnapshotInfo> {
+    vec![
+        snapshot("1", 1, 9),
+        snapshot("2", 1, 18),
+        snapshot("3", 2, 12),
+        snapshot("4", 4, 8),
+        snapshot("5", 4, 20),
+    ]
+}
+
+fn sorted(mut ids: Vec<String>) -> Vec<String> {
+    ids.sort();
+    ids
+}
+
+struct TestRepository {
+    base_path: Utf8PathBuf,
+    repository: BackupRepository,
+}
+
+impl TestRepository {
+    fn create() -> Self {
+        let base_path = Utf8PathBuf::from_path_buf(std::env::temp_dir()).unwrap()
+            .join(format!("lappi-backup-test-{:08x}", rand::random::<u32>()));
+        std::fs::create_dir_all(&base_path).unwrap();
+        Self {
+            repository: Self::open(&base_path),
+            base_path,
+        }
+    }
+
+    fn open(base_path: &Utf8PathBuf) -> BackupRepository {
+        let adapter = FileSystemFactory::new().connect(&RemoteStorageSettings {
+            url: base_path.join("repository").to_string(),
+            user: String::new(),
+            password: String::new(),
+            path: String::new(),
+            region: String::new(),
+            host_key: String::new(),
+        }).unwrap();
+        BackupRepository::new(adapter, Utf8PathBuf::new())
+    }
+
+    // Source files live next to the repository, not in it
+    fn write_source(&self, name: &str, content: &[u8]) -> (String, Utf8PathBuf) {
+        let path = self.base_path.join("source").join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        (blake3::hash(content).to_hex().to_string(), path)
+    }
+}
+
+impl Drop for TestRepository {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.base_path);
+    }
+}
+
+#[test]
+fn keeps_all_snapshots_without_rules() {
+    assert!(PrunePolicy::default().select_for_removal(&get_snapshots()).is_empty());
+}
+
+#[test]
+fn keeps_last_snapshots() {
+    let policy = PrunePolicy { keep_last: Some(2), keep_daily: None };
+    assert_eq!(sorted(policy.select_for_removal(&get_snapshots())), vec!["1", "2", "3"]);
+}
+
+#[test]
+fn keeps_newest_snapshot_of_each_day() {
+    let policy = PrunePolicy { keep_last: None, keep_daily: Some(2) };
+    assert_eq!(sorted(policy.select_for_removal(&get_snapshots())), vec!["1", "2", "4"]);
+}
+
+#[test]
+fn keeps_snapshots_any_rule_keeps() {
+    let policy = PrunePolicy { keep_last: Some(1), keep_daily: Some(3) };
+    assert_eq!(sorted(policy.select_for_removal(&get_snapshots())), vec!["1", "4"]);
+}
+
+#[test]
+fn empty_repository_has_no_snapshots() {
+    let mut test = TestRepository::create();
+    assert!(test.repository.get_snapshot_ids().unwrap().is_empty());
+    assert!(test.repository.get_object_hashes().unwrap().is_empty());
+}
+
+#[test]
+fn listing_errors_are_not_hidden() {
+    let mut test = TestRepository::create();
+    // The repository folder is a file, so it can't be listed
+    std::fs::create_dir_all(test.base_path.join("repository")).unwrap();
+    std::fs::write(test.base_path.join("repository").join("snapshots"), b"").unwrap();
+    assert!(test.repository.get_snapshot_ids().is_err());
+}
+
+#[test]
+fn restores_stored_snapshot() {
+    let mut test = TestRepository::create();
+    let (hash, src_path) = test.write_source("music/song.mp3", b"song");
+    test.repository.write_object(&hash, &src_path).unwrap();
+    let manifest = SnapshotManifest {
+        id: "20240301-090000-000".to_string(),
+        created: "2024-03-01T09:00:00+00:00".to_string(),
+        files: vec![SnapshotFile { path: "music/song.mp3".to_string(), hash: hash.clone(), size: 4 }],
+    };
+    test.repository.write_manifest(&manifest).unwrap();
+
+    let snapshots = test.repository.get_snapshots().unwrap();
+    assert_eq!(snapshots.len(), 1);
+    assert_eq!(snapshots[0].id, manifest.id);
+    assert_eq!(snapshots[0].total_size, 4);
+    assert_eq!(test.repository.get_object_hashes().unwrap().into_iter().collect::<Vec<_>>(), vec![hash.clone()]);
+
+    let file = test.repository.read_manifest(&manifest.id).unwrap().find_file("music/song.mp3").unwrap().clone();
+    let dst_path = test.base_path.join("restored").join(file.get_safe_path().unwrap());
+    test.repository.read_object(&file.hash, &dst_path).unwrap();
+    assert_eq!(std::fs::read(&dst_path).unwrap(), b"song");
+}
+
+#[test]
+fn corrupted_object_is_not_restored() {
+    let mut test = TestRepository::create();
+    let (hash, src_path) = test.write_source("song.mp3", b"song");
+    let (_, other_path) = test.write_source("other.mp3", b"other");
+    // An object whose content doesn't match its name
+    test.repository.write_object(&hash, &other_path).unwrap();
+
+    let dst_path = test.base_path.join("restored").join("song.mp3");
+    assert!(test.repository.read_object(&hash, &dst_path).is_err());
+    assert!(!dst_path.exists());
+    assert!(src_path.exists());
+}
+
+#[test]
+fn lock_blocks_other_clients() {
+    let mut test = TestRepository::create();
+    let base_path = test.base_path.clone();
+    test.repository.with_lock("backup", |_| {
+        let mut other = TestRepository::open(&base_path);
+        let err = other.with_lock("prune", |_| Ok(())).unwrap_err();
+        assert!(err.to_string().contains("locked by backup"), "{}", err);
+        Ok(())
+    }).unwrap();
+
+    // The lock is released after an error too
+    assert!(test.repository.with_lock("backup", |_| -> anyhow::Result<()> { anyhow::bail!("Backup failed") }).is_err());
+    test.repository.with_lock("prune", |_| Ok(())).unwrap();
+}
@@ -3,6 +3,7 @@ use camino::Utf8Path;
 use serde::{Serialize, Deserialize};
 use amina_core::events::Event;
 
+use crate::collection::integrity::database_api::IntegrityDbApi;
 use crate::collection::internal_files::database_api::InternalFilesDbApi;
 use crate::collection::folders::database_api::FoldersDbApi;
 use crate::collection::lyrics::database_api::LyricsDbApi;
@@ -31,9 +32,11 @@ pub trait CollectionDbApi: Send + Sync {
     fn get_music_sources_api(&self) -> Box<dyn MusicSourcesDbApi>;
     fn get_pictures_api(&self) -> Box<dyn PicturesDbApi>;
     fn get_playlist(&self) -> Box<dyn PlaylistsDbApi>;
+    fn get_integrity_api(&self) -> Box<dyn IntegrityDbApi>;
 
-    fn start_batch(&self);
-    fn stop_batch(&self);
+    fn start_batch(&self) -> Result<()>;
+    fn commit_batch(&self) -> Result<()>;
+    fn rollback_batch(&self) -> Result<()>;
  
     fn export(&self, base_path: &Utf8Path) -> Result<()>;
     fn import(&self, base_path: &Utf8Path) -> Result<()>;
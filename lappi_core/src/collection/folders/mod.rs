@@ -116,21 +116,23 @@ impl FoldersCollection {
         self.folders_db.get_folders_in_folder(folder_id)
     }
 
+    pub fn get_music_items_in_folder(&self, folder_id: FolderId) -> Result<Vec<MusicItemId>> {
+        self.folders_db.get_music_items_in_folder(folder_id)
+    }
+
     pub fn find_parent_node(&self, folder_id: FolderId, folder_type: FolderType) -> Result<Option<FolderDesc>> {
         let parent_folders = self.get_folders_chain(folder_id)?;
         Ok(parent_folders.iter().find(|f| f.folder_type == folder_type).cloned())
     }
 
     pub fn get_folder_content(&self, folder_id: FolderId) -> Result<FolderContent> {
-        let items_id = self.folders_db.get_music_items_in_folder(folder_id)?;
-        let mut items = Vec::new();
-        for item_id in items_id {
-            let name = self.music_db.get_music_item_description(item_id)?.name;
-            items.push(ItemDescription {
-                item_id,
-                name,
+        let items_id = self.get_music_items_in_folder(folder_id)?;
+        let items = self.music_db.get_music_item_descriptions(&items_id)?.into_iter()
+            .map(|description| ItemDescription {
+                item_id: description.item_id,
+                name: description.name,
             })
-        }
+            .collect();
 
         let folders = self.get_folders_in_folder(folder_id)?;
 
@@ -178,17 +180,8 @@ impl FoldersCollection {
 
     pub fn get_own_folder_tag(&self, folder_id: FolderId) -> Result<Option<Tag>> {
         let description = self.folders_db.get_folder_description(folder_id)?;
-        Ok(match description.folder_type {
-            FolderType::Folder => {
-                None
-            }
-            FolderType::Album => {
-                Some(Tag::new_string("album".to_string(), description.name))
-            }
-            FolderType::Artist => {
-                Some(Tag::new_string("artist".to_string(), description.name))
-            }
-        })
+        Ok(description.folder_type.get_own_tag_name()
+            .map(|tag_name| Tag::new_string(tag_name.to_string(), description.name)))
     }
 
     pub fn get_tags(&self, folder_id: FolderId) -> Result<Vec<Tag>> {
@@ -197,6 +190,10 @@ impl FoldersCollection {
 
     pub fn get_inherited_tags(&self, folder_id: FolderId) -> Result<Vec<Tag>> {
         let mut tags = vec![];
+        // The root folder has no row, so there is no parent to inherit from
+        if folder_id == self.get_root_folder() {
+            return Ok(tags);
+        }
 
         let own_tag = self.get_own_folder_tag(folder_id)?;
         if let Some(own_tag) = own_tag {
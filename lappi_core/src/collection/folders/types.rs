@@ -13,6 +13,17 @@ pub enum FolderType {
     Album = 2,
 }
 
+impl FolderType {
+    // Artist and album folders give their name to the items inside as a tag
+    pub fn get_own_tag_name(&self) -> Option<&'static str> {
+        match self {
+            FolderType::Folder => None,
+            FolderType::Artist => Some("artist"),
+            FolderType::Album => Some("album"),
+        }
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct FolderDesc {
     pub folder_id: FolderId,
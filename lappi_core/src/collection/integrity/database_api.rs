@@ -0,0 +1,46 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::collection::internal_files::InternalFileId;
+
+pub struct InternalFileRecord {
+    pub file_id: InternalFileId,
+    pub internal_path: String,
+    pub hash: Vec<u8>,
+}
+
+// References from collection tables to rows that don't exist anymore
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DanglingReference {
+    MusicFileToInternalFile,
+    LyricsToInternalFile,
+    PictureToInternalFile,
+    PictureToFolder,
+    FolderDescriptionToInternalFile,
+    FolderCoverToPicture,
+    PlaylistCoverToPicture,
+}
+
+impl DanglingReference {
+    // Pictures go before covers, because removed pictures leave covers dangling
+    pub fn get_all() -> [DanglingReference; 7] {
+        [
+            DanglingReference::MusicFileToInternalFile,
+            DanglingReference::LyricsToInternalFile,
+            DanglingReference::PictureToInternalFile,
+            DanglingReference::PictureToFolder,
+            DanglingReference::FolderDescriptionToInternalFile,
+            DanglingReference::FolderCoverToPicture,
+            DanglingReference::PlaylistCoverToPicture,
+        ]
+    }
+}
+
+pub trait IntegrityDbApi: Send + Sync {
+    fn clone_api(&self) -> Box<dyn IntegrityDbApi>;
+    fn get_internal_files(&self) -> Result<Vec<InternalFileRecord>>;
+    fn find_dangling_rows(&self, reference: DanglingReference) -> Result<Vec<i64>>;
+    // Rows that can't exist without the reference are deleted, optional references are cleared
+    fn repair_dangling_rows(&self, reference: DanglingReference, row_ids: &[i64]) -> Result<()>;
+}
@@ -0,0 +1,128 @@
+pub mod database_api;
+pub mod report;
+
+#[cfg(test)]
+mod tests;
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use amina_core::register_rpc_handler;
+use amina_core::rpc::Rpc;
+use amina_core::service::{Context, ServiceApi, ServiceInitializer};
+
+use crate::collection::Collection;
+use crate::collection::internal_files::InternalFileId;
+use crate::database::Database;
+
+use database_api::{DanglingReference, IntegrityDbApi};
+use report::{FileIssue, IntegrityReport};
+
+// Keeps the report of the last integrity check
+pub struct CollectionIntegrity {
+    db: Box<dyn IntegrityDbApi>,
+    report: RwLock<Option<IntegrityReport>>,
+}
+
+impl CollectionIntegrity {
+    pub fn db(&self) -> &dyn IntegrityDbApi {
+        self.db.as_ref()
+    }
+
+    pub fn get_report(&self) -> Option<IntegrityReport> {
+        self.report.read().unwrap().clone()
+    }
+
+    pub fn set_report(&self, report: IntegrityReport) -> Result<()> {
+        report.save()?;
+        *self.report.write().unwrap() = Some(report);
+        Ok(())
+    }
+
+    // Rows of missing files are dropped only on request, for the files the user confirmed from the report
+    pub fn drop_missing_files(&self, file_ids: Vec<InternalFileId>) -> Result<()> {
+        let mut report = self.get_report().ok_or_else(|| anyhow::anyhow!("Collection integrity was not checked yet"))?;
+        let context = crate::context();
+        let collection = context.get_service::<Collection>();
+        let internal_files_db = context.get_service::<Database>().get_internal_files_api();
+
+        collection.transaction(|collection| {
+            for file_id in &file_ids {
+                let issue = report.missing_files.iter()
+                    .find(|issue| issue.file_id == *file_id)
+                    .ok_or_else(|| anyhow::anyhow!("File {} is not reported as missing", file_id))?;
+                // The file may have come back since the check
+                if collection.get_local_path().join(&issue.path).exists() {
+                    anyhow::bail!("File {} is not missing anymore", issue.path);
+                }
+                log::info!("Drop internal file row of missing {}", issue.path);
+                internal_files_db.delete_file(*file_id)?;
+                report.repairs.push(format!("Dropped row of missing file {}", issue.path));
+            }
+            report.missing_files.retain(|issue| !file_ids.contains(&issue.file_id));
+            // Dropped rows may leave new references dangling
+            self.repair_dangling_rows(&mut report)
+        })?;
+
+        collection.save();
+        self.set_report(report)
+    }
+
+    pub fn repair_dangling_rows(&self, report: &mut IntegrityReport) -> Result<()> {
+        for reference in DanglingReference::get_all() {
+            let row_ids = self.db.find_dangling_rows(reference)?;
+            if row_ids.is_empty() {
+                continue;
+            }
+            self.db.repair_dangling_rows(reference, &row_ids)?;
+            report.repairs.push(format!("Repaired {} rows with dangling reference {:?}", row_ids.len(), reference));
+        }
+        report.dangling_rows.clear();
+        Ok(())
+    }
+}
+
+// Pairs missing files with orphan files of the same content, which is how moved files are found.
+// An orphan file is given to one missing file at most.
+pub fn match_moved_files(missing_files: &[FileIssue], expected_hashes: &HashMap<InternalFileId, Vec<u8>>, orphan_hashes: &[(String, Vec<u8>)]) -> HashMap<InternalFileId, String> {
+    let mut orphans_by_hash: HashMap<&Vec<u8>, &String> = HashMap::new();
+    for (orphan_path, hash) in orphan_hashes {
+        orphans_by_hash.entry(hash).or_insert(orphan_path);
+    }
+
+    let mut moved_files = HashMap::new();
+    for issue in missing_files {
+        let orphan_path = expected_hashes.get(&issue.file_id)
+            .filter(|hash| !hash.is_empty())
+            .and_then(|hash| orphans_by_hash.remove(hash));
+        if let Some(orphan_path) = orphan_path {
+            moved_files.insert(issue.file_id, orphan_path.clone());
+        }
+    }
+    moved_files
+}
+
+impl ServiceApi for CollectionIntegrity {
+
+}
+
+impl ServiceInitializer for CollectionIntegrity {
+    fn initialize(context: &Context) -> Arc<Self> {
+        let rpc = context.get_service::<Rpc>();
+        let database = context.get_service::<Database>();
+
+        let integrity = Arc::new(Self {
+            db: database.get_integrity_api(),
+            report: RwLock::new(IntegrityReport::load().unwrap_or_else(|err| {
+                log::error!("Failed to load integrity report: {}", err);
+                None
+            })),
+        });
+
+        register_rpc_handler!(rpc, integrity, "lappi.collection.integrity.get_report", get_report());
+        register_rpc_handler!(rpc, integrity, "lappi.collection.integrity.drop_missing_files", drop_missing_files(file_ids: Vec<InternalFileId>));
+
+        integrity
+    }
+}
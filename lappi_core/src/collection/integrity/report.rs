@@ -0,0 +1,72 @@
+use std::fs::File;
+
+use anyhow::Result;
+use camino::Utf8PathBuf;
+use serde::{Deserialize, Serialize};
+
+use crate::collection::internal_files::InternalFileId;
+use crate::workspace::Workspace;
+
+use super::database_api::DanglingReference;
+
+static REPORT_FILE_NAME: &str = "integrity_report.yaml";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileIssue {
+    pub file_id: InternalFileId,
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DanglingRows {
+    pub reference: DanglingReference,
+    pub row_ids: Vec<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UnreadableFile {
+    pub path: String,
+    pub error: String,
+}
+
+// Issues are grouped by category, repairs list what was fixed when the job ran in repair mode
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct IntegrityReport {
+    pub created: String,
+    pub missing_files: Vec<FileIssue>,
+    pub orphan_files: Vec<String>,
+    pub hash_mismatches: Vec<FileIssue>,
+    pub dangling_rows: Vec<DanglingRows>,
+    #[serde(default)]
+    pub unreadable_files: Vec<UnreadableFile>,
+    pub repairs: Vec<String>,
+}
+
+impl IntegrityReport {
+    pub fn get_issues_count(&self) -> usize {
+        self.missing_files.len()
+            + self.orphan_files.len()
+            + self.hash_mismatches.len()
+            + self.dangling_rows.iter().map(|rows| rows.row_ids.len()).sum::<usize>()
+            + self.unreadable_files.len()
+    }
+
+    fn get_path() -> Utf8PathBuf {
+        let workspace = crate::context().get_service::<Workspace>();
+        workspace.get_workspace_dir().join(REPORT_FILE_NAME)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let file = File::create(Self::get_path())?;
+        serde_yaml::to_writer(file, self)?;
+        Ok(())
+    }
+
+    pub fn load() -> Result<Option<Self>> {
+        let path = Self::get_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_yaml::from_reader(File::open(path)?)?))
+    }
+}
@@ -0,0 +1,40 @@
+use std::collections::HashMap;
+
+use super::match_moved_files;
+use super::report::FileIssue;
+
+fn issue(file_id: i64, path: &str) -> FileIssue {
+    FileIssue {
+        file_id,
+        path: path.to_string(),
+    }
+}
+
+#[test]
+fn relinks_missing_files_by_hash() {
+    let missing_files = vec![issue(1, "a.mp3"), issue(2, "b.mp3"), issue(3, "c.mp3")];
+    let expected_hashes = HashMap::from([(1, vec![1]), (2, vec![2]), (3, vec![3])]);
+    let orphan_hashes = vec![("moved/b.mp3".to_string(), vec![2]), ("moved/a.mp3".to_string(), vec![1])];
+
+    let moved_files = match_moved_files(&missing_files, &expected_hashes, &orphan_hashes);
+    assert_eq!(moved_files, HashMap::from([(1, "moved/a.mp3".to_string()), (2, "moved/b.mp3".to_string())]));
+}
+
+#[test]
+fn gives_orphan_file_to_one_missing_file() {
+    let missing_files = vec![issue(1, "a.mp3"), issue(2, "copy of a.mp3")];
+    let expected_hashes = HashMap::from([(1, vec![1]), (2, vec![1])]);
+    let orphan_hashes = vec![("moved/a.mp3".to_string(), vec![1])];
+
+    let moved_files = match_moved_files(&missing_files, &expected_hashes, &orphan_hashes);
+    assert_eq!(moved_files, HashMap::from([(1, "moved/a.mp3".to_string())]));
+}
+
+#[test]
+fn skips_files_that_were_never_hashed() {
+    let missing_files = vec![issue(1, "a.mp3")];
+    let expected_hashes = HashMap::from([(1, vec![])]);
+    let orphan_hashes = vec![("empty.mp3".to_string(), vec![])];
+
+    assert!(match_moved_files(&missing_files, &expected_hashes, &orphan_hashes).is_empty());
+}
@@ -2,6 +2,7 @@ pub mod types;
 pub mod database_api;
 
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use anyhow::Result;
 use camino::{Utf8Path, Utf8PathBuf};
@@ -17,6 +18,23 @@ use crate::storage::local::LocalStorage;
 pub use types::*;
 
 static FILE_HANDLER_KEY: &str = "lappi.collection.internal";
+static PREPARED_FILES_FOLDER: &str = ".import";
+static NEXT_PREPARED_FILE: AtomicUsize = AtomicUsize::new(0);
+
+// A file copied into the collection storage before the transaction that adds it, which then only moves it.
+// The copy is removed if it is never added.
+pub struct PreparedFile {
+    path: Utf8PathBuf,
+    hash: FileHash,
+}
+
+impl Drop for PreparedFile {
+    fn drop(&mut self) {
+        if self.path.exists() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct InternalFiles {
@@ -68,6 +86,30 @@ impl InternalFiles {
         Ok(file_id)
     }
 
+    pub fn prepare_file_copy(&self, src_path: &Utf8Path) -> Result<PreparedFile> {
+        let folder_path = self.local_storage.get_collection_base_path().join(PREPARED_FILES_FOLDER);
+        std::fs::create_dir_all(&folder_path)?;
+        let file_name = format!("{}-{}", std::process::id(), NEXT_PREPARED_FILE.fetch_add(1, Ordering::SeqCst));
+        let mut prepared_file = PreparedFile {
+            path: folder_path.join(file_name),
+            hash: FileHash::from(Vec::new()),
+        };
+        std::fs::copy(src_path, &prepared_file.path)?;
+        prepared_file.hash = FileHash::from(crate::utils::hash::blake3::calc_file_hash(&prepared_file.path)?);
+        Ok(prepared_file)
+    }
+
+    pub fn add_prepared_file(&self, prepared_file: PreparedFile, internal_path: &InternalPath) -> Result<InternalFileId> {
+        let file_id = self.db.add_file_path(internal_path)?;
+        let path = self.get_system_path(file_id)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(&prepared_file.path, path)?;
+        self.db.set_file_hash(file_id, &prepared_file.hash)?;
+        Ok(file_id)
+    }
+
     pub fn move_file(&self, file_id: InternalFileId, new_path: &InternalPath) -> Result<()> {
         log::info!("Move. file_id: {}, new_path: {}", file_id, new_path.as_str());
         let current_path = self.get_system_path(file_id)?;
@@ -129,10 +171,20 @@ impl InternalFiles {
         Ok(file_hash)
     }
 
-    pub fn update_file_hashes(&self) -> Result<()> {
-        let files_list = self.db.get_all_files()?;
-        for file_id in files_list {
-            self.update_file_hash(file_id)?;
+    // Reading files takes long, so hashes are computed outside of transactions and written with `set_file_hashes`
+    pub fn calc_file_hashes(&self) -> Result<Vec<(InternalFileId, FileHash)>> {
+        let mut file_hashes = Vec::new();
+        for file_id in self.db.get_all_files()? {
+            let path = self.get_system_path(file_id)?;
+            let hash_result = crate::utils::hash::blake3::calc_file_hash(&path)?;
+            file_hashes.push((file_id, FileHash::from(hash_result)));
+        }
+        Ok(file_hashes)
+    }
+
+    pub fn set_file_hashes(&self, file_hashes: &[(InternalFileId, FileHash)]) -> Result<()> {
+        for (file_id, file_hash) in file_hashes {
+            self.db.set_file_hash(*file_id, file_hash)?;
         }
         Ok(())
     }
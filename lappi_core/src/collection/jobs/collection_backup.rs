@@ -0,0 +1,196 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use amina_core::service::{Context as AppContext, Service};
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::collection::Collection;
+use crate::collection::backup::CollectionBackup;
+use crate::collection::backup::manifest::{SnapshotFile, SnapshotManifest};
+use crate::collection::backup::repository::BackupRepository;
+use crate::database::sqlite::utils::ProtobufImporter;
+use crate::jobs::{JobContext, JobDescription, JobFactory, Jobs};
+use crate::proto::collection::InternalFilesRow;
+
+struct CollectionBackupJob {
+    job_ctx: Arc<JobContext>,
+    collection: Service<Collection>,
+    backup: Service<CollectionBackup>,
+}
+
+impl CollectionBackupJob {
+    fn set_progress(&self, progress: f32, title: &str) {
+        self.job_ctx.set_progress(progress, title.to_string());
+    }
+
+    fn get_meta_path(&self) -> Utf8PathBuf {
+        Utf8PathBuf::from(".lappi/meta")
+    }
+
+    // Files that can't be read are left out of the snapshot and reported, so one broken file does not stop the backup
+    fn collect_internal_files(&self, skipped_files: &mut Vec<String>) -> Result<Vec<SnapshotFile>> {
+        let base_path = self.collection.get_local_path();
+        let mut importer = ProtobufImporter::create(&base_path.join(self.get_meta_path()).join("internal_files.pb"))?;
+        let mut files = Vec::new();
+        while let Some(row) = importer.read_next_row::<InternalFilesRow>()? {
+            match self.get_snapshot_file(&base_path, row) {
+                Ok(file) => files.push(file),
+                Err(err) => {
+                    log::warn!("Skip file in backup: {:#}", err);
+                    skipped_files.push(err.to_string());
+                },
+            }
+        }
+        Ok(files)
+    }
+
+    fn get_snapshot_file(&self, base_path: &Utf8Path, row: InternalFilesRow) -> Result<SnapshotFile> {
+        let file_path = base_path.join(&row.internal_path);
+        let size = std::fs::metadata(&file_path).context(format!("Can't read {}", row.internal_path))?.len();
+        // The hash may be missing if the file could not be hashed when the collection was saved
+        let hash = if row.hash.is_empty() {
+            crate::utils::hash::blake3::calc_file_hash(&file_path).context(format!("Can't hash {}", row.internal_path))?
+        } else {
+            row.hash
+        };
+        Ok(SnapshotFile {
+            size,
+            path: row.internal_path,
+            hash: crate::utils::hash::blake3::to_hex(&hash),
+        })
+    }
+
+    fn collect_meta_files(&self) -> Result<Vec<SnapshotFile>> {
+        let meta_path = self.collection.get_local_path().join(self.get_meta_path());
+        let mut files = Vec::new();
+        for entry in meta_path.read_dir_utf8()? {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            files.push(SnapshotFile {
+                path: self.get_meta_path().join(entry.file_name()).to_string(),
+                hash: crate::utils::hash::blake3::to_hex(&crate::utils::hash::blake3::calc_file_hash(entry.path())?),
+                size: entry.metadata()?.len(),
+            });
+        }
+        Ok(files)
+    }
+
+    // Objects of earlier snapshots are already stored, this is what makes backups incremental
+    fn get_stored_hashes(&self, repository: &mut BackupRepository) -> Result<HashSet<String>> {
+        Ok(repository.read_manifests()?.into_iter()
+            .flat_map(|manifest| manifest.files.into_iter().map(|file| file.hash))
+            .collect())
+    }
+
+    fn store_objects(&self, repository: &mut BackupRepository, files: &[SnapshotFile]) -> Result<()> {
+        let mut stored_hashes = self.get_stored_hashes(repository)?;
+        let new_files: Vec<&SnapshotFile> = files.iter()
+            .filter(|file| !stored_hashes.contains(&file.hash))
+            .collect();
+        log::info!("Backup {} new files of {}", new_files.len(), files.len());
+
+        for (index, file) in new_files.iter().enumerate() {
+            if self.job_ctx.is_interrupted() {
+                anyhow::bail!("Backup interrupted");
+            }
+            self.set_progress(index as f32 / new_files.len() as f32, &format!("Backup files {}/{}", index, new_files.len()));
+
+            // The same content may appear under several paths
+            if stored_hashes.insert(file.hash.clone()) {
+                log::debug!("Backup file {}", file.path);
+                repository.write_object(&file.hash, &self.collection.get_local_path().join(Utf8Path::new(&file.path)))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_snapshot(&self, repository: &mut BackupRepository, files: Vec<SnapshotFile>) -> Result<()> {
+        self.store_objects(repository, &files)?;
+
+        let now = chrono::Local::now();
+        let manifest = SnapshotManifest {
+            id: now.format("%Y%m%d-%H%M%S-%3f").to_string(),
+            created: now.to_rfc3339(),
+            files,
+        };
+        // Ids have millisecond precision, an existing snapshot is still never replaced
+        if repository.get_snapshot_ids()?.contains(&manifest.id) {
+            anyhow::bail!("Backup snapshot {} already exists", manifest.id);
+        }
+        log::info!("Write backup snapshot {}", manifest.id);
+        repository.write_manifest(&manifest)?;
+
+        self.set_progress(1.0, "Prune old snapshots");
+        self.backup.prune_repository(repository, &self.backup.get_prune_policy())?;
+        Ok(())
+    }
+
+    fn run(&self) -> Result<()> {
+        self.set_progress(0.0, "Update file hashes");
+        let file_hashes = self.collection.internal_files().calc_file_hashes()?;
+        self.collection.transaction(|collection| collection.internal_files().set_file_hashes(&file_hashes))?;
+        self.set_progress(0.0, "Save collection");
+        self.collection.save();
+
+        self.set_progress(0.0, "Open backup repository");
+        let mut repository = self.backup.open_repository()?;
+
+        let mut skipped_files = Vec::new();
+        let mut files = self.collect_internal_files(&mut skipped_files)?;
+        files.extend(self.collect_meta_files()?);
+        repository.with_lock("backup", |repository| self.write_snapshot(repository, files))?;
+
+        if skipped_files.is_empty() {
+            self.set_progress(1.0, "Done");
+        } else {
+            self.set_progress(1.0, &format!("Done, {} files skipped: {}", skipped_files.len(), skipped_files.join("; ")));
+        }
+        Ok(())
+    }
+}
+
+struct CollectionBackupJobFactory {
+
+}
+
+impl CollectionBackupJobFactory {
+    fn create(_: &AppContext) -> Box<Self> {
+        Box::new(Self {
+
+        })
+    }
+}
+
+impl JobFactory for CollectionBackupJobFactory {
+    fn get_description(&self) -> Box<JobDescription> {
+        Box::new(JobDescription {
+            job_id: "Collection backup",
+            name: "Collection backup",
+            icon: "backup",
+            description: "Store a snapshot of the collection in the backup repository, only new files are copied.",
+        })
+    }
+
+    fn is_always_ready(&self) -> bool {
+        true
+    }
+
+    fn run(&self, job_ctx: Arc<JobContext>) -> Result<()> {
+        let context = crate::context();
+        let job = CollectionBackupJob {
+            job_ctx,
+            collection: context.get_service::<Collection>(),
+            backup: context.get_service::<CollectionBackup>(),
+        };
+        job.run()
+    }
+}
+
+pub fn initialize() {
+    let context = crate::context();
+    let jobs = context.get_service::<Jobs>();
+    jobs.register_job(CollectionBackupJobFactory::create(context));
+}
@@ -0,0 +1,269 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use amina_core::service::{Context as AppContext, Service};
+use anyhow::Result;
+use camino::{Utf8Path, Utf8PathBuf};
+use walkdir::WalkDir;
+
+use crate::collection::Collection;
+use crate::collection::integrity::{self, CollectionIntegrity};
+use crate::collection::integrity::database_api::{DanglingReference, InternalFileRecord};
+use crate::collection::integrity::report::{DanglingRows, FileIssue, IntegrityReport, UnreadableFile};
+use crate::collection::internal_files::{FileHash, InternalFileId, InternalPath};
+use crate::database::Database;
+use crate::jobs::{JobContext, JobDescription, JobFactory, Jobs};
+
+struct CollectionIntegrityJob {
+    job_ctx: Arc<JobContext>,
+    collection: Service<Collection>,
+    integrity: Service<CollectionIntegrity>,
+    database: Service<Database>,
+    repair: bool,
+    report: IntegrityReport,
+    // Hashes of the changed files found by the check
+    actual_hashes: HashMap<InternalFileId, Vec<u8>>,
+}
+
+impl CollectionIntegrityJob {
+    fn set_progress(&self, progress: f32, title: &str) {
+        self.job_ctx.set_progress(progress, title.to_string());
+    }
+
+    fn check_interrupted(&self) -> Result<()> {
+        if self.job_ctx.is_interrupted() {
+            anyhow::bail!("Integrity check interrupted");
+        }
+        Ok(())
+    }
+
+    fn get_system_path(&self, internal_path: &str) -> Utf8PathBuf {
+        self.collection.get_local_path().join(internal_path)
+    }
+
+    // Files under the collection root except the internal storage, as paths relative to the root
+    fn collect_disk_files(&self) -> Result<HashSet<String>> {
+        let base_path = self.collection.get_local_path();
+        let mut files = HashSet::new();
+        let walker = WalkDir::new(&base_path).into_iter()
+            .filter_entry(|entry| entry.depth() != 1 || entry.file_name() != ".lappi");
+        for entry in walker {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let path = Utf8Path::from_path(entry.path())
+                .ok_or_else(|| anyhow::anyhow!("Non UTF-8 path {:?}", entry.path()))?;
+            let relative_path = path.strip_prefix(&base_path)?;
+            files.insert(relative_path.components().map(|component| component.as_str()).collect::<Vec<_>>().join("/"));
+        }
+        Ok(files)
+    }
+
+    // A file that can't be read is reported and the check goes on with the other files
+    fn add_unreadable_file(&mut self, path: &str, err: anyhow::Error) {
+        log::warn!("Can't read {}: {:#}", path, err);
+        self.report.unreadable_files.push(UnreadableFile {
+            path: path.to_string(),
+            error: format!("{:#}", err),
+        });
+    }
+
+    fn check_files(&mut self, internal_files: &[InternalFileRecord]) -> Result<()> {
+        let mut disk_files = self.collect_disk_files()?;
+
+        for (index, file) in internal_files.iter().enumerate() {
+            self.check_interrupted()?;
+            self.set_progress(index as f32 / internal_files.len() as f32, &format!("Check files {}/{}", index, internal_files.len()));
+
+            let issue = FileIssue {
+                file_id: file.file_id,
+                path: file.internal_path.clone(),
+            };
+            if !disk_files.remove(&file.internal_path) {
+                self.report.missing_files.push(issue);
+                continue;
+            }
+            // Files that were never hashed have nothing to compare with
+            if file.hash.is_empty() {
+                continue;
+            }
+            match crate::utils::hash::blake3::calc_file_hash(&self.get_system_path(&file.internal_path)) {
+                Ok(hash) if hash != file.hash => {
+                    self.actual_hashes.insert(file.file_id, hash);
+                    self.report.hash_mismatches.push(issue);
+                },
+                Ok(_) => {},
+                Err(err) => self.add_unreadable_file(&file.internal_path, err),
+            }
+        }
+
+        let mut orphan_files: Vec<String> = disk_files.into_iter().collect();
+        orphan_files.sort();
+        self.report.orphan_files = orphan_files;
+        Ok(())
+    }
+
+    fn check_dangling_rows(&mut self) -> Result<()> {
+        self.set_progress(1.0, "Check references");
+        self.report.dangling_rows.clear();
+        for reference in DanglingReference::get_all() {
+            let row_ids = self.integrity.db().find_dangling_rows(reference)?;
+            if !row_ids.is_empty() {
+                self.report.dangling_rows.push(DanglingRows { reference, row_ids });
+            }
+        }
+        Ok(())
+    }
+
+    fn update_hashes(&mut self) -> Result<()> {
+        let internal_files_db = self.database.get_internal_files_api();
+        for issue in std::mem::take(&mut self.report.hash_mismatches) {
+            let hash = match self.actual_hashes.remove(&issue.file_id) {
+                Some(hash) => hash,
+                None => continue,
+            };
+            log::info!("Update hash of {}", issue.path);
+            internal_files_db.set_file_hash(issue.file_id, &FileHash::from(hash))?;
+            self.report.repairs.push(format!("Updated hash of {}", issue.path));
+        }
+        Ok(())
+    }
+
+    // Orphan files are hashed only if some missing file may have been moved to them
+    fn find_moved_files(&mut self, internal_files: &[InternalFileRecord]) -> Result<HashMap<InternalFileId, String>> {
+        let expected_hashes: HashMap<InternalFileId, Vec<u8>> = internal_files.iter()
+            .filter(|file| !file.hash.is_empty())
+            .map(|file| (file.file_id, file.hash.clone()))
+            .collect();
+        let missing_hashes: HashSet<&Vec<u8>> = self.report.missing_files.iter()
+            .filter_map(|issue| expected_hashes.get(&issue.file_id))
+            .collect();
+        if missing_hashes.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        let mut orphan_hashes = Vec::new();
+        let mut unreadable_files = Vec::new();
+        for (index, orphan_path) in self.report.orphan_files.iter().enumerate() {
+            self.check_interrupted()?;
+            self.set_progress(index as f32 / self.report.orphan_files.len() as f32, "Look for moved files");
+            match crate::utils::hash::blake3::calc_file_hash(&self.get_system_path(orphan_path)) {
+                Ok(hash) if missing_hashes.contains(&hash) => orphan_hashes.push((orphan_path.clone(), hash)),
+                Ok(_) => {},
+                Err(err) => unreadable_files.push((orphan_path.clone(), err)),
+            }
+        }
+        for (path, err) in unreadable_files {
+            self.add_unreadable_file(&path, err);
+        }
+        Ok(integrity::match_moved_files(&self.report.missing_files, &expected_hashes, &orphan_hashes))
+    }
+
+    fn relink_moved_files(&mut self, mut moved_files: HashMap<InternalFileId, String>) -> Result<()> {
+        let internal_files_db = self.database.get_internal_files_api();
+        let mut relinked_paths = HashSet::new();
+        let mut missing_files = Vec::new();
+        for issue in std::mem::take(&mut self.report.missing_files) {
+            match moved_files.remove(&issue.file_id) {
+                Some(orphan_path) => {
+                    log::info!("Relink {} to {}", issue.path, orphan_path);
+                    internal_files_db.set_file_path(issue.file_id, &InternalPath::from_string(orphan_path.clone()))?;
+                    self.report.repairs.push(format!("Relinked {} to {}", issue.path, orphan_path));
+                    relinked_paths.insert(orphan_path);
+                },
+                None => missing_files.push(issue),
+            }
+        }
+        self.report.missing_files = missing_files;
+        self.report.orphan_files.retain(|path| !relinked_paths.contains(path));
+        Ok(())
+    }
+
+    // Files are read before the transaction, so it holds the database only for the writes
+    fn repair(&mut self, internal_files: &[InternalFileRecord]) -> Result<()> {
+        let moved_files = self.find_moved_files(internal_files)?;
+        let collection = self.collection.clone();
+        collection.transaction(|_| {
+            self.update_hashes()?;
+            self.relink_moved_files(moved_files)?;
+            // Rows of files that are still missing are only reported, they are dropped with drop_missing_files
+            self.integrity.repair_dangling_rows(&mut self.report)
+        })?;
+
+        self.set_progress(1.0, "Save collection");
+        self.collection.save();
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<()> {
+        self.report.created = chrono::Local::now().to_rfc3339();
+
+        let internal_files = self.integrity.db().get_internal_files()?;
+        self.check_files(&internal_files)?;
+        self.check_dangling_rows()?;
+        log::info!("Integrity check found {} issues", self.report.get_issues_count());
+
+        if self.repair {
+            self.repair(&internal_files)?;
+        }
+
+        self.integrity.set_report(std::mem::take(&mut self.report))?;
+        self.set_progress(1.0, "Done");
+        Ok(())
+    }
+}
+
+struct CollectionIntegrityJobFactory {
+    repair: bool,
+}
+
+impl CollectionIntegrityJobFactory {
+    fn create(_: &AppContext, repair: bool) -> Box<Self> {
+        Box::new(Self {
+            repair,
+        })
+    }
+}
+
+impl JobFactory for CollectionIntegrityJobFactory {
+    fn get_description(&self) -> Box<JobDescription> {
+        let (name, icon, description) = if self.repair {
+            ("Collection integrity repair", "healing", "Update changed hashes, relink moved files and drop rows with dangling references. Missing files are only reported.")
+        } else {
+            ("Collection integrity check", "fact_check", "Find missing, orphan and changed files and rows with dangling references.")
+        };
+
+        Box::new(JobDescription {
+            job_id: name,
+            name,
+            icon,
+            description,
+        })
+    }
+
+    fn is_always_ready(&self) -> bool {
+        true
+    }
+
+    fn run(&self, job_ctx: Arc<JobContext>) -> Result<()> {
+        let context = crate::context();
+        let mut job = CollectionIntegrityJob {
+            job_ctx,
+            collection: context.get_service::<Collection>(),
+            integrity: context.get_service::<CollectionIntegrity>(),
+            database: context.get_service::<Database>(),
+            repair: self.repair,
+            report: IntegrityReport::default(),
+            actual_hashes: HashMap::new(),
+        };
+        job.run()
+    }
+}
+
+pub fn initialize() {
+    let context = crate::context();
+    let jobs = context.get_service::<Jobs>();
+    jobs.register_job(CollectionIntegrityJobFactory::create(context, false));
+    jobs.register_job(CollectionIntegrityJobFactory::create(context, true));
+}
@@ -0,0 +1,106 @@
+use std::sync::Arc;
+
+use amina_core::service::{Context as AppContext, Service};
+use anyhow::Result;
+
+use crate::collection::Collection;
+use crate::collection::backup::CollectionBackup;
+use crate::collection::backup::manifest::SnapshotFile;
+use crate::jobs::{JobContext, JobDescription, JobFactory, Jobs};
+
+pub static RESTORE_JOB_ID: &str = "Collection restore";
+static META_PATH: &str = ".lappi/meta/";
+
+struct CollectionRestoreJob {
+    job_ctx: Arc<JobContext>,
+    collection: Service<Collection>,
+    backup: Service<CollectionBackup>,
+}
+
+impl CollectionRestoreJob {
+    fn run(&self) -> Result<()> {
+        let snapshot_id = self.backup.take_pending_restore()
+            .ok_or_else(|| anyhow::anyhow!("No backup snapshot is selected for restore"))?;
+        self.job_ctx.set_progress(0.0, "Open backup repository".to_string());
+        let mut repository = self.backup.open_repository()?;
+        let manifest = repository.read_manifest(&snapshot_id)?;
+        // Every path is checked before anything is written
+        for file in &manifest.files {
+            file.get_safe_path()?;
+        }
+        log::info!("Restore snapshot {} with {} files", snapshot_id, manifest.files.len());
+
+        // Meta tables go last, so they never point to files that are not restored yet
+        let (meta_files, internal_files): (Vec<&SnapshotFile>, Vec<&SnapshotFile>) = manifest.files.iter()
+            .partition(|file| file.path.starts_with(META_PATH));
+        let files_count = manifest.files.len();
+        for (index, file) in internal_files.iter().enumerate() {
+            if self.job_ctx.is_interrupted() {
+                anyhow::bail!("Restore interrupted, the collection meta is not changed");
+            }
+            self.job_ctx.set_progress(index as f32 / files_count as f32, format!("Restore files {}/{}", index, files_count));
+            log::debug!("Restore file {}", file.path);
+            repository.read_object(&file.hash, &self.collection.get_local_path().join(file.get_safe_path()?))?;
+        }
+
+        // Tables are restored into a new generation, which replaces the current one only when all of them are there
+        self.job_ctx.set_progress(internal_files.len() as f32 / files_count as f32, "Restore collection tables".to_string());
+        self.collection.replace_meta(None, |staging_path| {
+            for file in &meta_files {
+                if self.job_ctx.is_interrupted() {
+                    anyhow::bail!("Restore interrupted, the collection meta is not changed");
+                }
+                let safe_path = file.get_safe_path()?;
+                let relative_path = safe_path.strip_prefix(META_PATH)?;
+                log::debug!("Restore file {}", file.path);
+                repository.read_object(&file.hash, &staging_path.join(relative_path))?;
+            }
+            Ok(())
+        })?;
+        self.job_ctx.set_progress(1.0, "Done".to_string());
+        Ok(())
+    }
+}
+
+struct CollectionRestoreJobFactory {
+
+}
+
+impl CollectionRestoreJobFactory {
+    fn create(_: &AppContext) -> Box<Self> {
+        Box::new(Self {
+
+        })
+    }
+}
+
+impl JobFactory for CollectionRestoreJobFactory {
+    fn get_description(&self) -> Box<JobDescription> {
+        Box::new(JobDescription {
+            job_id: RESTORE_JOB_ID,
+            name: RESTORE_JOB_ID,
+            icon: "restore",
+            description: "Restore the collection from the backup snapshot selected with lappi.collection.backup.restore_snapshot.",
+        })
+    }
+
+    fn is_always_ready(&self) -> bool {
+        true
+    }
+
+    fn run(&self, job_ctx: Arc<JobContext>) -> Result<()> {
+        let context = crate::context();
+        let job = CollectionRestoreJob {
+            job_ctx,
+            collection: context.get_service::<Collection>(),
+            backup: context.get_service::<CollectionBackup>(),
+        };
+        job.run()
+    }
+}
+
+pub fn initialize() {
+    let context = crate::context();
+    let jobs = context.get_service::<Jobs>();
+    jobs.register_job(CollectionRestoreJobFactory::create(context));
+}
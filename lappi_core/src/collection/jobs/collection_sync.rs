@@ -8,42 +8,68 @@ use amina_core::service::Context as AppContext;
 use anyhow::{Context, Result};
 use camino::{Utf8Path, Utf8PathBuf};
 
+use crate::database::sqlite::meta_format::{MetaFormat, FORMAT_FILE_NAME};
 use crate::database::sqlite::utils::ProtobufImporter;
 use crate::proto::collection::InternalFilesRow;
 use crate::storage::remote::RemoteStorage;
 use crate::storage::remote::adapter::{DummyRemoteStorage, RemoteStorageAdapter};
 use crate::workspace::Workspace;
 use crate::jobs::{JobContext, JobDescription, JobFactory, Jobs};
-use crate::collection::Collection;
+use crate::collection::{Collection, SavePoint};
 use crate::collection::internal_files::InternalFileId;
+use crate::collection::sync::CollectionSync;
+use crate::collection::sync::journal::SyncJournal;
+use crate::collection::sync::plan::{SyncAction, SyncPlan, SyncPlanEntry, SyncReason};
+use crate::collection::sync::rules::SyncFilter;
+use crate::collection::sync::transfer::{FileTransfer, TransferDirection, TransferRunner};
 
-#[derive(PartialEq, Eq)]
+#[derive(PartialEq, Eq, Clone, Copy)]
 enum SyncMode {
     Download,
     Upload,
 }
 
+struct PlannedFile {
+    path: Utf8PathBuf,
+    reason: SyncReason,
+    hash: Option<Vec<u8>>,
+}
+
 struct CollectionSyncJob {
     job_ctx: Arc<JobContext>,
     collection: Service<Collection>,
+    sync: Service<CollectionSync>,
     remote_storage: Box<dyn RemoteStorageAdapter>,
     sync_mode: SyncMode,
-    files_to_copy: Vec<Utf8PathBuf>,
-    files_for_remove: Vec<Utf8PathBuf>,
+    dry_run: bool,
+    filter: Option<SyncFilter>,
+    excluded_files: usize,
+    remote_format_exists: bool,
+    files_to_copy: Vec<PlannedFile>,
+    files_for_remove: Vec<PlannedFile>,
+    journal: Option<SyncJournal>,
+    save_point: Option<SavePoint>,
 }
 
 impl CollectionSyncJob {
-    fn create(job_ctx: Arc<JobContext>, sync_mode: SyncMode) -> Result<Self> {
+    fn create(job_ctx: Arc<JobContext>, sync_mode: SyncMode, dry_run: bool) -> Result<Self> {
         let collection = crate::context().get_service::<Collection>();
         let remote_storage = Box::new(DummyRemoteStorage::new());
 
         Ok(Self {
             job_ctx,
             collection,
+            sync: crate::context().get_service::<CollectionSync>(),
             remote_storage,
             sync_mode,
+            dry_run,
+            filter: None,
+            excluded_files: 0,
+            remote_format_exists: false,
             files_to_copy: Vec::new(),
             files_for_remove: Vec::new(),
+            journal: None,
+            save_point: None,
         })
     }
 
@@ -66,6 +92,11 @@ impl CollectionSyncJob {
         self.collection.get_local_path().join(internal_path)
     }
 
+    // Kept in the collection folder rather than in the temp one, so a resumed download still has the tables it got
+    fn get_meta_download_path(&self) -> Utf8PathBuf {
+        self.get_local_storage_path(Utf8Path::new(".lappi/sync_download"))
+    }
+
     fn get_remote_storage_temp_path(&self, internal_path: &Utf8Path) -> Utf8PathBuf {
         let mut path = Self::get_temp_path();
         path.push("remote_files");
@@ -77,15 +108,33 @@ impl CollectionSyncJob {
         self.job_ctx.set_progress(progress, title.to_string());
     }
 
-    fn add_file_for_copy(&mut self, file_path: &Utf8Path) -> Result<()> {
-        log::debug!("Add file to copy '{}'", file_path);
-        self.files_to_copy.push(file_path.to_owned());
+    fn add_file_for_copy(&mut self, file_path: &Utf8Path, reason: SyncReason) -> Result<()> {
+        log::debug!("Add file to copy '{}' ({:?})", file_path, reason);
+        self.files_to_copy.push(PlannedFile {
+            path: file_path.to_owned(),
+            reason,
+            hash: None,
+        });
+        Ok(())
+    }
+
+    fn add_internal_file_for_copy(&mut self, file: &InternalFilesRow, reason: SyncReason) -> Result<()> {
+        log::debug!("Add internal file to copy '{}' ({:?})", file.internal_path, reason);
+        self.files_to_copy.push(PlannedFile {
+            path: Utf8PathBuf::from(&file.internal_path),
+            reason,
+            hash: Some(file.hash.clone()),
+        });
         Ok(())
     }
 
-    fn add_file_for_remove(&mut self, file_path: &Utf8Path) -> Result<()> {
-        log::debug!("Add file for remove. file path {}", file_path);
-        self.files_for_remove.push(file_path.to_owned());
+    fn add_file_for_remove(&mut self, file_path: &Utf8Path, reason: SyncReason) -> Result<()> {
+        log::debug!("Add file for remove. file path {} ({:?})", file_path, reason);
+        self.files_for_remove.push(PlannedFile {
+            path: file_path.to_owned(),
+            reason,
+            hash: None,
+        });
         Ok(())
     }
 
@@ -111,14 +160,15 @@ impl CollectionSyncJob {
     fn update_hashes(&self) -> Result<()> {
         log::info!("Update file hashes");
         self.set_progress(0.0, "Update file hashes");
-        self.collection.internal_files().update_file_hashes()?;
+        let file_hashes = self.collection.internal_files().calc_file_hashes()?;
+        self.collection.transaction(|collection| collection.internal_files().set_file_hashes(&file_hashes))?;
         Ok(())
     }
 
-    fn save_collection(&self) -> Result<()> {
+    fn save_collection(&mut self) -> Result<()> {
         log::info!("Save collection");
         self.set_progress(0.0, "Save collection");
-        self.collection.save();
+        self.save_point = Some(self.collection.save_for_replace()?);
         Ok(())
     }
 
@@ -154,15 +204,15 @@ impl CollectionSyncJob {
                         }
                         else
                         {
-                            self.add_file_for_copy(&meta_file_path)?;
-                            self.add_file_for_copy(&meta_hash_path)?;
+                            self.add_file_for_copy(&meta_file_path, SyncReason::Meta)?;
+                            self.add_file_for_copy(&meta_hash_path, SyncReason::Meta)?;
                         }
                     },
                     Err(err) => {
                         match self.sync_mode {
                             SyncMode::Download => {
-                                self.add_file_for_copy(&meta_file_path)?;
-                                self.add_file_for_copy(&meta_hash_path)?;
+                                self.add_file_for_copy(&meta_file_path, SyncReason::Meta)?;
+                                self.add_file_for_copy(&meta_hash_path, SyncReason::Meta)?;
                             },
                             SyncMode::Upload => {
                                 return Err(err);
@@ -178,8 +228,8 @@ impl CollectionSyncJob {
                         return Err(err);
                     },
                     SyncMode::Upload => {
-                        self.add_file_for_copy(&meta_file_path)?;
-                        self.add_file_for_copy(&meta_hash_path)?;
+                        self.add_file_for_copy(&meta_file_path, SyncReason::Meta)?;
+                        self.add_file_for_copy(&meta_hash_path, SyncReason::Meta)?;
                     },
                 }
             },
@@ -188,6 +238,13 @@ impl CollectionSyncJob {
         Ok(())
     }
 
+    // A collection in a newer format can't be read here, and must not be overwritten with an older one
+    fn check_remote_format(&mut self) -> Result<()> {
+        let meta_path = Utf8PathBuf::from(".lappi/meta");
+        self.remote_format_exists = self.download_file_to_temp_folder(&meta_path.join(FORMAT_FILE_NAME)).is_ok();
+        MetaFormat::read(&self.get_remote_storage_temp_path(&meta_path))?.check_supported()
+    }
+
     fn process_meta_files(&mut self) -> Result<()> {
         let meta_files_list = vec![
             "internal_files.pb",
@@ -207,6 +264,12 @@ impl CollectionSyncJob {
             self.process_meta_file(meta_file_name)?;
         }
 
+        // The format manifest goes with the tables it describes
+        let has_meta_changes = !self.files_to_copy.is_empty();
+        if has_meta_changes && (self.sync_mode == SyncMode::Upload || self.remote_format_exists) {
+            self.add_file_for_copy(&Utf8PathBuf::from(".lappi/meta").join(FORMAT_FILE_NAME), SyncReason::Meta)?;
+        }
+
         Ok(())
     }
 
@@ -247,25 +310,35 @@ impl CollectionSyncJob {
 
         for (src_file_id, src_file_desc) in &src_files_map {
             let src_path = Utf8PathBuf::from(&src_file_desc.internal_path);
+            if !self.is_included(src_file_desc) {
+                log::debug!("Internal file is excluded by sync rules '{}'", &src_path);
+                self.excluded_files += 1;
+                if let (SyncMode::Download, Some(dst_file_desc)) = (self.sync_mode, dst_files_map.get(src_file_id)) {
+                    // Free the space taken by files that were synced before the rules changed
+                    let dst_path = Utf8PathBuf::from(&dst_file_desc.internal_path);
+                    if self.get_local_storage_path(&dst_path).exists() {
+                        self.add_file_for_remove(&dst_path, SyncReason::Excluded)?;
+                    }
+                }
+                continue;
+            }
+
             match dst_files_map.get(src_file_id) {
                 Some(dst_file_desc) => {
                     if src_file_desc.internal_path == dst_file_desc.internal_path {
-                        if src_file_desc.hash == src_file_desc.hash {
+                        if src_file_desc.hash == dst_file_desc.hash {
                             log::debug!("Internal file is up to date '{}", &src_path);
                         } else {
-                            // File changed
-                            self.add_file_for_copy(&src_path)?;
+                            self.add_internal_file_for_copy(src_file_desc, SyncReason::Changed)?;
                         }
                     } else {
-                        // File moved
                         let dst_path = Utf8PathBuf::from(&dst_file_desc.internal_path);
-                        self.add_file_for_remove(&dst_path)?;
-                        self.add_file_for_copy(&src_path)?;
+                        self.add_file_for_remove(&dst_path, SyncReason::Moved)?;
+                        self.add_internal_file_for_copy(src_file_desc, SyncReason::Moved)?;
                     }
                 },
                 None => {
-                    // File does not exist
-                    self.add_file_for_copy(&src_path)?;
+                    self.add_internal_file_for_copy(src_file_desc, SyncReason::New)?;
                 },
             }
         }
@@ -277,8 +350,7 @@ impl CollectionSyncJob {
 
                 },
                 None => {
-                    // File does not exist
-                    self.add_file_for_remove(&dst_path)?;
+                    self.add_file_for_remove(&dst_path, SyncReason::Deleted)?;
                 },
             }
         }
@@ -286,39 +358,68 @@ impl CollectionSyncJob {
         Ok(())
     }
 
+    fn get_mode_name(&self) -> &'static str {
+        match self.sync_mode {
+            SyncMode::Download => "Download",
+            SyncMode::Upload => "Upload",
+        }
+    }
+
+    fn open_journal(&mut self) -> Result<()> {
+        let remote_identity = crate::context().get_service::<RemoteStorage>().get_identity();
+        self.journal = Some(SyncJournal::open(self.get_mode_name(), &remote_identity)?);
+        Ok(())
+    }
+
+    fn get_journal(&self) -> Result<&SyncJournal> {
+        self.journal.as_ref().ok_or_else(|| anyhow::anyhow!("Sync journal is not opened"))
+    }
+
     fn copy_files(&mut self) -> Result<()> {
-        let action = match self.sync_mode {
-            SyncMode::Download => "Downloading",
-            SyncMode::Upload => "Uploading",
+        let remote_storage = crate::context().get_service::<RemoteStorage>();
+        let runner = TransferRunner {
+            direction: match self.sync_mode {
+                SyncMode::Download => TransferDirection::Download,
+                SyncMode::Upload => TransferDirection::Upload,
+            },
+            local_base: self.collection.get_local_path(),
+            connections: remote_storage.get_parallel_transfers(),
+            retries: remote_storage.get_transfer_retries(),
+            journal: self.get_journal()?,
+            job_ctx: &self.job_ctx,
         };
-        let files_num = self.files_to_copy.len();
-        for (index, file_path) in self.files_to_copy.iter().enumerate() {
-            let progress = index as f32 / files_num as f32;
-            let title = format!("{} files {}/{}", action, index, files_num);
-            self.set_progress(progress, &title);
-
-            let local_path = self.get_local_storage_path(file_path);
-            log::info!("{} file {}", action, &file_path);
 
-            match self.sync_mode {
-                SyncMode::Download => {
-                    self.ensure_directory_exists(&local_path)?;
-                    let mut local_file = File::create(&local_path)?;
-                    self.remote_storage.read_file(file_path, &mut local_file)?;
-                },
-                SyncMode::Upload => {
-                    let mut local_file = File::open(&local_path)?;
-                    self.remote_storage.write_file(file_path, &mut local_file)?;
-                },
-            };
-        }
+        // Meta tables go last, so an interrupted sync never leaves tables that point to missing files
+        let (meta_files, internal_files): (Vec<_>, Vec<_>) = self.files_to_copy.iter()
+            .map(|planned_file| (planned_file.reason, FileTransfer {
+                path: planned_file.path.clone(),
+                hash: planned_file.hash.clone(),
+            }))
+            .partition(|(reason, _)| *reason == SyncReason::Meta);
+        let connect = || remote_storage.connect();
+        runner.run(internal_files.into_iter().map(|(_, transfer)| transfer).collect(), &connect)?;
+        // Downloaded tables wait aside until they replace the local ones as one generation
+        let meta_runner = TransferRunner {
+            local_base: match self.sync_mode {
+                SyncMode::Download => self.get_meta_download_path(),
+                SyncMode::Upload => self.collection.get_local_path(),
+            },
+            ..runner
+        };
+        meta_runner.run(meta_files.into_iter().map(|(_, transfer)| transfer).collect(), &connect)?;
 
         Ok(())
     }
 
     fn remove_files(&mut self) -> Result<()> {
         let files_num = self.files_for_remove.len();
-        for (index, file_path) in self.files_for_remove.iter().enumerate() {
+        for index in 0..files_num {
+            let file_path = self.files_for_remove[index].path.clone();
+            let key = SyncJournal::get_key("remove", &file_path, None);
+            if self.get_journal()?.is_completed(&key) {
+                continue;
+            }
+
             let progress = index as f32 / files_num as f32;
             let title = format!("Removing files {}/{}", index, files_num);
             self.set_progress(progress, &title);
@@ -326,15 +427,82 @@ impl CollectionSyncJob {
 
             match self.sync_mode {
                 SyncMode::Download => {
-                    let local_path = self.get_local_storage_path(file_path);
+                    let local_path = self.get_local_storage_path(&file_path);
                     std::fs::remove_file(&local_path).context(format!("Failed to remove file {:?}", local_path))?;
                 },
                 SyncMode::Upload => {
-                    self.remote_storage.remove_file(file_path)?;
+                    self.remote_storage.remove_file(&file_path)?;
                 },
             };
+            self.get_journal()?.mark_completed(&key)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish_journal(&mut self) -> Result<()> {
+        if let Some(journal) = self.journal.take() {
+            journal.finish()?;
+        }
+        Ok(())
+    }
+
+    fn is_included(&self, file: &InternalFilesRow) -> bool {
+        self.filter.as_ref().map_or(true, |filter| filter.is_included(file))
+    }
+
+    fn create_sync_filter(&mut self) -> Result<()> {
+        let rules = self.sync.get_sync_rules();
+        if rules.is_empty() {
+            return Ok(());
+        }
+
+        self.set_progress(0.0, "Apply sync rules");
+        let meta_path = Utf8PathBuf::from(".lappi/meta");
+        let src_meta_path = match self.sync_mode {
+            SyncMode::Download => {
+                for file_name in ["music_files.pb", "folders.pb", "music_items.pb", "playlist_items.pb"] {
+                    self.download_file_to_temp_folder(&meta_path.join(file_name))?;
+                }
+                self.get_remote_storage_temp_path(&meta_path)
+            },
+            SyncMode::Upload => self.get_local_storage_path(&meta_path),
+        };
+        self.filter = Some(SyncFilter::create(rules, &src_meta_path)?);
+        Ok(())
+    }
+
+    // Sizes are taken from the side that holds the file, files that can't be checked have no size
+    fn get_file_size(&mut self, file_path: &Utf8Path, on_local_side: bool) -> Option<u64> {
+        if on_local_side {
+            std::fs::metadata(self.get_local_storage_path(file_path)).ok().map(|metadata| metadata.len())
+        } else {
+            self.remote_storage.get_file_size(file_path).ok()
+        }
+    }
+
+    fn store_sync_plan(&mut self) -> Result<()> {
+        self.set_progress(0.0, "Collect file sizes");
+        let copy_from_local = self.sync_mode == SyncMode::Upload;
+
+        let files_to_copy = std::mem::take(&mut self.files_to_copy);
+        let files_for_remove = std::mem::take(&mut self.files_for_remove);
+        let mut entries = Vec::new();
+        for (planned_files, action, on_local_side) in [
+            (&files_to_copy, SyncAction::Copy, copy_from_local),
+            (&files_for_remove, SyncAction::Remove, !copy_from_local),
+        ] {
+            for planned_file in planned_files {
+                entries.push(SyncPlanEntry {
+                    path: planned_file.path.to_string(),
+                    action,
+                    reason: planned_file.reason,
+                    size: self.get_file_size(&planned_file.path, on_local_side),
+                });
+            }
         }
 
+        self.sync.set_sync_plan(SyncPlan::new(self.get_mode_name(), entries, self.excluded_files));
         Ok(())
     }
 
@@ -343,8 +511,27 @@ impl CollectionSyncJob {
             return Ok(())
         }
 
-        self.collection.reload()?;
+        let meta_paths: Vec<Utf8PathBuf> = self.files_to_copy.iter()
+            .filter(|planned_file| planned_file.reason == SyncReason::Meta)
+            .map(|planned_file| self.get_meta_download_path().join(&planned_file.path))
+            .collect();
+        if !meta_paths.is_empty() {
+            self.set_progress(1.0, "Load downloaded tables");
+            // Local changes made after the save would be dropped with the replaced tables
+            self.collection.replace_meta(self.save_point, |staging_path| {
+                for meta_path in &meta_paths {
+                    let file_name = meta_path.file_name().ok_or_else(|| anyhow::anyhow!("Invalid meta file {}", meta_path))?;
+                    std::fs::copy(meta_path, staging_path.join(file_name))
+                        .context(format!("Downloaded table {} is missing", meta_path))?;
+                }
+                Ok(())
+            })?;
+        }
 
+        let download_path = self.get_meta_download_path();
+        if download_path.exists() {
+            std::fs::remove_dir_all(&download_path)?;
+        }
         Ok(())
     }
 
@@ -352,12 +539,23 @@ impl CollectionSyncJob {
         self.format_temp_folder()?;
         self.update_hashes()?;
         self.save_collection()?;
+        // Local tables are compared with the remote ones and may be replaced at the end, an autosave between would be lost
+        let _autosave_pause = self.collection.pause_autosave();
         self.open_remote_connection()?;
+        self.check_remote_format()?;
         self.process_meta_files()?;
+        self.create_sync_filter()?;
         self.process_internal_files()?;
+        if self.dry_run {
+            self.store_sync_plan()?;
+            self.set_progress(1.0, "Done");
+            return Ok(());
+        }
+        self.open_journal()?;
         self.copy_files()?;
         self.remove_files()?;
         self.reload_collection()?;
+        self.finish_journal()?;
         //self.format_temp_folder()?;
         self.set_progress(1.0, "Done");
 
@@ -365,61 +563,34 @@ impl CollectionSyncJob {
     }
 }
 
-struct CollectionUploadJobFactory {
-
-}
-
-impl CollectionUploadJobFactory {
-    fn create(_: &AppContext) -> Box<Self> {
-        Box::new(Self {
-
-        })
-    }
-}
-
-impl JobFactory for CollectionUploadJobFactory {
-    fn get_description(&self) -> Box<JobDescription> {
-        let name = "Collection upload";
-
-        Box::new(JobDescription {
-            job_id: name,
-            name,
-            icon: "drive_file_move",
-            description: "Upload collection to remote server.",
-        })
-    }
-
-    fn is_always_ready(&self) -> bool {
-        true
-    }
-
-    fn run(&self, job_ctx: Arc<JobContext>) -> Result<()> {
-        let mut job = CollectionSyncJob::create(job_ctx, SyncMode::Upload)?;
-        job.run()
-    }
-}
-
-struct CollectionDownloadJobFactory {
-
+struct CollectionSyncJobFactory {
+    sync_mode: SyncMode,
+    dry_run: bool,
 }
 
-impl CollectionDownloadJobFactory {
-    fn create(_: &AppContext) -> Box<Self> {
+impl CollectionSyncJobFactory {
+    fn create(_: &AppContext, sync_mode: SyncMode, dry_run: bool) -> Box<Self> {
         Box::new(Self {
-
+            sync_mode,
+            dry_run,
         })
     }
 }
 
-impl JobFactory for CollectionDownloadJobFactory {
+impl JobFactory for CollectionSyncJobFactory {
     fn get_description(&self) -> Box<JobDescription> {
-        let name = "Collection download";
+        let (name, icon, description) = match (self.sync_mode, self.dry_run) {
+            (SyncMode::Upload, false) => ("Collection upload", "drive_file_move", "Upload collection to remote server."),
+            (SyncMode::Download, false) => ("Collection download", "drive_file_move", "Download collection from remote server."),
+            (SyncMode::Upload, true) => ("Collection upload preview", "preview", "Show files that the collection upload would copy and remove."),
+            (SyncMode::Download, true) => ("Collection download preview", "preview", "Show files that the collection download would copy and remove."),
+        };
 
         Box::new(JobDescription {
             job_id: name,
             name,
-            icon: "drive_file_move",
-            description: "Download collection from remote server.",
+            icon,
+            description,
         })
     }
 
@@ -428,7 +599,7 @@ impl JobFactory for CollectionDownloadJobFactory {
     }
 
     fn run(&self, job_ctx: Arc<JobContext>) -> Result<()> {
-        let mut job = CollectionSyncJob::create(job_ctx, SyncMode::Download)?;
+        let mut job = CollectionSyncJob::create(job_ctx, self.sync_mode, self.dry_run)?;
         job.run()
     }
 }
@@ -436,7 +607,8 @@ impl JobFactory for CollectionDownloadJobFactory {
 pub fn initialize() {
     let context = crate::context();
     let jobs = context.get_service::<Jobs>();
-    jobs.register_job(CollectionUploadJobFactory::create(context));
-    jobs.register_job(CollectionDownloadJobFactory::create(context));
+    jobs.register_job(CollectionSyncJobFactory::create(context, SyncMode::Upload, false));
+    jobs.register_job(CollectionSyncJobFactory::create(context, SyncMode::Download, false));
+    jobs.register_job(CollectionSyncJobFactory::create(context, SyncMode::Upload, true));
+    jobs.register_job(CollectionSyncJobFactory::create(context, SyncMode::Download, true));
 }
-
@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::Arc;
+
+use amina_core::service::Service;
+use amina_core::service::Context as AppContext;
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::database::sqlite::meta_format::{self, MetaFormat, FORMAT_FILE_NAME};
+use crate::proto::collection::InternalFilesRow;
+use crate::storage::remote::RemoteStorage;
+use crate::storage::remote::adapter::{self, DummyRemoteStorage, RemoteStorageAdapter};
+use crate::workspace::Workspace;
+use crate::jobs::{JobContext, JobDescription, JobFactory, Jobs};
+use crate::collection::{meta_store, Collection, SavePoint};
+use crate::collection::internal_files::InternalFileId;
+use crate::collection::sync::CollectionSync;
+use crate::collection::sync::merge::{self, MetaTables, SyncConflict};
+use crate::collection::sync::tables::{self, SyncTable, TableRows};
+
+static REMOTE_META_PATH: &str = ".lappi/meta";
+static INTERNAL_FILES_TABLE: &str = "internal_files.pb";
+static PART_SUFFIX: &str = ".lappi-part";
+
+enum FileOperation {
+    // Path and expected hash of the file
+    Download(Utf8PathBuf, Vec<u8>),
+    Upload(Utf8PathBuf),
+    RemoveLocal(Utf8PathBuf),
+    RemoveRemote(Utf8PathBuf),
+}
+
+struct CollectionTwoWaySyncJob {
+    job_ctx: Arc<JobContext>,
+    collection: Service<Collection>,
+    sync: Service<CollectionSync>,
+    remote_storage: Box<dyn RemoteStorageAdapter>,
+    tables: Vec<SyncTable>,
+    local_tables: MetaTables,
+    remote_tables: MetaTables,
+    merged_tables: MetaTables,
+    file_operations: Vec<FileOperation>,
+    save_point: Option<SavePoint>,
+}
+
+impl CollectionTwoWaySyncJob {
+    fn create(job_ctx: Arc<JobContext>) -> Self {
+        let context = crate::context();
+        Self {
+            job_ctx,
+            collection: context.get_service::<Collection>(),
+            sync: context.get_service::<CollectionSync>(),
+            remote_storage: Box::new(DummyRemoteStorage::new()),
+            tables: tables::get_sync_tables(),
+            local_tables: MetaTables::new(),
+            remote_tables: MetaTables::new(),
+            merged_tables: MetaTables::new(),
+            file_operations: Vec::new(),
+            save_point: None,
+        }
+    }
+
+    fn get_temp_path() -> Utf8PathBuf {
+        let workspace = crate::context().get_service::<Workspace>();
+        workspace.get_temp_dir().join("two_way_sync_job")
+    }
+
+    fn get_remote_meta_temp_path() -> Utf8PathBuf {
+        Self::get_temp_path().join("remote_meta")
+    }
+
+    fn get_local_meta_path(&self) -> Utf8PathBuf {
+        self.collection.get_local_path().join(REMOTE_META_PATH)
+    }
+
+    fn set_progress(&self, progress: f32, title: &str) {
+        self.job_ctx.set_progress(progress, title.to_string());
+    }
+
+    fn ensure_directory_exists(path: &Utf8Path) -> Result<()> {
+        let parent_path = path.parent().ok_or_else(|| anyhow::anyhow!("Failed to get parent directory of {:?}", path))?;
+        if !parent_path.exists() {
+            std::fs::create_dir_all(parent_path).context(format!("Failed to create directory {:?}", parent_path))?;
+        }
+        Ok(())
+    }
+
+    fn format_temp_folder(&self) -> Result<()> {
+        let temp_path = Self::get_temp_path();
+        if temp_path.exists() {
+            std::fs::remove_dir_all(&temp_path)?;
+        }
+        std::fs::create_dir_all(&temp_path)?;
+        Ok(())
+    }
+
+    fn prepare_local_collection(&mut self) -> Result<()> {
+        self.set_progress(0.0, "Update file hashes");
+        let file_hashes = self.collection.internal_files().calc_file_hashes()?;
+        self.collection.transaction(|collection| collection.internal_files().set_file_hashes(&file_hashes))?;
+        self.set_progress(0.0, "Save collection");
+        self.save_point = Some(self.collection.save_for_replace()?);
+        Ok(())
+    }
+
+    fn open_remote_connection(&mut self) -> Result<()> {
+        log::info!("Connect to remote server");
+        self.set_progress(0.0, "Connect to remote server");
+        let remote_storage = crate::context().get_service::<RemoteStorage>();
+        self.remote_storage = remote_storage.connect()?;
+        Ok(())
+    }
+
+    // Only a file the server reports as missing is skipped, any other error stops the sync
+    fn download_if_exists(&mut self, remote_path: &Utf8Path, temp_path: &Utf8Path) -> Result<()> {
+        let mut temp_file = File::create(temp_path)?;
+        match self.remote_storage.read_file(remote_path, &mut temp_file) {
+            Ok(()) => Ok(()),
+            Err(err) if adapter::is_not_found(&err) => {
+                log::info!("{} is missing on the remote side", remote_path);
+                drop(temp_file);
+                std::fs::remove_file(temp_path)?;
+                Ok(())
+            },
+            Err(err) => Err(err.context(format!("Failed to download {}", remote_path))),
+        }
+    }
+
+    fn download_remote_tables(&mut self) -> Result<()> {
+        let temp_meta_path = Self::get_remote_meta_temp_path();
+        std::fs::create_dir_all(&temp_meta_path)?;
+        let file_names: Vec<&str> = self.tables.iter().map(|table| table.file_name).collect();
+        for (index, file_name) in file_names.iter().enumerate() {
+            self.set_progress(0.0, &format!("Download remote tables [{}/{}]", index + 1, file_names.len()));
+            // A table that is missing on the remote side is treated as empty
+            self.download_if_exists(&Utf8PathBuf::from(REMOTE_META_PATH).join(file_name), &temp_meta_path.join(file_name))?;
+        }
+
+        // Remote tables of an older format are upgraded before they are merged with the local ones
+        let format_path = Utf8PathBuf::from(REMOTE_META_PATH).join(FORMAT_FILE_NAME);
+        self.download_if_exists(&format_path, &temp_meta_path.join(FORMAT_FILE_NAME))?;
+        meta_format::migrate(&temp_meta_path)
+    }
+
+    fn read_tables(&self, meta_path: &Utf8Path) -> Result<MetaTables> {
+        let mut meta_tables = MetaTables::new();
+        for table in &self.tables {
+            meta_tables.insert(table.file_name, table.read(meta_path)?);
+        }
+        Ok(meta_tables)
+    }
+
+    fn merge_tables(&mut self) -> Result<()> {
+        self.set_progress(0.0, "Merge changes");
+        let base_path = self.sync.get_base_snapshot_path();
+        meta_store::prepare_load(&base_path)?;
+        meta_format::migrate(&base_path)?;
+        let base_tables = self.read_tables(&base_path)?;
+        self.local_tables = self.read_tables(&self.get_local_meta_path())?;
+        let remote_tables = self.read_tables(&Self::get_remote_meta_temp_path())?;
+        self.remote_tables = merge::remap_remote_rows(&self.tables, &base_tables, &self.local_tables, &remote_tables)?;
+        let resolutions = self.sync.get_conflicts();
+
+        let mut conflicts: Vec<SyncConflict> = Vec::new();
+        for table in &self.tables {
+            let base = &base_tables[table.file_name];
+            let local = &self.local_tables[table.file_name];
+            let remote = &self.remote_tables[table.file_name];
+            let table_merge = merge::merge_table(table, base, local, remote, &resolutions);
+            log::info!("Merge {}: {} local changes, {} remote changes, {} conflicts",
+                table.get_name(), table_merge.stats.local_changes, table_merge.stats.remote_changes, table_merge.conflicts.len());
+
+            conflicts.extend(table_merge.conflicts);
+            self.merged_tables.insert(table.file_name, table_merge.rows);
+        }
+
+        let unresolved_count = conflicts.iter().filter(|conflict| conflict.resolution.is_none()).count();
+        self.sync.set_conflicts(conflicts)?;
+        if unresolved_count > 0 {
+            anyhow::bail!("Sync stopped: {} conflicts need to be resolved", unresolved_count);
+        }
+
+        // Nothing is written yet, so a merge that breaks the collection leaves both sides as they are
+        let broken_references = merge::find_broken_references(&self.tables, &self.merged_tables)?;
+        if !broken_references.is_empty() {
+            for broken_reference in &broken_references {
+                log::warn!("Merge result is inconsistent: {}", broken_reference);
+            }
+            anyhow::bail!("Sync stopped: merged collection has {} broken references, e.g. {}", broken_references.len(), broken_references[0]);
+        }
+        let unique_violations = merge::find_unique_violations(&self.tables, &self.merged_tables)?;
+        if !unique_violations.is_empty() {
+            for unique_violation in &unique_violations {
+                log::warn!("Merge result is inconsistent: {}", unique_violation);
+            }
+            anyhow::bail!("Sync stopped: merged collection has {} duplicate values, e.g. {}", unique_violations.len(), unique_violations[0]);
+        }
+        Ok(())
+    }
+
+    fn parse_internal_files(rows: &TableRows) -> Result<HashMap<InternalFileId, InternalFilesRow>> {
+        let mut files = HashMap::new();
+        for (file_id, row) in rows {
+            files.insert(*file_id, tables::parse_row::<InternalFilesRow>(row)?);
+        }
+        Ok(files)
+    }
+
+    fn plan_file_operations(&mut self) -> Result<()> {
+        self.set_progress(0.0, "Process internal files");
+        let local_files = Self::parse_internal_files(&self.local_tables[INTERNAL_FILES_TABLE])?;
+        let remote_files = Self::parse_internal_files(&self.remote_tables[INTERNAL_FILES_TABLE])?;
+        let merged_files = Self::parse_internal_files(&self.merged_tables[INTERNAL_FILES_TABLE])?;
+
+        for (file_id, merged_file) in &merged_files {
+            let merged_path = Utf8PathBuf::from(&merged_file.internal_path);
+            let local_file = local_files.get(file_id);
+            let remote_file = remote_files.get(file_id);
+
+            if local_file == Some(merged_file) && remote_file != Some(merged_file) {
+                if let Some(remote_file) = remote_file.filter(|file| file.internal_path != merged_file.internal_path) {
+                    self.file_operations.push(FileOperation::RemoveRemote(Utf8PathBuf::from(&remote_file.internal_path)));
+                }
+                self.file_operations.push(FileOperation::Upload(merged_path));
+            } else if remote_file == Some(merged_file) && local_file != Some(merged_file) {
+                if let Some(local_file) = local_file.filter(|file| file.internal_path != merged_file.internal_path) {
+                    self.file_operations.push(FileOperation::RemoveLocal(Utf8PathBuf::from(&local_file.internal_path)));
+                }
+                self.file_operations.push(FileOperation::Download(merged_path, merged_file.hash.clone()));
+            }
+        }
+
+        for (file_id, local_file) in &local_files {
+            if !merged_files.contains_key(file_id) {
+                self.file_operations.push(FileOperation::RemoveLocal(Utf8PathBuf::from(&local_file.internal_path)));
+            }
+        }
+        for (file_id, remote_file) in &remote_files {
+            if !merged_files.contains_key(file_id) {
+                self.file_operations.push(FileOperation::RemoveRemote(Utf8PathBuf::from(&remote_file.internal_path)));
+            }
+        }
+
+        Ok(())
+    }
+
+    // The file is downloaded under a temporary name and replaces the local one only if its hash matches
+    fn download_file(remote_storage: &mut dyn RemoteStorageAdapter, path: &Utf8Path, hash: &[u8], local_path: &Utf8Path) -> Result<()> {
+        Self::ensure_directory_exists(local_path)?;
+        let part_path = Utf8PathBuf::from(format!("{}{}", local_path, PART_SUFFIX));
+        let result = File::create(&part_path).map_err(anyhow::Error::from)
+            .and_then(|mut part_file| {
+                remote_storage.read_file(path, &mut part_file)?;
+                part_file.sync_all()?;
+                Ok(())
+            })
+            .and_then(|_| crate::utils::hash::blake3::calc_file_hash(&part_path))
+            .and_then(|actual_hash| {
+                if !hash.is_empty() && actual_hash != hash {
+                    anyhow::bail!("Downloaded file {} doesn't match its hash", path);
+                }
+                Ok(())
+            });
+        if let Err(err) = result {
+            let _ = std::fs::remove_file(&part_path);
+            return Err(err);
+        }
+        std::fs::rename(&part_path, local_path).context(format!("Failed to rename {:?}", part_path))
+    }
+
+    fn apply_file_operations(&mut self) -> Result<()> {
+        let operations_num = self.file_operations.len();
+        for (index, operation) in self.file_operations.iter().enumerate() {
+            if self.job_ctx.is_interrupted() {
+                anyhow::bail!("Sync interrupted");
+            }
+            let progress = index as f32 / operations_num as f32;
+            self.job_ctx.set_progress(progress, format!("Syncing files {}/{}", index, operations_num));
+
+            match operation {
+                FileOperation::Download(path, hash) => {
+                    log::info!("Downloading file {}", path);
+                    let local_path = self.collection.get_local_path().join(path);
+                    Self::download_file(self.remote_storage.as_mut(), path, hash, &local_path)?;
+                },
+                FileOperation::Upload(path) => {
+                    log::info!("Uploading file {}", path);
+                    let mut local_file = File::open(self.collection.get_local_path().join(path))?;
+                    self.remote_storage.write_file(path, &mut local_file)?;
+                },
+                FileOperation::RemoveLocal(path) => {
+                    log::info!("Removing local file {}", path);
+                    let local_path = self.collection.get_local_path().join(path);
+                    if local_path.exists() {
+                        std::fs::remove_file(&local_path).context(format!("Failed to remove file {:?}", local_path))?;
+                    }
+                },
+                FileOperation::RemoveRemote(path) => {
+                    log::info!("Removing remote file {}", path);
+                    // A file removed by an interrupted run is already done
+                    match self.remote_storage.remove_file(path) {
+                        Err(err) if !adapter::is_not_found(&err) => return Err(err),
+                        _ => {},
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn write_tables(&self, meta_path: &Utf8Path) -> Result<()> {
+        for table in &self.tables {
+            table.write(meta_path, &self.merged_tables[table.file_name])?;
+        }
+        MetaFormat::current().write(meta_path)
+    }
+
+    // The base snapshot is written last, when both sides have the merged tables. Until then the next sync
+    // merges against the old base again, so changes written to one side only are not reverted.
+    fn write_merged_tables(&mut self) -> Result<()> {
+        self.set_progress(1.0, "Save merged tables");
+        let merged_path = Self::get_temp_path().join("merged_meta");
+        self.write_tables(&merged_path)?;
+        for table in &self.tables {
+            for file_name in [table.file_name.to_string(), format!("{}.hash", table.file_name)] {
+                let remote_path = Utf8PathBuf::from(REMOTE_META_PATH).join(&file_name);
+                let mut merged_file = File::open(merged_path.join(&file_name))?;
+                self.remote_storage.write_file(&remote_path, &mut merged_file)?;
+            }
+        }
+        let mut format_file = File::open(merged_path.join(FORMAT_FILE_NAME))?;
+        self.remote_storage.write_file(&Utf8PathBuf::from(REMOTE_META_PATH).join(FORMAT_FILE_NAME), &mut format_file)?;
+
+        // The local tables are replaced as one generation and loaded right away
+        // Local changes made after the save are not in the merge, so the tables are not replaced over them
+        self.collection.replace_meta(self.save_point, |staging_path| self.write_tables(staging_path))?;
+
+        meta_store::save(&self.sync.get_base_snapshot_path(), |staging_path| self.write_tables(staging_path))?;
+        self.sync.set_conflicts(Vec::new())?;
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<()> {
+        self.format_temp_folder()?;
+        self.prepare_local_collection()?;
+        // Local tables are read for the merge and replaced at the end, an autosave between would be lost
+        let _autosave_pause = self.collection.pause_autosave();
+        self.open_remote_connection()?;
+        self.download_remote_tables()?;
+        self.merge_tables()?;
+        self.plan_file_operations()?;
+        self.apply_file_operations()?;
+        self.write_merged_tables()?;
+        self.set_progress(1.0, "Done");
+        Ok(())
+    }
+}
+
+struct CollectionTwoWaySyncJobFactory {
+
+}
+
+impl CollectionTwoWaySyncJobFactory {
+    fn create(_: &AppContext) -> Box<Self> {
+        Box::new(Self {
+
+        })
+    }
+}
+
+impl JobFactory for CollectionTwoWaySyncJobFactory {
+    fn get_description(&self) -> Box<JobDescription> {
+        let name = "Collection sync";
+
+        Box::new(JobDescription {
+            job_id: name,
+            name,
+            icon: "sync",
+            description: "Merge changes of the local collection and the remote server. Conflicting changes stop the sync until they are resolved.",
+        })
+    }
+
+    fn is_always_ready(&self) -> bool {
+        true
+    }
+
+    fn run(&self, job_ctx: Arc<JobContext>) -> Result<()> {
+        let mut job = CollectionTwoWaySyncJob::create(job_ctx);
+        job.run()
+    }
+}
+
+pub fn initialize() {
+    let context = crate::context();
+    let jobs = context.get_service::<Jobs>();
+    jobs.register_job(CollectionTwoWaySyncJobFactory::create(context));
+}
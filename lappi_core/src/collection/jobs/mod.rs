@@ -1,8 +1,18 @@
+pub mod collection_backup;
+pub mod collection_integrity;
 pub mod collection_migration;
+pub mod collection_restore;
 pub mod collection_sync;
+pub mod collection_two_way_sync;
+pub mod replay_gain;
 
 pub fn initialize() {
+    collection_backup::initialize();
+    collection_integrity::initialize();
     collection_migration::initialize();
+    collection_restore::initialize();
     collection_sync::initialize();
+    collection_two_way_sync::initialize();
+    replay_gain::initialize();
 }
 
@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use amina_core::service::{Context, Service};
+use anyhow::Result;
+
+use crate::jobs::{JobContext, JobDescription, JobFactory, Jobs};
+use crate::collection::Collection;
+use crate::collection::folders::{FolderId, FolderType};
+use crate::collection::music::MusicItemId;
+use crate::collection::tags::{Tag, TagValue};
+use crate::metadata::loudness::Loudness;
+use crate::playback::replay_gain::{ALBUM_GAIN_TAG, ALBUM_PEAK_TAG, TRACK_GAIN_TAG, TRACK_PEAK_TAG};
+
+
+fn get_description() -> Box<JobDescription> {
+    let name = "ReplayGain analysis";
+    let icon = "equalizer";
+    let description = "Measure loudness (EBU R128) of tracks and albums without ReplayGain tags and store track and album gain and peak.";
+
+    Box::new(JobDescription {
+        job_id: name,
+        name,
+        icon,
+        description,
+    })
+}
+
+struct ReplayGainJob {
+    job_ctx: Arc<JobContext>,
+    collection: Service<Collection>,
+}
+
+impl ReplayGainJob {
+    fn create(job_ctx: Arc<JobContext>) -> Self {
+        Self {
+            job_ctx,
+            collection: crate::context().get_service::<Collection>(),
+        }
+    }
+
+    fn set_progress(&mut self, progress: f32, title: &str) {
+        self.job_ctx.set_progress(progress, title.to_string());
+    }
+
+    fn has_own_tag(tags: &[Tag], tag_name: &str) -> bool {
+        tags.iter().any(|tag| tag.get_key() == tag_name)
+    }
+
+    fn is_folder_analyzed(&self, folder_id: FolderId, items: &[MusicItemId], is_album: bool) -> Result<bool> {
+        if is_album {
+            let folder_tags = self.collection.folders().get_tags(folder_id)?;
+            if !Self::has_own_tag(&folder_tags, ALBUM_GAIN_TAG) {
+                return Ok(false);
+            }
+        }
+        for music_item_id in items {
+            let item_tags = self.collection.music().get_tags(*music_item_id)?;
+            if !Self::has_own_tag(&item_tags, TRACK_GAIN_TAG) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn analyze_item(&self, music_item_id: MusicItemId) -> Result<Option<Loudness>> {
+        let file_desc = match self.collection.music_sources().get_music_file(music_item_id)? {
+            Some(file_desc) => file_desc,
+            None => return Ok(None),
+        };
+        let path = self.collection.internal_files().get_system_path(file_desc.internal_file_id)?;
+
+        let loudness = Loudness::analyze_file(&path)?;
+        let gain = loudness.get_gain()?;
+        let peak = loudness.get_peak()?;
+        log::debug!("Track {} gain {:.2} dB, peak {:.6}", music_item_id, gain, peak);
+
+        self.collection.transaction(|collection| {
+            let music = collection.music();
+            music.set_tag(music_item_id, TRACK_GAIN_TAG.to_string(), TagValue::String(format!("{:.2}", gain)))?;
+            music.set_tag(music_item_id, TRACK_PEAK_TAG.to_string(), TagValue::String(format!("{:.6}", peak)))
+        })?;
+
+        Ok(Some(loudness))
+    }
+
+    fn analyze_folder(&self, folder_id: FolderId, items: &[MusicItemId], is_album: bool) -> Result<()> {
+        let mut tracks = vec![];
+        for music_item_id in items {
+            if self.job_ctx.is_interrupted() {
+                return Ok(());
+            }
+            match self.analyze_item(*music_item_id) {
+                Ok(Some(loudness)) => tracks.push(loudness),
+                Ok(None) => { },
+                Err(err) => log::error!("Failed to analyze music item {}: {}", music_item_id, err),
+            }
+        }
+
+        if is_album && !tracks.is_empty() {
+            let gain = Loudness::get_album_gain(&tracks)?;
+            let peak = Loudness::get_album_peak(&tracks)?;
+            log::debug!("Album {} gain {:.2} dB, peak {:.6}", folder_id, gain, peak);
+
+            self.collection.transaction(|collection| {
+                let folders = collection.folders();
+                folders.set_tag(folder_id, ALBUM_GAIN_TAG.to_string(), TagValue::String(format!("{:.2}", gain)))?;
+                folders.set_tag(folder_id, ALBUM_PEAK_TAG.to_string(), TagValue::String(format!("{:.6}", peak)))
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn run(&mut self) -> Result<()> {
+        let all_folders = self.collection.folders().get_all_folders()?;
+        let folders_count = all_folders.len();
+
+        for (i, folder_id) in all_folders.into_iter().enumerate() {
+            if self.job_ctx.is_interrupted() {
+                return Ok(());
+            }
+
+            let progress = (i as f32) / (folders_count as f32);
+            let title = format!("Analyze folders {}/{}", i, folders_count);
+            self.set_progress(progress, title.as_str());
+
+            let items = self.collection.folders().get_music_items_in_folder(folder_id)?;
+            if items.is_empty() {
+                continue;
+            }
+
+            let folder_type = self.collection.folders().get_folder_description(folder_id)?.folder_type;
+            let is_album = matches!(folder_type, FolderType::Album);
+
+            if !self.is_folder_analyzed(folder_id, &items, is_album)? {
+                self.analyze_folder(folder_id, &items, is_album)?;
+            }
+        }
+
+        self.set_progress(1.0, "Done");
+        Ok(())
+    }
+}
+
+struct ReplayGainJobFactory {
+
+}
+
+impl ReplayGainJobFactory {
+    fn create(_: &Context) -> Box<Self> {
+        Box::new(Self {
+
+        })
+    }
+}
+
+impl JobFactory for ReplayGainJobFactory {
+    fn get_description(&self) -> Box<JobDescription> {
+        get_description()
+    }
+
+    fn is_always_ready(&self) -> bool {
+        true
+    }
+
+    fn run(&self, job_ctx: Arc<JobContext>) -> Result<()> {
+        let mut job = ReplayGainJob::create(job_ctx);
+        job.run()
+    }
+}
+
+pub fn initialize() {
+    let context = crate::context();
+    let jobs = context.get_service::<Jobs>();
+    jobs.register_job(ReplayGainJobFactory::create(context));
+}
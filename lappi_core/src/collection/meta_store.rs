@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::hash::blake3::to_hex;
+
+static MANIFEST_FILE_NAME: &str = "manifest.yaml";
+
+// Lists every file of a saved generation of the meta tables with its hash
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetaManifest {
+    pub generation: u64,
+    pub created: String,
+    pub files: BTreeMap<String, String>,
+}
+
+impl MetaManifest {
+    fn create(meta_path: &Utf8Path, generation: u64) -> Result<Self> {
+        let mut files = BTreeMap::new();
+        for entry in meta_path.read_dir_utf8()? {
+            let entry = entry?;
+            if entry.file_name() == MANIFEST_FILE_NAME || !entry.file_type()?.is_file() {
+                continue;
+            }
+            let hash = crate::utils::hash::blake3::calc_file_hash(entry.path())?;
+            files.insert(entry.file_name().to_string(), to_hex(&hash));
+        }
+        Ok(Self {
+            generation,
+            created: chrono::Local::now().to_rfc3339(),
+            files,
+        })
+    }
+
+    fn read(meta_path: &Utf8Path) -> Result<Option<Self>> {
+        let path = meta_path.join(MANIFEST_FILE_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = File::open(&path)?;
+        Ok(Some(serde_yaml::from_reader(file).context(format!("Failed to parse {}", path))?))
+    }
+
+    fn write(&self, meta_path: &Utf8Path) -> Result<()> {
+        let mut file = File::create(meta_path.join(MANIFEST_FILE_NAME))?;
+        serde_yaml::to_writer(&mut file, self)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    fn validate(&self, meta_path: &Utf8Path) -> Result<()> {
+        for (file_name, expected_hash) in &self.files {
+            let path = meta_path.join(file_name);
+            let hash = crate::utils::hash::blake3::calc_file_hash(&path).context(format!("Failed to read {}", path))?;
+            if &to_hex(&hash) != expected_hash {
+                anyhow::bail!("{} doesn't match the manifest of generation {}", path, self.generation);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn get_sibling_path(meta_path: &Utf8Path, suffix: &str) -> Utf8PathBuf {
+    let file_name = meta_path.file_name().unwrap_or("meta");
+    meta_path.with_file_name(format!("{}.{}", file_name, suffix))
+}
+
+// Tables saved before manifests were introduced are accepted as they are
+fn check_generation(meta_path: &Utf8Path) -> Result<()> {
+    match MetaManifest::read(meta_path)? {
+        Some(manifest) => manifest.validate(meta_path),
+        None => Ok(()),
+    }
+}
+
+fn has_valid_generation(meta_path: &Utf8Path) -> bool {
+    matches!(MetaManifest::read(meta_path), Ok(Some(manifest)) if manifest.validate(meta_path).is_ok())
+}
+
+fn sync_dir(path: &Utf8Path) -> Result<()> {
+    // Directories can't be opened as files on Windows, renames there are durable without it
+    if cfg!(unix) {
+        File::open(path)?.sync_all()?;
+    }
+    Ok(())
+}
+
+fn rename_dir(from: &Utf8Path, to: &Utf8Path) -> Result<()> {
+    std::fs::rename(from, to).context(format!("Failed to rename {} to {}", from, to))
+}
+
+fn remove_dir_if_exists(path: &Utf8Path) -> Result<()> {
+    if path.exists() {
+        std::fs::remove_dir_all(path).context(format!("Failed to remove {}", path))?;
+    }
+    Ok(())
+}
+
+// Writes a new generation into a staging folder and swaps it with the current one,
+// so a crash never leaves a mix of old and new tables. The replaced generation is kept as a fallback.
+pub fn save(meta_path: &Utf8Path, export: impl FnOnce(&Utf8Path) -> Result<()>) -> Result<()> {
+    let staging_path = get_sibling_path(meta_path, "staging");
+    let previous_path = get_sibling_path(meta_path, "previous");
+
+    remove_dir_if_exists(&staging_path)?;
+    std::fs::create_dir_all(&staging_path)?;
+    export(&staging_path)?;
+
+    let generation = match MetaManifest::read(meta_path) {
+        Ok(Some(manifest)) => manifest.generation + 1,
+        _ => 1,
+    };
+    for entry in staging_path.read_dir_utf8()? {
+        File::open(entry?.path())?.sync_all()?;
+    }
+    MetaManifest::create(&staging_path, generation)?.write(&staging_path)?;
+    sync_dir(&staging_path)?;
+
+    remove_dir_if_exists(&previous_path)?;
+    if meta_path.exists() {
+        rename_dir(meta_path, &previous_path)?;
+    }
+    rename_dir(&staging_path, meta_path)?;
+    if let Some(parent_path) = meta_path.parent() {
+        sync_dir(parent_path)?;
+    }
+
+    log::debug!("Collection tables generation {} saved", generation);
+    Ok(())
+}
+
+// Makes the next generation from the current one with the files `write` puts into the staging folder,
+// so tables brought from a sync or a backup never leave a half-updated generation
+pub fn update(meta_path: &Utf8Path, write: impl FnOnce(&Utf8Path) -> Result<()>) -> Result<()> {
+    save(meta_path, |staging_path| {
+        if meta_path.exists() {
+            for entry in meta_path.read_dir_utf8()? {
+                let entry = entry?;
+                if entry.file_name() != MANIFEST_FILE_NAME && entry.file_type()?.is_file() {
+                    std::fs::copy(entry.path(), staging_path.join(entry.file_name()))
+                        .context(format!("Failed to copy {}", entry.path()))?;
+                }
+            }
+        }
+        write(staging_path)
+    })
+}
+
+// Finishes a swap interrupted by a crash, and falls back to the previous generation
+// if the current one doesn't match its manifest
+pub fn prepare_load(meta_path: &Utf8Path) -> Result<()> {
+    let staging_path = get_sibling_path(meta_path, "staging");
+    let previous_path = get_sibling_path(meta_path, "previous");
+
+    if !meta_path.exists() && has_valid_generation(&staging_path) {
+        log::warn!("Complete an interrupted collection save");
+        rename_dir(&staging_path, meta_path)?;
+    }
+    if !meta_path.exists() {
+        return Ok(());
+    }
+
+    if let Err(err) = check_generation(meta_path) {
+        log::error!("Collection tables are inconsistent: {:#}", err);
+        if !has_valid_generation(&previous_path) {
+            return Err(err);
+        }
+
+        let broken_path = get_sibling_path(meta_path, "broken");
+        remove_dir_if_exists(&broken_path)?;
+        rename_dir(meta_path, &broken_path)?;
+        rename_dir(&previous_path, meta_path)?;
+        log::warn!("Collection tables are restored from the previous generation, the inconsistent ones are kept in {}", broken_path);
+    }
+    Ok(())
+}
@@ -1,3 +1,4 @@
+pub mod backup;
 pub mod database_api;
 pub mod internal_files;
 pub mod music;
@@ -5,18 +6,26 @@ pub mod music_sources;
 pub mod debug;
 pub mod pictures;
 pub mod folders;
+pub mod integrity;
 pub mod tags;
 pub mod lyrics;
+pub mod meta_store;
 pub mod playlists;
 pub mod jobs;
+pub mod sync;
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
+use amina_core::events::EventEmitter;
 use amina_core::service::{ServiceApi, ServiceInitializer, Context, Service};
+use amina_core::tasks::TaskManager;
 
 use crate::database::Database;
+use crate::settings::Settings;
 use crate::storage::local::LocalStorage;
 use crate::collection::internal_files::InternalFiles;
 use crate::collection::folders::FoldersCollection;
@@ -25,9 +34,15 @@ use crate::collection::music::MusicCollection;
 use crate::collection::music_sources::MusicSourcesCollection;
 use crate::collection::pictures::PicturesCollection;
 use crate::collection::playlists::PlaylistsCollection;
+use crate::collection::sync::CollectionSync;
+use crate::collection::backup::CollectionBackup;
+use crate::collection::integrity::CollectionIntegrity;
 
 pub use crate::collection::database_api::OnCollectionUpdated;
 
+static DEFAULT_AUTOSAVE_INTERVAL_MINUTES: u64 = 5;
+static AUTOSAVE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 pub struct Collection {
     local_storage: Service<LocalStorage>,
     internal_files: Service<InternalFiles>,
@@ -38,6 +53,32 @@ pub struct Collection {
     folders: Service<FoldersCollection>,
     playlists: Service<PlaylistsCollection>,
     db: Service<Database>,
+    settings: Service<Settings>,
+    save_lock: Mutex<()>,
+    has_unsaved_changes: Arc<AtomicBool>,
+    // Number of committed changes, tells if the collection changed since a save
+    change_count: Arc<AtomicU64>,
+    // Tables that failed to load, e.g. of a newer format, must not be overwritten with the empty collection
+    load_failed: AtomicBool,
+    autosave_stopped: Arc<AtomicBool>,
+    autosave_pauses: Arc<AtomicUsize>,
+}
+
+// Collection changes that a save wrote to the tables
+#[derive(Clone, Copy, Debug)]
+pub struct SavePoint {
+    change_count: u64,
+}
+
+// Autosave stays off while a job holds this
+pub struct AutosavePause {
+    pauses: Arc<AtomicUsize>,
+}
+
+impl Drop for AutosavePause {
+    fn drop(&mut self) {
+        self.pauses.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 impl Collection {
@@ -69,12 +110,19 @@ impl Collection {
         &self.playlists
     }
     
-    pub fn start_batch(&self) {
-        self.db.start_batch();
-    }
-
-    pub fn stop_batch(&self) {
-        self.db.stop_batch();
+    // Runs the closure in an SQLite transaction, which is rolled back if the closure fails.
+    // Update events are sent once after the commit, nested calls join the outer transaction
+    // and other threads wait for the database until it ends.
+    pub fn transaction<T>(&self, f: impl FnOnce(&Collection) -> Result<T>) -> Result<T> {
+        self.db.start_batch()?;
+        let mut scope = TransactionScope {
+            collection: self,
+            committed: false,
+        };
+        let value = f(self)?;
+        self.db.commit_batch()?;
+        scope.committed = true;
+        Ok(value)
     }
 
     pub fn is_empty(&self) -> bool {
@@ -86,31 +134,106 @@ impl Collection {
     }
 
     pub fn save(&self) {
+        if self.load_failed.load(Ordering::SeqCst) {
+            log::error!("Collection is not saved, because it failed to load");
+            return;
+        }
         if self.local_storage.is_available() {
-            let result = self.db.export(&self.local_storage.get_meta_path());
-            match result {
-                Ok(_) => {
-                    log::info!("Collection saved");
-                }
-                Err(e) => {
-                    log::error!("Failed to save collection: {}", e);
-                }
-                
+            if let Err(e) = self.save_tables() {
+                log::error!("Failed to save collection: {}", e);
             }
         }
     }
 
+    // Saves the collection for a job that reads the saved tables and replaces them later with `replace_meta`
+    pub fn save_for_replace(&self) -> Result<SavePoint> {
+        self.check_loaded()?;
+        if !self.local_storage.is_available() {
+            anyhow::bail!("Local storage is not available");
+        }
+        self.save_tables()
+    }
+
+    fn save_tables(&self) -> Result<SavePoint> {
+        let _save_guard = self.save_lock.lock().unwrap();
+        // Read before the export, so a change committed meanwhile counts as unsaved
+        let save_point = SavePoint {
+            change_count: self.change_count.load(Ordering::SeqCst),
+        };
+        self.has_unsaved_changes.store(false, Ordering::SeqCst);
+        let result = meta_store::save(&self.local_storage.get_meta_path(), |path| self.db.export(path));
+        if result.is_err() {
+            self.has_unsaved_changes.store(true, Ordering::SeqCst);
+        }
+        result?;
+        log::info!("Collection saved");
+        Ok(save_point)
+    }
+
+    fn check_loaded(&self) -> Result<()> {
+        if self.load_failed.load(Ordering::SeqCst) {
+            anyhow::bail!("Collection failed to load, its tables are not changed until it loads");
+        }
+        Ok(())
+    }
+
+    fn check_save_point(&self, save_point: &SavePoint) -> Result<()> {
+        if self.change_count.load(Ordering::SeqCst) != save_point.change_count {
+            anyhow::bail!("The collection was changed while the job was running, run it again to include the changes");
+        }
+        Ok(())
+    }
+
+    // Replaces meta tables with a generation made by `write` and loads it.
+    // Saving waits meanwhile, so the tables in memory never overwrite the new ones.
+    // With a save point the tables are replaced only if the collection has no changes made after it,
+    // `None` drops such changes, e.g. when a backup is restored.
+    pub fn replace_meta(&self, save_point: Option<SavePoint>, write: impl FnOnce(&Utf8Path) -> Result<()>) -> Result<()> {
+        self.check_loaded()?;
+        if !self.local_storage.is_available() {
+            anyhow::bail!("Local storage is not available");
+        }
+        let _save_guard = self.save_lock.lock().unwrap();
+        if let Some(save_point) = &save_point {
+            self.check_save_point(save_point)?;
+        }
+        meta_store::update(&self.local_storage.get_meta_path(), write)?;
+        // Other writers wait for the transaction, so nothing changes between the last check and the reload
+        self.transaction(|_| {
+            if let Some(save_point) = &save_point {
+                self.check_save_point(save_point)?;
+            }
+            self.reload()
+        })?;
+        self.has_unsaved_changes.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    // Jobs that read saved tables and replace them later keep autosave from saving in between
+    pub fn pause_autosave(&self) -> AutosavePause {
+        self.autosave_pauses.fetch_add(1, Ordering::SeqCst);
+        AutosavePause {
+            pauses: self.autosave_pauses.clone(),
+        }
+    }
+
+    pub fn has_unsaved_changes(&self) -> bool {
+        self.has_unsaved_changes.load(Ordering::SeqCst)
+    }
+
     pub fn load(&self) {
         if self.local_storage.is_available() {
             log::debug!("Import collection from local storage");
 
-            let result = self.db.import(&self.local_storage.get_meta_path());
+            let meta_path = self.local_storage.get_meta_path();
+            let result = meta_store::prepare_load(&meta_path).and_then(|_| self.db.import(&meta_path));
             match result {
                 Ok(_) => {
                     log::info!("Collection loaded");
                 }
                 Err(e) => {
                     log::error!("Failed to load collection: {}", e);
+                    self.load_failed.store(true, Ordering::SeqCst);
                 }
             }
         }
@@ -121,21 +244,76 @@ impl Collection {
     pub fn reload(&self) -> Result<()> {
         if self.local_storage.is_available() {
             log::debug!("Reload collection from local storage");
-            self.db.format()?;
-            self.db.import(&self.local_storage.get_meta_path())?;
+            let meta_path = self.local_storage.get_meta_path();
+            meta_store::prepare_load(&meta_path)?;
+            // Old tables are dropped in the import transaction, so a failed import keeps them loaded
+            self.transaction(|_| {
+                self.db.format()?;
+                self.db.import(&meta_path)
+            })?;
         }
 
         Ok(())
     }
+
+    fn get_autosave_interval(&self) -> Option<Duration> {
+        let minutes = self.settings.get_string("collection.autosave_interval").get()
+            .parse()
+            .unwrap_or(DEFAULT_AUTOSAVE_INTERVAL_MINUTES);
+        if minutes == 0 { None } else { Some(Duration::from_secs(minutes * 60)) }
+    }
+
+    // Saves changes in the background, so a crash loses at most one interval of work
+    fn start_autosave(&self) {
+        let interval = match self.get_autosave_interval() {
+            Some(interval) => interval,
+            None => return,
+        };
+        let stopped = self.autosave_stopped.clone();
+        let pauses = self.autosave_pauses.clone();
+        crate::context().get_service::<TaskManager>().run(move |task_ctx| {
+            let mut last_save = Instant::now();
+            while !task_ctx.is_interrupted() && !stopped.load(Ordering::SeqCst) {
+                std::thread::sleep(AUTOSAVE_POLL_INTERVAL);
+                if last_save.elapsed() < interval || pauses.load(Ordering::SeqCst) > 0 {
+                    continue;
+                }
+                last_save = Instant::now();
+                let collection = crate::context().get_service::<Collection>();
+                if collection.has_unsaved_changes() {
+                    log::debug!("Autosave collection");
+                    collection.save();
+                }
+            }
+        });
+    }
+}
+
+// Rolls back the transaction when the closure fails or panics
+struct TransactionScope<'a> {
+    collection: &'a Collection,
+    committed: bool,
+}
+
+impl<'a> Drop for TransactionScope<'a> {
+    fn drop(&mut self) {
+        if !self.committed {
+            if let Err(err) = self.collection.db.rollback_batch() {
+                log::error!("Failed to roll back transaction: {}", err);
+            }
+        }
+    }
 }
 
 impl ServiceApi for Collection {
 
     fn start(&self) {
         self.load();
+        self.start_autosave();
     }
 
     fn stop(&self) {
+        self.autosave_stopped.store(true, Ordering::SeqCst);
         self.save();
     }
 
@@ -145,6 +323,10 @@ impl ServiceInitializer for Collection {
     fn initialize(context: &Context) -> Arc<Self> {
         let database = context.get_service::<Database>();
         let local_storage = context.get_service::<LocalStorage>();
+        let event_emitter = context.get_service::<EventEmitter>();
+
+        let settings = context.get_service::<Settings>();
+        let _ = settings.get_string("collection.autosave_interval");
 
         let collection = Arc::new(Self {
             local_storage,
@@ -156,6 +338,20 @@ impl ServiceInitializer for Collection {
             folders: context.get_service::<FoldersCollection>(),
             playlists: context.get_service::<PlaylistsCollection>(),
             db: database,
+            settings,
+            save_lock: Mutex::new(()),
+            has_unsaved_changes: Arc::new(AtomicBool::new(false)),
+            change_count: Arc::new(AtomicU64::new(0)),
+            load_failed: AtomicBool::new(false),
+            autosave_stopped: Arc::new(AtomicBool::new(false)),
+            autosave_pauses: Arc::new(AtomicUsize::new(0)),
+        });
+
+        let has_unsaved_changes = collection.has_unsaved_changes.clone();
+        let change_count = collection.change_count.clone();
+        event_emitter.on_event_fn(move |_: &OnCollectionUpdated| {
+            change_count.fetch_add(1, Ordering::SeqCst);
+            has_unsaved_changes.store(true, Ordering::SeqCst);
         });
 
         return collection;
@@ -174,6 +370,9 @@ pub fn initialize() {
     context.init_service::<PlaylistsCollection>();
 
     context.init_service::<Collection>();
+    context.init_service::<CollectionSync>();
+    context.init_service::<CollectionBackup>();
+    context.init_service::<CollectionIntegrity>();
 
     jobs::initialize();
 }
\ No newline at end of file
@@ -1,6 +1,7 @@
 pub mod types;
 pub mod database_api;
 
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use anyhow::Result;
@@ -43,6 +44,10 @@ impl MusicCollection {
         self.music_db.get_music_item_description(item_id)
     }
 
+    pub fn get_item_descriptions(&self, items: &[MusicItemId]) -> Result<Vec<MusicItemDesc>> {
+        self.music_db.get_music_item_descriptions(items)
+    }
+
     pub fn get_caption_tag(&self, item_id: MusicItemId) -> Result<Option<Tag>> {
         self.get_tag(item_id, "track")
     }
@@ -95,6 +100,11 @@ impl MusicCollection {
         return Ok(None);
     }
 
+    // Tags of several items in the order get_tag looks them up
+    pub fn get_effective_tags(&self, items: &[MusicItemId]) -> Result<HashMap<MusicItemId, Vec<Tag>>> {
+        self.tags_db.get_effective_item_tags(items)
+    }
+
     pub fn delete_tag(&self, item_id: MusicItemId, tag_name: String) -> Result<()> {
         self.tags_db.delete_item_tag(item_id, &tag_name)
     }
@@ -4,7 +4,7 @@ use crate::collection::folders::FolderId;
 
 pub type MusicItemId = i64;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct MusicItemDesc {
     pub item_id: MusicItemId,
     pub name: String,
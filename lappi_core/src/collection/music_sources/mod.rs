@@ -10,13 +10,19 @@ use amina_core::rpc::Rpc;
 use amina_core::service::{AppContext, Service, ServiceApi, ServiceInitializer};
 
 use crate::database::Database;
-use crate::collection::internal_files::{InternalFiles, InternalPath};
+use crate::collection::internal_files::{InternalFiles, InternalPath, PreparedFile};
 use crate::collection::music::{MusicCollection, MusicItemId};
 
 use database_api::MusicSourcesDbApi;
 
 pub use types::*;
 
+// A music file copied into the collection storage ahead of `add_music_file`
+pub struct PreparedMusicFile {
+    file: PreparedFile,
+    file_type: MusicFileType,
+}
+
 pub struct MusicSourcesCollection {
     music_sources_db: Arc<Box<dyn MusicSourcesDbApi>>,
     internal_files: Service<InternalFiles>,
@@ -25,8 +31,11 @@ pub struct MusicSourcesCollection {
 
 impl MusicSourcesCollection {
     pub fn import_music_file(&self, item_id: MusicItemId, src_path: &Utf8Path) -> Result<()> {
-        self.delete_music_file(item_id)?;
+        let music_file = self.prepare_music_file(src_path)?;
+        self.add_music_file(item_id, music_file)
+    }
 
+    pub fn prepare_music_file(&self, src_path: &Utf8Path) -> Result<PreparedMusicFile> {
         if !src_path.exists() {
             return Err(Error::msg("Path does not exist"));
         }
@@ -45,14 +54,21 @@ impl MusicSourcesCollection {
             
         };
 
-        let internal_path = self.gen_generic_internal_path(item_id, file_type)?;
+        let file = self.internal_files.prepare_file_copy(src_path)?;
+        Ok(PreparedMusicFile { file, file_type })
+    }
+
+    pub fn add_music_file(&self, item_id: MusicItemId, music_file: PreparedMusicFile) -> Result<()> {
+        self.delete_music_file(item_id)?;
+
+        let internal_path = self.gen_generic_internal_path(item_id, music_file.file_type)?;
 
-        let file_id = self.internal_files.add_and_copy_file(src_path, &internal_path)?;
+        let file_id = self.internal_files.add_prepared_file(music_file.file, &internal_path)?;
 
         let file_desc = MusicFileDesc {
             music_item_id: item_id,
             internal_file_id: file_id,
-            file_type,
+            file_type: music_file.file_type,
         };
         self.music_sources_db.add_music_file(&file_desc)?;
 
@@ -13,7 +13,7 @@ use crate::collection::music::{MusicCollection, MusicItemId};
 use crate::collection::pictures::PictureId;
 
 use database_api::PlaylistsDbApi;
-use types::{PlaylistDesc, PlaylistId, PlaylistItemDesc};
+use types::{PlaylistDesc, PlaylistId, PlaylistItemDesc, PlaylistItemId};
 
 pub struct PlaylistsCollection {
     db: Box<dyn PlaylistsDbApi>,
@@ -59,25 +59,27 @@ impl PlaylistsCollection {
     pub fn get_playlist_items(&self, playlist_id: PlaylistId) -> Result<Vec<PlaylistItemDesc>> {
         let playlist_items = self.db.get_playlist_items(playlist_id)?;
 
-        let mut result = Vec::new();
-
-        for (id, music_item_id) in playlist_items {
-            let music_item_desc = self.music.get_item_description(music_item_id)?;
-            let artist = self.music.get_tag(music_item_id, "artist")?
-                .map(|tag| tag.to_string())
-                .unwrap_or_else(|| "".to_string());
-            let album = self.music.get_tag(music_item_id, "album")?
+        let music_items: Vec<MusicItemId> = playlist_items.iter().map(|(_, music_item_id)| *music_item_id).collect();
+        let descriptions = self.music.get_item_descriptions(&music_items)?;
+        let effective_tags = self.music.get_effective_tags(&music_items)?;
+        let get_tag = |music_item_id: MusicItemId, tag_name: &str| {
+            effective_tags.get(&music_item_id)
+                .and_then(|tags| tags.iter().find(|tag| tag.get_key() == tag_name))
                 .map(|tag| tag.to_string())
-                .unwrap_or_else(|| "".to_string());
-
-            result.push(PlaylistItemDesc {
-                id,
-                music_item_id,
-                title: music_item_desc.name,
-                artist,
-                album, 
-            });
-        }
+                .unwrap_or_default()
+        };
+
+        let result = playlist_items.into_iter().zip(descriptions)
+            .map(|((id, music_item_id), music_item_desc)| {
+                PlaylistItemDesc {
+                    id,
+                    music_item_id,
+                    title: music_item_desc.name,
+                    artist: get_tag(music_item_id, "artist"),
+                    album: get_tag(music_item_id, "album"),
+                }
+            })
+            .collect();
 
         Ok(result)
     }
@@ -93,6 +95,10 @@ impl PlaylistsCollection {
     pub fn delete_item_from_playlist(&self, playlist_id: PlaylistId, music_item_id: MusicItemId) -> Result<()> {
         self.db.delete_item_from_playlist(playlist_id, music_item_id)
     }
+
+    pub fn delete_playlist_item(&self, playlist_item_id: PlaylistItemId) -> Result<()> {
+        self.db.delete_playlist_item(playlist_item_id)
+    }
 }
 
 impl ServiceApi for PlaylistsCollection {
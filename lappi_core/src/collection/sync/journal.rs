@@ -0,0 +1,80 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Mutex;
+
+use anyhow::Result;
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::workspace::Workspace;
+
+static JOURNAL_FILE_PREFIX: &str = "sync_journal";
+static JOURNAL_HEADER: &str = "lappi-sync-journal";
+
+// Records finished file operations, so a sync job that was interrupted continues where it stopped.
+// Every remote storage has its own journal, switching the remote does not lose or reuse progress.
+pub struct SyncJournal {
+    path: Utf8PathBuf,
+    file: Mutex<File>,
+    completed: HashSet<String>,
+}
+
+impl SyncJournal {
+    pub fn open(mode: &str, remote_identity: &str) -> Result<Self> {
+        let workspace = crate::context().get_service::<Workspace>();
+        let remote_hash = blake3::hash(remote_identity.as_bytes()).to_hex();
+        let file_name = format!("{}_{}.log", JOURNAL_FILE_PREFIX, &remote_hash[..16]);
+        let path = workspace.get_workspace_dir().join(file_name);
+        let header = format!("{} {} {}", JOURNAL_HEADER, mode, remote_identity);
+
+        let mut completed = HashSet::new();
+        let mut is_resumed = false;
+        if path.exists() {
+            let mut lines = BufReader::new(File::open(&path)?).lines();
+            if lines.next().transpose()?.as_deref() == Some(header.as_str()) {
+                is_resumed = true;
+                for line in lines {
+                    completed.insert(line?);
+                }
+            }
+        }
+
+        let file = if is_resumed {
+            log::info!("Resume sync, {} operations are already done", completed.len());
+            OpenOptions::new().append(true).open(&path)?
+        } else {
+            let mut file = File::create(&path)?;
+            writeln!(file, "{}", header)?;
+            file
+        };
+
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+            completed,
+        })
+    }
+
+    // Operations are told apart by the content they deal with, so a changed file is transferred again
+    pub fn get_key(action: &str, path: &Utf8Path, hash: Option<&[u8]>) -> String {
+        let hash = hash.map(crate::utils::hash::blake3::to_hex);
+        format!("{}\t{}\t{}", action, path, hash.unwrap_or_default())
+    }
+
+    pub fn is_completed(&self, key: &str) -> bool {
+        self.completed.contains(key)
+    }
+
+    pub fn mark_completed(&self, key: &str) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", key)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        drop(self.file);
+        std::fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::tables::{self, IdMaps, SyncTable, TableRows};
+
+// Rows of all meta tables by table file name
+pub type MetaTables = HashMap<&'static str, TableRows>;
+
+// Folder 0 is the root, it has no row
+static ROOT_FOLDER_ID: i64 = 0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ConflictResolution {
+    KeepLocal,
+    KeepRemote,
+}
+
+// A row changed differently on both sides since the last sync
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyncConflict {
+    pub table: String,
+    pub row_id: i64,
+    pub base: Option<String>,
+    pub local: Option<String>,
+    pub remote: Option<String>,
+    // Resolutions are valid only for the exact versions of the row they were made for
+    pub local_hash: String,
+    pub remote_hash: String,
+    pub resolution: Option<ConflictResolution>,
+}
+
+impl SyncConflict {
+    pub fn is_same(&self, other: &SyncConflict) -> bool {
+        self.table == other.table
+            && self.row_id == other.row_id
+            && self.local_hash == other.local_hash
+            && self.remote_hash == other.remote_hash
+    }
+}
+
+#[derive(Default, Serialize, Debug, Clone)]
+pub struct MergeStats {
+    pub local_changes: usize,
+    pub remote_changes: usize,
+}
+
+pub struct TableMerge {
+    pub rows: TableRows,
+    pub conflicts: Vec<SyncConflict>,
+    pub stats: MergeStats,
+}
+
+// Three-way merge of table rows, `resolutions` holds earlier decisions of the user for conflicting rows
+pub fn merge_table(table: &SyncTable, base: &TableRows, local: &TableRows, remote: &TableRows, resolutions: &[SyncConflict]) -> TableMerge {
+    let mut merge = TableMerge {
+        rows: TableRows::new(),
+        conflicts: Vec::new(),
+        stats: MergeStats::default(),
+    };
+
+    let row_ids: BTreeSet<i64> = base.keys().chain(local.keys()).chain(remote.keys()).cloned().collect();
+    for row_id in row_ids {
+        let base_row = base.get(&row_id);
+        let local_row = local.get(&row_id);
+        let remote_row = remote.get(&row_id);
+
+        let merged_row = if local_row == remote_row {
+            local_row
+        } else if local_row == base_row {
+            merge.stats.remote_changes += 1;
+            remote_row
+        } else if remote_row == base_row {
+            merge.stats.local_changes += 1;
+            local_row
+        } else {
+            let mut conflict = SyncConflict {
+                table: table.get_name().to_string(),
+                row_id,
+                base: base_row.map(|row| table.describe(row)),
+                local: local_row.map(|row| table.describe(row)),
+                remote: remote_row.map(|row| table.describe(row)),
+                local_hash: get_row_hash(local_row),
+                remote_hash: get_row_hash(remote_row),
+                resolution: None,
+            };
+            conflict.resolution = resolutions.iter()
+                .find(|resolved| resolved.is_same(&conflict))
+                .and_then(|resolved| resolved.resolution);
+
+            let merged_row = match conflict.resolution {
+                Some(ConflictResolution::KeepLocal) => {
+                    merge.stats.local_changes += 1;
+                    local_row
+                },
+                Some(ConflictResolution::KeepRemote) => {
+                    merge.stats.remote_changes += 1;
+                    remote_row
+                },
+                None => local_row,
+            };
+            merge.conflicts.push(conflict);
+            merged_row
+        };
+
+        if let Some(row) = merged_row {
+            merge.rows.insert(row_id, row.clone());
+        }
+    }
+
+    merge
+}
+
+// Both sides take ids of new rows from their own autoincrement, so rows added independently get the same ids.
+// A remote row added under an id that a different local row took is moved to a free id with the rows pointing to it.
+// Identical rows added on both sides, e.g. on the first sync of copies of one collection, keep their ids.
+pub fn remap_remote_rows(tables: &[SyncTable], base: &MetaTables, local: &MetaTables, remote: &MetaTables) -> Result<MetaTables> {
+    let mut id_maps = IdMaps::new();
+    // A row that points to a moved row differs from the local one after the move, so it is checked again
+    loop {
+        let mut is_changed = false;
+        // Rows of tables that share ids with another table move together with the rows of that table
+        for table in tables.iter().filter(|table| table.owner_table.is_none()) {
+            let (base_rows, local_rows) = (&base[table.file_name], &local[table.file_name]);
+            let remote_rows = &remote[table.file_name];
+            let mut next_id = base_rows.keys().chain(local_rows.keys()).chain(remote_rows.keys())
+                .chain(id_maps.get(table.file_name).into_iter().flat_map(|id_map| id_map.values()))
+                .max()
+                .map_or(1, |max_id| max_id + 1);
+
+            for (row_id, row) in remote_rows {
+                let is_moved = id_maps.get(table.file_name).map_or(false, |id_map| id_map.contains_key(row_id));
+                if base_rows.contains_key(row_id) || is_moved {
+                    continue;
+                }
+                if let Some(local_row) = local_rows.get(row_id) {
+                    if *local_row != table.remap(row, &id_maps)? {
+                        id_maps.entry(table.file_name).or_default().insert(*row_id, next_id);
+                        next_id += 1;
+                        is_changed = true;
+                    }
+                }
+            }
+        }
+        if !is_changed {
+            break;
+        }
+    }
+
+    let mut remapped = MetaTables::new();
+    for table in tables {
+        let mut rows = TableRows::new();
+        for (row_id, row) in &remote[table.file_name] {
+            rows.insert(tables::remap_id(&id_maps, table.get_id_table(), *row_id), table.remap(row, &id_maps)?);
+        }
+        remapped.insert(table.file_name, rows);
+    }
+    Ok(remapped)
+}
+
+// Rows that point to rows missing after the merge, e.g. a tag added on one side to an item removed on the other
+pub fn find_broken_references(tables: &[SyncTable], merged: &MetaTables) -> Result<Vec<String>> {
+    let mut broken_references = Vec::new();
+    for table in tables {
+        for (row_id, row) in &merged[table.file_name] {
+            for (ref_table, ref_id) in table.get_references(row)? {
+                if ref_table == "folders.pb" && ref_id == ROOT_FOLDER_ID {
+                    continue;
+                }
+                if !merged.get(ref_table).map_or(false, |rows| rows.contains_key(&ref_id)) {
+                    broken_references.push(format!("{} row {} points to missing {} row {}",
+                        table.get_name(), row_id, ref_table.trim_end_matches(".pb"), ref_id));
+                }
+            }
+        }
+    }
+    Ok(broken_references)
+}
+
+// Rows that would break UNIQUE constraints, e.g. playlists with the same name added on both sides
+pub fn find_unique_violations(tables: &[SyncTable], merged: &MetaTables) -> Result<Vec<String>> {
+    let mut violations = Vec::new();
+    for table in tables {
+        let mut taken_keys: HashMap<(&'static str, String), i64> = HashMap::new();
+        for (row_id, row) in &merged[table.file_name] {
+            for key in table.get_unique_keys(row)? {
+                if let Some(other_row_id) = taken_keys.get(&key) {
+                    violations.push(format!("{} rows {} and {} have the same {} {}",
+                        table.get_name(), other_row_id, row_id, key.0, key.1));
+                } else {
+                    taken_keys.insert(key, *row_id);
+                }
+            }
+        }
+    }
+    Ok(violations)
+}
+
+fn get_row_hash(row: Option<&Vec<u8>>) -> String {
+    match row {
+        Some(row) => blake3::hash(row).to_hex().to_string(),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,172 @@
+pub mod journal;
+pub mod merge;
+pub mod plan;
+pub mod rules;
+pub mod tables;
+pub mod transfer;
+
+#[cfg(test)]
+mod tests;
+
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use camino::Utf8PathBuf;
+use serde::{Deserialize, Serialize};
+use amina_core::events::{Event, EventEmitter};
+use amina_core::register_rpc_handler;
+use amina_core::rpc::Rpc;
+use amina_core::service::{Context, Service, ServiceApi, ServiceInitializer};
+
+use crate::storage::local::LocalStorage;
+
+use merge::{ConflictResolution, SyncConflict};
+use plan::SyncPlan;
+use rules::SyncRules;
+
+static CONFLICTS_FILE_NAME: &str = "conflicts.yaml";
+
+#[derive(Serialize, Deserialize)]
+#[derive(Event)]
+#[key = "lappi.collection.sync.OnSyncConflictsUpdated"]
+pub struct OnSyncConflictsUpdated {
+    pub conflicts_count: usize,
+    pub unresolved_count: usize,
+}
+
+// Keeps the state of sync jobs between runs: the last synced snapshot, conflicts waiting for the user, rules and plans
+pub struct CollectionSync {
+    local_storage: Service<LocalStorage>,
+    event_emitter: Service<EventEmitter>,
+    conflicts: RwLock<Vec<SyncConflict>>,
+    plan: RwLock<Option<SyncPlan>>,
+    rules: RwLock<SyncRules>,
+}
+
+impl CollectionSync {
+    pub fn get_conflicts(&self) -> Vec<SyncConflict> {
+        self.conflicts.read().unwrap().clone()
+    }
+
+    pub fn resolve_conflict(&self, table: String, row_id: i64, resolution: ConflictResolution) -> Result<()> {
+        let mut conflicts = self.conflicts.write().unwrap();
+        let conflict = conflicts.iter_mut()
+            .find(|conflict| conflict.table == table && conflict.row_id == row_id)
+            .ok_or_else(|| anyhow::anyhow!("No sync conflict for row {} of {}", row_id, table))?;
+        conflict.resolution = Some(resolution);
+        self.on_conflicts_updated(&conflicts)
+    }
+
+    pub fn resolve_all_conflicts(&self, resolution: ConflictResolution) -> Result<()> {
+        let mut conflicts = self.conflicts.write().unwrap();
+        for conflict in conflicts.iter_mut() {
+            conflict.resolution = Some(resolution);
+        }
+        self.on_conflicts_updated(&conflicts)
+    }
+
+    pub fn get_unresolved_count(&self) -> usize {
+        self.conflicts.read().unwrap().iter()
+            .filter(|conflict| conflict.resolution.is_none())
+            .count()
+    }
+
+    pub fn set_conflicts(&self, new_conflicts: Vec<SyncConflict>) -> Result<()> {
+        let mut conflicts = self.conflicts.write().unwrap();
+        *conflicts = new_conflicts;
+        self.on_conflicts_updated(&conflicts)
+    }
+
+    pub fn get_sync_plan(&self) -> Option<SyncPlan> {
+        self.plan.read().unwrap().clone()
+    }
+
+    pub fn set_sync_plan(&self, plan: SyncPlan) {
+        *self.plan.write().unwrap() = Some(plan);
+    }
+
+    pub fn get_sync_rules(&self) -> SyncRules {
+        self.rules.read().unwrap().clone()
+    }
+
+    pub fn set_sync_rules(&self, rules: SyncRules) -> Result<()> {
+        rules.save()?;
+        *self.rules.write().unwrap() = rules;
+        Ok(())
+    }
+
+    pub fn get_base_snapshot_path(&self) -> Utf8PathBuf {
+        self.get_sync_path().join("base")
+    }
+
+    fn get_sync_path(&self) -> Utf8PathBuf {
+        self.local_storage.get_internal_storage_folder("sync")
+    }
+
+    fn on_conflicts_updated(&self, conflicts: &[SyncConflict]) -> Result<()> {
+        self.save_conflicts(conflicts)?;
+        self.event_emitter.emit_event(&OnSyncConflictsUpdated {
+            conflicts_count: conflicts.len(),
+            unresolved_count: conflicts.iter().filter(|conflict| conflict.resolution.is_none()).count(),
+        });
+        Ok(())
+    }
+
+    fn save_conflicts(&self, conflicts: &[SyncConflict]) -> Result<()> {
+        let path = self.get_sync_path().join(CONFLICTS_FILE_NAME);
+        if conflicts.is_empty() {
+            if path.exists() {
+                std::fs::remove_file(&path)?;
+            }
+            return Ok(());
+        }
+        std::fs::create_dir_all(self.get_sync_path())?;
+        let file = std::fs::File::create(&path)?;
+        serde_yaml::to_writer(file, conflicts)?;
+        Ok(())
+    }
+
+    fn load_conflicts(&self) -> Result<Vec<SyncConflict>> {
+        let path = self.get_sync_path().join(CONFLICTS_FILE_NAME);
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        let file = std::fs::File::open(&path)?;
+        Ok(serde_yaml::from_reader(file)?)
+    }
+}
+
+impl ServiceApi for CollectionSync {
+
+}
+
+impl ServiceInitializer for CollectionSync {
+    fn initialize(context: &Context) -> Arc<Self> {
+        let rpc = context.get_service::<Rpc>();
+
+        let sync = Arc::new(Self {
+            local_storage: context.get_service::<LocalStorage>(),
+            event_emitter: context.get_service::<EventEmitter>(),
+            conflicts: RwLock::new(Vec::new()),
+            plan: RwLock::new(None),
+            rules: RwLock::new(SyncRules::load().unwrap_or_else(|err| {
+                log::error!("Failed to load sync rules: {}", err);
+                SyncRules::default()
+            })),
+        });
+
+        match sync.load_conflicts() {
+            Ok(conflicts) => *sync.conflicts.write().unwrap() = conflicts,
+            Err(err) => log::error!("Failed to load sync conflicts: {}", err),
+        }
+
+        register_rpc_handler!(rpc, sync, "lappi.collection.sync.get_conflicts", get_conflicts());
+        register_rpc_handler!(rpc, sync, "lappi.collection.sync.resolve_conflict", resolve_conflict(table: String, row_id: i64, resolution: ConflictResolution));
+        register_rpc_handler!(rpc, sync, "lappi.collection.sync.resolve_all_conflicts", resolve_all_conflicts(resolution: ConflictResolution));
+        register_rpc_handler!(rpc, sync, "lappi.collection.sync.get_sync_plan", get_sync_plan());
+        register_rpc_handler!(rpc, sync, "lappi.collection.sync.get_sync_rules", get_sync_rules());
+        register_rpc_handler!(rpc, sync, "lappi.collection.sync.set_sync_rules", set_sync_rules(rules: SyncRules));
+
+        sync
+    }
+}
@@ -0,0 +1,53 @@
+use serde::Serialize;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum SyncAction {
+    Copy,
+    Remove,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum SyncReason {
+    New,
+    Changed,
+    Moved,
+    Deleted,
+    Excluded,
+    Meta,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SyncPlanEntry {
+    pub path: String,
+    pub action: SyncAction,
+    pub reason: SyncReason,
+    pub size: Option<u64>,
+}
+
+// What a sync job is going to do, built by its dry-run variant
+#[derive(Serialize, Debug, Clone)]
+pub struct SyncPlan {
+    pub mode: String,
+    pub created: String,
+    pub entries: Vec<SyncPlanEntry>,
+    pub copy_size: u64,
+    pub remove_size: u64,
+    pub excluded_files: usize,
+}
+
+impl SyncPlan {
+    pub fn new(mode: &str, entries: Vec<SyncPlanEntry>, excluded_files: usize) -> Self {
+        let get_size = |action: SyncAction| entries.iter()
+            .filter(|entry| entry.action == action)
+            .filter_map(|entry| entry.size)
+            .sum();
+        Self {
+            mode: mode.to_string(),
+            created: chrono::Local::now().to_rfc3339(),
+            copy_size: get_size(SyncAction::Copy),
+            remove_size: get_size(SyncAction::Remove),
+            entries,
+            excluded_files,
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+
+use anyhow::Result;
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::collection::folders::FolderId;
+use crate::collection::internal_files::InternalFileId;
+use crate::collection::music::MusicItemId;
+use crate::collection::playlists::types::PlaylistId;
+use crate::database::sqlite::utils::ProtobufImporter;
+use crate::proto::collection::{FoldersRow, InternalFilesRow, MusicFilesRow, MusicItemsRow, PlaylistItemsRow};
+use crate::workspace::Workspace;
+
+static RULES_FILE_NAME: &str = "sync_rules.yaml";
+
+// Limits which files are synced to this device, folders and playlists restrict only music files
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SyncRules {
+    #[serde(default)]
+    pub exclude_extensions: Vec<String>,
+    #[serde(default)]
+    pub include_folders: Vec<FolderId>,
+    #[serde(default)]
+    pub include_playlists: Vec<PlaylistId>,
+}
+
+impl SyncRules {
+    fn get_path() -> Utf8PathBuf {
+        let workspace = crate::context().get_service::<Workspace>();
+        workspace.get_workspace_dir().join(RULES_FILE_NAME)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let file = File::create(Self::get_path())?;
+        serde_yaml::to_writer(file, self)?;
+        Ok(())
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::get_path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_yaml::from_reader(File::open(path)?)?)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exclude_extensions.is_empty() && !self.has_item_filters()
+    }
+
+    fn has_item_filters(&self) -> bool {
+        !self.include_folders.is_empty() || !self.include_playlists.is_empty()
+    }
+}
+
+pub struct SyncFilter {
+    rules: SyncRules,
+    music_files: HashMap<InternalFileId, MusicItemId>,
+    included_items: HashSet<MusicItemId>,
+}
+
+impl SyncFilter {
+    // Reads the collection structure from the meta tables of the side that files are copied from
+    pub fn create(rules: SyncRules, meta_path: &Utf8Path) -> Result<Self> {
+        let mut filter = Self {
+            rules,
+            music_files: HashMap::new(),
+            included_items: HashSet::new(),
+        };
+        if !filter.rules.has_item_filters() {
+            return Ok(filter);
+        }
+
+        let mut importer = ProtobufImporter::create(&meta_path.join("music_files.pb"))?;
+        while let Some(row) = importer.read_next_row::<MusicFilesRow>()? {
+            filter.music_files.insert(row.internal_file_id, row.id);
+        }
+
+        let mut child_folders: HashMap<FolderId, Vec<FolderId>> = HashMap::new();
+        let mut importer = ProtobufImporter::create(&meta_path.join("folders.pb"))?;
+        while let Some(row) = importer.read_next_row::<FoldersRow>()? {
+            child_folders.entry(row.parent_folder_id).or_default().push(row.folder_id);
+        }
+
+        let mut included_folders = HashSet::new();
+        let mut folders_to_visit = filter.rules.include_folders.clone();
+        while let Some(folder_id) = folders_to_visit.pop() {
+            if included_folders.insert(folder_id) {
+                folders_to_visit.extend(child_folders.get(&folder_id).into_iter().flatten());
+            }
+        }
+
+        let mut importer = ProtobufImporter::create(&meta_path.join("music_items.pb"))?;
+        while let Some(row) = importer.read_next_row::<MusicItemsRow>()? {
+            if included_folders.contains(&row.folder_id) {
+                filter.included_items.insert(row.music_item_id);
+            }
+        }
+
+        let mut importer = ProtobufImporter::create(&meta_path.join("playlist_items.pb"))?;
+        while let Some(row) = importer.read_next_row::<PlaylistItemsRow>()? {
+            if let Some(music_item_id) = row.music_item_id.filter(|_| filter.rules.include_playlists.contains(&row.playlist_id)) {
+                filter.included_items.insert(music_item_id);
+            }
+        }
+
+        Ok(filter)
+    }
+
+    pub fn is_included(&self, file: &InternalFilesRow) -> bool {
+        let extension = Utf8Path::new(&file.internal_path).extension().unwrap_or("").to_lowercase();
+        if self.rules.exclude_extensions.iter().any(|excluded| excluded.trim_start_matches('.').to_lowercase() == extension) {
+            return false;
+        }
+
+        match self.music_files.get(&file.file_id) {
+            Some(music_item_id) => self.included_items.contains(music_item_id),
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use camino::Utf8Path;
+use protobuf::Message;
+
+use crate::database::sqlite::utils::{ProtobufExporter, ProtobufImporter};
+use crate::proto::collection::*;
+
+// Serialized rows of a meta table by their ids
+pub type TableRows = BTreeMap<i64, Vec<u8>>;
+
+// New ids of rows that were moved to free ids, by table file name
+pub type IdMaps = HashMap<&'static str, BTreeMap<i64, i64>>;
+
+pub trait SyncRow: Message {
+    // Table whose row ids this table shares, e.g. a music file row has the id of its music item
+    fn get_owner_table() -> Option<&'static str>;
+    fn get_row_id(&self) -> i64;
+    fn set_row_id(&mut self, row_id: i64);
+    // Table file names and ids of the rows this row points to
+    fn get_references(&self) -> Vec<(&'static str, i64)>;
+    fn remap_references(&mut self, id_maps: &IdMaps);
+    // Values of the columns that have to be unique in the table, by constraint name
+    fn get_unique_keys(&self) -> Vec<(&'static str, String)>;
+}
+
+macro_rules! impl_sync_row {
+    (
+        $row_type:ty, $id_field:ident $(=> $owner_table:expr)?,
+        [$($ref_field:ident => $ref_table:expr),*],
+        [$($optional_field:ident => $optional_table:expr),*]
+        $(, unique [$($unique_name:literal => ($($unique_field:ident),+)),*])?
+    ) => {
+        impl SyncRow for $row_type {
+            fn get_owner_table() -> Option<&'static str> {
+                None $(.or(Some($owner_table)))?
+            }
+
+            fn get_row_id(&self) -> i64 {
+                self.$id_field
+            }
+
+            fn set_row_id(&mut self, row_id: i64) {
+                self.$id_field = row_id;
+            }
+
+            fn get_references(&self) -> Vec<(&'static str, i64)> {
+                let references: Vec<(&'static str, i64)> = vec![$(($ref_table, self.$ref_field)),*];
+                references.into_iter()
+                    .chain(Self::get_owner_table().map(|owner_table| (owner_table, self.$id_field)))
+                    $(.chain(self.$optional_field.map(|id| ($optional_table, id))))*
+                    .collect()
+            }
+
+            #[allow(unused_variables)]
+            fn remap_references(&mut self, id_maps: &IdMaps) {
+                $(self.$ref_field = remap_id(id_maps, $ref_table, self.$ref_field);)*
+                $(self.$optional_field = self.$optional_field.map(|id| remap_id(id_maps, $optional_table, id));)*
+            }
+
+            fn get_unique_keys(&self) -> Vec<(&'static str, String)> {
+                vec![$($(($unique_name, format!("{:?}", ($(&self.$unique_field,)+)))),*)?]
+            }
+        }
+    };
+}
+
+impl_sync_row!(InternalFilesRow, file_id, [], []);
+impl_sync_row!(FoldersRow, folder_id, [parent_folder_id => "folders.pb"], [avatar_picture_id => "picture_items.pb", description_file_id => "internal_files.pb"]);
+impl_sync_row!(MusicItemsRow, music_item_id, [folder_id => "folders.pb"], []);
+impl_sync_row!(LyricsItemsRow, lyrics_item_id, [music_item_id => "music_items.pb", internal_file_id => "internal_files.pb"], [],
+    unique ["unique_lyrics" => (music_item_id, lyrics_tag), "unique_file" => (internal_file_id)]);
+impl_sync_row!(PictureItemsRow, id, [folder_id => "folders.pb", internal_file_id => "internal_files.pb"], []);
+impl_sync_row!(TagsRow, tag_id, [], [music_item_id => "music_items.pb", folder_id => "folders.pb"]);
+impl_sync_row!(MusicFilesRow, id => "music_items.pb", [internal_file_id => "internal_files.pb"], []);
+impl_sync_row!(MusicLinksRow, id, [music_item_id => "music_items.pb"], []);
+impl_sync_row!(PlaylistsRow, playlist_id, [], [avatar_picture_id => "picture_items.pb"], unique ["name" => (name)]);
+impl_sync_row!(PlaylistItemsRow, playlist_item_id, [playlist_id => "playlists.pb"], [music_item_id => "music_items.pb"]);
+
+pub struct SyncTable {
+    pub file_name: &'static str,
+    pub owner_table: Option<&'static str>,
+    read_rows: fn(&Utf8Path) -> Result<TableRows>,
+    describe_row: fn(&[u8]) -> String,
+    remap_row: fn(&str, &[u8], &IdMaps) -> Result<Vec<u8>>,
+    get_row_references: fn(&[u8]) -> Result<Vec<(&'static str, i64)>>,
+    get_row_unique_keys: fn(&[u8]) -> Result<Vec<(&'static str, String)>>,
+}
+
+impl SyncTable {
+    fn new<M: SyncRow>(file_name: &'static str) -> Self {
+        Self {
+            file_name,
+            owner_table: M::get_owner_table(),
+            read_rows: read_rows::<M>,
+            describe_row: describe_row::<M>,
+            remap_row: remap_row::<M>,
+            get_row_references: get_row_references::<M>,
+            get_row_unique_keys: get_row_unique_keys::<M>,
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        self.file_name.trim_end_matches(".pb")
+    }
+
+    // A missing file is read as an empty table
+    pub fn read(&self, meta_path: &Utf8Path) -> Result<TableRows> {
+        (self.read_rows)(&meta_path.join(self.file_name))
+    }
+
+    pub fn write(&self, meta_path: &Utf8Path, rows: &TableRows) -> Result<()> {
+        std::fs::create_dir_all(meta_path)?;
+        let mut exporter = ProtobufExporter::create(meta_path, self.file_name)?;
+        for row in rows.values() {
+            exporter.write_row_bytes(row)?;
+        }
+        exporter.generate_hash()
+    }
+
+    pub fn describe(&self, row: &[u8]) -> String {
+        (self.describe_row)(row)
+    }
+
+    // Table that gives ids to the rows of this one
+    pub fn get_id_table(&self) -> &'static str {
+        self.owner_table.unwrap_or(self.file_name)
+    }
+
+    // Moves the row and the rows it points to according to `id_maps`
+    pub fn remap(&self, row: &[u8], id_maps: &IdMaps) -> Result<Vec<u8>> {
+        (self.remap_row)(self.get_id_table(), row, id_maps)
+    }
+
+    pub fn get_references(&self, row: &[u8]) -> Result<Vec<(&'static str, i64)>> {
+        (self.get_row_references)(row)
+    }
+
+    pub fn get_unique_keys(&self, row: &[u8]) -> Result<Vec<(&'static str, String)>> {
+        (self.get_row_unique_keys)(row)
+    }
+}
+
+pub fn get_sync_tables() -> Vec<SyncTable> {
+    vec![
+        SyncTable::new::<InternalFilesRow>("internal_files.pb"),
+        SyncTable::new::<FoldersRow>("folders.pb"),
+        SyncTable::new::<MusicItemsRow>("music_items.pb"),
+        SyncTable::new::<LyricsItemsRow>("lyrics.pb"),
+        SyncTable::new::<PictureItemsRow>("picture_items.pb"),
+        SyncTable::new::<TagsRow>("tags.pb"),
+        SyncTable::new::<MusicFilesRow>("music_files.pb"),
+        SyncTable::new::<MusicLinksRow>("music_links.pb"),
+        SyncTable::new::<PlaylistsRow>("playlists.pb"),
+        SyncTable::new::<PlaylistItemsRow>("playlist_items.pb"),
+    ]
+}
+
+pub fn parse_row<M: SyncRow>(row: &[u8]) -> Result<M> {
+    Ok(M::parse_from_bytes(row)?)
+}
+
+fn read_rows<M: SyncRow>(file_path: &Utf8Path) -> Result<TableRows> {
+    let mut rows = TableRows::new();
+    let mut importer = ProtobufImporter::create(file_path)?;
+    while let Some(row) = importer.read_next_row::<M>()? {
+        rows.insert(row.get_row_id(), row.write_to_bytes()?);
+    }
+    Ok(rows)
+}
+
+fn remap_row<M: SyncRow>(id_table: &str, row: &[u8], id_maps: &IdMaps) -> Result<Vec<u8>> {
+    let mut message = M::parse_from_bytes(row)?;
+    message.set_row_id(remap_id(id_maps, id_table, message.get_row_id()));
+    message.remap_references(id_maps);
+    Ok(message.write_to_bytes()?)
+}
+
+fn get_row_references<M: SyncRow>(row: &[u8]) -> Result<Vec<(&'static str, i64)>> {
+    Ok(M::parse_from_bytes(row)?.get_references())
+}
+
+fn get_row_unique_keys<M: SyncRow>(row: &[u8]) -> Result<Vec<(&'static str, String)>> {
+    Ok(M::parse_from_bytes(row)?.get_unique_keys())
+}
+
+pub fn remap_id(id_maps: &IdMaps, file_name: &str, row_id: i64) -> i64 {
+    id_maps.get(file_name)
+        .and_then(|id_map| id_map.get(&row_id))
+        .copied()
+        .unwrap_or(row_id)
+}
+
+fn describe_row<M: SyncRow>(row: &[u8]) -> String {
+    match M::parse_from_bytes(row) {
+        Ok(message) => protobuf::text_format::print_to_string(&message),
+        Err(err) => format!("Invalid row: {}", err),
+    }
+}
@@ -0,0 +1,195 @@
+use protobuf::Message;
+
+use crate::proto::collection::{MusicFilesRow, MusicItemsRow, PlaylistsRow, TagsRow};
+
+use super::merge::{self, ConflictResolution, MetaTables, SyncConflict};
+use super::tables::{self, SyncTable, TableRows};
+
+fn music_item(music_item_id: i64, name: &str) -> (i64, Vec<u8>) {
+    let mut row = MusicItemsRow::new();
+    row.music_item_id = music_item_id;
+    row.name = name.to_string();
+    (music_item_id, row.write_to_bytes().unwrap())
+}
+
+fn tag(tag_id: i64, music_item_id: i64, value: &str) -> (i64, Vec<u8>) {
+    let mut row = TagsRow::new();
+    row.tag_id = tag_id;
+    row.music_item_id = Some(music_item_id);
+    row.tag_name = "title".to_string();
+    row.string_value = Some(value.to_string());
+    (tag_id, row.write_to_bytes().unwrap())
+}
+
+fn music_file(music_item_id: i64, internal_file_id: i64) -> (i64, Vec<u8>) {
+    let mut row = MusicFilesRow::new();
+    row.id = music_item_id;
+    row.internal_file_id = internal_file_id;
+    (music_item_id, row.write_to_bytes().unwrap())
+}
+
+fn playlist(playlist_id: i64, name: &str) -> (i64, Vec<u8>) {
+    let mut row = PlaylistsRow::new();
+    row.playlist_id = playlist_id;
+    row.name = name.to_string();
+    (playlist_id, row.write_to_bytes().unwrap())
+}
+
+fn get_table(file_name: &str) -> SyncTable {
+    tables::get_sync_tables().into_iter().find(|table| table.file_name == file_name).unwrap()
+}
+
+fn rows(rows: Vec<(i64, Vec<u8>)>) -> TableRows {
+    rows.into_iter().collect()
+}
+
+fn get_tables() -> Vec<SyncTable> {
+    tables::get_sync_tables().into_iter()
+        .filter(|table| table.file_name == "music_items.pb" || table.file_name == "tags.pb")
+        .collect()
+}
+
+fn meta_tables(music_items: Vec<(i64, Vec<u8>)>, tags: Vec<(i64, Vec<u8>)>) -> MetaTables {
+    let mut meta_tables = MetaTables::new();
+    meta_tables.insert("music_items.pb", music_items.into_iter().collect::<TableRows>());
+    meta_tables.insert("tags.pb", tags.into_iter().collect::<TableRows>());
+    meta_tables
+}
+
+#[test]
+fn moves_remote_rows_added_under_taken_ids() {
+    let base = meta_tables(vec![music_item(1, "Base")], vec![]);
+    let local = meta_tables(vec![music_item(1, "Base"), music_item(2, "Local")], vec![tag(1, 2, "Local")]);
+    let remote = meta_tables(vec![music_item(1, "Base"), music_item(2, "Remote")], vec![tag(1, 2, "Remote")]);
+
+    let remapped = merge::remap_remote_rows(&get_tables(), &base, &local, &remote).unwrap();
+    let expected = meta_tables(vec![music_item(1, "Base"), music_item(3, "Remote")], vec![tag(2, 3, "Remote")]);
+    assert_eq!(remapped, expected);
+}
+
+#[test]
+fn keeps_ids_of_rows_added_on_both_sides() {
+    let base = meta_tables(vec![], vec![]);
+    let local = meta_tables(vec![music_item(1, "Same")], vec![tag(1, 1, "Same")]);
+    let remote = local.clone();
+
+    let remapped = merge::remap_remote_rows(&get_tables(), &base, &local, &remote).unwrap();
+    assert_eq!(remapped, local);
+}
+
+// The tag is the same on both sides, but it points to different music items
+#[test]
+fn moves_rows_pointing_to_moved_rows() {
+    let base = meta_tables(vec![], vec![]);
+    let local = meta_tables(vec![music_item(1, "Local")], vec![tag(1, 1, "Same")]);
+    let remote = meta_tables(vec![music_item(1, "Remote")], vec![tag(1, 1, "Same")]);
+
+    let remapped = merge::remap_remote_rows(&get_tables(), &base, &local, &remote).unwrap();
+    let expected = meta_tables(vec![music_item(2, "Remote")], vec![tag(2, 2, "Same")]);
+    assert_eq!(remapped, expected);
+}
+
+#[test]
+fn finds_broken_references() {
+    let merged = meta_tables(vec![music_item(1, "Item")], vec![tag(1, 1, "Valid"), tag(2, 5, "Broken")]);
+    let broken_references = merge::find_broken_references(&get_tables(), &merged).unwrap();
+    assert_eq!(broken_references, vec!["tags row 2 points to missing music_items row 5"]);
+}
+
+#[test]
+fn takes_changes_of_each_side() {
+    let table = get_table("music_items.pb");
+    let base = rows(vec![music_item(1, "Base"), music_item(2, "Base"), music_item(3, "Removed")]);
+    let local = rows(vec![music_item(1, "Local"), music_item(2, "Base"), music_item(3, "Removed")]);
+    let remote = rows(vec![music_item(1, "Base"), music_item(2, "Remote")]);
+
+    let merge = merge::merge_table(&table, &base, &local, &remote, &[]);
+    assert_eq!(merge.rows, rows(vec![music_item(1, "Local"), music_item(2, "Remote")]));
+    assert!(merge.conflicts.is_empty());
+    assert_eq!(merge.stats.local_changes, 1);
+    assert_eq!(merge.stats.remote_changes, 2);
+}
+
+#[test]
+fn keeps_local_row_of_unresolved_conflict() {
+    let table = get_table("music_items.pb");
+    let base = rows(vec![music_item(1, "Base")]);
+    let local = rows(vec![music_item(1, "Local")]);
+    let remote = rows(vec![music_item(1, "Remote")]);
+
+    let merge = merge::merge_table(&table, &base, &local, &remote, &[]);
+    assert_eq!(merge.rows, local);
+    assert_eq!(merge.conflicts.len(), 1);
+    assert_eq!(merge.conflicts[0].row_id, 1);
+    assert_eq!(merge.conflicts[0].resolution, None);
+}
+
+#[test]
+fn applies_resolution_made_for_same_versions() {
+    let table = get_table("music_items.pb");
+    let base = rows(vec![music_item(1, "Base")]);
+    let local = rows(vec![music_item(1, "Local")]);
+    let remote = rows(vec![music_item(1, "Remote")]);
+    let resolve = |conflicts: Vec<SyncConflict>| -> Vec<SyncConflict> {
+        conflicts.into_iter()
+            .map(|conflict| SyncConflict { resolution: Some(ConflictResolution::KeepRemote), ..conflict })
+            .collect()
+    };
+
+    let resolutions = resolve(merge::merge_table(&table, &base, &local, &remote, &[]).conflicts);
+    let merge = merge::merge_table(&table, &base, &local, &remote, &resolutions);
+    assert_eq!(merge.rows, remote);
+    assert_eq!(merge.conflicts[0].resolution, Some(ConflictResolution::KeepRemote));
+
+    // The remote row changed again after the resolution, so the conflict is open again
+    let changed_remote = rows(vec![music_item(1, "Remote again")]);
+    let merge = merge::merge_table(&table, &base, &local, &changed_remote, &resolutions);
+    assert_eq!(merge.rows, local);
+    assert_eq!(merge.conflicts[0].resolution, None);
+}
+
+#[test]
+fn moves_music_files_with_their_music_items() {
+    let tables: Vec<SyncTable> = tables::get_sync_tables().into_iter()
+        .filter(|table| table.file_name == "music_items.pb" || table.file_name == "music_files.pb")
+        .collect();
+    let meta_tables = |music_items: Vec<(i64, Vec<u8>)>, music_files: Vec<(i64, Vec<u8>)>| {
+        let mut meta_tables = MetaTables::new();
+        meta_tables.insert("music_items.pb", rows(music_items));
+        meta_tables.insert("music_files.pb", rows(music_files));
+        meta_tables
+    };
+    let base = meta_tables(vec![], vec![]);
+    let local = meta_tables(vec![music_item(1, "Local")], vec![music_file(1, 10)]);
+    let remote = meta_tables(vec![music_item(1, "Remote")], vec![music_file(1, 20)]);
+
+    let remapped = merge::remap_remote_rows(&tables, &base, &local, &remote).unwrap();
+    assert_eq!(remapped, meta_tables(vec![music_item(2, "Remote")], vec![music_file(2, 20)]));
+}
+
+#[test]
+fn finds_broken_music_file_references() {
+    let tables: Vec<SyncTable> = tables::get_sync_tables().into_iter()
+        .filter(|table| table.file_name == "music_items.pb" || table.file_name == "music_files.pb" || table.file_name == "internal_files.pb")
+        .collect();
+    let mut merged = MetaTables::new();
+    merged.insert("music_items.pb", rows(vec![music_item(1, "Item")]));
+    merged.insert("music_files.pb", rows(vec![music_file(2, 10)]));
+    merged.insert("internal_files.pb", TableRows::new());
+
+    let broken_references = merge::find_broken_references(&tables, &merged).unwrap();
+    assert_eq!(broken_references, vec![
+        "music_files row 2 points to missing internal_files row 10",
+        "music_files row 2 points to missing music_items row 2",
+    ]);
+}
+
+#[test]
+fn finds_playlists_with_same_name() {
+    let tables = vec![get_table("playlists.pb")];
+    let mut merged = MetaTables::new();
+    merged.insert("playlists.pb", rows(vec![playlist(1, "Favorites"), playlist(2, "Road"), playlist(3, "Favorites")]));
+
+    let unique_violations = merge::find_unique_violations(&tables, &merged).unwrap();
+    assert_eq!(unique_violations, vec!["playlists rows 1 and 3 have the same name (\"Favorites\",)"]);
+}
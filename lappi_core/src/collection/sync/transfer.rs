@@ -0,0 +1,241 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::jobs::JobContext;
+use crate::storage::remote::adapter::RemoteStorageAdapter;
+
+use super::journal::SyncJournal;
+
+static PART_SUFFIX: &str = ".lappi-part";
+static INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+// The content itself is wrong, so the transfer is not retried
+#[derive(Debug, thiserror::Error)]
+#[error("Hash mismatch for {0}")]
+struct HashMismatch(Utf8PathBuf);
+
+pub type ConnectFn<'a> = dyn Fn() -> Result<Box<dyn RemoteStorageAdapter>> + Sync + 'a;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TransferDirection {
+    Download,
+    Upload,
+}
+
+pub struct FileTransfer {
+    pub path: Utf8PathBuf,
+    // Expected blake3 hash of the content, files without it are not verified
+    pub hash: Option<Vec<u8>>,
+}
+
+impl FileTransfer {
+    fn get_journal_key(&self) -> String {
+        SyncJournal::get_key("copy", &self.path, self.hash.as_deref())
+    }
+}
+
+// Copies files between the local collection and a remote storage with a connection per worker.
+// Files are written under temporary names and renamed only after they are verified.
+pub struct TransferRunner<'a> {
+    pub direction: TransferDirection,
+    pub local_base: Utf8PathBuf,
+    pub connections: usize,
+    pub retries: u32,
+    pub journal: &'a SyncJournal,
+    pub job_ctx: &'a JobContext,
+}
+
+impl<'a> TransferRunner<'a> {
+    pub fn run(&self, transfers: Vec<FileTransfer>, connect: &ConnectFn) -> Result<()> {
+        let pending: VecDeque<FileTransfer> = transfers.into_iter()
+            .filter(|transfer| !self.journal.is_completed(&transfer.get_journal_key()))
+            .collect();
+        let total = pending.len();
+        if total == 0 {
+            return Ok(());
+        }
+
+        let queue = Mutex::new(pending);
+        let done = AtomicUsize::new(0);
+        let failure: Mutex<Option<anyhow::Error>> = Mutex::new(None);
+        std::thread::scope(|scope| {
+            for _ in 0..self.connections.clamp(1, total) {
+                scope.spawn(|| self.run_worker(&queue, &done, total, &failure, connect));
+            }
+        });
+
+        if let Some(err) = failure.into_inner().unwrap() {
+            return Err(err);
+        }
+        if self.job_ctx.is_interrupted() {
+            anyhow::bail!("Transfer interrupted, it will be resumed on the next run");
+        }
+        Ok(())
+    }
+
+    fn run_worker(&self, queue: &Mutex<VecDeque<FileTransfer>>, done: &AtomicUsize, total: usize, failure: &Mutex<Option<anyhow::Error>>, connect: &ConnectFn) {
+        let mut adapter = None;
+        loop {
+            if failure.lock().unwrap().is_some() || self.job_ctx.is_interrupted() {
+                return;
+            }
+            let transfer = match queue.lock().unwrap().pop_front() {
+                Some(transfer) => transfer,
+                None => return,
+            };
+
+            let result = self.transfer_with_retries(&mut adapter, &transfer, connect)
+                .and_then(|_| self.journal.mark_completed(&transfer.get_journal_key()));
+            if let Err(err) = result {
+                failure.lock().unwrap().get_or_insert(err);
+                return;
+            }
+
+            let done = done.fetch_add(1, Ordering::SeqCst) + 1;
+            let action = match self.direction {
+                TransferDirection::Download => "Downloading",
+                TransferDirection::Upload => "Uploading",
+            };
+            self.job_ctx.set_progress(done as f32 / total as f32, format!("{} files {}/{}", action, done, total));
+        }
+    }
+
+    fn transfer_with_retries(&self, adapter: &mut Option<Box<dyn RemoteStorageAdapter>>, transfer: &FileTransfer, connect: &ConnectFn) -> Result<()> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut attempt = 0;
+        loop {
+            match self.try_transfer(adapter, transfer, connect) {
+                Ok(()) => return Ok(()),
+                Err(err) if attempt < self.retries && !self.job_ctx.is_interrupted() && err.downcast_ref::<HashMismatch>().is_none() => {
+                    attempt += 1;
+                    log::warn!("Transfer of {} failed, retry {}/{} in {:?}: {:#}", transfer.path, attempt, self.retries, backoff, err);
+                    // The connection may be broken, so a new one is opened for the next attempt
+                    *adapter = None;
+                    std::thread::sleep(backoff);
+                    backoff *= 2;
+                },
+                Err(err) => return Err(err.context(format!("Failed to transfer {}", transfer.path))),
+            }
+        }
+    }
+
+    fn try_transfer(&self, adapter: &mut Option<Box<dyn RemoteStorageAdapter>>, transfer: &FileTransfer, connect: &ConnectFn) -> Result<()> {
+        if adapter.is_none() {
+            *adapter = Some(connect()?);
+        }
+        let adapter = adapter.as_mut().unwrap().as_mut();
+        match self.direction {
+            TransferDirection::Download => self.download(adapter, transfer),
+            TransferDirection::Upload => self.upload(adapter, transfer),
+        }
+    }
+
+    fn download(&self, adapter: &mut dyn RemoteStorageAdapter, transfer: &FileTransfer) -> Result<()> {
+        log::info!("Downloading file {}", transfer.path);
+        let local_path = self.local_base.join(&transfer.path);
+        let part_path = get_part_path(&local_path);
+        let parent_path = local_path.parent().ok_or_else(|| anyhow::anyhow!("Failed to get parent directory of {:?}", local_path))?;
+        std::fs::create_dir_all(parent_path).context(format!("Failed to create directory {:?}", parent_path))?;
+
+        let mut writer = HashingWriter::new(File::create(&part_path)?);
+        let result = adapter.read_file(&transfer.path, &mut writer)
+            .and_then(|_| writer.inner.sync_all().map_err(Into::into))
+            .and_then(|_| verify_hash(transfer, &writer.hasher));
+        if let Err(err) = result {
+            let _ = std::fs::remove_file(&part_path);
+            return Err(err);
+        }
+
+        std::fs::rename(&part_path, &local_path).context(format!("Failed to rename {:?}", part_path))?;
+        Ok(())
+    }
+
+    fn upload(&self, adapter: &mut dyn RemoteStorageAdapter, transfer: &FileTransfer) -> Result<()> {
+        log::info!("Uploading file {}", transfer.path);
+        let local_path = self.local_base.join(&transfer.path);
+        let part_path = get_part_path(&transfer.path);
+        let file = File::open(&local_path).context(format!("Failed to open file {:?}", local_path))?;
+
+        // Storages have no common checksum, the stored size is checked instead of reading the file back
+        let mut reader = HashingReader::new(file);
+        let result = adapter.write_file(&part_path, &mut reader)
+            .and_then(|_| verify_hash(transfer, &reader.hasher))
+            .and_then(|_| adapter.get_file_size(&part_path).context("Failed to get size of uploaded file"))
+            .and_then(|stored_size| {
+                if stored_size != reader.size {
+                    anyhow::bail!("Uploaded file {} has {} bytes instead of {}", transfer.path, stored_size, reader.size);
+                }
+                Ok(())
+            });
+        if let Err(err) = result {
+            let _ = adapter.remove_file(&part_path);
+            return Err(err);
+        }
+
+        adapter.rename_file(&part_path, &transfer.path)
+    }
+}
+
+fn get_part_path(path: &Utf8Path) -> Utf8PathBuf {
+    Utf8PathBuf::from(format!("{}{}", path, PART_SUFFIX))
+}
+
+fn verify_hash(transfer: &FileTransfer, hasher: &blake3::Hasher) -> Result<()> {
+    match transfer.hash.as_deref().filter(|hash| !hash.is_empty()) {
+        Some(expected) if expected != hasher.finalize().as_bytes() => {
+            Err(HashMismatch(transfer.path.clone()).into())
+        },
+        _ => Ok(()),
+    }
+}
+
+struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: blake3::Hasher,
+}
+
+impl<W: Write> HashingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, hasher: blake3::Hasher::new() }
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct HashingReader<R: Read> {
+    inner: R,
+    hasher: blake3::Hasher,
+    size: u64,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self { inner, hasher: blake3::Hasher::new(), size: 0 }
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.size += read as u64;
+        Ok(read)
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
 
 use crate::collection::folders::FolderId;
@@ -12,6 +14,8 @@ pub trait TagsDbApi: Send + Sync {
     fn get_item_tag(&self, item_id: MusicItemId, tag_name: &str) -> Result<Option<Tag>>;
     fn get_item_tags(&self, item_id: MusicItemId) -> Result<Vec<Tag>>;
     fn delete_item_tag(&self, item_id: MusicItemId, tag_name: &str) -> Result<()>;
+    // Own tags of the items followed by the tags inherited from the folders, nearest first
+    fn get_effective_item_tags(&self, items: &[MusicItemId]) -> Result<HashMap<MusicItemId, Vec<Tag>>>;
     
     fn set_add_folder_tag(&self, folder_id: FolderId, tag_name: &str, tag_value: &TagValue) -> Result<()>;
     fn get_folder_tag(&self, folder_id: FolderId, tag_name: &str) -> Result<Option<Tag>>;
@@ -0,0 +1,98 @@
+use anyhow::Result;
+use rusqlite::params;
+
+use crate::collection::integrity::database_api::{DanglingReference, IntegrityDbApi, InternalFileRecord};
+use crate::database::sqlite::utils::{DatabaseContext, DatabaseUtils};
+
+struct ReferenceColumn {
+    table_name: &'static str,
+    field_name: &'static str,
+    target_table_name: &'static str,
+    required: bool,
+}
+
+fn get_reference_column(reference: DanglingReference) -> ReferenceColumn {
+    let (table_name, field_name, target_table_name, required) = match reference {
+        DanglingReference::MusicFileToInternalFile => ("music_files", "internal_file_id", "internal_files", true),
+        DanglingReference::LyricsToInternalFile => ("lyrics_items", "internal_file_id", "internal_files", true),
+        DanglingReference::PictureToInternalFile => ("picture_items", "internal_file_id", "internal_files", true),
+        DanglingReference::PictureToFolder => ("picture_items", "folder_id", "folders", true),
+        DanglingReference::FolderDescriptionToInternalFile => ("folders", "description_file_id", "internal_files", false),
+        DanglingReference::FolderCoverToPicture => ("folders", "avatar_picture_id", "picture_items", false),
+        DanglingReference::PlaylistCoverToPicture => ("playlists", "avatar_picture_id", "picture_items", false),
+    };
+    ReferenceColumn {
+        table_name,
+        field_name,
+        target_table_name,
+        required,
+    }
+}
+
+fn notify_table_updated(context: &mut DatabaseContext, table_name: &str) {
+    match table_name {
+        "folders" | "picture_items" => context.on_folders_updated(),
+        "playlists" => context.on_playlists_updated(),
+        _ => context.on_music_updated(),
+    }
+}
+
+pub struct IntegrityDb {
+    db_utils: DatabaseUtils,
+}
+
+impl IntegrityDb {
+    pub fn new(db_utils: DatabaseUtils) -> Self {
+        Self {
+            db_utils,
+        }
+    }
+}
+
+impl IntegrityDbApi for IntegrityDb {
+    fn clone_api(&self) -> Box<dyn IntegrityDbApi> {
+        return Box::new(IntegrityDb::new(self.db_utils.clone()));
+    }
+
+    fn get_internal_files(&self) -> Result<Vec<InternalFileRecord>> {
+        let db_context = self.db_utils.lock();
+        let mut stmt = db_context.connection().prepare("SELECT id, internal_path, hash FROM internal_files")?;
+        let rows = stmt.query_map([], |row| {
+            Ok(InternalFileRecord {
+                file_id: row.get::<_, i64>(0)?,
+                internal_path: row.get::<_, String>(1)?,
+                hash: row.get::<_, Vec<u8>>(2)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    fn find_dangling_rows(&self, reference: DanglingReference) -> Result<Vec<i64>> {
+        let column = get_reference_column(reference);
+        let db_context = self.db_utils.lock();
+        let query = format!(
+            "SELECT id FROM {table} WHERE {field} IS NOT NULL AND {field} NOT IN (SELECT id FROM {target})",
+            table = column.table_name,
+            field = column.field_name,
+            target = column.target_table_name,
+        );
+        let mut stmt = db_context.connection().prepare(&query)?;
+        let mut rows = stmt.query([])?;
+        DatabaseContext::collect_rows(&mut rows)
+    }
+
+    fn repair_dangling_rows(&self, reference: DanglingReference, row_ids: &[i64]) -> Result<()> {
+        let column = get_reference_column(reference);
+        let mut db_context = self.db_utils.lock();
+        for row_id in row_ids {
+            if column.required {
+                db_context.remove_row(column.table_name, *row_id)?;
+            } else {
+                let query = format!("UPDATE {} SET {} = NULL WHERE id = ?1", column.table_name, column.field_name);
+                db_context.connection().execute(&query, params![row_id])?;
+            }
+        }
+        notify_table_updated(&mut db_context, column.table_name);
+        Ok(())
+    }
+}
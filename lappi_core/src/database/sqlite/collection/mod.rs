@@ -1,3 +1,4 @@
+pub mod integrity;
 pub mod internal_files;
 pub mod folders;
 pub mod lyrics;
@@ -6,3 +7,6 @@ pub mod music;
 pub mod music_sources;
 pub mod playlists;
 pub mod tags;
+
+#[cfg(test)]
+mod tests;
@@ -1,11 +1,13 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
 use camino::Utf8Path;
-use rusqlite::params;
+use rusqlite::{params, params_from_iter};
 
 use crate::collection::folders::FolderId;
 use crate::collection::music::database_api::MusicDbApi;
 use crate::collection::music::{MusicItemDesc, MusicItemId};
-use crate::database::sqlite::utils::{DatabaseUtils, ProtobufExporter, ProtobufImporter};
+use crate::database::sqlite::utils::{gen_placeholders, DatabaseUtils, ProtobufExporter, ProtobufImporter, IDS_CHUNK_SIZE};
 
 pub struct MusicDb {
     db_utils: DatabaseUtils,
@@ -90,6 +92,32 @@ impl MusicDbApi for MusicDb {
         Ok(description) 
     }
 
+    fn get_music_item_descriptions(&self, items: &[MusicItemId]) -> Result<Vec<MusicItemDesc>> {
+        let context = self.db_utils.lock();
+
+        let mut descriptions = HashMap::new();
+        for chunk in items.chunks(IDS_CHUNK_SIZE) {
+            let sql = format!("SELECT id, name, folder_id FROM music_items WHERE id IN ({})", gen_placeholders(chunk.len()));
+            let mut stmt = context.connection().prepare(&sql)?;
+            let rows = stmt.query_map(params_from_iter(chunk), |row| {
+                Ok(MusicItemDesc {
+                    item_id: row.get::<_, i64>(0)?,
+                    name: row.get::<_, String>(1)?,
+                    folder_id: row.get::<_, i64>(2)? as FolderId,
+                })
+            })?;
+            for row in rows {
+                let description = row?;
+                descriptions.insert(description.item_id, description);
+            }
+        }
+
+        items.iter().map(|item_id| {
+            descriptions.get(item_id).cloned()
+                .ok_or_else(|| anyhow::anyhow!("Music item {} not found", item_id))
+        }).collect()
+    }
+
     fn get_all_music_items(&self) -> Result<Vec<MusicItemId>> {
         self.db_utils.lock().get_rows_list("music_items")
     }
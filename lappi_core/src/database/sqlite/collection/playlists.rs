@@ -165,9 +165,16 @@ impl PlaylistsDbApi for PlaylistsDb {
         Ok(())
     }
 
+    fn delete_playlist_item(&self, playlist_item_id: PlaylistItemId) -> Result<()> {
+        let mut context = self.db_utils.lock();
+        context.remove_row("playlist_items", playlist_item_id)?;
+        context.on_playlists_updated();
+        Ok(())
+    }
+
     fn get_playlist_items(&self, playlist_id: PlaylistId) -> Result<Vec<(PlaylistItemId, MusicItemId)>> {
         let context = self.db_utils.lock();
-        let mut stmt = context.connection().prepare("SELECT id, music_item_id FROM playlist_items WHERE playlist_id=(?1)")?;
+        let mut stmt = context.connection().prepare("SELECT id, music_item_id FROM playlist_items WHERE playlist_id=(?1) ORDER BY id")?;
         let rows = stmt.query_map(params![playlist_id], |row| {
             Ok((row.get::<_, i32>(0)? as i64, row.get::<_, i32>(1)? as i64))
         })?;
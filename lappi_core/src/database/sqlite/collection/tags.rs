@@ -1,15 +1,25 @@
 use std::borrow::BorrowMut;
+use std::collections::HashMap;
 
 use anyhow::Result;
 use camino::Utf8Path;
-use rusqlite::{params, OptionalExtension};
+use rusqlite::{params, params_from_iter, OptionalExtension};
 
-use crate::database::sqlite::utils::{DatabaseContext, DatabaseUtils, ProtobufExporter, ProtobufImporter};
-use crate::collection::folders::FolderId;
+use crate::database::sqlite::utils::{gen_placeholders, parse_enum, DatabaseContext, DatabaseUtils, ProtobufExporter, ProtobufImporter, IDS_CHUNK_SIZE};
+use crate::collection::folders::{FolderId, FolderType};
 use crate::collection::music::MusicItemId;
 use crate::collection::tags::{Tag, TagValue};
 use crate::collection::tags::database_api::TagsDbApi;
 
+fn parse_tag_value(string_value: Option<String>, int_value: Option<i32>) -> rusqlite::Result<TagValue> {
+    match (string_value, int_value) {
+        (Some(value), None) => Ok(TagValue::String(value)),
+        (None, Some(value)) => Ok(TagValue::Number(value)),
+        (None, None) => Ok(TagValue::Bool),
+        _ => Err(rusqlite::Error::InvalidQuery),
+    }
+}
+
 struct TagsUtils<'a> {
     context: &'a mut DatabaseContext,
     id_field_name: &'static str,
@@ -82,14 +92,7 @@ impl <'a> TagsUtils<'a> {
         let mut tags_stmt = self.context.connection().prepare(sql.as_str())?;
         let tags_rows = tags_stmt.query_map(params![id_field],|row| {
             let tag_name = row.get(0)?;
-            let string_value: Option<String> = row.get(1)?;
-            let int_value: Option<i32> = row.get(2)?;
-            let tag_value = match (string_value, int_value) {
-                (Some(value), None) => TagValue::String(value),
-                (None, Some(value)) => TagValue::Number(value),
-                (None, None) => TagValue::Bool,
-                _ => return Err(rusqlite::Error::InvalidQuery),
-            };
+            let tag_value = parse_tag_value(row.get(1)?, row.get(2)?)?;
             Ok(Tag::new(tag_name, tag_value))
         })?;
         Ok(tags_rows.collect::<Result<Vec<_>, _>>()?)
@@ -123,6 +126,77 @@ impl TagsDb {
         }
     }
 
+    fn collect_items_tags(&self, context: &DatabaseContext, items: &[MusicItemId], tags: &mut HashMap<MusicItemId, Vec<Tag>>) -> Result<()> {
+        let sql = format!("SELECT music_item_id, tag_name, string_value, int_value FROM tags WHERE music_item_id IN ({}) ORDER BY id",
+            gen_placeholders(items.len()));
+        let mut stmt = context.connection().prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(items), |row| {
+            let tag_value = parse_tag_value(row.get(2)?, row.get(3)?)?;
+            Ok((row.get::<_, MusicItemId>(0)?, Tag::new(row.get(1)?, tag_value)))
+        })?;
+        for row in rows {
+            let (item_id, tag) = row?;
+            tags.entry(item_id).or_default().push(tag);
+        }
+        Ok(())
+    }
+
+    // Walks up the folders of every item in one query. Tags of each folder come before the tag
+    // given by the folder type, the root folder is not a part of the chain.
+    fn collect_folders_tags(&self, context: &DatabaseContext, items: &[MusicItemId], tags: &mut HashMap<MusicItemId, Vec<Tag>>) -> Result<()> {
+        let sql = format!("
+            WITH RECURSIVE chain(item_id, folder_id, depth) AS (
+                SELECT id, folder_id, 0 FROM music_items WHERE id IN ({})
+                UNION ALL
+                SELECT chain.item_id, folders.parent_id, chain.depth + 1
+                FROM chain JOIN folders ON folders.id = chain.folder_id
+                WHERE folders.parent_id != ?
+            )
+            SELECT chain.item_id, chain.depth, folders.name, folders.folder_type, tags.tag_name, tags.string_value, tags.int_value
+            FROM chain
+            LEFT JOIN folders ON folders.id = chain.folder_id
+            LEFT JOIN tags ON tags.folder_id = chain.folder_id
+            ORDER BY chain.item_id, chain.depth, tags.id", gen_placeholders(items.len()));
+        let root_folder_id: FolderId = 0;
+
+        let mut stmt = context.connection().prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(items.iter().chain(std::iter::once(&root_folder_id))), |row| {
+            let item_id: MusicItemId = row.get(0)?;
+            let depth: i64 = row.get(1)?;
+            let own_tag = match (row.get::<_, Option<String>>(2)?, row.get::<_, Option<i32>>(3)?) {
+                (Some(folder_name), Some(folder_type)) => parse_enum::<FolderType>(folder_type)?
+                    .get_own_tag_name()
+                    .map(|tag_name| Tag::new_string(tag_name.to_string(), folder_name)),
+                _ => None,
+            };
+            let tag = match row.get::<_, Option<String>>(4)? {
+                Some(tag_name) => Some(Tag::new(tag_name, parse_tag_value(row.get(5)?, row.get(6)?)?)),
+                None => None,
+            };
+            Ok((item_id, depth, own_tag, tag))
+        })?;
+
+        let mut current_folder = None;
+        let mut pending_own_tag: Option<(MusicItemId, Tag)> = None;
+        for row in rows {
+            let (item_id, depth, own_tag, tag) = row?;
+            if current_folder != Some((item_id, depth)) {
+                if let Some((own_item_id, own_tag)) = pending_own_tag.take() {
+                    tags.entry(own_item_id).or_default().push(own_tag);
+                }
+                current_folder = Some((item_id, depth));
+                pending_own_tag = own_tag.map(|own_tag| (item_id, own_tag));
+            }
+            if let Some(tag) = tag {
+                tags.entry(item_id).or_default().push(tag);
+            }
+        }
+        if let Some((own_item_id, own_tag)) = pending_own_tag {
+            tags.entry(own_item_id).or_default().push(own_tag);
+        }
+        Ok(())
+    }
+
     pub fn import(&self, base_path: &Utf8Path) -> Result<()> {
         let db_context = self.db_utils.lock();
 
@@ -188,6 +262,21 @@ impl TagsDbApi for TagsDb {
         return tags_utils.delete_tag(item_id, tag_name);
     }
 
+    fn get_effective_item_tags(&self, items: &[MusicItemId]) -> Result<HashMap<MusicItemId, Vec<Tag>>> {
+        // Every item has to be queried once, otherwise its tags would be collected twice
+        let mut unique_items = items.to_vec();
+        unique_items.sort_unstable();
+        unique_items.dedup();
+
+        let context = self.db_utils.lock();
+        let mut tags = unique_items.iter().map(|item_id| (*item_id, Vec::new())).collect();
+        for chunk in unique_items.chunks(IDS_CHUNK_SIZE) {
+            self.collect_items_tags(&context, chunk, &mut tags)?;
+            self.collect_folders_tags(&context, chunk, &mut tags)?;
+        }
+        Ok(tags)
+    }
+
     fn set_add_folder_tag(&self, folder_id: FolderId, tag_name: &str, tag_value: &TagValue) -> Result<()> {
         let mut context = self.db_utils.lock();
         let mut tags_utils = TagsUtils::new_folder_utils(context.borrow_mut());
@@ -0,0 +1,179 @@
+use amina_core::events::EventEmitter;
+use amina_core::service::Context;
+use anyhow::Result;
+use rusqlite::Connection;
+
+use crate::collection::folders::database_api::FoldersDbApi;
+use crate::collection::folders::{FolderId, FolderType};
+use crate::collection::integrity::database_api::{DanglingReference, IntegrityDbApi};
+use crate::collection::internal_files::database_api::InternalFilesDbApi;
+use crate::collection::internal_files::InternalPath;
+use crate::collection::music::database_api::MusicDbApi;
+use crate::collection::music::MusicItemId;
+use crate::collection::music_sources::database_api::MusicSourcesDbApi;
+use crate::collection::music_sources::{MusicFileDesc, MusicFileType};
+use crate::collection::playlists::database_api::PlaylistsDbApi;
+use crate::collection::tags::database_api::TagsDbApi;
+use crate::collection::tags::{Tag, TagValue};
+use crate::database::sqlite::migrations;
+use crate::database::sqlite::utils::DatabaseUtils;
+
+use super::folders::FoldersDb;
+use super::integrity::IntegrityDb;
+use super::internal_files::InternalFilesDb;
+use super::music::MusicDb;
+use super::music_sources::MusicSourcesDb;
+use super::playlists::PlaylistsDb;
+use super::tags::TagsDb;
+
+struct TestDb {
+    folders: FoldersDb,
+    music: MusicDb,
+    tags: TagsDb,
+    internal_files: InternalFilesDb,
+    music_sources: MusicSourcesDb,
+    playlists: PlaylistsDb,
+    integrity: IntegrityDb,
+}
+
+impl TestDb {
+    fn create() -> Self {
+        let context = Context::new();
+        context.init_service::<EventEmitter>();
+        let connection = Connection::open_in_memory().unwrap();
+        migrations::prepare_database(&connection).unwrap();
+        let db_utils = DatabaseUtils::new(&context, connection);
+        Self {
+            folders: FoldersDb::new(db_utils.clone()),
+            music: MusicDb::new(db_utils.clone()),
+            tags: TagsDb::new(db_utils.clone()),
+            internal_files: InternalFilesDb::new(db_utils.clone()),
+            music_sources: MusicSourcesDb::new(db_utils.clone()),
+            playlists: PlaylistsDb::new(db_utils.clone()),
+            integrity: IntegrityDb::new(db_utils),
+        }
+    }
+
+    fn add_folder(&self, parent_id: FolderId, name: &str, folder_type: FolderType, tags: &[(&str, TagValue)]) -> FolderId {
+        let folder_id = self.folders.find_or_add_folder(parent_id, name, folder_type).unwrap();
+        for (tag_name, tag_value) in tags {
+            self.tags.set_add_folder_tag(folder_id, tag_name, tag_value).unwrap();
+        }
+        folder_id
+    }
+
+    fn add_item(&self, folder_id: FolderId, name: &str, tags: &[(&str, TagValue)]) -> MusicItemId {
+        let item_id = self.music.add_music_item(name, folder_id).unwrap();
+        for (tag_name, tag_value) in tags {
+            self.tags.set_add_item_tag(item_id, tag_name, tag_value).unwrap();
+        }
+        item_id
+    }
+
+    // Tags in the order MusicCollection::get_tag looks them up: the item, its folder,
+    // then the folder type tag and the parents up to the root folder
+    fn get_tags_one_by_one(&self, item_id: MusicItemId) -> Result<Vec<Tag>> {
+        let root_folder_id = self.folders.get_root_folder();
+        let mut folder_id = self.music.get_music_item_folder(item_id)?;
+
+        let mut tags = self.tags.get_item_tags(item_id)?;
+        tags.extend(self.tags.get_folder_tags(folder_id)?);
+        while folder_id != root_folder_id {
+            let description = FoldersDbApi::get_folder_description(&self.folders, folder_id)?;
+            if let Some(tag_name) = description.folder_type.get_own_tag_name() {
+                tags.push(Tag::new_string(tag_name.to_string(), description.name));
+            }
+            folder_id = self.folders.get_folder_parent(folder_id)?;
+            if folder_id == root_folder_id {
+                break;
+            }
+            tags.extend(self.tags.get_folder_tags(folder_id)?);
+        }
+        Ok(tags)
+    }
+}
+
+fn to_pairs(tags: &[Tag]) -> Vec<(String, String)> {
+    tags.iter().map(|tag| (tag.get_key().to_string(), tag.to_string())).collect()
+}
+
+fn string(value: &str) -> TagValue {
+    TagValue::String(value.to_string())
+}
+
+#[test]
+fn effective_tags_follow_get_tag_order() {
+    let db = TestDb::create();
+    let root_folder_id = db.folders.get_root_folder();
+    db.tags.set_add_folder_tag(root_folder_id, "comment", &string("Root")).unwrap();
+
+    let artist_id = db.add_folder(root_folder_id, "Artist", FolderType::Artist, &[("genre", string("Rock")), ("year", TagValue::Number(1990))]);
+    let album_id = db.add_folder(artist_id, "Album", FolderType::Album, &[("year", TagValue::Number(1994)), ("live", TagValue::Bool)]);
+    let plain_id = db.add_folder(root_folder_id, "Misc", FolderType::Folder, &[("genre", string("Jazz"))]);
+
+    let nested_item_id = db.add_item(album_id, "Nested", &[("title", string("Nested")), ("year", TagValue::Number(1995))]);
+    let untagged_item_id = db.add_item(album_id, "Untagged", &[]);
+    let plain_item_id = db.add_item(plain_id, "Plain", &[("artist", string("Someone"))]);
+    let root_item_id = db.add_item(root_folder_id, "Root level", &[("title", string("Root level"))]);
+
+    let items = vec![nested_item_id, root_item_id, untagged_item_id, plain_item_id, nested_item_id];
+    let effective_tags = db.tags.get_effective_item_tags(&items).unwrap();
+    assert_eq!(effective_tags.len(), 4);
+    for item_id in &items {
+        let expected = to_pairs(&db.get_tags_one_by_one(*item_id).unwrap());
+        assert_eq!(to_pairs(&effective_tags[item_id]), expected, "Tags of item {} differ", item_id);
+    }
+
+    // The first tag of a name is the one get_tag returns
+    let nested_tags = to_pairs(&effective_tags[&nested_item_id]);
+    assert_eq!(nested_tags, vec![
+        ("title".to_string(), "Nested".to_string()),
+        ("year".to_string(), "1995".to_string()),
+        ("year".to_string(), "1994".to_string()),
+        ("live".to_string(), "True".to_string()),
+        ("album".to_string(), "Album".to_string()),
+        ("genre".to_string(), "Rock".to_string()),
+        ("year".to_string(), "1990".to_string()),
+        ("artist".to_string(), "Artist".to_string()),
+    ]);
+    assert_eq!(to_pairs(&effective_tags[&root_item_id]), vec![
+        ("title".to_string(), "Root level".to_string()),
+        ("comment".to_string(), "Root".to_string()),
+    ]);
+}
+
+#[test]
+fn repairs_dangling_rows() {
+    let db = TestDb::create();
+    let root_folder_id = db.folders.get_root_folder();
+    let kept_item_id = db.add_item(root_folder_id, "Kept", &[]);
+    let broken_item_id = db.add_item(root_folder_id, "Broken", &[]);
+    for (item_id, path) in [(kept_item_id, "kept.mp3"), (broken_item_id, "broken.mp3")] {
+        let file_id = db.internal_files.add_file_path(&InternalPath::from_string(path.to_string())).unwrap();
+        db.music_sources.add_music_file(&MusicFileDesc {
+            music_item_id: item_id,
+            internal_file_id: file_id,
+            file_type: MusicFileType::MP3,
+        }).unwrap();
+    }
+    let broken_file_id = db.music_sources.get_music_file(broken_item_id).unwrap().unwrap().internal_file_id;
+    db.internal_files.delete_file(broken_file_id).unwrap();
+    let playlist_id = db.playlists.create_playlist("Covered").unwrap();
+    db.playlists.set_playlist_cover(playlist_id, Some(42)).unwrap();
+
+    assert_eq!(db.integrity.find_dangling_rows(DanglingReference::MusicFileToInternalFile).unwrap(), vec![broken_item_id]);
+    assert_eq!(db.integrity.find_dangling_rows(DanglingReference::PlaylistCoverToPicture).unwrap(), vec![playlist_id]);
+    assert!(db.integrity.find_dangling_rows(DanglingReference::PictureToFolder).unwrap().is_empty());
+
+    // Music files can't exist without their file and are deleted, playlist covers are optional and cleared
+    db.integrity.repair_dangling_rows(DanglingReference::MusicFileToInternalFile, &[broken_item_id]).unwrap();
+    db.integrity.repair_dangling_rows(DanglingReference::PlaylistCoverToPicture, &[playlist_id]).unwrap();
+    assert!(db.music_sources.get_music_file(broken_item_id).unwrap().is_none());
+    assert!(db.music_sources.get_music_file(kept_item_id).unwrap().is_some());
+    let playlist = db.playlists.get_playlist_description(playlist_id).unwrap();
+    assert_eq!(playlist.name, "Covered");
+    assert_eq!(playlist.avatar_picture_id, None);
+    for reference in DanglingReference::get_all() {
+        assert!(db.integrity.find_dangling_rows(reference).unwrap().is_empty(), "{:?} rows are left", reference);
+    }
+}
@@ -0,0 +1,107 @@
+use std::fs::File;
+
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use serde::{Deserialize, Serialize};
+
+// Version of the protobuf tables in .lappi/meta, increased with every migration
+pub static META_FORMAT_VERSION: u32 = 1;
+pub static FORMAT_FILE_NAME: &str = "format.yaml";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetaFormat {
+    pub version: u32,
+    // Version of lappi that wrote the tables, to tell the user what to update to
+    #[serde(default)]
+    pub app_version: String,
+}
+
+impl MetaFormat {
+    pub fn current() -> Self {
+        Self {
+            version: META_FORMAT_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    // Collections saved before the manifest was introduced have the first version
+    pub fn read(meta_path: &Utf8Path) -> Result<Self> {
+        let path = meta_path.join(FORMAT_FILE_NAME);
+        if !path.exists() {
+            return Ok(Self {
+                version: 1,
+                app_version: String::new(),
+            });
+        }
+        let file = File::open(&path)?;
+        serde_yaml::from_reader(file).context(format!("Failed to parse {}", path))
+    }
+
+    pub fn write(&self, meta_path: &Utf8Path) -> Result<()> {
+        let file = File::create(meta_path.join(FORMAT_FILE_NAME))?;
+        serde_yaml::to_writer(file, self)?;
+        Ok(())
+    }
+
+    pub fn check_supported(&self) -> Result<()> {
+        if self.version > META_FORMAT_VERSION {
+            anyhow::bail!(
+                "Collection format {} was written by lappi {} and is newer than the supported format {}, please update lappi",
+                self.version, self.app_version, META_FORMAT_VERSION
+            );
+        }
+        Ok(())
+    }
+}
+
+pub(super) struct MetaMigration {
+    // Format version the tables have after the migration
+    pub version: u32,
+    pub description: &'static str,
+    pub apply: fn(&Utf8Path) -> Result<()>,
+}
+
+fn get_migrations() -> Vec<MetaMigration> {
+    Vec::new()
+}
+
+// Upgrades tables of an older format as a new generation, a copy of the original tables is kept next to them
+pub fn migrate(meta_path: &Utf8Path) -> Result<()> {
+    migrate_with(meta_path, get_migrations())
+}
+
+pub(super) fn migrate_with(meta_path: &Utf8Path, migrations: Vec<MetaMigration>) -> Result<()> {
+    if !meta_path.exists() {
+        return Ok(());
+    }
+    let format = MetaFormat::read(meta_path)?;
+    format.check_supported()?;
+
+    let migrations: Vec<MetaMigration> = migrations.into_iter()
+        .filter(|migration| migration.version > format.version)
+        .collect();
+    if migrations.is_empty() {
+        return Ok(());
+    }
+
+    let backup_path = meta_path.with_file_name(format!("meta_v{}", format.version));
+    log::info!("Back up collection tables of format {} to {}", format.version, backup_path);
+    std::fs::create_dir_all(&backup_path)?;
+    for entry in meta_path.read_dir_utf8()? {
+        let entry = entry?;
+        std::fs::copy(entry.path(), backup_path.join(entry.file_name()))?;
+    }
+
+    // Migrations run on a staged copy, so a failed one leaves the tables as they were
+    crate::collection::meta_store::update(meta_path, |staging_path| {
+        for migration in migrations {
+            log::info!("Migrate collection tables to format {}: {}", migration.version, migration.description);
+            (migration.apply)(staging_path).context(format!("Failed to migrate collection tables to format {}", migration.version))?;
+            MetaFormat {
+                version: migration.version,
+                app_version: env!("CARGO_PKG_VERSION").to_string(),
+            }.write(staging_path)?;
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,60 @@
+use anyhow::{Context, Result};
+use rusqlite::Connection;
+
+use super::init;
+
+// Version of the SQLite schema, stored in the user_version pragma of the database
+pub static SCHEMA_VERSION: u32 = 1;
+
+pub(super) struct SchemaMigration {
+    // Schema version the database has after the migration
+    pub version: u32,
+    pub description: &'static str,
+    pub apply: fn(&Connection) -> rusqlite::Result<()>,
+}
+
+fn get_migrations() -> Vec<SchemaMigration> {
+    Vec::new()
+}
+
+pub(super) fn get_schema_version(connection: &Connection) -> Result<u32> {
+    Ok(connection.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+fn set_schema_version(connection: &Connection, version: u32) -> Result<()> {
+    connection.execute_batch(&format!("PRAGMA user_version = {}", version))?;
+    Ok(())
+}
+
+fn has_tables(connection: &Connection) -> Result<bool> {
+    let tables_num: i64 = connection.query_row("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table'", [], |row| row.get(0))?;
+    Ok(tables_num > 0)
+}
+
+// Creates the schema in an empty database and upgrades the schema of an older one
+pub fn prepare_database(connection: &Connection) -> Result<()> {
+    prepare_database_with(connection, SCHEMA_VERSION, get_migrations())
+}
+
+pub(super) fn prepare_database_with(connection: &Connection, schema_version: u32, migrations: Vec<SchemaMigration>) -> Result<()> {
+    if !has_tables(connection)? {
+        init::create_tables(connection)?;
+        return set_schema_version(connection, schema_version);
+    }
+
+    // Databases created before the schema was versioned have the first version
+    let version = get_schema_version(connection)?.max(1);
+    if version > schema_version {
+        anyhow::bail!("Database schema {} is newer than the supported schema {}, please update lappi", version, schema_version);
+    }
+
+    for migration in migrations.into_iter().filter(|migration| migration.version > version) {
+        log::info!("Migrate database schema to version {}: {}", migration.version, migration.description);
+        let transaction = connection.unchecked_transaction()?;
+        (migration.apply)(&transaction).context(format!("Failed to migrate database schema to version {}", migration.version))?;
+        set_schema_version(&transaction, migration.version)?;
+        transaction.commit()?;
+    }
+    // Marks unversioned databases too
+    set_schema_version(connection, schema_version)
+}
@@ -1,13 +1,19 @@
 pub mod utils;
 pub mod init;
+pub mod meta_format;
+pub mod migrations;
 pub mod collection;
 
+#[cfg(test)]
+mod tests;
+
 use anyhow::Result;
 use camino::Utf8Path;
 use rusqlite::Connection;
 use amina_core::service::Context;
 
 use crate::collection::database_api::CollectionDbApi;
+use crate::collection::integrity::database_api::IntegrityDbApi;
 use crate::collection::internal_files::database_api::InternalFilesDbApi;
 use crate::collection::folders::database_api::FoldersDbApi;
 use crate::collection::lyrics::database_api::LyricsDbApi;
@@ -19,6 +25,8 @@ use crate::collection::music_sources::database_api::MusicSourcesDbApi;
 use crate::app_config::{self, AppConfig};
 
 use utils::DatabaseUtils;
+use meta_format::MetaFormat;
+use collection::integrity::IntegrityDb;
 use collection::internal_files::InternalFilesDb;
 use collection::folders::FoldersDb;
 use collection::pictures::PicturesDb;
@@ -38,6 +46,7 @@ pub struct SqliteDb {
     tags_api: Box<TagsDb>,
     lyrics_api: Box<LyricsDb>,
     playlists_api: Box<PlaylistsDb>,
+    integrity_api: Box<IntegrityDb>,
 }
 
 impl CollectionDbApi for SqliteDb {
@@ -73,16 +82,72 @@ impl CollectionDbApi for SqliteDb {
         self.playlists_api.clone_api()
     }
 
-    fn start_batch(&self) {
-        self.db_utils.lock().start_batch();
+    fn get_integrity_api(&self) -> Box<dyn IntegrityDbApi> {
+        self.integrity_api.clone_api()
+    }
+
+    fn start_batch(&self) -> Result<()> {
+        self.db_utils.lock().start_batch()
     }
 
-    fn stop_batch(&self) {
-        self.db_utils.lock().stop_batch();
+    fn commit_batch(&self) -> Result<()> {
+        self.db_utils.lock().commit_batch()
+    }
+
+    fn rollback_batch(&self) -> Result<()> {
+        self.db_utils.lock().rollback_batch()
     }
  
     fn export(&self, base_path: &Utf8Path) -> Result<()> {
         std::fs::create_dir_all(base_path)?;
+
+        // Tables are read in one transaction, so they are exported as of the same moment
+        self.start_batch()?;
+        let result = self.export_tables(base_path);
+        match result {
+            Ok(_) => self.commit_batch(),
+            Err(err) => {
+                if let Err(rollback_err) = self.rollback_batch() {
+                    log::error!("Failed to roll back export: {}", rollback_err);
+                }
+                Err(err)
+            },
+        }
+    }
+
+    fn import(&self, base_path: &Utf8Path) -> Result<()> {
+        meta_format::migrate(base_path)?;
+
+        // All tables are inserted in one transaction, a broken table leaves the database as it was
+        self.start_batch()?;
+        let result = self.import_tables(base_path);
+        match result {
+            Ok(_) => self.commit_batch(),
+            Err(err) => {
+                if let Err(rollback_err) = self.rollback_batch() {
+                    log::error!("Failed to roll back import: {}", rollback_err);
+                }
+                Err(err)
+            },
+        }
+    }
+
+    fn format(&self) -> Result<()> {
+        let db = self.db_utils.lock();
+
+        let tables = init::get_tables_list();
+        for table in tables.into_iter().rev() {
+            db.connection().execute(&format!("DROP TABLE {}", table), [])?;
+        }
+
+        init::create_tables(db.connection())?;
+
+        Ok(())
+    }
+}
+
+impl SqliteDb {
+    fn export_tables(&self, base_path: &Utf8Path) -> Result<()> {
         self.internal_files_api.export(base_path)?;
         self.folders_api.export(base_path)?;
         self.music_api.export(base_path)?;
@@ -91,10 +156,11 @@ impl CollectionDbApi for SqliteDb {
         self.pictures_api.export(base_path)?;
         self.lyrics_api.export(base_path)?;
         self.playlists_api.export(base_path)?;
+        MetaFormat::current().write(base_path)?;
         Ok(())
     }
 
-    fn import(&self, base_path: &Utf8Path) -> Result<()> {
+    fn import_tables(&self, base_path: &Utf8Path) -> Result<()> {
         self.internal_files_api.import(base_path)?;
         self.folders_api.import(base_path)?;
         self.music_api.import(base_path)?;
@@ -105,19 +171,6 @@ impl CollectionDbApi for SqliteDb {
         self.playlists_api.import(base_path)?;
         Ok(())
     }
-
-    fn format(&self) -> Result<()> {
-        let db = self.db_utils.lock();
-
-        let tables = init::get_tables_list();
-        for table in tables.into_iter().rev() {
-            db.connection().execute(&format!("DROP TABLE {}", table), [])?;
-        }
-
-        init::create_tables(db.connection())?;
-
-        Ok(())
-    }
 }
 
 pub fn initialize(context: &Context) -> SqliteDb {
@@ -132,11 +185,9 @@ pub fn initialize(context: &Context) -> SqliteDb {
             let platform_api = context.get_service::<PlatformApi>();
             let mut path = platform_api.file_system.get_workspace_dir();
             path.push("db.sql");
-            let db_file_exist = path.exists();
             let connection = Connection::open(&path).unwrap();
-
-            if !db_file_exist {
-                init::create_tables(&connection).unwrap();
+            if let Err(err) = migrations::prepare_database(&connection) {
+                panic!("Failed to open database {}: {:#}", path, err);
             }
 
             connection
@@ -145,7 +196,7 @@ pub fn initialize(context: &Context) -> SqliteDb {
             log::debug!("Initialize SQLite DB in RAM");
 
             let connection = Connection::open_in_memory().unwrap();
-            init::create_tables(&connection).unwrap();
+            migrations::prepare_database(&connection).unwrap();
 
             connection
         }
@@ -163,5 +214,6 @@ pub fn initialize(context: &Context) -> SqliteDb {
         music_sources_api: Box::new(MusicSourcesDb::new(db_utils.clone())),
         lyrics_api: Box::new(LyricsDb::new(db_utils.clone())),
         playlists_api: Box::new(PlaylistsDb::new(db_utils.clone())),
+        integrity_api: Box::new(IntegrityDb::new(db_utils.clone())),
     }
 }
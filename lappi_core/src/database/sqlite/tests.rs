@@ -0,0 +1,150 @@
+use anyhow::Result;
+use camino::{Utf8Path, Utf8PathBuf};
+use rusqlite::Connection;
+
+use super::meta_format::{self, MetaFormat, MetaMigration, META_FORMAT_VERSION};
+use super::migrations::{self, SchemaMigration, SCHEMA_VERSION};
+
+struct TempMeta {
+    base_path: Utf8PathBuf,
+}
+
+impl TempMeta {
+    // Tables of the first format with one table file
+    fn create() -> Self {
+        let base_path = Utf8PathBuf::from_path_buf(std::env::temp_dir()).unwrap()
+            .join(format!("lappi-meta-test-{:08x}", rand::random::<u32>()));
+        std::fs::create_dir_all(base_path.join("meta")).unwrap();
+        std::fs::write(base_path.join("meta").join("tags.pb"), b"old").unwrap();
+        Self { base_path }
+    }
+
+    fn get_meta_path(&self) -> Utf8PathBuf {
+        self.base_path.join("meta")
+    }
+}
+
+impl Drop for TempMeta {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.base_path);
+    }
+}
+
+fn rewrite_tags(meta_path: &Utf8Path) -> Result<()> {
+    std::fs::write(meta_path.join("tags.pb"), b"new")?;
+    Ok(())
+}
+
+fn break_tags(meta_path: &Utf8Path) -> Result<()> {
+    std::fs::write(meta_path.join("tags.pb"), b"broken")?;
+    anyhow::bail!("Migration failed")
+}
+
+fn meta_migration(apply: fn(&Utf8Path) -> Result<()>) -> MetaMigration {
+    MetaMigration {
+        version: META_FORMAT_VERSION + 1,
+        description: "Test migration",
+        apply,
+    }
+}
+
+#[test]
+fn migrates_tables_and_keeps_original_copy() {
+    let meta = TempMeta::create();
+    meta_format::migrate_with(&meta.get_meta_path(), vec![meta_migration(rewrite_tags)]).unwrap();
+
+    assert_eq!(std::fs::read(meta.get_meta_path().join("tags.pb")).unwrap(), b"new");
+    assert_eq!(MetaFormat::read(&meta.get_meta_path()).unwrap().version, META_FORMAT_VERSION + 1);
+    let backup_path = meta.base_path.join(format!("meta_v{}", META_FORMAT_VERSION));
+    assert_eq!(std::fs::read(backup_path.join("tags.pb")).unwrap(), b"old");
+}
+
+#[test]
+fn skips_applied_migrations() {
+    let meta = TempMeta::create();
+    meta_format::migrate_with(&meta.get_meta_path(), vec![MetaMigration { version: 1, ..meta_migration(rewrite_tags) }]).unwrap();
+
+    assert_eq!(std::fs::read(meta.get_meta_path().join("tags.pb")).unwrap(), b"old");
+    assert!(!meta.base_path.join("meta_v1").exists());
+}
+
+#[test]
+fn failed_migration_keeps_tables() {
+    let meta = TempMeta::create();
+    assert!(meta_format::migrate_with(&meta.get_meta_path(), vec![meta_migration(break_tags)]).is_err());
+
+    assert_eq!(std::fs::read(meta.get_meta_path().join("tags.pb")).unwrap(), b"old");
+    assert_eq!(MetaFormat::read(&meta.get_meta_path()).unwrap().version, META_FORMAT_VERSION);
+}
+
+#[test]
+fn refuses_newer_format() {
+    let meta = TempMeta::create();
+    MetaFormat { version: META_FORMAT_VERSION + 1, app_version: "9.9.9".to_string() }.write(&meta.get_meta_path()).unwrap();
+
+    let err = meta_format::migrate(&meta.get_meta_path()).unwrap_err();
+    assert!(err.to_string().contains("9.9.9"), "{}", err);
+    assert_eq!(std::fs::read(meta.get_meta_path().join("tags.pb")).unwrap(), b"old");
+}
+
+fn get_table_names(connection: &Connection) -> Vec<String> {
+    let mut stmt = connection.prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name").unwrap();
+    let names = stmt.query_map([], |row| row.get::<_, String>(0)).unwrap();
+    names.collect::<rusqlite::Result<Vec<_>>>().unwrap()
+}
+
+fn add_notes_table(connection: &Connection) -> rusqlite::Result<()> {
+    connection.execute_batch("CREATE TABLE notes (id INTEGER PRIMARY KEY)")
+}
+
+fn add_notes_table_and_fail(connection: &Connection) -> rusqlite::Result<()> {
+    connection.execute_batch("CREATE TABLE notes (id INTEGER PRIMARY KEY); SELECT * FROM missing_table")
+}
+
+fn schema_migration(apply: fn(&Connection) -> rusqlite::Result<()>) -> SchemaMigration {
+    SchemaMigration {
+        version: SCHEMA_VERSION + 1,
+        description: "Test migration",
+        apply,
+    }
+}
+
+#[test]
+fn creates_schema_of_current_version() {
+    let connection = Connection::open_in_memory().unwrap();
+    migrations::prepare_database(&connection).unwrap();
+
+    assert!(get_table_names(&connection).contains(&"music_items".to_string()));
+    assert_eq!(migrations::get_schema_version(&connection).unwrap(), SCHEMA_VERSION);
+}
+
+#[test]
+fn refuses_newer_schema() {
+    let connection = Connection::open_in_memory().unwrap();
+    migrations::prepare_database(&connection).unwrap();
+    connection.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION + 1)).unwrap();
+
+    assert!(migrations::prepare_database(&connection).is_err());
+    assert_eq!(migrations::get_schema_version(&connection).unwrap(), SCHEMA_VERSION + 1);
+}
+
+#[test]
+fn migrates_older_schema() {
+    let connection = Connection::open_in_memory().unwrap();
+    migrations::prepare_database(&connection).unwrap();
+    migrations::prepare_database_with(&connection, SCHEMA_VERSION + 1, vec![schema_migration(add_notes_table)]).unwrap();
+
+    assert!(get_table_names(&connection).contains(&"notes".to_string()));
+    assert_eq!(migrations::get_schema_version(&connection).unwrap(), SCHEMA_VERSION + 1);
+}
+
+#[test]
+fn failed_schema_migration_is_rolled_back() {
+    let connection = Connection::open_in_memory().unwrap();
+    migrations::prepare_database(&connection).unwrap();
+    let result = migrations::prepare_database_with(&connection, SCHEMA_VERSION + 1, vec![schema_migration(add_notes_table_and_fail)]);
+
+    assert!(result.is_err());
+    assert!(!get_table_names(&connection).contains(&"notes".to_string()));
+    assert_eq!(migrations::get_schema_version(&connection).unwrap(), SCHEMA_VERSION);
+}
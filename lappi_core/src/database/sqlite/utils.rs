@@ -1,6 +1,7 @@
 use std::fs::File;
 use std::io::{Read, Write};
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::ThreadId;
 
 use anyhow::Result;
 use camino::{Utf8Path, Utf8PathBuf};
@@ -15,18 +16,21 @@ use crate::collection::OnCollectionUpdated;
 
 struct BatchContext {
     events_emitter: Service<EventEmitter>,
-    batch: bool,
+    depth: usize,
     event: OnCollectionUpdated,
 }
 
 impl BatchContext {
     fn reset(&mut self) {
         self.event = OnCollectionUpdated::default();
-        self.batch = false;
+    }
+
+    fn has_updates(&self) -> bool {
+        self.event.folders_updated || self.event.music_updated || self.event.plalists_updated
     }
 
     pub fn on_collection_updated(&mut self) {
-        if !self.batch {
+        if self.depth == 0 {
             self.events_emitter.emit_event(&self.event);
             self.reset();
         }
@@ -37,6 +41,9 @@ impl BatchContext {
 pub struct DatabaseContext {
     connection: Connection,
     batch_context: BatchContext,
+    // Thread of the open outermost batch, other threads can't use the connection until it ends
+    batch_owner: Option<ThreadId>,
+    batch_released: Arc<Condvar>,
 }
 
 impl DatabaseContext {
@@ -55,15 +62,51 @@ impl DatabaseContext {
         self.batch_context.on_collection_updated();
     }
 
-    pub fn start_batch(&mut self) {
-        log::debug!("start_batch");
-        self.batch_context.batch = true;
+    // Batches are savepoints, so they can be nested and the outermost one is an SQLite transaction.
+    // Update events are held back until the outermost batch is committed.
+    pub fn start_batch(&mut self) -> Result<()> {
+        let depth = self.batch_context.depth + 1;
+        log::debug!("start_batch {}", depth);
+        self.connection.execute_batch(&format!("SAVEPOINT batch_{}", depth))?;
+        self.batch_context.depth = depth;
+        self.batch_owner = Some(std::thread::current().id());
+        Ok(())
     }
 
-    pub fn stop_batch(&mut self) {
-        log::debug!("stop_batch");
-        self.batch_context.events_emitter.emit_event(&self.batch_context.event);
-        self.batch_context.reset();
+    pub fn commit_batch(&mut self) -> Result<()> {
+        let depth = self.batch_context.depth;
+        log::debug!("commit_batch {}", depth);
+        self.connection.execute_batch(&format!("RELEASE batch_{}", depth))?;
+        self.batch_context.depth -= 1;
+        if self.batch_context.depth == 0 {
+            self.release_batch();
+            // Batches that only read, like exports, don't notify anyone
+            if self.batch_context.has_updates() {
+                self.batch_context.events_emitter.emit_event(&self.batch_context.event);
+            }
+            self.batch_context.reset();
+        }
+        Ok(())
+    }
+
+    pub fn rollback_batch(&mut self) -> Result<()> {
+        let depth = self.batch_context.depth;
+        log::debug!("rollback_batch {}", depth);
+        self.batch_context.depth -= 1;
+        if self.batch_context.depth == 0 {
+            self.batch_context.reset();
+        }
+        let result = self.connection.execute_batch(&format!("ROLLBACK TO batch_{depth}; RELEASE batch_{depth}", depth = depth));
+        if self.batch_context.depth == 0 {
+            self.release_batch();
+        }
+        result?;
+        Ok(())
+    }
+
+    fn release_batch(&mut self) {
+        self.batch_owner = None;
+        self.batch_released.notify_all();
     }
 
     pub fn connection(&self) -> &Connection {
@@ -201,16 +244,25 @@ impl<'a> DatabaseUtils {
             context: Arc::new(Mutex::new(DatabaseContext {
                 connection,
                 batch_context: BatchContext {
-                    batch: false,
+                    depth: 0,
                     events_emitter: context.get_service(),
                     event: OnCollectionUpdated::default(),
                 },
+                batch_owner: None,
+                batch_released: Arc::new(Condvar::new()),
             })),
         }
     }
 
+    // Waits while another thread has a batch open, so nobody reads or writes in the middle of it
     pub fn lock(&'a self) -> MutexGuard<'a, DatabaseContext> {
-        self.context.lock().unwrap()
+        let thread_id = std::thread::current().id();
+        let mut db = self.context.lock().unwrap();
+        while matches!(db.batch_owner, Some(owner) if owner != thread_id) {
+            let batch_released = db.batch_released.clone();
+            db = batch_released.wait(db).unwrap();
+        }
+        db
     }
 }
 
@@ -279,11 +331,15 @@ impl ProtobufExporter {
     {
         // Serialize the message
         let message_bytes = message.write_to_bytes()?;
+        self.write_row_bytes(&message_bytes)
+    }
+
+    pub fn write_row_bytes(&mut self, message_bytes: &[u8]) -> Result<()> {
         // Write the length of the message
         let length = message_bytes.len() as u32;
         self.file.write_all(&length.to_le_bytes())?;
         // Write the message bytes
-        self.file.write_all(&message_bytes)?;
+        self.file.write_all(message_bytes)?;
         Ok(())
     }
 
@@ -305,6 +361,13 @@ impl ProtobufExporter {
     }
 }
 
+// Bulk queries bind ids in chunks, so that they stay below the SQLite limit of host parameters
+pub const IDS_CHUNK_SIZE: usize = 500;
+
+pub fn gen_placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
 pub fn parse_enum<T>(value: i32) -> rusqlite::Result<T>
 where 
     T: FromPrimitive
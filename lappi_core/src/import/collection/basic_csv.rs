@@ -27,19 +27,18 @@ impl BasicCsvCollectionImporter {
             .delimiter(b'|')
             .from_reader(File::open(file_path)?);
 
-        let collection = self.collection.clone();
-        collection.start_batch();
-        for result in reader.records() {
-            let record = result?;
-            log::trace!("{:?}", record);
-            let mut tags = TagsMap::new();
-            tags.add_string_tag("artist", record.get(0).unwrap().to_string());
-            tags.add_string_tag("album", record.get(1).unwrap().to_string());
-            tags.add_string_tag("title", record.get(4).unwrap().to_string());
-            crate::import::collection::utils::import_song(&collection, &tags)?;
-        }
-        collection.stop_batch();
-        Ok(())
+        self.collection.transaction(|collection| {
+            for result in reader.records() {
+                let record = result?;
+                log::trace!("{:?}", record);
+                let mut tags = TagsMap::new();
+                tags.add_string_tag("artist", record.get(0).unwrap().to_string());
+                tags.add_string_tag("album", record.get(1).unwrap().to_string());
+                tags.add_string_tag("title", record.get(4).unwrap().to_string());
+                crate::import::collection::utils::import_song(collection, &tags)?;
+            }
+            Ok(())
+        })
     }
 
     pub fn import(&self, path: &Utf8Path) -> Result<()> {
@@ -60,13 +60,11 @@ impl BasicYamlCollectionImporter {
         file_path.push("collection.yaml");
         let file = File::open(file_path)?;
         let collection_entry: CollectionEntry = serde_yaml::from_reader(file)?;
-        let collection = &self.collection;
-    
-        collection.start_batch();
-        self.import_playlists(collection_entry.playlists)?;
-        self.import_artists(collection_entry.artists)?;
-        collection.stop_batch();
-        Ok(())
+
+        self.collection.transaction(|_| {
+            self.import_playlists(collection_entry.playlists)?;
+            self.import_artists(collection_entry.artists)
+        })
     }
 
     fn import_playlists(&self, playlists: Option<Vec<String>>) -> Result<()> {
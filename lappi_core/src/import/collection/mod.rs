@@ -90,9 +90,15 @@ struct AudioImporter {
 impl Importer for AudioImporter {
     fn import(&self, path: &Utf8Path, logger: &mut dyn ImportLogger) -> Result<()> {
         if let Some(metadata) = metadata::read_from_path(path)? {
-            if let Some(item_id) = utils::import_song(&self.collection, &metadata.tags)? {
-                let link = path.to_string();
-                self.collection.music_sources().add_music_link(item_id, MusicLinkType::ExternalFile, link)?;
+            // A song is added together with its link, or not at all
+            let item_id = self.collection.transaction(|collection| {
+                let item_id = utils::import_song(collection, &metadata.tags)?;
+                if let Some(item_id) = item_id {
+                    collection.music_sources().add_music_link(item_id, MusicLinkType::ExternalFile, path.to_string())?;
+                }
+                Ok(item_id)
+            })?;
+            if item_id.is_some() {
                 logger.log_song(&metadata.tags)?;
             }
         }
@@ -203,12 +209,16 @@ impl CollectionImporter {
     }
 
     pub fn import_basic(&self, tags: HashMap<String, String>, file_path: String) -> Result<()> {
-        let music_item_id = utils::import_song(&self.collection, &TagsMap::from_map(tags))?;
-        if let Some(music_item_id) = music_item_id {
-            let path = Utf8PathBuf::from_str(&file_path)?;
-            self.collection.music_sources().import_music_file(music_item_id, &path)?;
-        }
-        Ok(())
+        let path = Utf8PathBuf::from_str(&file_path)?;
+        // The file is copied before the transaction, which only moves the copy into place
+        let music_file = self.collection.music_sources().prepare_music_file(&path)?;
+        self.collection.transaction(|collection| {
+            let music_item_id = utils::import_song(collection, &TagsMap::from_map(tags))?;
+            if let Some(music_item_id) = music_item_id {
+                collection.music_sources().add_music_file(music_item_id, music_file)?;
+            }
+            Ok(())
+        })
     }
 
 }
@@ -2,6 +2,10 @@ use crate::storage::local::LocalStorage;
 use crate::storage::remote::RemoteStorage;
 use crate::playback::players::web_player::WebPlayerService;
 use crate::playback::Playback;
+use crate::playback::scheduler::PlaybackScheduler;
+use crate::mpd_server::MpdServer;
+use crate::subsonic::SubsonicServer;
+use crate::transcoding::Transcoder;
 use crate::database::Database;
 use crate::exploring::chat::ChatService;
 use crate::exploring::chat::templates::ChatTemplates;
@@ -21,11 +25,14 @@ pub mod exploring;
 pub mod file_manager;
 pub mod import;
 pub mod metadata;
+pub mod mpd_server;
+pub mod subsonic;
 pub mod playback;
 pub mod proto;
 pub mod jobs;
 pub mod scripting_engine;
 pub mod storage;
+pub mod transcoding;
 pub mod utils;
 pub mod workspace;
 
@@ -67,8 +74,12 @@ pub fn initilaize() {
 
     crate::collection::initialize();
 
+    context.init_service::<Transcoder>();
     context.init_service::<WebPlayerService>();
     context.init_service::<Playback>();
+    context.init_service::<PlaybackScheduler>();
+    context.init_service::<MpdServer>();
+    context.init_service::<SubsonicServer>();
     context.init_service::<CollectionImporter>();
     context.init_service::<PyServerClient>();
     context.init_service::<ChatService>();
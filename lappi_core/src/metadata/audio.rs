@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use camino::{Utf8Path, Utf8PathBuf};
+use once_cell::sync::Lazy;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+pub fn read_duration(path: &Utf8Path) -> Result<Option<f32>> {
+    let file = File::open(path)?;
+    let media_source = MediaSourceStream::new(Box::new(file), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension() {
+        hint.with_extension(extension);
+    }
+
+    let probed = symphonia::default::get_probe()
+        .format(&hint, media_source, &FormatOptions::default(), &MetadataOptions::default())?;
+
+    let duration = probed.format.default_track().and_then(|track| {
+        let params = &track.codec_params;
+        let time = params.time_base?.calc_time(params.n_frames?);
+        Some(time.seconds as f32 + time.frac as f32)
+    });
+
+    Ok(duration)
+}
+
+// Probing a file for its duration reads its headers, so listings reuse the result until the file changes
+static DURATIONS_CACHE: Lazy<Mutex<HashMap<Utf8PathBuf, (u64, SystemTime, Option<f32>)>>> = Lazy::new(|| {
+    Mutex::new(HashMap::new())
+});
+
+pub fn get_duration(path: &Utf8Path) -> Option<f32> {
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            log::debug!("Failed to read duration of {}: {}", path, err);
+            return None;
+        },
+    };
+    let size = metadata.len();
+    let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+
+    if let Some((cached_size, cached_modified, duration)) = DURATIONS_CACHE.lock().unwrap().get(path) {
+        if *cached_size == size && *cached_modified == modified {
+            return *duration;
+        }
+    }
+
+    let duration = read_duration(path).unwrap_or_else(|err| {
+        log::debug!("Failed to read duration of {}: {}", path, err);
+        None
+    });
+    DURATIONS_CACHE.lock().unwrap().insert(path.to_path_buf(), (size, modified, duration));
+    duration
+}
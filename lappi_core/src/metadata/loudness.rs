@@ -0,0 +1,107 @@
+use std::fs::File;
+
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use ebur128::{EbuR128, Mode};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+// ReplayGain 2.0 reference level
+pub static REFERENCE_LOUDNESS: f64 = -18.0;
+
+pub struct Loudness {
+    state: EbuR128,
+}
+
+impl Loudness {
+    pub fn analyze_file(path: &Utf8Path) -> Result<Self> {
+        let file = File::open(path)?;
+        let media_source = MediaSourceStream::new(Box::new(file), Default::default());
+
+        let mut hint = Hint::new();
+        if let Some(extension) = path.extension() {
+            hint.with_extension(extension);
+        }
+
+        let probed = symphonia::default::get_probe()
+            .format(&hint, media_source, &FormatOptions::default(), &MetadataOptions::default())?;
+        let mut format = probed.format;
+
+        let track = format.default_track().context("File has no audio track")?;
+        let track_id = track.id;
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&track.codec_params, &DecoderOptions::default())?;
+
+        let mut sample_buffer: Option<SampleBuffer<f32>> = None;
+        let mut state: Option<EbuR128> = None;
+
+        loop {
+            let packet = match format.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            };
+
+            if packet.track_id() != track_id {
+                continue;
+            }
+
+            let decoded = match decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(SymphoniaError::DecodeError(err)) => {
+                    log::warn!("Skip malformed packet in {}: {}", path, err);
+                    continue;
+                },
+                Err(err) => return Err(err.into()),
+            };
+
+            let spec = *decoded.spec();
+            if sample_buffer.is_none() {
+                sample_buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+                state = Some(EbuR128::new(spec.channels.count() as u32, spec.rate, Mode::I | Mode::SAMPLE_PEAK)?);
+            }
+
+            let sample_buffer = sample_buffer.as_mut().unwrap();
+            sample_buffer.copy_interleaved_ref(decoded);
+            state.as_mut().unwrap().add_frames_f32(sample_buffer.samples())?;
+        }
+
+        Ok(Self {
+            state: state.context("File has no audio data")?,
+        })
+    }
+
+    pub fn get_integrated_loudness(&self) -> Result<f64> {
+        Ok(self.state.loudness_global()?)
+    }
+
+    pub fn get_peak(&self) -> Result<f64> {
+        let mut peak = 0f64;
+        for channel in 0..self.state.channels() {
+            peak = peak.max(self.state.sample_peak(channel)?);
+        }
+        Ok(peak)
+    }
+
+    pub fn get_gain(&self) -> Result<f64> {
+        Ok(REFERENCE_LOUDNESS - self.get_integrated_loudness()?)
+    }
+
+    pub fn get_album_gain(tracks: &[Loudness]) -> Result<f64> {
+        let loudness = EbuR128::loudness_global_multiple(tracks.iter().map(|track| &track.state))?;
+        Ok(REFERENCE_LOUDNESS - loudness)
+    }
+
+    pub fn get_album_peak(tracks: &[Loudness]) -> Result<f64> {
+        let mut peak = 0f64;
+        for track in tracks {
+            peak = peak.max(track.get_peak()?);
+        }
+        Ok(peak)
+    }
+}
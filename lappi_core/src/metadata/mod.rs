@@ -1,4 +1,6 @@
 pub mod mp3;
+pub mod loudness;
+pub mod audio;
 
 use std::collections::HashMap;
 use std::io::Read;
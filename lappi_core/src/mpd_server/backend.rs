@@ -0,0 +1,141 @@
+use anyhow::Result;
+use amina_core::service::Service;
+
+use crate::collection::folders::{FolderDesc, FolderId, FoldersCollection};
+use crate::collection::music::{MusicCollection, MusicItemDesc, MusicItemId};
+use crate::collection::playlists::PlaylistsCollection;
+use crate::collection::playlists::types::PlaylistDesc;
+use crate::collection::tags::Tag;
+use crate::playback::{Playback, PlayerState};
+use crate::playback::sources::PlaybackSource;
+
+// Everything an MPD session needs from the playback and the collection
+pub trait MpdBackend: Send {
+    fn get_player_state(&self) -> PlayerState;
+    fn get_volume(&self) -> i32;
+    fn get_queue_sources(&self) -> Option<(Vec<Box<PlaybackSource>>, usize)>;
+    fn add_to_queue(&self, items: &[MusicItemId]) -> Result<()>;
+    fn clear_queue(&self);
+    fn play_queue_position(&self, position: usize);
+    fn resume(&self);
+    fn pause(&self);
+    fn toggle(&self);
+    fn play_next(&self);
+    fn play_previous(&self);
+    fn seek(&self, progress: i32);
+    fn set_volume(&self, volume: i32);
+
+    fn get_root_folder(&self) -> FolderId;
+    fn get_folders_chain(&self, folder_id: FolderId) -> Result<Vec<FolderDesc>>;
+    fn get_folders_in_folder(&self, folder_id: FolderId) -> Result<Vec<FolderDesc>>;
+    fn get_music_items_in_folder(&self, folder_id: FolderId) -> Result<Vec<MusicItemId>>;
+    fn get_all_music_items(&self) -> Result<Vec<MusicItemId>>;
+    fn get_item_description(&self, item_id: MusicItemId) -> Result<MusicItemDesc>;
+    fn get_tag(&self, item_id: MusicItemId, tag_name: &str) -> Result<Option<Tag>>;
+    fn get_playlists(&self) -> Result<Vec<PlaylistDesc>>;
+}
+
+pub struct ServicesBackend {
+    playback: Service<Playback>,
+    folders: Service<FoldersCollection>,
+    music: Service<MusicCollection>,
+    playlists: Service<PlaylistsCollection>,
+}
+
+impl ServicesBackend {
+    pub fn create() -> Self {
+        let context = crate::context();
+        Self {
+            playback: context.get_service::<Playback>(),
+            folders: context.get_service::<FoldersCollection>(),
+            music: context.get_service::<MusicCollection>(),
+            playlists: context.get_service::<PlaylistsCollection>(),
+        }
+    }
+}
+
+impl MpdBackend for ServicesBackend {
+    fn get_player_state(&self) -> PlayerState {
+        self.playback.get_player_state()
+    }
+
+    fn get_volume(&self) -> i32 {
+        self.playback.get_volume()
+    }
+
+    fn get_queue_sources(&self) -> Option<(Vec<Box<PlaybackSource>>, usize)> {
+        self.playback.get_queue_sources()
+    }
+
+    fn add_to_queue(&self, items: &[MusicItemId]) -> Result<()> {
+        self.playback.add_to_queue(items)
+    }
+
+    fn clear_queue(&self) {
+        self.playback.clear_queue();
+    }
+
+    fn play_queue_position(&self, position: usize) {
+        self.playback.play_queue_position(position);
+    }
+
+    fn resume(&self) {
+        self.playback.resume();
+    }
+
+    fn pause(&self) {
+        self.playback.pause();
+    }
+
+    fn toggle(&self) {
+        self.playback.toggle();
+    }
+
+    fn play_next(&self) {
+        self.playback.play_next();
+    }
+
+    fn play_previous(&self) {
+        self.playback.play_previous();
+    }
+
+    fn seek(&self, progress: i32) {
+        self.playback.seek(progress);
+    }
+
+    fn set_volume(&self, volume: i32) {
+        self.playback.set_volume(volume);
+    }
+
+    fn get_root_folder(&self) -> FolderId {
+        self.folders.get_root_folder()
+    }
+
+    fn get_folders_chain(&self, folder_id: FolderId) -> Result<Vec<FolderDesc>> {
+        self.folders.get_folders_chain(folder_id)
+    }
+
+    fn get_folders_in_folder(&self, folder_id: FolderId) -> Result<Vec<FolderDesc>> {
+        self.folders.get_folders_in_folder(folder_id)
+    }
+
+    fn get_music_items_in_folder(&self, folder_id: FolderId) -> Result<Vec<MusicItemId>> {
+        self.folders.get_music_items_in_folder(folder_id)
+    }
+
+    fn get_all_music_items(&self) -> Result<Vec<MusicItemId>> {
+        self.music.get_all_music_items()
+    }
+
+    fn get_item_description(&self, item_id: MusicItemId) -> Result<MusicItemDesc> {
+        self.music.get_item_description(item_id)
+    }
+
+    fn get_tag(&self, item_id: MusicItemId, tag_name: &str) -> Result<Option<Tag>> {
+        self.music.get_tag(item_id, tag_name)
+    }
+
+    fn get_playlists(&self) -> Result<Vec<PlaylistDesc>> {
+        self.playlists.get_playlists()
+    }
+}
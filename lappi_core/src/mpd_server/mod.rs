@@ -0,0 +1,77 @@
+mod backend;
+mod session;
+#[cfg(test)]
+mod tests;
+
+use std::io::ErrorKind;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use amina_core::service::{Context, ServiceApi, ServiceInitializer};
+use amina_core::tasks::{TaskContext, TaskManager};
+
+use crate::settings::Settings;
+
+static DEFAULT_ADDRESS: &str = "127.0.0.1:6601";
+
+pub struct MpdServer {
+
+}
+
+impl MpdServer {
+    fn run_task(task_context: &TaskContext, address: &str) -> Result<()> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        log::info!("MPD server is listening on {}", address);
+
+        while !task_context.is_interrupted() {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    log::debug!("MPD client connected from {}", peer);
+                    stream.set_nonblocking(false)?;
+                    thread::spawn(move || {
+                        if let Err(err) = session::MpdSession::new(stream, backend::ServicesBackend::create()).and_then(|mut session| session.run()) {
+                            log::debug!("MPD session with {} closed: {}", peer, err);
+                        }
+                    });
+                },
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(200));
+                },
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ServiceApi for MpdServer {
+
+}
+
+impl ServiceInitializer for MpdServer {
+    fn initialize(context: &Context) -> Arc<Self> {
+        let settings = context.get_service::<Settings>();
+        let task_manager = context.get_service::<TaskManager>();
+
+        let enabled = settings.get_string("mpd_server.enabled").get() == "true";
+        let address = settings.get_string("mpd_server.address").get();
+        let address = if address.is_empty() { DEFAULT_ADDRESS.to_string() } else { address };
+
+        if enabled {
+            task_manager.run(move |task_context| {
+                if let Err(err) = Self::run_task(&task_context, &address) {
+                    log::error!("MPD server failed: {}", err);
+                }
+            });
+        }
+
+        Arc::new(Self {
+
+        })
+    }
+}
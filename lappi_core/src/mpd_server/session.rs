@@ -0,0 +1,601 @@
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+
+use crate::collection::folders::FolderId;
+use crate::collection::music::MusicItemId;
+use crate::metadata::audio;
+use crate::playback::PlayerState;
+use crate::playback::sources::{PlaybackSource, SourceType};
+
+use super::backend::MpdBackend;
+
+static GREETING: &str = "OK MPD 0.21.0\n";
+static IDLE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+static ACK_ERROR_ARG: i32 = 2;
+static ACK_ERROR_UNKNOWN: i32 = 5;
+static ACK_ERROR_NO_EXIST: i32 = 50;
+
+static SUPPORTED_COMMANDS: &[&str] = &[
+    "add", "clear", "close", "command_list_begin", "command_list_end", "command_list_ok_begin", "commands",
+    "currentsong", "idle", "listplaylists", "lsinfo", "next", "noidle", "notcommands", "outputs",
+    "pause", "ping", "play", "playid", "playlistinfo", "plchanges", "previous", "search", "seek",
+    "seekcur", "seekid", "setvol", "stats", "status", "stop", "tagtypes",
+];
+
+struct AckError {
+    code: i32,
+    message: String,
+}
+
+impl AckError {
+    fn new(code: i32, message: &str) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<anyhow::Error> for AckError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::new(ACK_ERROR_NO_EXIST, &err.to_string())
+    }
+}
+
+type CommandResult = std::result::Result<(), AckError>;
+
+#[derive(PartialEq)]
+struct PlayerSnapshot {
+    state: &'static str,
+    title: String,
+}
+
+// Identifies a queue entry, a changed list of them is a new playlist version
+type QueueEntry = (Option<MusicItemId>, String);
+
+pub struct MpdSession<B: MpdBackend> {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    input: String,
+    backend: B,
+    playlist_version: u32,
+    last_queue: Vec<QueueEntry>,
+}
+
+impl<B: MpdBackend> MpdSession<B> {
+    pub fn new(stream: TcpStream, backend: B) -> Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            input: String::new(),
+            backend,
+            playlist_version: 1,
+            last_queue: vec![],
+        })
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        self.writer.write_all(GREETING.as_bytes())?;
+
+        let mut command_list: Option<(Vec<String>, bool)> = None;
+
+        while let Some(line) = self.read_line()? {
+            if let Some((commands, list_ok)) = command_list.as_mut() {
+                if line == "command_list_end" {
+                    let (commands, list_ok) = (std::mem::take(commands), *list_ok);
+                    command_list = None;
+                    self.run_command_list(&commands, list_ok)?;
+                } else {
+                    commands.push(line);
+                }
+                continue;
+            }
+
+            match line.as_str() {
+                "command_list_begin" => command_list = Some((vec![], false)),
+                "command_list_ok_begin" => command_list = Some((vec![], true)),
+                "close" => return Ok(()),
+                _ => self.run_command_list(&[line], false)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<Option<String>> {
+        loop {
+            match self.reader.read_line(&mut self.input) {
+                Ok(0) => return Ok(None),
+                Ok(_) => {
+                    if self.input.ends_with('\n') {
+                        let line = self.input.trim_end().to_string();
+                        self.input.clear();
+                        return Ok(Some(line));
+                    }
+                },
+                Err(err) if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => {
+                    return Ok(Some(String::new()));
+                },
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    fn run_command_list(&mut self, commands: &[String], list_ok: bool) -> Result<()> {
+        let mut response = String::new();
+
+        for (idx, line) in commands.iter().enumerate() {
+            let args = match Self::parse_args(line) {
+                Some(args) if !args.is_empty() => args,
+                _ => {
+                    response.push_str(&format!("ACK [{}@{}] {{}} malformed command\n", ACK_ERROR_ARG, idx));
+                    self.writer.write_all(response.as_bytes())?;
+                    return Ok(());
+                }
+            };
+
+            if args[0] == "idle" {
+                self.writer.write_all(response.as_bytes())?;
+                response.clear();
+                self.idle(&args[1..])?;
+                continue;
+            }
+
+            if let Err(err) = self.run_command(&args[0], &args[1..], &mut response) {
+                response.push_str(&format!("ACK [{}@{}] {{{}}} {}\n", err.code, idx, args[0], err.message));
+                self.writer.write_all(response.as_bytes())?;
+                return Ok(());
+            }
+
+            if list_ok {
+                response.push_str("list_OK\n");
+            }
+        }
+
+        response.push_str("OK\n");
+        self.writer.write_all(response.as_bytes())?;
+        Ok(())
+    }
+
+    fn parse_args(line: &str) -> Option<Vec<String>> {
+        let mut args = vec![];
+        let mut chars = line.chars().peekable();
+
+        loop {
+            while chars.peek() == Some(&' ') {
+                chars.next();
+            }
+            let first = match chars.peek() {
+                Some(first) => *first,
+                None => return Some(args),
+            };
+
+            let mut arg = String::new();
+            if first == '"' {
+                chars.next();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => arg.push(chars.next()?),
+                        ch => arg.push(ch),
+                    }
+                }
+            } else {
+                while let Some(ch) = chars.peek() {
+                    if *ch == ' ' {
+                        break;
+                    }
+                    arg.push(*ch);
+                    chars.next();
+                }
+            }
+            args.push(arg);
+        }
+    }
+
+    fn get_player_snapshot(&self) -> PlayerSnapshot {
+        let title = match self.backend.get_queue_sources() {
+            Some((sources, current_idx)) => sources.get(current_idx)
+                .map(|source| source.get_name().to_string())
+                .unwrap_or_default(),
+            None => String::new(),
+        };
+        PlayerSnapshot {
+            state: Self::get_state_name(self.backend.get_player_state()),
+            title,
+        }
+    }
+
+    // The queue has no version of its own, so every session counts the changes it sees
+    fn get_playlist_version(&mut self) -> u32 {
+        let queue: Vec<QueueEntry> = self.backend.get_queue_sources()
+            .map(|(sources, _)| sources.iter()
+                .map(|source| (source.get_music_item_id(), source.get_name().to_string()))
+                .collect())
+            .unwrap_or_default();
+        if queue != self.last_queue {
+            self.last_queue = queue;
+            self.playlist_version += 1;
+        }
+        self.playlist_version
+    }
+
+    // Without subsystems every change is reported
+    fn idle(&mut self, subsystems: &[String]) -> Result<()> {
+        let is_watched = |subsystem: &str| subsystems.is_empty() || subsystems.iter().any(|name| name == subsystem);
+        let initial_player = self.get_player_snapshot();
+        let initial_volume = self.backend.get_volume();
+        let initial_version = self.get_playlist_version();
+        self.writer.set_read_timeout(Some(IDLE_POLL_INTERVAL))?;
+
+        let result = loop {
+            let mut changes = String::new();
+            if is_watched("playlist") && self.get_playlist_version() != initial_version {
+                changes.push_str("changed: playlist\n");
+            }
+            if is_watched("player") && self.get_player_snapshot() != initial_player {
+                changes.push_str("changed: player\n");
+            }
+            if is_watched("mixer") && self.backend.get_volume() != initial_volume {
+                changes.push_str("changed: mixer\n");
+            }
+            if !changes.is_empty() {
+                changes.push_str("OK\n");
+                break changes;
+            }
+            match self.read_line()? {
+                None => bail!("Client disconnected while idle"),
+                Some(line) if line == "noidle" => break "OK\n".to_string(),
+                Some(line) if line.is_empty() => { },
+                Some(line) => {
+                    log::debug!("Unexpected command while idle: {}", line);
+                    break "OK\n".to_string();
+                },
+            }
+        };
+
+        self.writer.set_read_timeout(None)?;
+        self.writer.write_all(result.as_bytes())?;
+        Ok(())
+    }
+
+    fn run_command(&mut self, command: &str, args: &[String], response: &mut String) -> CommandResult {
+        match command {
+            "ping" => Ok(()),
+            "status" => self.status(response),
+            "currentsong" => self.current_song(response),
+            "add" => self.add(args.get(0).map(String::as_str).unwrap_or("")),
+            "clear" => {
+                self.backend.clear_queue();
+                Ok(())
+            },
+            "play" | "playid" => self.play(args),
+            "pause" => self.pause(args),
+            "stop" => {
+                self.backend.pause();
+                Ok(())
+            },
+            "next" => {
+                self.backend.play_next();
+                Ok(())
+            },
+            "previous" => {
+                self.backend.play_previous();
+                Ok(())
+            },
+            "seek" | "seekid" => self.seek(args.get(1)),
+            "seekcur" => self.seek(args.get(0)),
+            "setvol" => {
+                let volume = Self::parse_number::<i32>(args.get(0))?;
+                self.backend.set_volume(volume);
+                Ok(())
+            },
+            "playlistinfo" | "plchanges" => self.playlist_info(response),
+            "listplaylists" => self.list_playlists(response),
+            "lsinfo" => self.ls_info(args.get(0).map(String::as_str).unwrap_or(""), response),
+            "search" => self.search(args, response),
+            "outputs" => {
+                response.push_str("outputid: 0\noutputname: lappi\nplugin: lappi\noutputenabled: 1\n");
+                Ok(())
+            },
+            "stats" => {
+                let songs = self.backend.get_all_music_items()?.len();
+                response.push_str(&format!("songs: {}\n", songs));
+                Ok(())
+            },
+            "commands" => {
+                for command in SUPPORTED_COMMANDS {
+                    response.push_str(&format!("command: {}\n", command));
+                }
+                Ok(())
+            },
+            "notcommands" | "tagtypes" => Ok(()),
+            _ => Err(AckError::new(ACK_ERROR_UNKNOWN, &format!("unknown command \"{}\"", command))),
+        }
+    }
+
+    fn parse_number<T: std::str::FromStr>(arg: Option<&String>) -> std::result::Result<T, AckError> {
+        arg.and_then(|arg| arg.parse().ok())
+            .ok_or_else(|| AckError::new(ACK_ERROR_ARG, "invalid or missing argument"))
+    }
+
+    fn get_state_name(state: PlayerState) -> &'static str {
+        match state {
+            PlayerState::Playing(_) | PlayerState::PlaybackFinished => "play",
+            PlayerState::Paused(_) => "pause",
+            PlayerState::Stopped => "stop",
+        }
+    }
+
+    fn get_duration(&self, source: &PlaybackSource) -> Option<f32> {
+        let path = match source.get_source_type() {
+            SourceType::LocalFile(path) | SourceType::ExternalFile(path) => path,
+            SourceType::Url(_) => return None,
+        };
+        audio::get_duration(path)
+    }
+
+    fn get_current_source(&self) -> Option<(Box<PlaybackSource>, usize)> {
+        let (sources, current_idx) = self.backend.get_queue_sources()?;
+        let source = sources.into_iter().nth(current_idx)?;
+        Some((source, current_idx))
+    }
+
+    fn status(&mut self, response: &mut String) -> CommandResult {
+        let state = self.backend.get_player_state();
+        let queue_length = self.backend.get_queue_sources().map(|(sources, _)| sources.len()).unwrap_or(0);
+
+        response.push_str("repeat: 0\nrandom: 0\nsingle: 0\nconsume: 0\n");
+        let playlist_version = self.get_playlist_version();
+        response.push_str(&format!("volume: {}\n", self.backend.get_volume()));
+        response.push_str(&format!("playlist: {}\nplaylistlength: {}\n", playlist_version, queue_length));
+        response.push_str(&format!("state: {}\n", Self::get_state_name(state)));
+
+        if let Some((source, current_idx)) = self.get_current_source() {
+            response.push_str(&format!("song: {}\nsongid: {}\n", current_idx, current_idx));
+
+            let progress = match state {
+                PlayerState::Playing(progress) | PlayerState::Paused(progress) => progress,
+                _ => 0.,
+            };
+            if let Some(duration) = self.get_duration(&source) {
+                let elapsed = duration * progress;
+                response.push_str(&format!("time: {}:{}\n", elapsed as i32, duration as i32));
+                response.push_str(&format!("elapsed: {:.3}\nduration: {:.3}\n", elapsed, duration));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn current_song(&mut self, response: &mut String) -> CommandResult {
+        if let Some((source, current_idx)) = self.get_current_source() {
+            self.write_source_info(&source, current_idx, response)?;
+        }
+        Ok(())
+    }
+
+    fn play(&mut self, args: &[String]) -> CommandResult {
+        match args.get(0) {
+            Some(_) => {
+                let position = Self::parse_number::<usize>(args.get(0))?;
+                self.backend.play_queue_position(position);
+            },
+            None => self.backend.resume(),
+        }
+        Ok(())
+    }
+
+    fn collect_folder_items(&self, folder_id: FolderId, items: &mut Vec<MusicItemId>) -> Result<()> {
+        items.extend(self.backend.get_music_items_in_folder(folder_id)?);
+        for folder in self.backend.get_folders_in_folder(folder_id)? {
+            self.collect_folder_items(folder.folder_id, items)?;
+        }
+        Ok(())
+    }
+
+    fn find_item(&self, uri: &str) -> Result<Option<MusicItemId>> {
+        let (folder_uri, name) = uri.rsplit_once('/').unwrap_or(("", uri));
+        let folder_id = match self.find_folder(folder_uri)? {
+            Some(folder_id) => folder_id,
+            None => return Ok(None),
+        };
+        for music_item_id in self.backend.get_music_items_in_folder(folder_id)? {
+            if self.backend.get_item_description(music_item_id)?.name == name {
+                return Ok(Some(music_item_id));
+            }
+        }
+        Ok(None)
+    }
+
+    // Directories are added recursively like MPD does
+    fn add(&mut self, uri: &str) -> CommandResult {
+        let mut items = vec![];
+        if let Some(music_item_id) = self.find_item(uri)? {
+            items.push(music_item_id);
+        } else if let Some(folder_id) = self.find_folder(uri)? {
+            self.collect_folder_items(folder_id, &mut items)?;
+        } else {
+            return Err(AckError::new(ACK_ERROR_NO_EXIST, "No such directory"));
+        }
+        self.backend.add_to_queue(&items)?;
+        Ok(())
+    }
+
+    fn pause(&mut self, args: &[String]) -> CommandResult {
+        match args.get(0).map(String::as_str) {
+            Some("1") => self.backend.pause(),
+            Some("0") => self.backend.resume(),
+            _ => self.backend.toggle(),
+        }
+        Ok(())
+    }
+
+    fn seek(&mut self, time: Option<&String>) -> CommandResult {
+        let time = Self::parse_number::<f32>(time)?;
+        let (source, _) = self.get_current_source()
+            .ok_or_else(|| AckError::new(ACK_ERROR_NO_EXIST, "nothing is playing"))?;
+        let duration = self.get_duration(&source)
+            .ok_or_else(|| AckError::new(ACK_ERROR_NO_EXIST, "unknown duration"))?;
+        if duration > 0. {
+            self.backend.seek(((time / duration).clamp(0., 1.) * 1000.) as i32);
+        }
+        Ok(())
+    }
+
+    fn playlist_info(&mut self, response: &mut String) -> CommandResult {
+        if let Some((sources, _)) = self.backend.get_queue_sources() {
+            for (idx, source) in sources.iter().enumerate() {
+                self.write_source_info(source, idx, response)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn list_playlists(&mut self, response: &mut String) -> CommandResult {
+        for playlist in self.backend.get_playlists()? {
+            response.push_str(&format!("playlist: {}\n", playlist.name));
+        }
+        Ok(())
+    }
+
+    fn get_folder_uri(&self, folder_id: FolderId) -> Result<String> {
+        let root_folder = self.backend.get_root_folder();
+        let names: Vec<String> = self.backend.get_folders_chain(folder_id)?
+            .into_iter()
+            .filter(|folder| folder.folder_id != root_folder)
+            .map(|folder| folder.name)
+            .collect();
+        Ok(names.join("/"))
+    }
+
+    fn get_item_uri(&self, music_item_id: MusicItemId) -> Result<String> {
+        let description = self.backend.get_item_description(music_item_id)?;
+        let folder_uri = self.get_folder_uri(description.folder_id)?;
+        if folder_uri.is_empty() {
+            Ok(description.name)
+        } else {
+            Ok(format!("{}/{}", folder_uri, description.name))
+        }
+    }
+
+    fn find_folder(&self, uri: &str) -> Result<Option<FolderId>> {
+        let mut folder_id = self.backend.get_root_folder();
+        for name in uri.split('/').filter(|name| !name.is_empty()) {
+            let child = self.backend.get_folders_in_folder(folder_id)?
+                .into_iter()
+                .find(|folder| folder.name == name);
+            match child {
+                Some(child) => folder_id = child.folder_id,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(folder_id))
+    }
+
+    fn get_tag_string(&self, music_item_id: MusicItemId, tag_name: &str) -> Result<Option<String>> {
+        Ok(self.backend.get_tag(music_item_id, tag_name)?.map(|tag| tag.to_string()))
+    }
+
+    fn write_item_info(&self, music_item_id: MusicItemId, response: &mut String) -> Result<()> {
+        let description = self.backend.get_item_description(music_item_id)?;
+        response.push_str(&format!("file: {}\n", self.get_item_uri(music_item_id)?));
+        response.push_str(&format!("Title: {}\n", description.name));
+        if let Some(artist) = self.get_tag_string(music_item_id, "artist")? {
+            response.push_str(&format!("Artist: {}\n", artist));
+        }
+        if let Some(album) = self.get_tag_string(music_item_id, "album")? {
+            response.push_str(&format!("Album: {}\n", album));
+        }
+        Ok(())
+    }
+
+    fn write_source_info(&mut self, source: &PlaybackSource, idx: usize, response: &mut String) -> Result<()> {
+        match source.get_music_item_id() {
+            Some(music_item_id) => self.write_item_info(music_item_id, response)?,
+            None => {
+                response.push_str(&format!("file: {}\n", source.get_name()));
+                response.push_str(&format!("Title: {}\n", source.get_name()));
+            }
+        }
+        if let Some(duration) = self.get_duration(source) {
+            response.push_str(&format!("Time: {}\nduration: {:.3}\n", duration as i32, duration));
+        }
+        response.push_str(&format!("Pos: {}\nId: {}\n", idx, idx));
+        Ok(())
+    }
+
+    fn ls_info(&mut self, uri: &str, response: &mut String) -> CommandResult {
+        let folder_id = self.find_folder(uri)?
+            .ok_or_else(|| AckError::new(ACK_ERROR_NO_EXIST, "No such directory"))?;
+
+        for folder in self.backend.get_folders_in_folder(folder_id)? {
+            response.push_str(&format!("directory: {}\n", self.get_folder_uri(folder.folder_id)?));
+        }
+        for music_item_id in self.backend.get_music_items_in_folder(folder_id)? {
+            self.write_item_info(music_item_id, response)?;
+        }
+        if uri.is_empty() {
+            for playlist in self.backend.get_playlists()? {
+                response.push_str(&format!("playlist: {}\n", playlist.name));
+            }
+        }
+        Ok(())
+    }
+
+    // Supports legacy "TYPE WHAT" pairs and a single "(TYPE OP 'WHAT')" filter expression
+    fn parse_search_filters(args: &[String]) -> Option<Vec<(String, String)>> {
+        if args.len() == 1 && args[0].starts_with('(') {
+            let expression = args[0].trim_start_matches('(').trim_end_matches(')');
+            let mut parts = expression.splitn(3, ' ');
+            let tag = parts.next()?.to_lowercase();
+            let _operator = parts.next()?;
+            let value = parts.next()?.trim_matches(|ch| ch == '\'' || ch == '"').to_string();
+            return Some(vec![(tag, value)]);
+        }
+
+        if args.is_empty() || args.len() % 2 != 0 {
+            return None;
+        }
+        Some(args.chunks(2).map(|pair| (pair[0].to_lowercase(), pair[1].clone())).collect())
+    }
+
+    fn matches_filter(&self, music_item_id: MusicItemId, tag: &str, value: &str) -> Result<bool> {
+        let value = value.to_lowercase();
+        let contains = |text: Option<String>| text.map_or(false, |text| text.to_lowercase().contains(&value));
+
+        Ok(match tag {
+            "title" => contains(Some(self.backend.get_item_description(music_item_id)?.name)),
+            "file" => contains(Some(self.get_item_uri(music_item_id)?)),
+            "any" => {
+                contains(Some(self.get_item_uri(music_item_id)?))
+                    || contains(self.get_tag_string(music_item_id, "artist")?)
+                    || contains(self.get_tag_string(music_item_id, "album")?)
+            },
+            tag => contains(self.get_tag_string(music_item_id, tag)?),
+        })
+    }
+
+    fn search(&mut self, args: &[String], response: &mut String) -> CommandResult {
+        let filters = Self::parse_search_filters(args)
+            .ok_or_else(|| AckError::new(ACK_ERROR_ARG, "incorrect arguments"))?;
+
+        for music_item_id in self.backend.get_all_music_items()? {
+            let mut matches = true;
+            for (tag, value) in &filters {
+                if !self.matches_filter(music_item_id, tag, value)? {
+                    matches = false;
+                    break;
+                }
+            }
+            if matches {
+                self.write_item_info(music_item_id, response)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use camino::Utf8PathBuf;
+
+use crate::collection::folders::{FolderDesc, FolderId, FolderType};
+use crate::collection::music::{MusicItemDesc, MusicItemId};
+use crate::collection::playlists::types::PlaylistDesc;
+use crate::collection::tags::Tag;
+use crate::playback::PlayerState;
+use crate::playback::sources::PlaybackSource;
+
+use super::backend::MpdBackend;
+use super::session::MpdSession;
+
+static ARTIST_FOLDER: FolderId = 1;
+static ALBUM_FOLDER: FolderId = 2;
+
+struct FakePlayback {
+    state: PlayerState,
+    queue: Vec<MusicItemId>,
+    current_index: usize,
+    volume: i32,
+}
+
+// Root / "Artist" / "Album" / "Song 1", "Song 2"
+#[derive(Clone)]
+struct FakeBackend {
+    playback: Arc<Mutex<FakePlayback>>,
+}
+
+impl FakeBackend {
+    fn new() -> Self {
+        Self {
+            playback: Arc::new(Mutex::new(FakePlayback {
+                state: PlayerState::Stopped,
+                queue: vec![],
+                current_index: 0,
+                volume: 50,
+            })),
+        }
+    }
+
+    fn get_folder(folder_id: FolderId) -> FolderDesc {
+        let (name, folder_type) = if folder_id == ARTIST_FOLDER {
+            ("Artist", FolderType::Artist)
+        } else {
+            ("Album", FolderType::Album)
+        };
+        FolderDesc {
+            folder_id,
+            name: name.to_string(),
+            folder_type,
+            avatar_picture_id: None,
+        }
+    }
+
+    fn set_state(&self, state: PlayerState) {
+        let mut playback = self.playback.lock().unwrap();
+        if !playback.queue.is_empty() {
+            playback.state = state;
+        }
+    }
+}
+
+impl MpdBackend for FakeBackend {
+    fn get_player_state(&self) -> PlayerState {
+        self.playback.lock().unwrap().state
+    }
+
+    fn get_volume(&self) -> i32 {
+        self.playback.lock().unwrap().volume
+    }
+
+    fn get_queue_sources(&self) -> Option<(Vec<Box<PlaybackSource>>, usize)> {
+        let playback = self.playback.lock().unwrap();
+        if playback.queue.is_empty() {
+            return None;
+        }
+        let sources = playback.queue.iter()
+            .map(|item_id| {
+                let name = self.get_item_description(*item_id).unwrap().name;
+                PlaybackSource::local_file(name, Utf8PathBuf::from(format!("/missing/{}.mp3", item_id)))
+            })
+            .collect();
+        Some((sources, playback.current_index))
+    }
+
+    fn add_to_queue(&self, items: &[MusicItemId]) -> Result<()> {
+        self.playback.lock().unwrap().queue.extend_from_slice(items);
+        Ok(())
+    }
+
+    fn clear_queue(&self) {
+        let mut playback = self.playback.lock().unwrap();
+        playback.queue.clear();
+        playback.current_index = 0;
+        playback.state = PlayerState::Stopped;
+    }
+
+    fn play_queue_position(&self, position: usize) {
+        self.playback.lock().unwrap().current_index = position;
+        self.set_state(PlayerState::Playing(0.));
+    }
+
+    fn resume(&self) {
+        self.set_state(PlayerState::Playing(0.));
+    }
+
+    fn pause(&self) {
+        self.set_state(PlayerState::Paused(0.));
+    }
+
+    fn toggle(&self) {
+        match self.get_player_state() {
+            PlayerState::Playing(_) => self.pause(),
+            _ => self.resume(),
+        }
+    }
+
+    fn play_next(&self) { }
+
+    fn play_previous(&self) { }
+
+    fn seek(&self, _progress: i32) { }
+
+    fn set_volume(&self, volume: i32) {
+        self.playback.lock().unwrap().volume = volume;
+    }
+
+    fn get_root_folder(&self) -> FolderId {
+        0
+    }
+
+    fn get_folders_chain(&self, folder_id: FolderId) -> Result<Vec<FolderDesc>> {
+        let mut chain = vec![Self::get_folder(ARTIST_FOLDER)];
+        if folder_id == ALBUM_FOLDER {
+            chain.push(Self::get_folder(ALBUM_FOLDER));
+        }
+        Ok(chain)
+    }
+
+    fn get_folders_in_folder(&self, folder_id: FolderId) -> Result<Vec<FolderDesc>> {
+        Ok(match folder_id {
+            0 => vec![Self::get_folder(ARTIST_FOLDER)],
+            1 => vec![Self::get_folder(ALBUM_FOLDER)],
+            _ => vec![],
+        })
+    }
+
+    fn get_music_items_in_folder(&self, folder_id: FolderId) -> Result<Vec<MusicItemId>> {
+        Ok(if folder_id == ALBUM_FOLDER { vec![10, 11] } else { vec![] })
+    }
+
+    fn get_all_music_items(&self) -> Result<Vec<MusicItemId>> {
+        Ok(vec![10, 11])
+    }
+
+    fn get_item_description(&self, item_id: MusicItemId) -> Result<MusicItemDesc> {
+        Ok(MusicItemDesc {
+            item_id,
+            name: format!("Song {}", item_id - 9),
+            folder_id: ALBUM_FOLDER,
+        })
+    }
+
+    fn get_tag(&self, _item_id: MusicItemId, tag_name: &str) -> Result<Option<Tag>> {
+        Ok(match tag_name {
+            "artist" => Some(Tag::new_string("artist".to_string(), "Artist".to_string())),
+            "album" => Some(Tag::new_string("album".to_string(), "Album".to_string())),
+            _ => None,
+        })
+    }
+
+    fn get_playlists(&self) -> Result<Vec<PlaylistDesc>> {
+        Ok(vec![])
+    }
+}
+
+struct MpdTestClient {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl MpdTestClient {
+    fn connect(backend: FakeBackend) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            MpdSession::new(stream, backend).unwrap().run().unwrap();
+        });
+
+        let writer = TcpStream::connect(address).unwrap();
+        let mut reader = BufReader::new(writer.try_clone().unwrap());
+        let mut greeting = String::new();
+        reader.read_line(&mut greeting).unwrap();
+        assert!(greeting.starts_with("OK MPD "));
+
+        Self {
+            reader,
+            writer,
+        }
+    }
+
+    // Returns the response lines including the final OK or ACK
+    fn run(&mut self, command: &str) -> Vec<String> {
+        writeln!(self.writer, "{}", command).unwrap();
+        let mut lines = vec![];
+        loop {
+            let mut line = String::new();
+            assert!(self.reader.read_line(&mut line).unwrap() > 0, "Connection closed after {}", command);
+            let line = line.trim_end().to_string();
+            let is_last = line == "OK" || line.starts_with("ACK ");
+            lines.push(line);
+            if is_last {
+                return lines;
+            }
+        }
+    }
+}
+
+#[test]
+fn status_of_empty_queue() {
+    let mut client = MpdTestClient::connect(FakeBackend::new());
+
+    let status = client.run("status");
+    assert!(status.contains(&"state: stop".to_string()));
+    assert!(status.contains(&"playlistlength: 0".to_string()));
+    assert_eq!(status.last().unwrap(), "OK");
+}
+
+#[test]
+fn add_and_play() {
+    let backend = FakeBackend::new();
+    let mut client = MpdTestClient::connect(backend.clone());
+
+    assert_eq!(client.run("add \"Artist/Album/Song 2\""), vec!["OK"]);
+    assert_eq!(client.run("add Artist"), vec!["OK"]);
+    assert_eq!(backend.playback.lock().unwrap().queue, vec![11, 10, 11]);
+
+    assert_eq!(client.run("play 1"), vec!["OK"]);
+    let status = client.run("status");
+    assert!(status.contains(&"state: play".to_string()));
+    assert!(status.contains(&"playlistlength: 3".to_string()));
+    assert!(status.contains(&"song: 1".to_string()));
+
+    let current_song = client.run("currentsong");
+    assert!(current_song.contains(&"Title: Song 1".to_string()));
+    assert!(current_song.contains(&"Pos: 1".to_string()));
+
+    assert_eq!(client.run("pause 1"), vec!["OK"]);
+    assert!(client.run("status").contains(&"state: pause".to_string()));
+}
+
+#[test]
+fn command_list_with_clear_add_play() {
+    let backend = FakeBackend::new();
+    let mut client = MpdTestClient::connect(backend.clone());
+    backend.add_to_queue(&[10]).unwrap();
+
+    writeln!(client.writer, "command_list_ok_begin\nclear\nadd \"Artist/Album/Song 2\"\nplay").unwrap();
+    assert_eq!(client.run("command_list_end"), vec!["list_OK", "list_OK", "list_OK", "OK"]);
+    assert_eq!(backend.playback.lock().unwrap().queue, vec![11]);
+    assert_eq!(backend.get_player_state(), PlayerState::Playing(0.));
+}
+
+#[test]
+fn add_missing_uri() {
+    let mut client = MpdTestClient::connect(FakeBackend::new());
+
+    assert_eq!(client.run("add \"Artist/Missing\""), vec!["ACK [50@0] {add} No such directory"]);
+    assert_eq!(client.run("ping"), vec!["OK"]);
+}
+
+#[test]
+fn unknown_command() {
+    let mut client = MpdTestClient::connect(FakeBackend::new());
+
+    assert_eq!(client.run("rescan"), vec!["ACK [5@0] {rescan} unknown command \"rescan\""]);
+}
+
+#[test]
+fn playlist_version_follows_queue() {
+    let backend = FakeBackend::new();
+    let mut client = MpdTestClient::connect(backend.clone());
+
+    assert!(client.run("status").contains(&"playlist: 1".to_string()));
+    assert_eq!(client.run("add Artist"), vec!["OK"]);
+    assert!(client.run("status").contains(&"playlist: 2".to_string()));
+    assert!(client.run("status").contains(&"playlist: 2".to_string()));
+    assert_eq!(client.run("clear"), vec!["OK"]);
+    assert!(client.run("status").contains(&"playlist: 3".to_string()));
+}
+
+#[test]
+fn idle_reports_playlist_and_mixer_changes() {
+    let backend = FakeBackend::new();
+    let mut client = MpdTestClient::connect(backend.clone());
+
+    let changing_backend = backend.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(300));
+        changing_backend.add_to_queue(&[10]).unwrap();
+    });
+    assert_eq!(client.run("idle playlist"), vec!["changed: playlist", "OK"]);
+
+    let changing_backend = backend.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(300));
+        changing_backend.set_volume(80);
+    });
+    assert_eq!(client.run("idle mixer"), vec!["changed: mixer", "OK"]);
+    assert!(client.run("status").contains(&"volume: 80".to_string()));
+}
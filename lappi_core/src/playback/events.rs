@@ -2,9 +2,12 @@ use serde::{Serialize, Deserialize};
 use amina_core::events::Event;
 
 use crate::collection::pictures::PictureId;
+use crate::playback::zone::ZoneId;
 
 #[derive(Serialize, Deserialize)]
 pub struct OnStateUpdated<'a> {
+    pub zone_id: ZoneId,
+    pub zone_name: &'a str,
     pub current_player_name: &'a str,
     pub title: &'a str,
     pub cover_picture: Option<PictureId>,
@@ -12,6 +15,8 @@ pub struct OnStateUpdated<'a> {
     pub is_next_available: bool,
     pub is_previous_available: bool,
     pub progress: i32,
+    pub volume: i32,
+    pub is_muted: bool,
 }
 
 impl Event for OnStateUpdated<'_> {
@@ -23,6 +28,8 @@ impl Event for OnStateUpdated<'_> {
 impl Default for OnStateUpdated<'_> {
     fn default() -> Self {
         Self {
+            zone_id: 0,
+            zone_name: "",
             current_player_name: "",
             title: "",
             cover_picture: Option::None,
@@ -30,6 +37,8 @@ impl Default for OnStateUpdated<'_> {
             is_next_available: false,
             is_previous_available: false,
             progress: 0,
+            volume: 100,
+            is_muted: false,
         }
     }
 }
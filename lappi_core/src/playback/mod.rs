@@ -2,41 +2,41 @@ pub mod players;
 pub mod play_queue;
 pub mod sources;
 pub mod events;
+pub mod snapshot;
+pub mod replay_gain;
+pub mod scheduler;
+pub mod zone;
 
-use std::collections::HashMap;
-use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TryRecvError};
-use std::thread;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
-use std::sync::{Arc, Mutex, RwLock};
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use amina_core::events::EventEmitter;
 use amina_core::register_rpc_handler;
 use amina_core::rpc::Rpc;
-use amina_core::service::{Context, Service, ServiceApi, ServiceInitializer};
-use amina_core::tasks::{TaskContext, TaskManager};
+use amina_core::service::{Context, ServiceApi, ServiceInitializer};
+use amina_core::tasks::TaskManager;
 
+use crate::collection::folders::FolderId;
 use crate::collection::music::MusicItemId;
 use crate::collection::playlists::types::{PlaylistId, PlaylistItemId};
 use crate::collection::OnCollectionUpdated;
 use crate::platform_api::PlatformApi;
-use crate::playback::events::OnStateUpdated;
+use crate::settings::Settings;
 
 use sources::PlaybackSource;
-use play_queue::{PlayQueue, SingleSourceQueue};
-use play_queue::playlist_queue::PlaylistQueue;
+use snapshot::PlaybackSnapshot;
+use play_queue::PlayQueue;
+use zone::{PlaybackZone, PlayerFactories, ZoneDesc, ZoneId};
 use players::vlc_http::VlcHttpPlayerFactory;
+use players::mpd::MpdPlayerFactory;
 use players::web_player::WebPlayerFactory;
 
-#[derive(Debug, Clone)]
-enum PlayerCommand {
-    SwitchPlayer(String),
-    Play(Box<PlaybackSource>),
-    Pause,
-    Resume,
-    Seek(f32),
-}
+static DEFAULT_PLAYER_ID: &str = "web";
+static DEFAULT_ZONE_NAME: &str = "Default";
+pub const DEFAULT_ZONE_ID: ZoneId = 0;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PlayerState {
@@ -46,6 +46,29 @@ pub enum PlayerState {
     PlaybackFinished
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlayerEvent {
+    StateChanged(PlayerState),
+    SwitchedToNext,
+}
+
+#[derive(Clone)]
+pub struct PlayerEventSender {
+    callback: Arc<dyn Fn(PlayerEvent) + Send + Sync>,
+}
+
+impl PlayerEventSender {
+    pub fn new(callback: impl Fn(PlayerEvent) + Send + Sync + 'static) -> Self {
+        Self {
+            callback: Arc::new(callback),
+        }
+    }
+
+    pub fn send(&self, event: PlayerEvent) {
+        (self.callback)(event);
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PlayerDesc {
     pub id: String,
@@ -58,7 +81,24 @@ pub trait Player {
     fn resume(&self);
     fn pause(&self);
     fn seek(&self, progress: f32);
-    fn get_state(&self) -> PlayerState;
+    fn set_volume(&self, volume: f32);
+    fn get_volume(&self) -> f32;
+    fn mute(&self, muted: bool);
+    fn is_muted(&self) -> bool;
+    fn set_event_sender(&self, events: PlayerEventSender);
+
+    fn set_next(&self, _source: Option<Box<sources::PlaybackSource>>) {
+
+    }
+
+    // Players that can't push their state are polled with this interval
+    fn get_poll_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    fn poll(&self) {
+
+    }
 }
 
 pub trait PlayerFactory: Send + Sync {
@@ -67,18 +107,11 @@ pub trait PlayerFactory: Send + Sync {
 }
 
 pub struct Playback {
-    event_emitter: Service<EventEmitter>,
-    commands_sender: SyncSender<PlayerCommand>,
-    player_factories: RwLock<HashMap<String, Box<dyn PlayerFactory>>>,
-    player_state: Arc<RwLock<PlayerState>>,
-    current_queue: Mutex<Option<Box<dyn PlayQueue>>>
+    player_factories: PlayerFactories,
+    zones: RwLock<BTreeMap<ZoneId, Arc<PlaybackZone>>>,
 }
 
 impl Playback {
-    pub fn switch_player(&self, player_id: String) {
-        self.commands_sender.send(PlayerCommand::SwitchPlayer(player_id)).unwrap();
-    }
-
     pub fn get_players_list(&self) -> Vec<PlayerDesc> {
         let player_factories = self.player_factories.read().unwrap();
         player_factories.keys().map(|id| PlayerDesc {
@@ -87,238 +120,364 @@ impl Playback {
         }).collect()
     }
 
-    pub fn play_item(&self, item_id: MusicItemId) -> Result<()> {
-        if let Some(source) = PlaybackSource::default_from_music_item(item_id)? {
-            self.play_queue(Box::new(SingleSourceQueue::new(source)));
-        } else {
-            log::debug!("No source files for music item {}", item_id);
-        }
-        Ok(())
+    // Looks for network renderers in the background, found ones are added to the players list
+    pub fn discover_players(&self) {
+        let player_factories = self.player_factories.clone();
+        crate::context().get_service::<TaskManager>().run(move |_| {
+            match players::dlna::discover_player_factories() {
+                Ok(factories) => {
+                    let mut player_factories = player_factories.write().unwrap();
+                    for factory in factories {
+                        player_factories.insert(factory.get_player_id(), Box::new(factory));
+                    }
+                },
+                Err(err) => log::error!("DLNA discovery failed: {}", err),
+            }
+        });
     }
 
-    pub fn play_playlist(&self, playlist_id: PlaylistId, playlist_item: PlaylistItemId) -> Result<()> {
-        let play_queue = PlaylistQueue::create(playlist_id, playlist_item)?;
-        self.play_queue(Box::new(play_queue));
+    pub fn get_zones(&self) -> Vec<ZoneDesc> {
+        self.zones.read().unwrap().values().map(|zone| zone.get_desc()).collect()
+    }
+
+    pub fn get_zone(&self, zone_id: ZoneId) -> Result<Arc<PlaybackZone>> {
+        self.zones.read().unwrap()
+            .get(&zone_id)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Playback zone {} not found", zone_id))
+    }
+
+    fn get_default_zone(&self) -> Arc<PlaybackZone> {
+        self.get_zone(DEFAULT_ZONE_ID).unwrap()
+    }
+
+    // Players are backed by a single device or service, so two zones can't drive the same one
+    fn check_player_is_free(zones: &BTreeMap<ZoneId, Arc<PlaybackZone>>, zone_id: Option<ZoneId>, player_id: &str) -> Result<()> {
+        let owner = zones.values()
+            .map(|zone| zone.get_desc())
+            .find(|desc| Some(desc.id) != zone_id && desc.player_id == player_id);
+        if let Some(owner) = owner {
+            anyhow::bail!("Player {} is already used by zone '{}'", player_id, owner.name);
+        }
         Ok(())
     }
 
-    pub fn play_queue(&self, play_queue: Box<dyn PlayQueue>) {
-        let source = play_queue.get_current_source();
-        self.current_queue.lock().unwrap().replace(play_queue);
-        self.commands_sender.send(PlayerCommand::Play(source)).unwrap();
+    pub fn create_zone(&self, name: String, player_id: String) -> Result<ZoneId> {
+        if !self.player_factories.read().unwrap().contains_key(&player_id) {
+            anyhow::bail!("Unknown player {}", player_id);
+        }
+
+        let mut zones = self.zones.write().unwrap();
+        Self::check_player_is_free(&zones, None, &player_id)?;
+        let zone_id = zones.keys().max().map(|id| id + 1).unwrap_or(DEFAULT_ZONE_ID);
+        let desc = ZoneDesc { id: zone_id, name, player_id };
+        zones.insert(zone_id, PlaybackZone::create(desc, self.player_factories.clone()));
+        drop(zones);
+
+        self.save_zones()?;
+        Ok(zone_id)
     }
 
-    pub fn resume(&self) {
-        self.commands_sender.send(PlayerCommand::Resume).unwrap();
+    pub fn rename_zone(&self, zone_id: ZoneId, name: String) -> Result<()> {
+        self.get_zone(zone_id)?.rename(name);
+        self.save_zones()
     }
 
-    pub fn pause(&self) {
-        self.commands_sender.send(PlayerCommand::Pause).unwrap();
+    pub fn remove_zone(&self, zone_id: ZoneId) -> Result<()> {
+        if zone_id == DEFAULT_ZONE_ID {
+            anyhow::bail!("The default playback zone can't be removed");
+        }
+        let zone = self.zones.write().unwrap().remove(&zone_id)
+            .ok_or_else(|| anyhow::anyhow!("Playback zone {} not found", zone_id))?;
+        zone.close();
+        PlaybackSnapshot::clear(zone_id)?;
+        self.save_zones()
     }
 
-    pub fn toggle(&self) {
-        let state = *self.player_state.read().unwrap();
-        match state {
-            PlayerState::Playing(_) => {
-                self.pause();
-            },
-            PlayerState::Paused(_) => {
-                self.resume();
+    pub fn transfer_playback(&self, from_zone_id: ZoneId, to_zone_id: ZoneId) -> Result<()> {
+        if from_zone_id == to_zone_id {
+            return Ok(());
+        }
+        let from_zone = self.get_zone(from_zone_id)?;
+        let to_zone = self.get_zone(to_zone_id)?;
+        match from_zone.take_playback() {
+            Some((queue, progress)) => {
+                log::debug!("Transfer playback from zone {} to zone {} at {}", from_zone_id, to_zone_id, progress);
+                to_zone.accept_playback(queue, progress);
             },
-            PlayerState::Stopped => { },
-            PlayerState::PlaybackFinished => { },     
+            None => log::debug!("Nothing is playing in zone {}", from_zone_id),
         }
+        Ok(())
     }
 
-    pub fn seek(&self, progress: i32) {
-        self.commands_sender.send(PlayerCommand::Seek((progress as f32) / 1000.)).unwrap();
+    fn save_zones(&self) -> Result<()> {
+        let zones: Vec<ZoneDesc> = self.get_zones()
+            .into_iter()
+            .filter(|zone| zone.id != DEFAULT_ZONE_ID)
+            .collect();
+        ZoneDesc::save_all(&zones)
     }
 
-    pub fn play_next(&self) {
-        let mut play_queue = self.current_queue.lock().unwrap();
-        if let Some(play_queue) = play_queue.as_mut() {
-            if play_queue.has_next() {
-                play_queue.switch_to_next();
-                self.commands_sender.send(PlayerCommand::Play(play_queue.get_current_source())).unwrap();
-            }
-        }
+    pub fn zone_switch_player(&self, zone_id: ZoneId, player_id: String) -> Result<()> {
+        let zones = self.zones.read().unwrap();
+        let zone = zones.get(&zone_id)
+            .ok_or_else(|| anyhow::anyhow!("Playback zone {} not found", zone_id))?;
+        Self::check_player_is_free(&zones, Some(zone_id), &player_id)?;
+        zone.switch_player(player_id);
+        Ok(())
     }
 
-    pub fn play_previous(&self) {
-        let mut play_queue = self.current_queue.lock().unwrap();
-        if let Some(play_queue) = play_queue.as_mut() {
-            if play_queue.has_previous() {
-                play_queue.switch_to_previous();
-                self.commands_sender.send(PlayerCommand::Play(play_queue.get_current_source())).unwrap();
-            }
-        } 
+    pub fn zone_play_item(&self, zone_id: ZoneId, item_id: MusicItemId) -> Result<()> {
+        self.get_zone(zone_id)?.play_item(item_id)
     }
 
-    fn create_defaut_player(&self) -> Box<dyn Player> {
-        let player_factories = self.player_factories.read().unwrap();
-        let factory = player_factories.get("web").unwrap();
-        let player = factory.create_player().unwrap();
-        return player;
+    pub fn zone_play_playlist(&self, zone_id: ZoneId, playlist_id: PlaylistId, playlist_item: PlaylistItemId) -> Result<()> {
+        self.get_zone(zone_id)?.play_playlist(playlist_id, playlist_item)
     }
 
-    fn update_player_state(&self, player: &dyn Player) -> PlayerState {
-        let state = player.get_state();
-        let mut player_state = self.player_state.write().unwrap();
-        *player_state = state;
+    pub fn zone_play_folder(&self, zone_id: ZoneId, folder_id: FolderId, start_item: Option<MusicItemId>) -> Result<()> {
+        self.get_zone(zone_id)?.play_folder(folder_id, start_item)
+    }
 
-        let mut event = OnStateUpdated::default();
+    pub fn zone_toggle(&self, zone_id: ZoneId) -> Result<()> {
+        self.get_zone(zone_id)?.toggle();
+        Ok(())
+    }
 
-        event.current_player_name = player.get_name();
+    pub fn zone_resume(&self, zone_id: ZoneId) -> Result<()> {
+        self.get_zone(zone_id)?.resume();
+        Ok(())
+    }
 
-        let queue = self.current_queue.lock().unwrap();
-        if let Some(queue) = queue.as_ref() {
-            event.title = queue.get_current_title();
-            event.cover_picture = queue.get_current_cover();
-        } else {
-            event.title = "Playback stopped";
-        }
+    pub fn zone_pause(&self, zone_id: ZoneId) -> Result<()> {
+        self.get_zone(zone_id)?.pause();
+        Ok(())
+    }
 
-        match state {
-            PlayerState::Playing(position) => {
-                event.is_playing = true;
-                event.progress = (position * 1000.) as i32;
-            },
-            PlayerState::Paused(position) => {
-                event.is_playing = false;
-                event.progress = (position * 1000.) as i32;
-            },
-            PlayerState::Stopped => {
-                event.is_playing = false;
-                event.progress = 0;
+    pub fn zone_seek(&self, zone_id: ZoneId, progress: i32) -> Result<()> {
+        self.get_zone(zone_id)?.seek(progress);
+        Ok(())
+    }
+
+    pub fn zone_set_volume(&self, zone_id: ZoneId, volume: i32) -> Result<()> {
+        self.get_zone(zone_id)?.set_volume(volume);
+        Ok(())
+    }
+
+    pub fn zone_mute(&self, zone_id: ZoneId, muted: bool) -> Result<()> {
+        self.get_zone(zone_id)?.mute(muted);
+        Ok(())
+    }
+
+    pub fn zone_play_next(&self, zone_id: ZoneId) -> Result<()> {
+        self.get_zone(zone_id)?.play_next();
+        Ok(())
+    }
+
+    pub fn zone_play_previous(&self, zone_id: ZoneId) -> Result<()> {
+        self.get_zone(zone_id)?.play_previous();
+        Ok(())
+    }
+
+    pub fn switch_player(&self, player_id: String) -> Result<()> {
+        self.zone_switch_player(DEFAULT_ZONE_ID, player_id)
+    }
+
+    pub fn play_item(&self, item_id: MusicItemId) -> Result<()> {
+        self.get_default_zone().play_item(item_id)
+    }
+
+    pub fn play_playlist(&self, playlist_id: PlaylistId, playlist_item: PlaylistItemId) -> Result<()> {
+        self.get_default_zone().play_playlist(playlist_id, playlist_item)
+    }
+
+    pub fn play_folder(&self, folder_id: FolderId, start_item: Option<MusicItemId>) -> Result<()> {
+        self.get_default_zone().play_folder(folder_id, start_item)
+    }
+
+    pub fn play_queue(&self, play_queue: Box<dyn PlayQueue>) {
+        self.get_default_zone().play_queue(play_queue);
+    }
+
+    pub fn add_to_queue(&self, items: &[MusicItemId]) -> Result<()> {
+        let mut sources = vec![];
+        for item_id in items {
+            match PlaybackSource::default_from_music_item(*item_id)? {
+                Some(source) => sources.push(source),
+                None => log::debug!("No source files for music item {}", item_id),
             }
-            PlayerState::PlaybackFinished => {
-                event.is_playing = true;
-                event.progress = 0;
-            },
         }
-        self.event_emitter.emit_event(&event);
-
-        return state;
-    }
-
-    fn run_task(&self, task_context: &TaskContext, cmd_receiver: Receiver<PlayerCommand>) {
-        let mut player = self.create_defaut_player();
-
-        while !task_context.is_interrupted() {
-            match cmd_receiver.try_recv() {
-                Ok(cmd) => {
-                    match cmd {
-                        PlayerCommand::SwitchPlayer(player_id) => {
-                            let player_factories = self.player_factories.read().unwrap();
-                            let factory = player_factories.get(&player_id).unwrap();
-                            match factory.create_player() {
-                                Ok(new_player) => {
-                                    player.pause();
-                                    player = new_player;
-                                    log::debug!("Switched to player {}", player_id);
-                                },
-                                Err(err) => {
-                                    log::error!("Failed to create player {}: {}", player_id, err);
-                                }
-                            }
-                        },
-                        PlayerCommand::Play(source) => {
-                            log::debug!("Playing source {:?}", source);
-                            player.play(source);
-                        },
-                        PlayerCommand::Pause => {
-                            log::debug!("Pausing playback");
-                            player.pause();
-                        },
-                        PlayerCommand::Resume => {
-                            log::debug!("Resuming playback");
-                            player.resume();
-                        }
-                        PlayerCommand::Seek(progress) => {
-                            log::debug!("Seeking to {}", progress);
-                            player.seek(progress);
-                        }
-                    }
-                },
-                Err(TryRecvError::Empty) => { },
-                Err(TryRecvError::Disconnected) => {
-                    log::debug!("Player command channel disconnected");
-                    break;
-                }
-            };
+        self.get_default_zone().add_to_queue(sources);
+        Ok(())
+    }
 
-            let state = self.update_player_state(player.as_ref());
+    pub fn clear_queue(&self) {
+        self.get_default_zone().clear_queue();
+    }
 
-            if state == PlayerState::PlaybackFinished {
-                self.play_next();
-            }
+    pub fn resume(&self) {
+        self.get_default_zone().resume();
+    }
 
-            thread::sleep(Duration::from_millis(200));
-        }
+    pub fn pause(&self) {
+        self.get_default_zone().pause();
+    }
+
+    pub fn toggle(&self) {
+        self.get_default_zone().toggle();
+    }
+
+    pub fn seek(&self, progress: i32) {
+        self.get_default_zone().seek(progress);
+    }
+
+    pub fn set_volume(&self, volume: i32) {
+        self.get_default_zone().set_volume(volume);
+    }
+
+    pub fn mute(&self, muted: bool) {
+        self.get_default_zone().mute(muted);
+    }
+
+    pub fn get_player_state(&self) -> PlayerState {
+        self.get_default_zone().get_player_state()
+    }
+
+    pub fn get_volume(&self) -> i32 {
+        self.get_default_zone().get_volume()
+    }
+
+    pub fn get_queue_sources(&self) -> Option<(Vec<Box<PlaybackSource>>, usize)> {
+        self.get_default_zone().get_queue_sources()
+    }
+
+    pub fn play_queue_position(&self, position: usize) {
+        self.get_default_zone().play_queue_position(position);
+    }
+
+    pub fn play_next(&self) {
+        self.get_default_zone().play_next();
+    }
+
+    pub fn play_previous(&self) {
+        self.get_default_zone().play_previous();
     }
 
     fn on_collection_updated(&self, _event: &OnCollectionUpdated) {
-        if let Some(queue) = self.current_queue.lock().unwrap().as_mut() {
-            if let Err(err) = queue.refresh() {
-                log::error!("Failed to refresh queue: {}", err);
-            }
+        for zone in self.zones.read().unwrap().values() {
+            zone.on_collection_updated();
         }
     }
 }
 
 impl ServiceApi for Playback {
-    fn stop(&self) {
+    fn start(&self) {
+        for zone in self.zones.read().unwrap().values() {
+            if let Err(err) = zone.restore_snapshot() {
+                log::error!("Failed to restore playback state: {}", err);
+            }
+        }
+    }
 
+    fn stop(&self) {
+        for zone in self.zones.read().unwrap().values() {
+            if let Err(err) = zone.save_snapshot() {
+                log::error!("Failed to save playback state: {}", err);
+            }
+        }
+        if let Err(err) = self.save_zones() {
+            log::error!("Failed to save playback zones: {}", err);
+        }
     }
 }
 
 impl ServiceInitializer for Playback {
     fn initialize(context: &Context) -> Arc<Self> {
         let event_emitter = context.get_service::<EventEmitter>();
-        let task_manager = context.get_service::<TaskManager>();
         let rpc = context.get_service::<Rpc>();
         let platform_api = crate::context().get_service::<PlatformApi>();
 
+        let settings = context.get_service::<Settings>();
+        let _ = settings.get_string("playback.replay_gain.mode");
+        let _ = settings.get_string("playback.native.crossfade_ms");
+
         let mut player_factories: HashMap<String, Box<dyn PlayerFactory>> = HashMap::new();
         
         let platform_factories = platform_api.playback.get_platform_player_factories();
         player_factories.extend(platform_factories);
         
-        player_factories.insert("web".to_string(), Box::new(WebPlayerFactory::new()));
+        player_factories.insert(DEFAULT_PLAYER_ID.to_string(), Box::new(WebPlayerFactory::new()));
         player_factories.insert("vlc_http".to_string(), Box::new(VlcHttpPlayerFactory::new(context)));
-
-        let (commands_sender, cmd_reciver) = sync_channel(1);
-        let player_state = Arc::new(RwLock::new(PlayerState::Stopped));
+        player_factories.insert("mpd".to_string(), Box::new(MpdPlayerFactory::new(context)));
+
+        let player_factories: PlayerFactories = Arc::new(RwLock::new(player_factories));
+
+        let mut zone_descs = vec![ZoneDesc {
+            id: DEFAULT_ZONE_ID,
+            name: DEFAULT_ZONE_NAME.to_string(),
+            player_id: DEFAULT_PLAYER_ID.to_string(),
+        }];
+        match ZoneDesc::load_all() {
+            Ok(descs) => {
+                for desc in descs.into_iter().filter(|desc| desc.id != DEFAULT_ZONE_ID) {
+                    if zone_descs.iter().any(|zone_desc| zone_desc.player_id == desc.player_id) {
+                        log::error!("Zone '{}' is dropped, its player {} is used by another zone", desc.name, desc.player_id);
+                        continue;
+                    }
+                    zone_descs.push(desc);
+                }
+            },
+            Err(err) => log::error!("Failed to load playback zones: {}", err),
+        }
+        let zones = zone_descs.into_iter()
+            .map(|desc| (desc.id, PlaybackZone::create(desc, player_factories.clone())))
+            .collect();
 
         let playback = Arc::new(Self {
-            event_emitter: event_emitter.clone(),
-            player_factories: RwLock::new(player_factories),
-            player_state: player_state.clone(),
-            commands_sender,
-            current_queue: Mutex::new(None),
+            player_factories,
+            zones: RwLock::new(zones),
         });
 
         register_rpc_handler!(rpc, playback, "lappi.playback.switch_player", switch_player(player_id: String));
         register_rpc_handler!(rpc, playback, "lappi.playback.get_players_list", get_players_list());
+        register_rpc_handler!(rpc, playback, "lappi.playback.discover_players", discover_players());
         register_rpc_handler!(rpc, playback, "lappi.playback.play_item", play_item(item_id: MusicItemId));
         register_rpc_handler!(rpc, playback, "lappi.playback.play_playlist", play_playlist(playlist_id: PlaylistId, playlist_item: PlaylistItemId));
+        register_rpc_handler!(rpc, playback, "lappi.playback.play_folder", play_folder(folder_id: FolderId, start_item: Option<MusicItemId>));
         register_rpc_handler!(rpc, playback, "lappi.playback.toggle", toggle());
         register_rpc_handler!(rpc, playback, "lappi.playback.resume", resume());
         register_rpc_handler!(rpc, playback, "lappi.playback.pause", pause());
         register_rpc_handler!(rpc, playback, "lappi.playback.seek", seek(progress: i32));
+        register_rpc_handler!(rpc, playback, "lappi.playback.set_volume", set_volume(volume: i32));
+        register_rpc_handler!(rpc, playback, "lappi.playback.mute", mute(muted: bool));
         register_rpc_handler!(rpc, playback, "lappi.playback.play_next", play_next());
         register_rpc_handler!(rpc, playback, "lappi.playback.play_previous", play_previous());
 
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.get_zones", get_zones());
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.create_zone", create_zone(name: String, player_id: String));
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.rename_zone", rename_zone(zone_id: ZoneId, name: String));
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.remove_zone", remove_zone(zone_id: ZoneId));
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.transfer_playback", transfer_playback(from_zone_id: ZoneId, to_zone_id: ZoneId));
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.switch_player", zone_switch_player(zone_id: ZoneId, player_id: String));
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.play_item", zone_play_item(zone_id: ZoneId, item_id: MusicItemId));
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.play_playlist", zone_play_playlist(zone_id: ZoneId, playlist_id: PlaylistId, playlist_item: PlaylistItemId));
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.play_folder", zone_play_folder(zone_id: ZoneId, folder_id: FolderId, start_item: Option<MusicItemId>));
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.toggle", zone_toggle(zone_id: ZoneId));
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.resume", zone_resume(zone_id: ZoneId));
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.pause", zone_pause(zone_id: ZoneId));
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.seek", zone_seek(zone_id: ZoneId, progress: i32));
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.set_volume", zone_set_volume(zone_id: ZoneId, volume: i32));
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.mute", zone_mute(zone_id: ZoneId, muted: bool));
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.play_next", zone_play_next(zone_id: ZoneId));
+        register_rpc_handler!(rpc, playback, "lappi.playback.zones.play_previous", zone_play_previous(zone_id: ZoneId));
+
+        playback.discover_players();
+
         let playback_clone = playback.clone();
         event_emitter.on_event_fn(move |event: &OnCollectionUpdated| {
             playback_clone.on_collection_updated(event);
         });
 
-        let playback_clone = playback.clone();
-        task_manager.run(move |task_context| {
-            playback_clone.run_task(&task_context, cmd_reciver);
-        });
-
         return playback;
     }
 }
@@ -0,0 +1,177 @@
+use anyhow::Result;
+
+use crate::collection::folders::{FolderId, FolderType, FoldersCollection};
+use crate::collection::music::{MusicCollection, MusicItemId};
+use crate::collection::pictures::PictureId;
+use crate::collection::tags::{Tag, TagValue};
+use crate::playback::sources::PlaybackSource;
+use super::{PlayQueue, PlayQueueSnapshot};
+
+struct FolderQueueEntry {
+    music_item_id: MusicItemId,
+    playback_source: Box<PlaybackSource>,
+}
+
+struct SortedItem {
+    music_item_id: MusicItemId,
+    year: i32,
+    album_id: FolderId,
+    track: i32,
+}
+
+pub struct FolderQueue {
+    folder_id: FolderId,
+    current_item: Option<MusicItemId>,
+    current_idx: usize,
+    queue: Vec<FolderQueueEntry>,
+}
+
+impl FolderQueue {
+    pub fn create(folder_id: FolderId, start_item: Option<MusicItemId>) -> Result<Self> {
+        let mut folder_queue = Self {
+            folder_id,
+            current_item: start_item,
+            current_idx: 0,
+            queue: vec![],
+        };
+
+        folder_queue.refresh()?;
+
+        Ok(folder_queue)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    fn tag_to_number(tag: Option<Tag>) -> Option<i32> {
+        match tag?.get_value() {
+            TagValue::Number(value) => Some(*value),
+            TagValue::String(value) => value.parse().ok(),
+            TagValue::Bool => None,
+        }
+    }
+
+    fn collect_items(&self, folder_id: FolderId, items: &mut Vec<SortedItem>, recursive: bool) -> Result<()> {
+        let folders = crate::context().get_service::<FoldersCollection>();
+        let music = crate::context().get_service::<MusicCollection>();
+
+        let year = Self::tag_to_number(folders.get_tag(folder_id, "year", true)?).unwrap_or(i32::MAX);
+
+        for music_item_id in folders.get_music_items_in_folder(folder_id)? {
+            let track = Self::tag_to_number(music.get_tag(music_item_id, "track")?).unwrap_or(i32::MAX);
+            items.push(SortedItem {
+                music_item_id,
+                year,
+                album_id: folder_id,
+                track,
+            });
+        }
+
+        if recursive {
+            for folder_desc in folders.get_folders_in_folder(folder_id)? {
+                self.collect_items(folder_desc.folder_id, items, recursive)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get_sorted_items(&self) -> Result<Vec<MusicItemId>> {
+        let folders = crate::context().get_service::<FoldersCollection>();
+
+        let mut items = vec![];
+        match folders.get_folder_description(self.folder_id)?.folder_type {
+            FolderType::Album => {
+                self.collect_items(self.folder_id, &mut items, false)?;
+                items.sort_by_key(|item| item.track);
+            },
+            FolderType::Artist | FolderType::Folder => {
+                self.collect_items(self.folder_id, &mut items, true)?;
+                items.sort_by_key(|item| (item.year, item.album_id, item.track));
+            },
+        }
+
+        Ok(items.into_iter().map(|item| item.music_item_id).collect())
+    }
+}
+
+impl PlayQueue for FolderQueue {
+    fn get_current_source(&self) -> Option<Box<PlaybackSource>> {
+        self.queue.get(self.current_idx).map(|entry| entry.playback_source.clone())
+    }
+
+    fn get_current_title(&self) -> &str {
+        self.queue.get(self.current_idx).map_or("", |entry| entry.playback_source.get_name())
+    }
+
+    fn get_current_cover(&self) -> Option<PictureId> {
+        self.queue.get(self.current_idx).and_then(|entry| entry.playback_source.get_cover_picture())
+    }
+
+    fn get_next_source(&self) -> Option<Box<PlaybackSource>> {
+        self.queue.get(self.current_idx + 1).map(|entry| entry.playback_source.clone())
+    }
+
+    fn get_sources(&self) -> Vec<Box<PlaybackSource>> {
+        self.queue.iter().map(|entry| entry.playback_source.clone()).collect()
+    }
+
+    fn get_current_index(&self) -> usize {
+        self.current_idx
+    }
+
+    fn has_next(&self) -> bool {
+        self.current_idx + 1 < self.queue.len()
+    }
+
+    fn has_previous(&self) -> bool {
+        0 < self.current_idx
+    }
+
+    fn switch_to_next(&mut self) {
+        if self.has_next() {
+            self.current_idx += 1;
+            self.current_item = Some(self.queue[self.current_idx].music_item_id);
+        }
+    }
+
+    fn switch_to_previous(&mut self) {
+        if self.has_previous() {
+            self.current_idx -= 1;
+            self.current_item = Some(self.queue[self.current_idx].music_item_id);
+        }
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        let mut new_queue = vec![];
+        let mut current_idx = 0;
+
+        for music_item_id in self.get_sorted_items()? {
+            let playback_source = PlaybackSource::default_from_music_item(music_item_id)?;
+            if let Some(playback_source) = playback_source {
+                if Some(music_item_id) == self.current_item {
+                    current_idx = new_queue.len();
+                }
+
+                new_queue.push(FolderQueueEntry {
+                    music_item_id,
+                    playback_source,
+                });
+            }
+        }
+
+        self.queue = new_queue;
+        self.current_idx = current_idx;
+        self.current_item = self.queue.get(current_idx).map(|entry| entry.music_item_id);
+
+        Ok(())
+    }
+
+    fn get_snapshot(&self) -> Option<PlayQueueSnapshot> {
+        Some(PlayQueueSnapshot::Folder {
+            folder_id: self.folder_id,
+            music_item_id: self.current_item,
+        })
+    }
+}
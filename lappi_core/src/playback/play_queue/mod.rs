@@ -1,18 +1,87 @@
+pub mod folder_queue;
 pub mod playlist_queue;
 
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 
+use crate::collection::folders::FolderId;
+use crate::collection::music::MusicItemId;
+use crate::collection::playlists::types::{PlaylistId, PlaylistItemId};
 use crate::{collection::pictures::PictureId, playback::sources::PlaybackSource};
+use crate::playback::sources::PlaybackSourceSnapshot;
+
+use folder_queue::FolderQueue;
+use playlist_queue::PlaylistQueue;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum PlayQueueSnapshot {
+    SingleItem { music_item_id: MusicItemId },
+    Playlist { playlist_id: PlaylistId, playlist_item_id: PlaylistItemId },
+    Folder { folder_id: FolderId, music_item_id: Option<MusicItemId> },
+    SourceList { sources: Vec<PlaybackSourceSnapshot>, current_index: usize },
+}
+
+impl PlayQueueSnapshot {
+    pub fn restore(&self) -> Result<Option<Box<dyn PlayQueue>>> {
+        let play_queue: Box<dyn PlayQueue> = match self {
+            Self::SingleItem { music_item_id } => {
+                match PlaybackSource::default_from_music_item(*music_item_id)? {
+                    Some(source) => Box::new(SingleSourceQueue::new(source)),
+                    None => return Ok(None),
+                }
+            },
+            Self::Playlist { playlist_id, playlist_item_id } => {
+                let queue = PlaylistQueue::create(*playlist_id, *playlist_item_id)?;
+                if queue.is_empty() {
+                    return Ok(None);
+                }
+                Box::new(queue)
+            },
+            Self::Folder { folder_id, music_item_id } => {
+                let queue = FolderQueue::create(*folder_id, *music_item_id)?;
+                if queue.is_empty() {
+                    return Ok(None);
+                }
+                Box::new(queue)
+            },
+            Self::SourceList { sources, current_index } => {
+                let mut restored = Vec::new();
+                // Sources that can not be restored are dropped, the current one moves to the next restored source
+                let mut restored_index = None;
+                for (index, source) in sources.iter().enumerate() {
+                    if let Some(source) = source.restore()? {
+                        if index >= *current_index && restored_index.is_none() {
+                            restored_index = Some(restored.len());
+                        }
+                        restored.push(source);
+                    }
+                }
+                if restored.is_empty() {
+                    return Ok(None);
+                }
+                let current_index = restored_index.unwrap_or(restored.len() - 1);
+                Box::new(SourceListQueue::new(restored, current_index))
+            },
+        };
+        Ok(Some(play_queue))
+    }
+}
 
 pub trait PlayQueue: Send + Sync {
-    fn get_current_source(&self) -> Box<PlaybackSource>;
+    // None only when a refresh left the queue empty
+    fn get_current_source(&self) -> Option<Box<PlaybackSource>>;
     fn get_current_title(&self) -> &str;
     fn get_current_cover(&self) -> Option<PictureId>;
+    fn get_next_source(&self) -> Option<Box<PlaybackSource>>;
+    fn get_sources(&self) -> Vec<Box<PlaybackSource>>;
+    fn get_current_index(&self) -> usize;
     fn has_next(&self) -> bool;
     fn has_previous(&self) -> bool;
     fn switch_to_next(&mut self);
     fn switch_to_previous(&mut self);
     fn refresh(&mut self) -> Result<()>;
+    fn get_snapshot(&self) -> Option<PlayQueueSnapshot>;
 }
 
 pub struct SingleSourceQueue {
@@ -29,8 +98,8 @@ impl SingleSourceQueue {
 
 impl PlayQueue for SingleSourceQueue {
 
-    fn get_current_source(&self) -> Box<PlaybackSource> {
-        self.source.clone()
+    fn get_current_source(&self) -> Option<Box<PlaybackSource>> {
+        Some(self.source.clone())
     }
 
     fn get_current_title(&self) -> &str {
@@ -41,6 +110,18 @@ impl PlayQueue for SingleSourceQueue {
         return self.source.get_cover_picture();
     }
 
+    fn get_next_source(&self) -> Option<Box<PlaybackSource>> {
+        None
+    }
+
+    fn get_sources(&self) -> Vec<Box<PlaybackSource>> {
+        vec![self.source.clone()]
+    }
+
+    fn get_current_index(&self) -> usize {
+        0
+    }
+
     fn has_next(&self) -> bool {
         false
     }
@@ -60,4 +141,79 @@ impl PlayQueue for SingleSourceQueue {
     fn refresh(&mut self) -> Result<()> {
         Ok(())
     }
+
+    fn get_snapshot(&self) -> Option<PlayQueueSnapshot> {
+        self.source.get_music_item_id().map(|music_item_id| PlayQueueSnapshot::SingleItem {
+            music_item_id,
+        })
+    }
+}
+
+// A queue edited item by item, e.g. by MPD clients
+pub struct SourceListQueue {
+    sources: Vec<Box<PlaybackSource>>,
+    current_index: usize,
+}
+
+impl SourceListQueue {
+    pub fn new(sources: Vec<Box<PlaybackSource>>, current_index: usize) -> Self {
+        assert!(current_index < sources.len());
+        Self {
+            sources,
+            current_index,
+        }
+    }
+}
+
+impl PlayQueue for SourceListQueue {
+    fn get_current_source(&self) -> Option<Box<PlaybackSource>> {
+        self.sources.get(self.current_index).cloned()
+    }
+
+    fn get_current_title(&self) -> &str {
+        self.sources[self.current_index].get_name()
+    }
+
+    fn get_current_cover(&self) -> Option<PictureId> {
+        self.sources[self.current_index].get_cover_picture()
+    }
+
+    fn get_next_source(&self) -> Option<Box<PlaybackSource>> {
+        self.sources.get(self.current_index + 1).cloned()
+    }
+
+    fn get_sources(&self) -> Vec<Box<PlaybackSource>> {
+        self.sources.clone()
+    }
+
+    fn get_current_index(&self) -> usize {
+        self.current_index
+    }
+
+    fn has_next(&self) -> bool {
+        self.current_index + 1 < self.sources.len()
+    }
+
+    fn has_previous(&self) -> bool {
+        self.current_index > 0
+    }
+
+    fn switch_to_next(&mut self) {
+        self.current_index += 1;
+    }
+
+    fn switch_to_previous(&mut self) {
+        self.current_index -= 1;
+    }
+
+    fn refresh(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn get_snapshot(&self) -> Option<PlayQueueSnapshot> {
+        Some(PlayQueueSnapshot::SourceList {
+            sources: self.sources.iter().map(|source| source.get_snapshot()).collect(),
+            current_index: self.current_index,
+        })
+    }
 }
@@ -4,7 +4,7 @@ use crate::collection::pictures::PictureId;
 use crate::collection::playlists::types::{PlaylistId, PlaylistItemId};
 use crate::collection::playlists::PlaylistsCollection;
 use crate::playback::sources::PlaybackSource;
-use super::PlayQueue;
+use super::{PlayQueue, PlayQueueSnapshot};
 
 struct PlaylistQueueEntry {
     playlist_item_id: PlaylistItemId,
@@ -29,20 +29,36 @@ impl PlaylistQueue {
         playlist_queue.refresh()?;
 
         Ok(playlist_queue)
-    }    
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
 }
 
 impl PlayQueue for PlaylistQueue {
-    fn get_current_source(&self) -> Box<PlaybackSource> {
-        self.queue[self.current_idx].playback_source.clone()
+    fn get_current_source(&self) -> Option<Box<PlaybackSource>> {
+        self.queue.get(self.current_idx).map(|entry| entry.playback_source.clone())
     }
 
     fn get_current_title(&self) -> &str {
-        self.queue[self.current_idx].playback_source.get_name()
+        self.queue.get(self.current_idx).map_or("", |entry| entry.playback_source.get_name())
     }
 
     fn get_current_cover(&self) -> Option<PictureId> {
-        self.queue[self.current_idx].playback_source.get_cover_picture()
+        self.queue.get(self.current_idx).and_then(|entry| entry.playback_source.get_cover_picture())
+    }
+
+    fn get_next_source(&self) -> Option<Box<PlaybackSource>> {
+        self.queue.get(self.current_idx + 1).map(|entry| entry.playback_source.clone())
+    }
+
+    fn get_sources(&self) -> Vec<Box<PlaybackSource>> {
+        self.queue.iter().map(|entry| entry.playback_source.clone()).collect()
+    }
+
+    fn get_current_index(&self) -> usize {
+        self.current_idx
     }
 
     fn has_next(&self) -> bool {
@@ -92,5 +108,11 @@ impl PlayQueue for PlaylistQueue {
 
         Ok(())
     }
-}
 
+    fn get_snapshot(&self) -> Option<PlayQueueSnapshot> {
+        Some(PlayQueueSnapshot::Playlist {
+            playlist_id: self.playlist_id,
+            playlist_item_id: self.current_playlist_item,
+        })
+    }
+}
@@ -0,0 +1,65 @@
+use std::net::SocketAddr;
+
+use anyhow::{Context as _, Result};
+use url::Url;
+
+use crate::utils::xml;
+
+use super::soap::SoapClient;
+use super::ssdp::SsdpResponse;
+
+pub static AV_TRANSPORT_TYPE: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+pub static RENDERING_CONTROL_TYPE: &str = "urn:schemas-upnp-org:service:RenderingControl:1";
+
+#[derive(Debug, Clone)]
+pub struct RendererDevice {
+    pub udn: String,
+    pub name: String,
+    pub address: SocketAddr,
+    pub av_transport_url: String,
+    pub rendering_control_url: Option<String>,
+}
+
+impl RendererDevice {
+    pub fn fetch(client: &SoapClient, response: &SsdpResponse) -> Result<Self> {
+        let description = client.get(&response.location)
+            .with_context(|| format!("Can't fetch device description from {}", response.location))?;
+
+        let base_url = match xml::find_element(&description, "URLBase") {
+            Some(base_url) => Url::parse(base_url)?,
+            None => Url::parse(&response.location)?,
+        };
+
+        let mut av_transport_url = None;
+        let mut rendering_control_url = None;
+        for service in xml::find_elements(&description, "service") {
+            let service_type = xml::find_element(service, "serviceType").unwrap_or("");
+            let control_url = match xml::find_element(service, "controlURL") {
+                Some(control_url) => base_url.join(&xml::unescape(control_url))?.to_string(),
+                None => continue,
+            };
+            if service_type == AV_TRANSPORT_TYPE {
+                av_transport_url = Some(control_url);
+            } else if service_type == RENDERING_CONTROL_TYPE {
+                rendering_control_url = Some(control_url);
+            }
+        }
+
+        let udn = xml::find_element(&description, "UDN")
+            .map(|udn| udn.trim_start_matches("uuid:").to_string())
+            .unwrap_or_else(|| response.usn.clone());
+        let name = xml::find_element(&description, "friendlyName")
+            .map(xml::unescape)
+            .unwrap_or_else(|| udn.clone());
+        let av_transport_url = av_transport_url
+            .with_context(|| format!("Renderer {} has no AVTransport service", name))?;
+
+        Ok(Self {
+            udn,
+            name,
+            address: response.address,
+            av_transport_url,
+            rendering_control_url,
+        })
+    }
+}
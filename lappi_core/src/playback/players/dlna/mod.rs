@@ -0,0 +1,233 @@
+mod device;
+mod renderer;
+mod soap;
+mod ssdp;
+#[cfg(test)]
+mod tests;
+
+use std::cell::Cell;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use anyhow::Result;
+use amina_core::service::Service;
+
+use crate::playback::{Player, PlayerEventSender, PlayerFactory, PlayerState};
+use crate::playback::players::polling::PollingAdapter;
+use crate::playback::players::web_player::WebPlayerService;
+use crate::playback::sources::{PlaybackSource, SourceType};
+use crate::utils::{http_file, xml};
+
+use device::RendererDevice;
+use renderer::Renderer;
+use soap::SoapClient;
+
+pub static DLNA_PLAYER_ID_PREFIX: &str = "dlna_";
+static DLNA_POLL_INTERVAL: Duration = Duration::from_secs(1);
+static DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub struct DlnaPlayer {
+    renderer: Renderer,
+    web_player_service: Service<WebPlayerService>,
+    local_address: IpAddr,
+    current_duration: Cell<f32>,
+    is_playing: Cell<bool>,
+    // The renderer reports STOPPED until it loads a new track, so the end of playback is detected only after it started
+    is_started: Cell<bool>,
+    volume: Cell<f32>,
+    is_muted: Cell<bool>,
+    polling: PollingAdapter,
+}
+
+impl Player for DlnaPlayer {
+    fn get_name(&self) -> &str {
+        &self.renderer.get_device().name
+    }
+
+    fn play(&self, source: Box<PlaybackSource>) {
+        if let Err(err) = self.play_source(&source) {
+            log::error!("Failed to play {} on {}: {}", source.get_name(), self.get_name(), err);
+        }
+    }
+
+    fn resume(&self) {
+        let _ = self.renderer.play();
+        self.is_playing.set(true);
+    }
+
+    fn pause(&self) {
+        let _ = self.renderer.pause();
+        self.is_playing.set(false);
+    }
+
+    fn seek(&self, progress: f32) {
+        self.fetch_state();
+        let position = self.current_duration.get() * progress;
+        log::debug!("Seeking to {} - {}", progress, position);
+        let _ = self.renderer.seek(position);
+    }
+
+    fn set_volume(&self, volume: f32) {
+        self.volume.set(volume);
+        let _ = self.renderer.set_volume(volume);
+    }
+
+    fn get_volume(&self) -> f32 {
+        self.volume.get()
+    }
+
+    fn mute(&self, muted: bool) {
+        self.is_muted.set(muted);
+        let _ = self.renderer.set_mute(muted);
+    }
+
+    fn is_muted(&self) -> bool {
+        self.is_muted.get()
+    }
+
+    fn set_event_sender(&self, events: PlayerEventSender) {
+        self.polling.set_event_sender(events);
+    }
+
+    fn get_poll_interval(&self) -> Option<Duration> {
+        Some(DLNA_POLL_INTERVAL)
+    }
+
+    fn poll(&self) {
+        self.polling.update_state(self.fetch_state());
+    }
+}
+
+impl DlnaPlayer {
+    fn new(device: RendererDevice, web_player_service: Service<WebPlayerService>) -> Result<Self> {
+        let local_address = get_local_address(device.address)?;
+        Ok(Self {
+            renderer: Renderer::new(device)?,
+            web_player_service,
+            local_address,
+            current_duration: Cell::new(0.),
+            is_playing: Cell::new(false),
+            is_started: Cell::new(false),
+            volume: Cell::new(1.),
+            is_muted: Cell::new(false),
+            polling: PollingAdapter::new(),
+        })
+    }
+
+    fn play_source(&self, source: &PlaybackSource) -> Result<()> {
+        let (url, extension) = match source.get_source_type() {
+            SourceType::LocalFile(path) | SourceType::ExternalFile(path) => {
+                let url = self.web_player_service.get_stream_url(path, self.local_address)?;
+                (url, path.extension().unwrap_or("").to_string())
+            },
+            SourceType::Url(url) => (url.clone(), String::new()),
+        };
+        log::debug!("Playing {} on {}", url, self.get_name());
+
+        let metadata = get_didl_metadata(source.get_name(), &url, &extension);
+        self.is_started.set(false);
+        self.renderer.set_uri(&url, &metadata)?;
+        self.renderer.play()?;
+        self.is_playing.set(true);
+        Ok(())
+    }
+
+    fn fetch_state(&self) -> PlayerState {
+        let transport_state = match self.renderer.get_transport_state() {
+            Ok(transport_state) => transport_state,
+            Err(err) => {
+                log::debug!("Can't get state of {}: {}", self.get_name(), err);
+                return PlayerState::Stopped;
+            },
+        };
+
+        let progress = self.fetch_progress().unwrap_or(0.);
+        match transport_state.as_str() {
+            "PLAYING" | "TRANSITIONING" => {
+                self.is_started.set(true);
+                PlayerState::Playing(progress)
+            },
+            "PAUSED_PLAYBACK" => PlayerState::Paused(progress),
+            _ => {
+                if !self.is_playing.get() {
+                    PlayerState::Stopped
+                } else if self.is_started.get() {
+                    PlayerState::PlaybackFinished
+                } else {
+                    PlayerState::Playing(0.)
+                }
+            },
+        }
+    }
+
+    fn fetch_progress(&self) -> Result<f32> {
+        let (position, duration) = self.renderer.get_position()?;
+        self.current_duration.set(duration);
+        if duration > 0. {
+            Ok((position / duration).clamp(0., 1.))
+        } else {
+            Ok(0.)
+        }
+    }
+}
+
+pub struct DlnaPlayerFactory {
+    device: RendererDevice,
+    web_player_service: Service<WebPlayerService>,
+}
+
+impl PlayerFactory for DlnaPlayerFactory {
+    fn get_name(&self) -> String {
+        self.device.name.clone()
+    }
+
+    fn create_player(&self) -> Result<Box<dyn Player>> {
+        Ok(Box::new(DlnaPlayer::new(self.device.clone(), self.web_player_service.clone())?))
+    }
+}
+
+impl DlnaPlayerFactory {
+    pub fn get_player_id(&self) -> String {
+        format!("{}{}", DLNA_PLAYER_ID_PREFIX, self.device.udn)
+    }
+}
+
+pub fn discover_player_factories() -> Result<Vec<DlnaPlayerFactory>> {
+    let web_player_service = crate::context().get_service::<WebPlayerService>();
+    let client = SoapClient::new()?;
+
+    let mut factories = Vec::new();
+    for response in ssdp::search_renderers(DISCOVERY_TIMEOUT)? {
+        match RendererDevice::fetch(&client, &response) {
+            Ok(device) => {
+                log::debug!("Found DLNA renderer {} at {}", device.name, response.location);
+                factories.push(DlnaPlayerFactory {
+                    device,
+                    web_player_service: web_player_service.clone(),
+                });
+            },
+            Err(err) => log::debug!("Skip renderer at {}: {}", response.location, err),
+        }
+    }
+    Ok(factories)
+}
+
+// Finds the address of the interface that routes to the renderer, so it can reach the stream server
+fn get_local_address(remote: SocketAddr) -> Result<IpAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect(remote)?;
+    Ok(socket.local_addr()?.ip())
+}
+
+fn get_didl_metadata(title: &str, url: &str, extension: &str) -> String {
+    let content_type = if extension.is_empty() { "*" } else { http_file::get_content_type(extension) };
+    format!(
+        "<DIDL-Lite xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:upnp=\"urn:schemas-upnp-org:metadata-1-0/upnp/\">\
+         <item id=\"0\" parentID=\"-1\" restricted=\"1\">\
+         <dc:title>{}</dc:title>\
+         <upnp:class>object.item.audioItem.musicTrack</upnp:class>\
+         <res protocolInfo=\"http-get:*:{}:*\">{}</res>\
+         </item></DIDL-Lite>",
+        xml::escape(title), content_type, xml::escape(url),
+    )
+}
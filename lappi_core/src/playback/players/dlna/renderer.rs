@@ -0,0 +1,96 @@
+use anyhow::Result;
+
+use crate::utils::xml;
+
+use super::device::{RendererDevice, AV_TRANSPORT_TYPE, RENDERING_CONTROL_TYPE};
+use super::soap::SoapClient;
+
+static INSTANCE_ID: (&str, &str) = ("InstanceID", "0");
+
+// AVTransport and RenderingControl actions of a renderer, times are in seconds and volume is from 0 to 1
+pub struct Renderer {
+    device: RendererDevice,
+    client: SoapClient,
+}
+
+impl Renderer {
+    pub fn new(device: RendererDevice) -> Result<Self> {
+        Ok(Self {
+            device,
+            client: SoapClient::new()?,
+        })
+    }
+
+    pub fn get_device(&self) -> &RendererDevice {
+        &self.device
+    }
+
+    pub fn set_uri(&self, url: &str, metadata: &str) -> Result<()> {
+        self.call_transport("SetAVTransportURI", &[INSTANCE_ID, ("CurrentURI", url), ("CurrentURIMetaData", metadata)])?;
+        Ok(())
+    }
+
+    pub fn play(&self) -> Result<()> {
+        self.call_transport("Play", &[INSTANCE_ID, ("Speed", "1")])?;
+        Ok(())
+    }
+
+    pub fn pause(&self) -> Result<()> {
+        self.call_transport("Pause", &[INSTANCE_ID])?;
+        Ok(())
+    }
+
+    pub fn seek(&self, position: f32) -> Result<()> {
+        let target = format_time(position);
+        self.call_transport("Seek", &[INSTANCE_ID, ("Unit", "REL_TIME"), ("Target", &target)])?;
+        Ok(())
+    }
+
+    pub fn get_transport_state(&self) -> Result<String> {
+        let response = self.call_transport("GetTransportInfo", &[INSTANCE_ID])?;
+        Ok(xml::find_element(&response, "CurrentTransportState").unwrap_or("").to_string())
+    }
+
+    // Returns the position and the duration of the current track
+    pub fn get_position(&self) -> Result<(f32, f32)> {
+        let response = self.call_transport("GetPositionInfo", &[INSTANCE_ID])?;
+        let position = xml::find_element(&response, "RelTime").and_then(parse_time).unwrap_or(0.);
+        let duration = xml::find_element(&response, "TrackDuration").and_then(parse_time).unwrap_or(0.);
+        Ok((position, duration))
+    }
+
+    pub fn set_volume(&self, volume: f32) -> Result<()> {
+        let volume = ((volume * 100.).round() as i32).to_string();
+        self.call_rendering("SetVolume", &[INSTANCE_ID, ("Channel", "Master"), ("DesiredVolume", &volume)])?;
+        Ok(())
+    }
+
+    pub fn set_mute(&self, muted: bool) -> Result<()> {
+        let muted = if muted { "1" } else { "0" };
+        self.call_rendering("SetMute", &[INSTANCE_ID, ("Channel", "Master"), ("DesiredMute", muted)])?;
+        Ok(())
+    }
+
+    fn call_transport(&self, action: &str, args: &[(&str, &str)]) -> Result<String> {
+        self.client.call(&self.device.av_transport_url, AV_TRANSPORT_TYPE, action, args)
+    }
+
+    fn call_rendering(&self, action: &str, args: &[(&str, &str)]) -> Result<String> {
+        let control_url = self.device.rendering_control_url.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("{} has no RenderingControl service", self.device.name))?;
+        self.client.call(control_url, RENDERING_CONTROL_TYPE, action, args)
+    }
+}
+
+pub fn parse_time(time: &str) -> Option<f32> {
+    let mut seconds = 0.;
+    for part in time.split(':') {
+        seconds = seconds * 60. + part.parse::<f32>().ok()?;
+    }
+    Some(seconds)
+}
+
+pub fn format_time(seconds: f32) -> String {
+    let seconds = seconds.max(0.) as u32;
+    format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
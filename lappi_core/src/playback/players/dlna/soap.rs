@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use anyhow::{Context as _, Result};
+use reqwest::blocking::Client;
+
+use crate::utils::xml;
+
+static REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct SoapClient {
+    client: Client,
+}
+
+impl SoapClient {
+    pub fn new() -> Result<Self> {
+        let client = Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+        Ok(Self { client })
+    }
+
+    pub fn get(&self, url: &str) -> Result<String> {
+        let response = self.client.get(url).send()?.error_for_status()?;
+        Ok(response.text()?)
+    }
+
+    // Invokes a UPnP action and returns the raw response envelope
+    pub fn call(&self, control_url: &str, service_type: &str, action: &str, args: &[(&str, &str)]) -> Result<String> {
+        let mut arguments = String::new();
+        for (name, value) in args {
+            arguments.push_str(&format!("<{0}>{1}</{0}>", name, xml::escape(value)));
+        }
+        let body = format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+             <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+             <s:Body><u:{0} xmlns:u=\"{1}\">{2}</u:{0}></s:Body>\
+             </s:Envelope>",
+            action, service_type, arguments,
+        );
+
+        let response = self.client.post(control_url)
+            .header("Content-Type", "text/xml; charset=\"utf-8\"")
+            .header("SOAPAction", format!("\"{}#{}\"", service_type, action))
+            .body(body)
+            .send()
+            .with_context(|| format!("Can't send {} to {}", action, control_url))?;
+
+        let status = response.status();
+        let text = response.text()?;
+        if !status.is_success() {
+            let description = xml::find_element(&text, "errorDescription").unwrap_or(status.as_str());
+            anyhow::bail!("{} failed: {}", action, description);
+        }
+        Ok(text)
+    }
+}
@@ -0,0 +1,78 @@
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+static SSDP_ADDRESS: (Ipv4Addr, u16) = (Ipv4Addr::new(239, 255, 255, 250), 1900);
+static MEDIA_RENDERER_TYPE: &str = "urn:schemas-upnp-org:device:MediaRenderer:1";
+
+#[derive(Debug, Clone)]
+pub struct SsdpResponse {
+    pub location: String,
+    pub usn: String,
+    pub address: SocketAddr,
+}
+
+// Sends an M-SEARCH for media renderers and collects answers until the timeout expires
+pub fn search_renderers(timeout: Duration) -> Result<Vec<SsdpResponse>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\n\
+         HOST: 239.255.255.250:1900\r\n\
+         MAN: \"ssdp:discover\"\r\n\
+         MX: {}\r\n\
+         ST: {}\r\n\r\n",
+        timeout.as_secs().max(1),
+        MEDIA_RENDERER_TYPE,
+    );
+    socket.send_to(request.as_bytes(), SSDP_ADDRESS)?;
+
+    let mut responses: Vec<SsdpResponse> = Vec::new();
+    let mut buffer = [0u8; 2048];
+    let deadline = Instant::now() + timeout;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        socket.set_read_timeout(Some(deadline - now))?;
+        let (size, address) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(err) if matches!(err.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => break,
+            Err(err) => return Err(err.into()),
+        };
+
+        let response = String::from_utf8_lossy(&buffer[..size]);
+        if let Some(response) = parse_response(&response, address) {
+            if !responses.iter().any(|known| known.location == response.location) {
+                responses.push(response);
+            }
+        }
+    }
+    Ok(responses)
+}
+
+fn parse_response(response: &str, address: SocketAddr) -> Option<SsdpResponse> {
+    let mut lines = response.lines();
+    if !lines.next()?.contains("200") {
+        return None;
+    }
+
+    let mut location = None;
+    let mut usn = None;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            match name.trim().to_ascii_lowercase().as_str() {
+                "location" => location = Some(value.trim().to_string()),
+                "usn" => usn = Some(value.trim().to_string()),
+                _ => {},
+            }
+        }
+    }
+
+    Some(SsdpResponse {
+        location: location?,
+        usn: usn.unwrap_or_default(),
+        address,
+    })
+}
@@ -0,0 +1,174 @@
+use std::io::Read;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use tiny_http::{Response, Server};
+
+use super::device::RendererDevice;
+use super::renderer::{format_time, parse_time, Renderer};
+use super::soap::SoapClient;
+use super::ssdp::SsdpResponse;
+
+static DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:MediaRenderer:1</deviceType>
+    <friendlyName>Living &amp; Room</friendlyName>
+    <UDN>uuid:stub-renderer</UDN>
+    <serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:RenderingControl:1</serviceType>
+        <controlURL>/RenderingControl/control</controlURL>
+      </service>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:AVTransport:1</serviceType>
+        <controlURL>/AVTransport/control</controlURL>
+      </service>
+    </serviceList>
+  </device>
+</root>"#;
+
+// Answers like a MediaRenderer and records the SOAP actions it receives
+struct StubRenderer {
+    address: SocketAddr,
+    actions: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl StubRenderer {
+    fn start() -> Self {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap();
+        let actions = Arc::new(Mutex::new(vec![]));
+
+        let recorded_actions = actions.clone();
+        std::thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                if request.url() == "/description.xml" {
+                    let _ = request.respond(Response::from_string(DESCRIPTION));
+                    continue;
+                }
+
+                let action = request.headers().iter()
+                    .find(|header| header.field.equiv("SOAPAction"))
+                    .map(|header| header.value.to_string())
+                    .and_then(|value| value.trim_matches('"').rsplit('#').next().map(str::to_string))
+                    .unwrap_or_default();
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body).unwrap();
+                recorded_actions.lock().unwrap().push((action.clone(), body));
+
+                let response = match action.as_str() {
+                    "GetTransportInfo" => soap_response(&action, "<CurrentTransportState>PLAYING</CurrentTransportState>"),
+                    "GetPositionInfo" => soap_response(&action, "<TrackDuration>0:04:00</TrackDuration><RelTime>0:01:00</RelTime>"),
+                    "Seek" => Response::from_string(
+                        "<s:Envelope><s:Body><s:Fault><detail><UPnPError>\
+                         <errorCode>701</errorCode><errorDescription>Transition not available</errorDescription>\
+                         </UPnPError></detail></s:Fault></s:Body></s:Envelope>"
+                    ).with_status_code(500),
+                    _ => soap_response(&action, ""),
+                };
+                let _ = request.respond(response);
+            }
+        });
+
+        Self {
+            address,
+            actions,
+        }
+    }
+
+    fn get_ssdp_response(&self) -> SsdpResponse {
+        SsdpResponse {
+            location: format!("http://{}/description.xml", self.address),
+            usn: "uuid:stub-renderer::urn:schemas-upnp-org:device:MediaRenderer:1".to_string(),
+            address: self.address,
+        }
+    }
+
+    fn create_renderer(&self) -> Renderer {
+        let device = RendererDevice::fetch(&SoapClient::new().unwrap(), &self.get_ssdp_response()).unwrap();
+        Renderer::new(device).unwrap()
+    }
+
+    fn take_actions(&self) -> Vec<(String, String)> {
+        std::mem::take(&mut *self.actions.lock().unwrap())
+    }
+}
+
+fn soap_response(action: &str, content: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string(format!(
+        "<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body>\
+         <u:{0}Response xmlns:u=\"urn:schemas-upnp-org:service:AVTransport:1\">{1}</u:{0}Response>\
+         </s:Body></s:Envelope>",
+        action, content,
+    ))
+}
+
+#[test]
+fn fetches_device_description() {
+    let stub = StubRenderer::start();
+    let device = RendererDevice::fetch(&SoapClient::new().unwrap(), &stub.get_ssdp_response()).unwrap();
+
+    assert_eq!(device.udn, "stub-renderer");
+    assert_eq!(device.name, "Living & Room");
+    assert_eq!(device.av_transport_url, format!("http://{}/AVTransport/control", stub.address));
+    assert_eq!(device.rendering_control_url, Some(format!("http://{}/RenderingControl/control", stub.address)));
+}
+
+#[test]
+fn sets_uri_and_plays() {
+    let stub = StubRenderer::start();
+    let renderer = stub.create_renderer();
+
+    renderer.set_uri("http://host/stream/a?b&c", "<DIDL-Lite/>").unwrap();
+    renderer.play().unwrap();
+
+    let actions = stub.take_actions();
+    let names: Vec<&str> = actions.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["SetAVTransportURI", "Play"]);
+    assert!(actions[0].1.contains("<CurrentURI>http://host/stream/a?b&amp;c</CurrentURI>"));
+    assert!(actions[0].1.contains("<CurrentURIMetaData>&lt;DIDL-Lite/&gt;</CurrentURIMetaData>"));
+    assert!(actions[1].1.contains("<Speed>1</Speed>"));
+}
+
+#[test]
+fn reads_state_and_position() {
+    let stub = StubRenderer::start();
+    let renderer = stub.create_renderer();
+
+    assert_eq!(renderer.get_transport_state().unwrap(), "PLAYING");
+    assert_eq!(renderer.get_position().unwrap(), (60., 240.));
+}
+
+#[test]
+fn sets_volume_through_rendering_control() {
+    let stub = StubRenderer::start();
+    let renderer = stub.create_renderer();
+
+    renderer.set_volume(0.42).unwrap();
+    renderer.set_mute(true).unwrap();
+
+    let actions = stub.take_actions();
+    assert_eq!(actions[0].0, "SetVolume");
+    assert!(actions[0].1.contains("<DesiredVolume>42</DesiredVolume>"));
+    assert_eq!(actions[1].0, "SetMute");
+    assert!(actions[1].1.contains("<DesiredMute>1</DesiredMute>"));
+}
+
+#[test]
+fn reports_upnp_errors() {
+    let stub = StubRenderer::start();
+    let renderer = stub.create_renderer();
+
+    let err = renderer.seek(90.).unwrap_err();
+    assert_eq!(err.to_string(), "Seek failed: Transition not available");
+    assert!(stub.take_actions()[0].1.contains("<Target>0:01:30</Target>"));
+}
+
+#[test]
+fn converts_times() {
+    assert_eq!(parse_time("1:02:03"), Some(3723.));
+    assert_eq!(parse_time("0:00:01.500"), Some(1.5));
+    assert_eq!(parse_time("NOT_IMPLEMENTED"), None);
+    assert_eq!(format_time(3723.9), "1:02:03");
+}
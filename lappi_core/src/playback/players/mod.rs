@@ -1,2 +1,6 @@
+pub mod polling;
 pub mod web_player;
 pub mod vlc_http;
+pub mod mpd;
+pub mod dlna;
+pub mod unavailable;
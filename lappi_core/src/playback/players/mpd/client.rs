@@ -0,0 +1,357 @@
+use std::cell::RefCell;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use amina_core::service::Service;
+use amina_core::settings::Property;
+
+use crate::settings::Settings;
+
+static DEFAULT_HOST: &str = "localhost";
+static DEFAULT_PORT: &str = "6600";
+static IO_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+pub struct MpdStatus {
+    pub state: String,
+    pub elapsed: f32,
+    pub duration: f32,
+    pub volume: i32,
+}
+
+impl MpdStatus {
+    pub fn parse(pairs: Vec<(String, String)>) -> Self {
+        let mut status = Self::default();
+        for (key, value) in pairs {
+            match key.as_str() {
+                "state" => status.state = value,
+                "elapsed" => status.elapsed = value.parse().unwrap_or(0.),
+                "duration" => status.duration = value.parse().unwrap_or(0.),
+                "volume" => status.volume = value.parse().unwrap_or(0),
+                _ => { },
+            }
+        }
+        status
+    }
+}
+
+// An error reported by MPD itself, the connection stays usable and the command must not be repeated
+#[derive(Debug, thiserror::Error)]
+#[error("MPD error: {0}")]
+pub struct MpdAck(String);
+
+pub fn make_command_list(commands: &[String]) -> String {
+    let mut list = "command_list_begin\n".to_string();
+    for command in commands {
+        list.push_str(command);
+        list.push('\n');
+    }
+    list.push_str("command_list_end");
+    list
+}
+
+pub fn quote(arg: &str) -> String {
+    let escaped = arg.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", escaped)
+}
+
+struct MpdConnection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl MpdConnection {
+    fn connect(address: &str, password: &str) -> Result<Self> {
+        let stream = Self::connect_stream(address)?;
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+        let mut connection = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+
+        let mut greeting = String::new();
+        connection.reader.read_line(&mut greeting)?;
+        if !greeting.starts_with("OK MPD") {
+            bail!("Unexpected MPD greeting: {}", greeting.trim_end());
+        }
+
+        if !password.is_empty() {
+            connection.run(&format!("password {}", quote(password)))?;
+        }
+
+        Ok(connection)
+    }
+
+    // An unreachable host must not block the player for the system connect timeout
+    fn connect_stream(address: &str) -> Result<TcpStream> {
+        let mut last_error = None;
+        for socket_address in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&socket_address, IO_TIMEOUT) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_error = Some(err),
+            }
+        }
+        match last_error {
+            Some(err) => Err(err.into()),
+            None => bail!("No addresses found for {}", address),
+        }
+    }
+
+    fn run(&mut self, command: &str) -> Result<Vec<(String, String)>> {
+        write!(self.writer, "{}\n", command)?;
+        self.writer.flush()?;
+
+        let mut pairs = vec![];
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                bail!("MPD closed the connection");
+            }
+            let line = line.trim_end_matches('\n');
+            if line == "OK" {
+                return Ok(pairs);
+            }
+            if line.starts_with("ACK ") {
+                return Err(MpdAck(line.to_string()).into());
+            }
+            if let Some((key, value)) = line.split_once(": ") {
+                pairs.push((key.to_string(), value.to_string()));
+            }
+        }
+    }
+}
+
+// Keeps a connection open between commands
+#[derive(Default)]
+pub struct MpdSession {
+    connection: RefCell<Option<MpdConnection>>,
+}
+
+impl MpdSession {
+    pub fn run(&self, address: &str, password: &str, command: &str) -> Result<Vec<(String, String)>> {
+        let mut connection = self.connection.borrow_mut();
+
+        // MPD drops idle connections, so retry once on a fresh one. Errors reported by MPD are not
+        // retried, the command was executed and may be not idempotent.
+        if let Some(current) = connection.as_mut() {
+            match current.run(command) {
+                Ok(pairs) => return Ok(pairs),
+                Err(err) if err.is::<MpdAck>() => return Err(err),
+                Err(err) => {
+                    log::debug!("MPD command '{}' failed, reconnecting: {}", command, err);
+                    connection.take();
+                }
+            }
+        }
+
+        let mut new_connection = MpdConnection::connect(address, password)?;
+        let result = new_connection.run(command);
+        connection.replace(new_connection);
+        result
+    }
+}
+
+pub struct MpdClient {
+    host: Property<String>,
+    port: Property<String>,
+    password: Property<String>,
+    session: MpdSession,
+}
+
+impl MpdClient {
+    pub fn new(settings: Service<Settings>) -> Self {
+        Self {
+            host: settings.get_string("playback.mpd.host"),
+            port: settings.get_string("playback.mpd.port"),
+            password: settings.get_string("playback.mpd.password"),
+            session: MpdSession::default(),
+        }
+    }
+
+    fn get_address(&self) -> String {
+        let host = self.host.get();
+        let port = self.port.get();
+        format!("{}:{}",
+            if host.is_empty() { DEFAULT_HOST.to_string() } else { host },
+            if port.is_empty() { DEFAULT_PORT.to_string() } else { port })
+    }
+
+    pub fn run(&self, command: &str) -> Result<Vec<(String, String)>> {
+        self.session.run(&self.get_address(), &self.password.get(), command)
+    }
+
+    pub fn run_list(&self, commands: &[String]) -> Result<()> {
+        self.run(&make_command_list(commands))?;
+        Ok(())
+    }
+
+    pub fn play_uri(&self, uri: &str) -> Result<()> {
+        self.run_list(&[
+            "clear".to_string(),
+            format!("add {}", quote(uri)),
+            "play 0".to_string(),
+        ])
+    }
+
+    pub fn pause(&self, paused: bool) -> Result<()> {
+        self.run(if paused { "pause 1" } else { "pause 0" })?;
+        Ok(())
+    }
+
+    pub fn seek(&self, position: f32) -> Result<()> {
+        self.run(&format!("seekcur {:.3}", position))?;
+        Ok(())
+    }
+
+    pub fn set_volume(&self, volume: i32) -> Result<()> {
+        self.run(&format!("setvol {}", volume))?;
+        Ok(())
+    }
+
+    pub fn get_status(&self) -> Result<MpdStatus> {
+        Ok(MpdStatus::parse(self.run("status")?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    type Commands = Arc<Mutex<Vec<String>>>;
+
+    // Serves connections one after another. The handler gets a command or a command list joined
+    // with ';' and returns the response, or None to drop the connection without answering.
+    fn start_fake_mpd<F>(handler: F) -> (String, Commands)
+    where
+        F: Fn(&str) -> Option<String> + Send + 'static
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let commands = Commands::default();
+
+        let received = commands.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut writer = stream.unwrap();
+                let mut reader = BufReader::new(writer.try_clone().unwrap());
+                writer.write_all(b"OK MPD 0.23.5\n").unwrap();
+
+                let mut list: Option<Vec<String>> = None;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                    let command = line.trim_end().to_string();
+                    line.clear();
+                    let command = match (command.as_str(), list.as_mut()) {
+                        ("command_list_begin", _) => {
+                            list = Some(vec![]);
+                            continue;
+                        },
+                        ("command_list_end", _) => list.take().unwrap().join(";"),
+                        (_, Some(list)) => {
+                            list.push(command);
+                            continue;
+                        },
+                        (_, None) => command,
+                    };
+
+                    received.lock().unwrap().push(command.clone());
+                    match handler(&command) {
+                        Some(response) => writer.write_all(response.as_bytes()).unwrap(),
+                        None => break,
+                    }
+                }
+            }
+        });
+
+        (address, commands)
+    }
+
+    fn get_commands(commands: &Commands) -> Vec<String> {
+        commands.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn parses_status() {
+        let (address, _) = start_fake_mpd(|_| {
+            Some("state: play\nelapsed: 12.500\nduration: 200.000\nvolume: 40\nOK\n".to_string())
+        });
+
+        let session = MpdSession::default();
+        let status = MpdStatus::parse(session.run(&address, "", "status").unwrap());
+        assert_eq!(status.state, "play");
+        assert_eq!(status.elapsed, 12.5);
+        assert_eq!(status.duration, 200.);
+        assert_eq!(status.volume, 40);
+    }
+
+    #[test]
+    fn sends_command_lists() {
+        let (address, commands) = start_fake_mpd(|_| Some("OK\n".to_string()));
+
+        let session = MpdSession::default();
+        let list = make_command_list(&["clear".to_string(), format!("add {}", quote("a \"b\".mp3")), "play 0".to_string()]);
+        session.run(&address, "", &list).unwrap();
+        assert_eq!(get_commands(&commands), vec!["clear;add \"a \\\"b\\\".mp3\";play 0"]);
+    }
+
+    #[test]
+    fn reconnects_after_dropped_connection() {
+        let served = Arc::new(Mutex::new(0));
+        let counter = served.clone();
+        let (address, commands) = start_fake_mpd(move |_| {
+            let mut served = counter.lock().unwrap();
+            *served += 1;
+            // The first connection is dropped after answering one command
+            if *served == 2 {
+                None
+            } else {
+                Some("state: stop\nOK\n".to_string())
+            }
+        });
+
+        let session = MpdSession::default();
+        session.run(&address, "", "status").unwrap();
+        let status = MpdStatus::parse(session.run(&address, "", "status").unwrap());
+        assert_eq!(status.state, "stop");
+        assert_eq!(get_commands(&commands), vec!["status", "status", "status"]);
+    }
+
+    #[test]
+    fn does_not_repeat_failed_commands() {
+        let (address, commands) = start_fake_mpd(|command| {
+            if command.starts_with("clear") {
+                Some("ACK [50@2] {add} No such directory\n".to_string())
+            } else {
+                Some("OK\n".to_string())
+            }
+        });
+
+        let session = MpdSession::default();
+        session.run(&address, "", "status").unwrap();
+        let list = make_command_list(&["clear".to_string(), "add \"missing.mp3\"".to_string(), "play 0".to_string()]);
+        let err = session.run(&address, "", &list).unwrap_err();
+        assert!(err.is::<MpdAck>());
+        assert_eq!(get_commands(&commands), vec!["status", "clear;add \"missing.mp3\";play 0"]);
+
+        // The connection stays usable after an error reported by MPD
+        session.run(&address, "", "status").unwrap();
+        assert_eq!(get_commands(&commands).len(), 3);
+    }
+
+    #[test]
+    fn sends_password() {
+        let (address, commands) = start_fake_mpd(|_| Some("OK\n".to_string()));
+
+        let session = MpdSession::default();
+        session.run(&address, "secret", "pause 1").unwrap();
+        assert_eq!(get_commands(&commands), vec!["password \"secret\"", "pause 1"]);
+    }
+}
@@ -0,0 +1,178 @@
+use std::cell::Cell;
+use std::time::Duration;
+
+use anyhow::Result;
+use amina_core::service::{Context, Service};
+use amina_core::settings::Property;
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::playback::{Player, PlayerEventSender, PlayerFactory, PlayerState};
+use crate::playback::players::polling::PollingAdapter;
+use crate::playback::sources::{PlaybackSource, SourceType};
+use crate::settings::Settings;
+
+pub mod client;
+
+static MPD_PLAYER_NAME: &str = "MPD";
+static MPD_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct MpdPlayer {
+    client: client::MpdClient,
+    local_path_prefix: Property<String>,
+    music_dir_prefix: Property<String>,
+    current_duration: Cell<f32>,
+    is_playing: Cell<bool>,
+    volume: Cell<f32>,
+    is_muted: Cell<bool>,
+    polling: PollingAdapter,
+}
+
+impl Player for MpdPlayer {
+    fn get_name(&self) -> &str {
+        MPD_PLAYER_NAME
+    }
+
+    fn play(&self, source: Box<PlaybackSource>) {
+        let uri = match source.get_source_type() {
+            SourceType::LocalFile(path) | SourceType::ExternalFile(path) => self.to_mpd_uri(path),
+            SourceType::Url(url) => url.clone(),
+        };
+        log::debug!("Playing MPD uri {}", uri);
+        if let Err(err) = self.client.play_uri(&uri) {
+            log::error!("Failed to play {} on MPD: {}", uri, err);
+        }
+        self.is_playing.set(true);
+    }
+
+    fn resume(&self) {
+        let _ = self.client.pause(false);
+        self.is_playing.set(true);
+    }
+
+    fn pause(&self) {
+        let _ = self.client.pause(true);
+        self.is_playing.set(false);
+    }
+
+    fn seek(&self, progress: f32) {
+        self.fetch_state();
+        let position = self.current_duration.get() * progress;
+        log::debug!("Seeking to {} - {}", progress, position);
+        let _ = self.client.seek(position);
+    }
+
+    fn set_volume(&self, volume: f32) {
+        self.volume.set(volume);
+        self.apply_volume();
+    }
+
+    fn get_volume(&self) -> f32 {
+        self.volume.get()
+    }
+
+    fn mute(&self, muted: bool) {
+        self.is_muted.set(muted);
+        self.apply_volume();
+    }
+
+    fn is_muted(&self) -> bool {
+        self.is_muted.get()
+    }
+
+    fn set_event_sender(&self, events: PlayerEventSender) {
+        self.polling.set_event_sender(events);
+    }
+
+    fn get_poll_interval(&self) -> Option<Duration> {
+        Some(MPD_POLL_INTERVAL)
+    }
+
+    fn poll(&self) {
+        self.polling.update_state(self.fetch_state());
+    }
+}
+
+impl MpdPlayer {
+    pub fn new(settings: Service<Settings>) -> Self {
+        MpdPlayer {
+            client: client::MpdClient::new(settings.clone()),
+            local_path_prefix: settings.get_string("playback.mpd.local_path_prefix"),
+            music_dir_prefix: settings.get_string("playback.mpd.music_dir_prefix"),
+            current_duration: Cell::new(0.),
+            is_playing: Cell::new(false),
+            volume: Cell::new(1.),
+            is_muted: Cell::new(false),
+            polling: PollingAdapter::new(),
+        }
+    }
+
+    fn to_mpd_uri(&self, path: &Utf8Path) -> String {
+        let local_path_prefix = self.local_path_prefix.get();
+        if local_path_prefix.is_empty() {
+            return path.to_string();
+        }
+        match path.strip_prefix(&local_path_prefix) {
+            Ok(relative_path) => {
+                let mut uri = Utf8PathBuf::from(self.music_dir_prefix.get());
+                uri.push(relative_path);
+                uri.as_str().replace('\\', "/")
+            },
+            Err(_) => {
+                log::warn!("Path {} is outside of MPD local prefix {}", path, local_path_prefix);
+                path.to_string()
+            }
+        }
+    }
+
+    fn fetch_state(&self) -> PlayerState {
+        match self.client.get_status() {
+            Ok(status) => {
+                self.current_duration.set(status.duration);
+                let position = if status.duration > 0. {
+                    status.elapsed / status.duration
+                } else {
+                    0.
+                };
+                match status.state.as_str() {
+                    "play" => PlayerState::Playing(position),
+                    "pause" => PlayerState::Paused(position),
+                    _ => {
+                        if self.is_playing.get() {
+                            PlayerState::PlaybackFinished
+                        } else {
+                            PlayerState::Stopped
+                        }
+                    }
+                }
+            },
+            Err(_) => PlayerState::Stopped,
+        }
+    }
+
+    fn apply_volume(&self) {
+        let volume = if self.is_muted.get() { 0. } else { self.volume.get() };
+        let _ = self.client.set_volume((volume * 100.).round() as i32);
+    }
+}
+
+pub struct MpdPlayerFactory {
+    settings: Service<Settings>,
+}
+
+impl PlayerFactory for MpdPlayerFactory {
+    fn get_name(&self) -> String {
+        MPD_PLAYER_NAME.to_string()
+    }
+
+    fn create_player(&self) -> Result<Box<dyn Player>> {
+        Ok(Box::new(MpdPlayer::new(self.settings.clone())))
+    }
+}
+
+impl MpdPlayerFactory {
+    pub fn new(context: &Context) -> Self {
+        MpdPlayerFactory {
+            settings: context.get_service::<Settings>(),
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use std::cell::{Cell, RefCell};
+
+use crate::playback::{PlayerEvent, PlayerEventSender, PlayerState};
+
+// Turns polled states of a player into events, a state is sent only when it differs from the previous one
+pub struct PollingAdapter {
+    events: RefCell<Option<PlayerEventSender>>,
+    last_state: Cell<Option<PlayerState>>,
+}
+
+impl PollingAdapter {
+    pub fn new() -> Self {
+        Self {
+            events: RefCell::new(None),
+            last_state: Cell::new(None),
+        }
+    }
+
+    pub fn set_event_sender(&self, events: PlayerEventSender) {
+        self.events.replace(Some(events));
+        self.last_state.set(None);
+    }
+
+    pub fn update_state(&self, state: PlayerState) {
+        if self.last_state.replace(Some(state)) != Some(state) {
+            self.send(PlayerEvent::StateChanged(state));
+        }
+    }
+
+    pub fn notify_switched_to_next(&self) {
+        self.send(PlayerEvent::SwitchedToNext);
+    }
+
+    fn send(&self, event: PlayerEvent) {
+        if let Some(events) = self.events.borrow().as_ref() {
+            events.send(event);
+        }
+    }
+}
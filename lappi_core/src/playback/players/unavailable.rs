@@ -0,0 +1,48 @@
+use crate::playback::sources::PlaybackSource;
+use crate::playback::{Player, PlayerEvent, PlayerEventSender, PlayerState};
+
+// Holds the place of a player that can't be created yet, e.g. a renderer that is not discovered.
+// The zone replaces it with the real player as soon as its factory appears.
+pub struct UnavailablePlayer {
+    name: String,
+}
+
+impl UnavailablePlayer {
+    pub fn new(player_id: &str) -> Self {
+        Self {
+            name: format!("{} (unavailable)", player_id),
+        }
+    }
+}
+
+impl Player for UnavailablePlayer {
+    fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    fn play(&self, source: Box<PlaybackSource>) {
+        log::debug!("Can't play {}, {}", source.get_name(), self.name);
+    }
+
+    fn resume(&self) { }
+
+    fn pause(&self) { }
+
+    fn seek(&self, _progress: f32) { }
+
+    fn set_volume(&self, _volume: f32) { }
+
+    fn get_volume(&self) -> f32 {
+        1.
+    }
+
+    fn mute(&self, _muted: bool) { }
+
+    fn is_muted(&self) -> bool {
+        false
+    }
+
+    fn set_event_sender(&self, events: PlayerEventSender) {
+        events.send(PlayerEvent::StateChanged(PlayerState::Stopped));
+    }
+}
@@ -2,7 +2,7 @@ use camino::Utf8Path;
 use reqwest::blocking::Client;
 use reqwest::Result as ReqwestResult;
 use serde::{Deserialize, Serialize};
-use url::Url;
+use url::{form_urlencoded, Url};
 use amina_core::service::Service;
 use amina_core::settings::Property;
 
@@ -49,9 +49,14 @@ impl VlcHttpApi {
 
     pub fn play_file(&self, file_path: &Utf8Path) -> ReqwestResult<StatusResponse> {
         let url = Url::from_file_path(file_path).expect("Invalid file path");
+        self.play_url(url.as_str())
+    }
+
+    pub fn play_url(&self, url: &str) -> ReqwestResult<StatusResponse> {
+        let input: String = form_urlencoded::byte_serialize(url.as_bytes()).collect();
         self.send_command(&[
             ("command", "in_play"),
-            ("input", url.as_str()),
+            ("input", input.as_str()),
         ])
     }
 
@@ -67,6 +72,13 @@ impl VlcHttpApi {
         ])
     }
 
+    pub fn set_volume(&self, volume: i32) -> ReqwestResult<StatusResponse> {
+        self.send_command(&[
+            ("command", "volume"),
+            ("val", &format!("{}", volume))
+        ])
+    }
+
     pub fn seek(&self, progress: i32) -> ReqwestResult<StatusResponse> {
         self.send_command(&[
             ("command", "seek"),
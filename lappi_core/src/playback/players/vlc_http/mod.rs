@@ -1,20 +1,28 @@
 use std::cell::Cell;
+use std::time::Duration;
 
 use anyhow::Result;
 use amina_core::service::{Context, Service};
 
-use crate::playback::{Player, PlayerFactory, PlayerState};
+use crate::playback::{Player, PlayerEventSender, PlayerFactory, PlayerState};
+use crate::playback::players::polling::PollingAdapter;
 use crate::playback::sources::{PlaybackSource, SourceType};
 use crate::settings::Settings;
 
 pub mod http_api;
 
 static VLC_HTTP_PLAYER_NAME: &str = "VLC Remote";
+static VLC_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// VLC volume scale where 256 corresponds to 100%
+static VLC_FULL_VOLUME: f32 = 256.;
 
 pub struct VlcHttpPlayer {
     api: http_api::VlcHttpApi,
     current_length: Cell<i32>,
     is_playing: Cell<bool>,
+    volume: Cell<f32>,
+    is_muted: Cell<bool>,
+    polling: PollingAdapter,
 }
 
 impl Player for VlcHttpPlayer {
@@ -24,10 +32,14 @@ impl Player for VlcHttpPlayer {
 
     fn play(&self, source: Box<PlaybackSource>) {
         match source.get_source_type() {
-            SourceType::LocalFile(path) => {
+            SourceType::LocalFile(path) | SourceType::ExternalFile(path) => {
                 let _ = self.api.play_file(&path);
                 self.is_playing.set(true);
             },
+            SourceType::Url(url) => {
+                let _ = self.api.play_url(&url);
+                self.is_playing.set(true);
+            },
         }
     }
 
@@ -42,14 +54,58 @@ impl Player for VlcHttpPlayer {
     }
 
     fn seek(&self, progress: f32) {
-        self.get_state();
+        self.fetch_state();
         let length = self.current_length.get();
         let vlc_progress = (length as f32 * progress) as i32;
         log::debug!("Seeking to {} - {}/{}", progress, vlc_progress, length);
         let _ = self.api.seek(vlc_progress);
     }
 
-    fn get_state(&self) -> PlayerState {
+    fn set_volume(&self, volume: f32) {
+        self.volume.set(volume);
+        self.apply_volume();
+    }
+
+    fn get_volume(&self) -> f32 {
+        self.volume.get()
+    }
+
+    fn mute(&self, muted: bool) {
+        self.is_muted.set(muted);
+        self.apply_volume();
+    }
+
+    fn is_muted(&self) -> bool {
+        self.is_muted.get()
+    }
+
+    fn set_event_sender(&self, events: PlayerEventSender) {
+        self.polling.set_event_sender(events);
+    }
+
+    fn get_poll_interval(&self) -> Option<Duration> {
+        Some(VLC_POLL_INTERVAL)
+    }
+
+    fn poll(&self) {
+        self.polling.update_state(self.fetch_state());
+    }
+
+}
+
+impl VlcHttpPlayer {
+    pub fn new(settings: Service<Settings>) -> Self {
+        VlcHttpPlayer {
+            api: http_api::VlcHttpApi::new(settings),
+            current_length: Cell::new(0),
+            is_playing: Cell::new(false),
+            volume: Cell::new(1.),
+            is_muted: Cell::new(false),
+            polling: PollingAdapter::new(),
+        }
+    }
+
+    fn fetch_state(&self) -> PlayerState {
         let status = self.api.get_status();
         match status {
             Ok(status) => {
@@ -64,22 +120,24 @@ impl Player for VlcHttpPlayer {
                             PlayerState::Stopped
                         }
                     }
-                    _ => panic!("Unknown state: {}", status.state),
+                    state => {
+                        // Transitional states like opening or buffering are reported while the input is loading
+                        log::debug!("Unknown VLC state: {}", state);
+                        if self.is_playing.get() {
+                            PlayerState::Playing(status.position as f32)
+                        } else {
+                            PlayerState::Stopped
+                        }
+                    }
                 }
             },
             Err(_) => PlayerState::Stopped,
         }
     }
 
-}
-
-impl VlcHttpPlayer {
-    pub fn new(settings: Service<Settings>) -> Self {
-        VlcHttpPlayer {
-            api: http_api::VlcHttpApi::new(settings),
-            current_length: Cell::new(0),
-            is_playing: Cell::new(false),
-        }
+    fn apply_volume(&self) {
+        let volume = if self.is_muted.get() { 0. } else { self.volume.get() };
+        let _ = self.api.set_volume((volume * VLC_FULL_VOLUME).round() as i32);
     }
 }
 
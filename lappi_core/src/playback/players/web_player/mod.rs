@@ -1,6 +1,10 @@
+mod stream;
+
+use std::cell::Cell;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, RwLock};
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use camino::Utf8Path;
 use serde::{Deserialize, Serialize};
 
@@ -9,12 +13,18 @@ use amina_core::events::Event;
 use amina_core::register_rpc_handler;
 use amina_core::rpc::Rpc;
 use amina_core::service::{Service, ServiceApi, ServiceInitializer};
+use amina_core::tasks::TaskManager;
+use tiny_http::Server;
 
 use crate::playback::sources::{PlaybackSource, SourceType};
-use crate::playback::{Player, PlayerFactory, PlayerState};
+use crate::playback::{Player, PlayerEvent, PlayerEventSender, PlayerFactory, PlayerState};
+use crate::settings::Settings;
+use crate::transcoding::Transcoder;
+
+use stream::{StreamSource, StreamSources};
 
 static WEB_PLAYER_NAME: &str = "Browser";
-static FILE_HANDLER_KEY: &str = "lappi.palyback.web";
+static DEFAULT_STREAM_ADDRESS: &str = "0.0.0.0:4041";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum WebPlayerState {
@@ -27,10 +37,12 @@ pub enum WebPlayerState {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum WebPlayerCommand {
-    Play { file_name: String },
+    Play { stream_path: String, stream_port: u16, gain: f32 },
+    PlayUrl { url: String, gain: f32 },
     Pause,
     Resume,
     Seek { progress: f32 },
+    SetVolume { volume: f32 },
     Stop,
 }
 
@@ -43,6 +55,9 @@ pub struct OnWebPlayerCommand {
 
 pub struct WebPlayer {
     web_player_service: Service<WebPlayerService>,
+    transcoding_profile: Option<String>,
+    volume: Cell<f32>,
+    is_muted: Cell<bool>,
 }
 
 impl Player for WebPlayer {
@@ -51,10 +66,8 @@ impl Player for WebPlayer {
     }
 
     fn play(&self, source: Box<PlaybackSource>) {
-        match source.get_source_type() {
-            SourceType::LocalFile(path) => {
-                self.web_player_service.play_file(&path).unwrap();
-            },
+        if let Err(err) = self.play_source(&source) {
+            log::error!("Failed to play {} on {}: {}", source.get_name(), self.get_name(), err);
         }
     }
 
@@ -70,8 +83,26 @@ impl Player for WebPlayer {
         self.web_player_service.seek(progress);
     }
 
-    fn get_state(&self) -> PlayerState {
-        return self.web_player_service.get_player_state();
+    fn set_event_sender(&self, events: PlayerEventSender) {
+        self.web_player_service.set_event_sender(events);
+    }
+
+    fn set_volume(&self, volume: f32) {
+        self.volume.set(volume);
+        self.apply_volume();
+    }
+
+    fn get_volume(&self) -> f32 {
+        self.volume.get()
+    }
+
+    fn mute(&self, muted: bool) {
+        self.is_muted.set(muted);
+        self.apply_volume();
+    }
+
+    fn is_muted(&self) -> bool {
+        self.is_muted.get()
     }
 }
 
@@ -83,11 +114,34 @@ impl Drop for WebPlayer {
 
 impl WebPlayer {
     pub fn new() -> Self {
-        let web_player_service: Service<WebPlayerService> = crate::context().get_service();
+        let context = crate::context();
+        let web_player_service: Service<WebPlayerService> = context.get_service();
+        let transcoding_profile = context.get_service::<Settings>().get_string("playback.web.transcoding_profile").get();
         WebPlayer {
             web_player_service,
+            transcoding_profile: Some(transcoding_profile).filter(|profile| !profile.is_empty()),
+            volume: Cell::new(1.),
+            is_muted: Cell::new(false),
         }
     }
+
+    fn play_source(&self, source: &PlaybackSource) -> Result<()> {
+        match source.get_source_type() {
+            SourceType::LocalFile(path) | SourceType::ExternalFile(path) => {
+                let transcoding_profile = self.transcoding_profile.as_deref();
+                self.web_player_service.play_file(path, transcoding_profile, source.get_replay_gain())?;
+            },
+            SourceType::Url(url) => {
+                self.web_player_service.play_url(&url, source.get_replay_gain());
+            },
+        }
+        Ok(())
+    }
+
+    fn apply_volume(&self) {
+        let volume = if self.is_muted.get() { 0. } else { self.volume.get() };
+        self.web_player_service.set_volume(volume);
+    }
 }
 
 pub struct WebPlayerFactory {
@@ -113,37 +167,61 @@ impl WebPlayerFactory {
 }
 
 pub struct WebPlayerContext {
-    current_file_path: String,
     player_state: PlayerState,
+    events: Option<PlayerEventSender>,
 }
 
 pub struct WebPlayerService {
     event_emitter: Service<EventEmitter>,
+    stream_sources: Arc<StreamSources>,
+    stream_port: Option<u16>,
     state: RwLock<WebPlayerContext>,
 }
 
 impl WebPlayerService {
-    pub fn play_file(&self, path: &Utf8Path) -> Result<()> {
-        let file_name = path.file_name()
-                .with_context(|| format!("Path '{:?}' has no file name", &path))?
-                .to_string();
+    pub fn play_file(&self, path: &Utf8Path, transcoding_profile: Option<&str>, gain: f32) -> Result<()> {
+        let stream_port = self.stream_port
+            .ok_or_else(|| anyhow::anyhow!("Web player stream server is not running"))?;
+        let token = self.stream_sources.register(StreamSource {
+            path: path.to_path_buf(),
+            transcoding_profile: transcoding_profile.map(str::to_string),
+        });
 
         let event = OnWebPlayerCommand {
             command: WebPlayerCommand::Play {
-                file_name: file_name.to_string(),
+                stream_path: stream::get_stream_path(&token),
+                stream_port,
+                gain,
             },
         };
 
-        let mut state = self.state.write().unwrap();
-        state.current_file_path = path.to_string();
-        state.player_state = PlayerState::Playing(0.);
-        drop(state);
-
+        self.set_player_state(PlayerState::Playing(0.));
         self.event_emitter.emit_event(&event);
 
         return Ok(());
     }
 
+    // Gives an absolute URL of the file for other devices, host is the address they can reach us by
+    pub fn get_stream_url(&self, path: &Utf8Path, host: IpAddr) -> Result<String> {
+        let stream_port = self.stream_port
+            .ok_or_else(|| anyhow::anyhow!("Web player stream server is not running"))?;
+        let token = self.stream_sources.register(StreamSource {
+            path: path.to_path_buf(),
+            transcoding_profile: None,
+        });
+        Ok(format!("http://{}{}", SocketAddr::new(host, stream_port), stream::get_stream_path(&token)))
+    }
+
+    pub fn play_url(&self, url: &str, gain: f32) {
+        self.set_player_state(PlayerState::Playing(0.));
+        self.event_emitter.emit_event(&OnWebPlayerCommand {
+            command: WebPlayerCommand::PlayUrl {
+                url: url.to_string(),
+                gain,
+            },
+        });
+    }
+
     pub fn resume(&self) {
         self.event_emitter.emit_event(&OnWebPlayerCommand {
             command: WebPlayerCommand::Resume
@@ -164,42 +242,44 @@ impl WebPlayerService {
         });
     }
 
+    pub fn set_volume(&self, volume: f32) {
+        self.event_emitter.emit_event(&OnWebPlayerCommand {
+            command: WebPlayerCommand::SetVolume {
+                volume,
+            }
+        });
+    }
+
     pub fn stop(&self) {
         self.event_emitter.emit_event(&OnWebPlayerCommand {
             command: WebPlayerCommand::Stop
         });
     }
 
-    fn get_player_state(&self) -> PlayerState {
-        let state = self.state.read().unwrap();
-        return state.player_state;
+    fn set_event_sender(&self, events: PlayerEventSender) {
+        let mut state = self.state.write().unwrap();
+        events.send(PlayerEvent::StateChanged(state.player_state));
+        state.events = Some(events);
     }
 
-    pub fn on_web_player_state_changed(&self, web_state: WebPlayerState, progress: f32) {
+    fn set_player_state(&self, player_state: PlayerState) {
         let mut state = self.state.write().unwrap();
-        match web_state {
-            WebPlayerState::Playing => {
-                state.player_state = PlayerState::Playing(progress);
-            }
-            WebPlayerState::Paused => {
-                state.player_state = PlayerState::Paused(progress);
-            }
-            WebPlayerState::Stopped => {
-                state.player_state = PlayerState::Stopped;
-            }
-            WebPlayerState::PlaybackFinished => {
-                state.player_state = PlayerState::PlaybackFinished;
+        if state.player_state != player_state {
+            state.player_state = player_state;
+            if let Some(events) = state.events.as_ref() {
+                events.send(PlayerEvent::StateChanged(player_state));
             }
         }
     }
 
-    pub fn get_audio_binary(&self, _path: &str) -> std::result::Result<Vec<u8>, std::io::Error> {
-        let state = self.state.read().unwrap();
-        let path = state.current_file_path.clone();
-        drop(state);
-
-        let file_content = std::fs::read(path.as_str())?;
-        return Ok(file_content);
+    pub fn on_web_player_state_changed(&self, web_state: WebPlayerState, progress: f32) {
+        let player_state = match web_state {
+            WebPlayerState::Playing => PlayerState::Playing(progress),
+            WebPlayerState::Paused => PlayerState::Paused(progress),
+            WebPlayerState::Stopped => PlayerState::Stopped,
+            WebPlayerState::PlaybackFinished => PlayerState::PlaybackFinished,
+        };
+        self.set_player_state(player_state);
     }
 }
 
@@ -213,22 +293,42 @@ impl ServiceInitializer for WebPlayerService {
     fn initialize(context: &amina_core::service::Context) -> Arc<Self> {
         let event_emitter = context.get_service::<EventEmitter>();
         let rpc = context.get_service::<Rpc>();
+        let settings = context.get_service::<Settings>();
+        let task_manager = context.get_service::<TaskManager>();
+
+        let stream_address = settings.get_string("playback.web.stream_address").get();
+        let stream_address = if stream_address.is_empty() { DEFAULT_STREAM_ADDRESS.to_string() } else { stream_address };
+        let stream_sources = Arc::new(StreamSources::new());
+        let stream_port = match Server::http(&stream_address) {
+            Ok(server) => {
+                let port = server.server_addr().to_ip().map(|address| address.port());
+                let stream_sources = stream_sources.clone();
+                let transcoder = context.get_service::<Transcoder>();
+                task_manager.run(move |task_context| {
+                    if let Err(err) = stream::run_stream_server(&task_context, server, stream_sources, transcoder) {
+                        log::error!("Web player stream server failed: {}", err);
+                    }
+                });
+                port
+            },
+            Err(err) => {
+                log::error!("Can't start web player stream server on {}: {}", stream_address, err);
+                None
+            },
+        };
 
         let web_player_service = Arc::new(Self {
             event_emitter: event_emitter.clone(),
+            stream_sources,
+            stream_port,
             state: RwLock::new(WebPlayerContext {
-                current_file_path: String::default(),
                 player_state: PlayerState::Stopped,
+                events: None,
             })
         });
 
         register_rpc_handler!(rpc, web_player_service, "lappi.playback.web.on_web_player_state_changed", on_web_player_state_changed(web_state: WebPlayerState, progress: f32));
 
-        let web_player_service_copy = web_player_service.clone();
-        rpc.add_get_file_handler(FILE_HANDLER_KEY, move|path| {
-            web_player_service_copy.get_audio_binary(path)
-        });
-
         return web_player_service;
     }
 }
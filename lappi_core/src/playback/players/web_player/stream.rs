@@ -0,0 +1,106 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, RwLock};
+
+use anyhow::Result;
+use amina_core::service::Service;
+use amina_core::tasks::TaskContext;
+use camino::Utf8PathBuf;
+use tiny_http::{Method, Request, Response, Server};
+
+use crate::playback::sources::PlaybackSource;
+use crate::transcoding::{TranscodedAudio, Transcoder};
+use crate::utils::{http_file, http_server};
+
+static STREAM_PATH_PREFIX: &str = "/stream/";
+static MAX_SOURCES_COUNT: usize = 64;
+static WORKERS_COUNT: usize = 4;
+static CACHE_MAX_AGE: u32 = 3600;
+
+#[derive(Clone, PartialEq)]
+pub struct StreamSource {
+    pub path: Utf8PathBuf,
+    // The file is transcoded when it is requested, so starting the playback doesn't wait for ffmpeg
+    pub transcoding_profile: Option<String>,
+}
+
+// Maps opaque tokens to the files they give access to, so the browser never sees real paths
+pub struct StreamSources {
+    sources: RwLock<VecDeque<(String, StreamSource)>>,
+}
+
+impl StreamSources {
+    pub fn new() -> Self {
+        Self {
+            sources: RwLock::new(VecDeque::new()),
+        }
+    }
+
+    pub fn register(&self, source: StreamSource) -> String {
+        let mut sources = self.sources.write().unwrap();
+        if let Some((token, _)) = sources.iter().find(|(_, registered_source)| *registered_source == source) {
+            return token.clone();
+        }
+
+        let token = format!("{:032x}", rand::random::<u128>());
+        sources.push_back((token.clone(), source));
+        if sources.len() > MAX_SOURCES_COUNT {
+            sources.pop_front();
+        }
+        token
+    }
+
+    pub fn find(&self, token: &str) -> Option<StreamSource> {
+        let sources = self.sources.read().unwrap();
+        sources.iter()
+            .find(|(source_token, _)| source_token == token)
+            .map(|(_, source)| source.clone())
+    }
+}
+
+pub fn run_stream_server(task_context: &TaskContext, server: Server, sources: Arc<StreamSources>, transcoder: Service<Transcoder>) -> Result<()> {
+    http_server::serve_requests(task_context, server, WORKERS_COUNT, "Web player stream", move |request| {
+        handle_request(request, &sources, &transcoder)
+    })
+}
+
+fn handle_request(request: Request, sources: &StreamSources, transcoder: &Transcoder) -> Result<()> {
+    let source = request.url()
+        .strip_prefix(STREAM_PATH_PREFIX)
+        .filter(|_| matches!(request.method(), Method::Get | Method::Head))
+        .and_then(|token| sources.find(token.split('?').next().unwrap_or(token)));
+
+    let source = match source {
+        Some(source) => source,
+        None => {
+            request.respond(Response::empty(404))?;
+            return Ok(());
+        },
+    };
+
+    if let Some(profile_name) = &source.transcoding_profile {
+        let name = source.path.file_name().unwrap_or("").to_string();
+        let playback_source = PlaybackSource::local_file(name, source.path.clone());
+        match transcoder.transcode(&playback_source, profile_name) {
+            Ok(TranscodedAudio::Cached(path)) => {
+                let content_type = http_file::get_content_type(path.extension().unwrap_or(""));
+                return http_file::respond_file(request, &path, content_type, CACHE_MAX_AGE);
+            },
+            Ok(TranscodedAudio::Stream(stream)) => {
+                let content_type = transcoder.find_profile(profile_name)
+                    .map(|profile| profile.content_type.as_str())
+                    .unwrap_or("application/octet-stream");
+                return http_file::respond_stream(request, stream, content_type);
+            },
+            Err(err) => {
+                log::error!("Can't transcode '{}', the original file is used: {}", source.path, err);
+            },
+        }
+    }
+
+    let content_type = http_file::get_content_type(source.path.extension().unwrap_or(""));
+    http_file::respond_file(request, &source.path, content_type, CACHE_MAX_AGE)
+}
+
+pub fn get_stream_path(token: &str) -> String {
+    format!("{}{}", STREAM_PATH_PREFIX, token)
+}
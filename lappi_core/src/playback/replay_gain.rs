@@ -0,0 +1,65 @@
+use anyhow::Result;
+
+use crate::collection::music::{MusicCollection, MusicItemId};
+use crate::collection::tags::{Tag, TagValue};
+use crate::settings::Settings;
+
+pub static TRACK_GAIN_TAG: &str = "replaygain_track_gain";
+pub static TRACK_PEAK_TAG: &str = "replaygain_track_peak";
+pub static ALBUM_GAIN_TAG: &str = "replaygain_album_gain";
+pub static ALBUM_PEAK_TAG: &str = "replaygain_album_peak";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayGainMode {
+    Track,
+    Album,
+    Off,
+}
+
+impl ReplayGainMode {
+    pub fn from_settings() -> Self {
+        let settings = crate::context().get_service::<Settings>();
+        match settings.get_string("playback.replay_gain.mode").get().as_str() {
+            "track" => Self::Track,
+            "album" => Self::Album,
+            _ => Self::Off,
+        }
+    }
+}
+
+fn tag_to_float(tag: Option<Tag>) -> Option<f32> {
+    match tag?.get_value() {
+        TagValue::String(value) => value.parse().ok(),
+        TagValue::Number(value) => Some(*value as f32),
+        TagValue::Bool => None,
+    }
+}
+
+fn get_gain_and_peak(music_item_id: MusicItemId, gain_tag: &str, peak_tag: &str) -> Result<Option<(f32, Option<f32>)>> {
+    let music = crate::context().get_service::<MusicCollection>();
+    let gain = tag_to_float(music.get_tag(music_item_id, gain_tag)?);
+    let peak = tag_to_float(music.get_tag(music_item_id, peak_tag)?);
+    Ok(gain.map(|gain| (gain, peak)))
+}
+
+pub fn get_gain_factor(music_item_id: MusicItemId) -> Result<f32> {
+    let gain_and_peak = match ReplayGainMode::from_settings() {
+        ReplayGainMode::Track => get_gain_and_peak(music_item_id, TRACK_GAIN_TAG, TRACK_PEAK_TAG)?,
+        ReplayGainMode::Album => match get_gain_and_peak(music_item_id, ALBUM_GAIN_TAG, ALBUM_PEAK_TAG)? {
+            Some(gain_and_peak) => Some(gain_and_peak),
+            None => get_gain_and_peak(music_item_id, TRACK_GAIN_TAG, TRACK_PEAK_TAG)?,
+        },
+        ReplayGainMode::Off => None,
+    };
+
+    Ok(match gain_and_peak {
+        Some((gain, peak)) => {
+            let factor = 10f32.powf(gain / 20.);
+            match peak {
+                Some(peak) if peak > 0. => factor.min(1. / peak),
+                _ => factor,
+            }
+        },
+        None => 1.,
+    })
+}
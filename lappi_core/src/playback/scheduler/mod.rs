@@ -0,0 +1,484 @@
+pub mod types;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use camino::Utf8PathBuf;
+use chrono::{DateTime, Datelike, Local, Utc};
+use amina_core::cmd_manager::{ArgDescription, ArgType, CmdDescription, CmdManager};
+use amina_core::register_rpc_handler;
+use amina_core::rpc::Rpc;
+use amina_core::service::{Context, Service, ServiceApi, ServiceInitializer};
+use amina_core::tasks::{TaskContext, TaskManager};
+
+use crate::collection::playlists::PlaylistsCollection;
+use crate::workspace::Workspace;
+use super::zone::{PlaybackZone, StopAfter, ZoneId};
+use super::{Playback, DEFAULT_ZONE_ID};
+
+use types::*;
+
+static SCHEDULE_FILE_NAME: &str = "playback_schedule.yaml";
+static DEFAULT_FADE_OUT_SECS: u32 = 30;
+
+struct VolumeRamp {
+    zone_id: ZoneId,
+    from: i32,
+    to: i32,
+    started: Instant,
+    duration: Duration,
+    // A fade out pauses the zone at the end and gives it back the saved volume
+    is_fade_out: bool,
+}
+
+pub struct PlaybackScheduler {
+    playback: Service<Playback>,
+    playlists: Service<PlaylistsCollection>,
+    schedule: Mutex<Schedule>,
+    ramps: Mutex<Vec<VolumeRamp>>,
+    // Alarms due since this time are fired, so a late or suspended check doesn't skip them
+    last_alarms_check: Mutex<DateTime<Local>>,
+}
+
+impl PlaybackScheduler {
+    pub fn get_sleep_timers(&self) -> Vec<SleepTimer> {
+        self.schedule.lock().unwrap().sleep_timers.clone()
+    }
+
+    pub fn set_sleep_timer(&self, zone_id: ZoneId, mode: SleepTimerMode) -> Result<()> {
+        let zone = self.playback.get_zone(zone_id)?;
+        let deadline = match &mode {
+            SleepTimerMode::Minutes { minutes } => Some(Utc::now().timestamp() + *minutes as i64 * 60),
+            _ => None,
+        };
+        zone.set_stop_after(Self::get_stop_after(&mode));
+
+        let mut schedule = self.schedule.lock().unwrap();
+        schedule.sleep_timers.retain(|timer| timer.zone_id != zone_id);
+        schedule.sleep_timers.push(SleepTimer {
+            zone_id,
+            mode,
+            deadline,
+            fade_out_secs: DEFAULT_FADE_OUT_SECS,
+        });
+        Self::save(&schedule)
+    }
+
+    pub fn cancel_sleep_timer(&self, zone_id: ZoneId) -> Result<()> {
+        if let Ok(zone) = self.playback.get_zone(zone_id) {
+            zone.set_stop_after(None);
+        }
+        if self.cancel_ramps(zone_id) {
+            if let Ok(zone) = self.playback.get_zone(zone_id) {
+                zone.restore_saved_volume();
+            }
+        }
+
+        let mut schedule = self.schedule.lock().unwrap();
+        schedule.sleep_timers.retain(|timer| timer.zone_id != zone_id);
+        Self::save(&schedule)
+    }
+
+    pub fn get_alarms(&self) -> Vec<Alarm> {
+        self.schedule.lock().unwrap().alarms.clone()
+    }
+
+    pub fn add_alarm(&self, mut alarm: Alarm) -> Result<AlarmId> {
+        Self::validate_alarm(&alarm)?;
+
+        let mut schedule = self.schedule.lock().unwrap();
+        alarm.id = schedule.alarms.iter().map(|alarm| alarm.id).max().unwrap_or(0) + 1;
+        alarm.last_fired = None;
+        let alarm_id = alarm.id;
+        schedule.alarms.push(alarm);
+        Self::save(&schedule)?;
+        Ok(alarm_id)
+    }
+
+    pub fn update_alarm(&self, alarm: Alarm) -> Result<()> {
+        Self::validate_alarm(&alarm)?;
+
+        let mut schedule = self.schedule.lock().unwrap();
+        let current = schedule.alarms.iter_mut()
+            .find(|current| current.id == alarm.id)
+            .ok_or_else(|| anyhow::anyhow!("Alarm {} not found", alarm.id))?;
+        *current = alarm;
+        Self::save(&schedule)
+    }
+
+    pub fn set_alarm_enabled(&self, alarm_id: AlarmId, enabled: bool) -> Result<()> {
+        let mut schedule = self.schedule.lock().unwrap();
+        let alarm = schedule.alarms.iter_mut()
+            .find(|alarm| alarm.id == alarm_id)
+            .ok_or_else(|| anyhow::anyhow!("Alarm {} not found", alarm_id))?;
+        alarm.enabled = enabled;
+        Self::save(&schedule)
+    }
+
+    pub fn remove_alarm(&self, alarm_id: AlarmId) -> Result<()> {
+        let mut schedule = self.schedule.lock().unwrap();
+        schedule.alarms.retain(|alarm| alarm.id != alarm_id);
+        Self::save(&schedule)
+    }
+
+    fn validate_alarm(alarm: &Alarm) -> Result<()> {
+        if alarm.hour > 23 || alarm.minute > 59 {
+            anyhow::bail!("Invalid alarm time {}:{}", alarm.hour, alarm.minute);
+        }
+        if alarm.weekdays.iter().any(|day| *day > 6) {
+            anyhow::bail!("Invalid alarm weekdays {:?}", alarm.weekdays);
+        }
+        Ok(())
+    }
+
+    fn get_stop_after(mode: &SleepTimerMode) -> Option<StopAfter> {
+        match mode {
+            SleepTimerMode::Minutes { .. } => None,
+            SleepTimerMode::EndOfTrack => Some(StopAfter::Track),
+            SleepTimerMode::EndOfQueue => Some(StopAfter::Queue),
+        }
+    }
+
+    fn get_path() -> Utf8PathBuf {
+        let workspace = crate::context().get_service::<Workspace>();
+        workspace.get_workspace_dir().join(SCHEDULE_FILE_NAME)
+    }
+
+    fn save(schedule: &Schedule) -> Result<()> {
+        let file = File::create(Self::get_path())?;
+        serde_yaml::to_writer(file, schedule)?;
+        Ok(())
+    }
+
+    fn load() -> Result<Schedule> {
+        let path = Self::get_path();
+        if !path.exists() {
+            return Ok(Schedule::default());
+        }
+        Ok(serde_yaml::from_reader(File::open(path)?)?)
+    }
+
+    fn start_ramp(&self, zone: &PlaybackZone, from: i32, to: i32, duration_secs: u32, is_fade_out: bool) {
+        let zone_id = zone.get_id();
+        self.cancel_ramps(zone_id);
+        zone.set_transient_volume(from);
+        self.ramps.lock().unwrap().push(VolumeRamp {
+            zone_id,
+            from,
+            to,
+            started: Instant::now(),
+            duration: Duration::from_secs(duration_secs as u64),
+            is_fade_out,
+        });
+    }
+
+    // Returns true if the zone had a ramp
+    fn cancel_ramps(&self, zone_id: ZoneId) -> bool {
+        let mut ramps = self.ramps.lock().unwrap();
+        let ramps_count = ramps.len();
+        ramps.retain(|ramp| ramp.zone_id != zone_id);
+        ramps.len() != ramps_count
+    }
+
+    fn update_ramps(&self) {
+        let mut ramps = self.ramps.lock().unwrap();
+        ramps.retain(|ramp| {
+            let zone = match self.playback.get_zone(ramp.zone_id) {
+                Ok(zone) => zone,
+                Err(_) => return false,
+            };
+
+            let elapsed = ramp.started.elapsed();
+            if elapsed >= ramp.duration {
+                zone.set_transient_volume(ramp.to);
+                if ramp.is_fade_out {
+                    zone.pause();
+                    zone.restore_saved_volume();
+                }
+                return false;
+            }
+
+            let fraction = elapsed.as_secs_f32() / ramp.duration.as_secs_f32();
+            let volume = ramp.from + ((ramp.to - ramp.from) as f32 * fraction).round() as i32;
+            if volume != zone.get_volume() {
+                zone.set_transient_volume(volume);
+            }
+            true
+        });
+    }
+
+    fn update_sleep_timers(&self) -> Result<()> {
+        let now = Utc::now().timestamp();
+        let mut fade_outs = vec![];
+
+        let mut schedule = self.schedule.lock().unwrap();
+        let timers_count = schedule.sleep_timers.len();
+        schedule.sleep_timers.retain(|timer| {
+            let zone = match self.playback.get_zone(timer.zone_id) {
+                Ok(zone) => zone,
+                Err(_) => return false,
+            };
+            match timer.deadline {
+                Some(deadline) if now >= deadline - timer.fade_out_secs as i64 => {
+                    let fade_out_secs = (deadline - now).max(0) as u32;
+                    fade_outs.push((zone, fade_out_secs));
+                    false
+                },
+                Some(_) => true,
+                // Timers that stop at the end of a track or queue are done once the zone consumed them
+                None => zone.get_stop_after().is_some(),
+            }
+        });
+        if schedule.sleep_timers.len() != timers_count {
+            Self::save(&schedule)?;
+        }
+        drop(schedule);
+
+        for (zone, fade_out_secs) in fade_outs {
+            log::debug!("Sleep timer of zone {} fades out in {} seconds", zone.get_id(), fade_out_secs);
+            let volume = zone.get_volume();
+            self.start_ramp(&zone, volume, 0, fade_out_secs, true);
+        }
+
+        Ok(())
+    }
+
+    // The latest time the alarm was due at, not later than now
+    fn get_last_due_time(alarm: &Alarm, now: &DateTime<Local>) -> Option<DateTime<Local>> {
+        for days_back in 0..=7 {
+            let date = now.date_naive() - chrono::Duration::days(days_back);
+            let weekday = date.weekday().num_days_from_monday();
+            if !alarm.weekdays.is_empty() && !alarm.weekdays.contains(&weekday) {
+                continue;
+            }
+            // The time doesn't exist on a day when the clock is moved forward
+            let due_time = date.and_hms_opt(alarm.hour, alarm.minute, 0)
+                .and_then(|time| time.and_local_timezone(Local).earliest());
+            match due_time {
+                Some(due_time) if due_time <= *now => return Some(due_time),
+                _ => continue,
+            }
+        }
+        None
+    }
+
+    fn update_alarms(&self) -> Result<()> {
+        let now = Local::now();
+        let last_check = std::mem::replace(&mut *self.last_alarms_check.lock().unwrap(), now);
+
+        let mut fired = vec![];
+        let mut schedule = self.schedule.lock().unwrap();
+        for alarm in schedule.alarms.iter_mut().filter(|alarm| alarm.enabled) {
+            let due_time = match Self::get_last_due_time(alarm, &now) {
+                Some(due_time) if due_time > last_check => due_time,
+                _ => continue,
+            };
+            let due_date = due_time.date_naive().to_string();
+            if alarm.last_fired.as_deref() != Some(due_date.as_str()) {
+                alarm.last_fired = Some(due_date);
+                fired.push(alarm.clone());
+            }
+        }
+        if !fired.is_empty() {
+            Self::save(&schedule)?;
+        }
+        drop(schedule);
+
+        for alarm in fired {
+            if let Err(err) = self.fire_alarm(&alarm) {
+                log::error!("Failed to start alarm '{}': {}", alarm.name, err);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fire_alarm(&self, alarm: &Alarm) -> Result<()> {
+        log::info!("Alarm '{}' started", alarm.name);
+        let zone = self.playback.get_zone(alarm.zone_id)?;
+
+        zone.set_transient_volume(0);
+        match &alarm.target {
+            AlarmTarget::Playlist { playlist_id } => {
+                let first_item = self.playlists.get_playlist_items(*playlist_id)?
+                    .first()
+                    .map(|item| item.id)
+                    .ok_or_else(|| anyhow::anyhow!("Playlist {} is empty", playlist_id))?;
+                zone.play_playlist(*playlist_id, first_item)?;
+            },
+            AlarmTarget::Folder { folder_id } => {
+                zone.play_folder(*folder_id, None)?;
+            },
+        }
+        self.start_ramp(&zone, 0, alarm.volume.clamp(0, 100), alarm.ramp_secs, false);
+
+        Ok(())
+    }
+
+    fn run_task(&self, task_context: &TaskContext) {
+        while !task_context.is_interrupted() {
+            if let Err(err) = self.update_sleep_timers() {
+                log::error!("Failed to update sleep timers: {}", err);
+            }
+            if let Err(err) = self.update_alarms() {
+                log::error!("Failed to update alarms: {}", err);
+            }
+            self.update_ramps();
+
+            thread::sleep(Duration::from_millis(200));
+        }
+    }
+
+    fn add_commands(scheduler: &Arc<Self>, cmd_manager: &CmdManager) {
+        let sleep_timer_cmd_description = CmdDescription {
+            call_name: "playback.sleep_timer".to_string(),
+            description: Some("Pause the playback after N minutes or at the end of the track or queue".to_string()),
+            args: HashMap::from([
+                ("after".to_string(), ArgDescription {
+                    call_name: "after".to_string(),
+                    description: Some("Minutes, 'track', 'queue' or 'off'".to_string()),
+                    arg_type: ArgType::STRING,
+                }),
+            ]),
+        };
+        let scheduler_copy = scheduler.clone();
+        cmd_manager.add_command(sleep_timer_cmd_description, move |args| {
+            let after = args.get_string("after");
+            let result = match after.as_str() {
+                "off" => scheduler_copy.cancel_sleep_timer(DEFAULT_ZONE_ID),
+                "track" => scheduler_copy.set_sleep_timer(DEFAULT_ZONE_ID, SleepTimerMode::EndOfTrack),
+                "queue" => scheduler_copy.set_sleep_timer(DEFAULT_ZONE_ID, SleepTimerMode::EndOfQueue),
+                minutes => match minutes.parse() {
+                    Ok(minutes) => scheduler_copy.set_sleep_timer(DEFAULT_ZONE_ID, SleepTimerMode::Minutes { minutes }),
+                    Err(_) => Err(anyhow::anyhow!("Invalid sleep timer value '{}'", minutes)),
+                },
+            };
+            if let Err(err) = result {
+                log::error!("Failed to set sleep timer: {}", err);
+            }
+        });
+
+        let add_alarm_cmd_description = CmdDescription {
+            call_name: "playback.add_alarm".to_string(),
+            description: Some("Play a playlist every day at the given time".to_string()),
+            args: HashMap::from([
+                ("time".to_string(), ArgDescription {
+                    call_name: "time".to_string(),
+                    description: Some("Time in HH:MM format".to_string()),
+                    arg_type: ArgType::STRING,
+                }),
+                ("playlist_id".to_string(), ArgDescription {
+                    call_name: "playlist_id".to_string(),
+                    description: Some("Playlist to play".to_string()),
+                    arg_type: ArgType::STRING,
+                }),
+            ]),
+        };
+        let scheduler_copy = scheduler.clone();
+        cmd_manager.add_command(add_alarm_cmd_description, move |args| {
+            let time = args.get_string("time");
+            let playlist_id = args.get_string("playlist_id");
+            let result = Self::parse_time(&time)
+                .zip(playlist_id.parse().ok())
+                .ok_or_else(|| anyhow::anyhow!("Invalid alarm '{}' for playlist '{}'", time, playlist_id))
+                .and_then(|((hour, minute), playlist_id)| scheduler_copy.add_alarm(Alarm {
+                    id: 0,
+                    name: format!("Alarm {}", time),
+                    zone_id: DEFAULT_ZONE_ID,
+                    hour,
+                    minute,
+                    weekdays: vec![],
+                    target: AlarmTarget::Playlist { playlist_id },
+                    volume: 50,
+                    ramp_secs: 60,
+                    enabled: true,
+                    last_fired: None,
+                }));
+            match result {
+                Ok(alarm_id) => log::info!("Alarm {} added", alarm_id),
+                Err(err) => log::error!("Failed to add alarm: {}", err),
+            }
+        });
+
+        let remove_alarm_cmd_description = CmdDescription {
+            call_name: "playback.remove_alarm".to_string(),
+            description: Some("Remove an alarm".to_string()),
+            args: HashMap::from([
+                ("alarm_id".to_string(), ArgDescription {
+                    call_name: "alarm_id".to_string(),
+                    description: Some("Alarm id".to_string()),
+                    arg_type: ArgType::STRING,
+                }),
+            ]),
+        };
+        let scheduler_copy = scheduler.clone();
+        cmd_manager.add_command(remove_alarm_cmd_description, move |args| {
+            let alarm_id = args.get_string("alarm_id");
+            let result = alarm_id.parse()
+                .map_err(|_| anyhow::anyhow!("Invalid alarm id '{}'", alarm_id))
+                .and_then(|alarm_id| scheduler_copy.remove_alarm(alarm_id));
+            if let Err(err) = result {
+                log::error!("Failed to remove alarm: {}", err);
+            }
+        });
+    }
+
+    fn parse_time(time: &str) -> Option<(u32, u32)> {
+        let (hour, minute) = time.split_once(':')?;
+        Some((hour.trim().parse().ok()?, minute.trim().parse().ok()?))
+    }
+}
+
+impl ServiceApi for PlaybackScheduler {
+    fn start(&self) {
+        // Sleep timers that wait for the end of a track or queue live in zones, so they are restored there
+        for timer in self.get_sleep_timers() {
+            if let Ok(zone) = self.playback.get_zone(timer.zone_id) {
+                zone.set_stop_after(Self::get_stop_after(&timer.mode));
+            }
+        }
+    }
+}
+
+impl ServiceInitializer for PlaybackScheduler {
+    fn initialize(context: &Context) -> Arc<Self> {
+        let rpc = context.get_service::<Rpc>();
+        let cmd_manager = context.get_service::<CmdManager>();
+        let task_manager = context.get_service::<TaskManager>();
+
+        let schedule = Self::load().unwrap_or_else(|err| {
+            log::error!("Failed to load playback schedule: {}", err);
+            Schedule::default()
+        });
+
+        let scheduler = Arc::new(Self {
+            playback: context.get_service::<Playback>(),
+            playlists: context.get_service::<PlaylistsCollection>(),
+            schedule: Mutex::new(schedule),
+            ramps: Mutex::new(vec![]),
+            last_alarms_check: Mutex::new(Local::now()),
+        });
+
+        register_rpc_handler!(rpc, scheduler, "lappi.playback.scheduler.get_sleep_timers", get_sleep_timers());
+        register_rpc_handler!(rpc, scheduler, "lappi.playback.scheduler.set_sleep_timer", set_sleep_timer(zone_id: ZoneId, mode: SleepTimerMode));
+        register_rpc_handler!(rpc, scheduler, "lappi.playback.scheduler.cancel_sleep_timer", cancel_sleep_timer(zone_id: ZoneId));
+        register_rpc_handler!(rpc, scheduler, "lappi.playback.scheduler.get_alarms", get_alarms());
+        register_rpc_handler!(rpc, scheduler, "lappi.playback.scheduler.add_alarm", add_alarm(alarm: Alarm));
+        register_rpc_handler!(rpc, scheduler, "lappi.playback.scheduler.update_alarm", update_alarm(alarm: Alarm));
+        register_rpc_handler!(rpc, scheduler, "lappi.playback.scheduler.set_alarm_enabled", set_alarm_enabled(alarm_id: AlarmId, enabled: bool));
+        register_rpc_handler!(rpc, scheduler, "lappi.playback.scheduler.remove_alarm", remove_alarm(alarm_id: AlarmId));
+
+        Self::add_commands(&scheduler, &cmd_manager);
+
+        let scheduler_clone = scheduler.clone();
+        task_manager.run(move |task_context| {
+            scheduler_clone.run_task(&task_context);
+        });
+
+        scheduler
+    }
+}
@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+
+use crate::collection::folders::FolderId;
+use crate::collection::playlists::types::PlaylistId;
+use crate::playback::zone::ZoneId;
+
+pub type AlarmId = i64;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind")]
+pub enum SleepTimerMode {
+    Minutes { minutes: u32 },
+    EndOfTrack,
+    EndOfQueue,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SleepTimer {
+    pub zone_id: ZoneId,
+    pub mode: SleepTimerMode,
+    // Unix time when the playback is paused, only for the minutes mode
+    pub deadline: Option<i64>,
+    pub fade_out_secs: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind")]
+pub enum AlarmTarget {
+    Playlist { playlist_id: PlaylistId },
+    Folder { folder_id: FolderId },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Alarm {
+    #[serde(default)]
+    pub id: AlarmId,
+    pub name: String,
+    pub zone_id: ZoneId,
+    pub hour: u32,
+    pub minute: u32,
+    // Days from Monday (0) to Sunday (6), empty means every day
+    #[serde(default)]
+    pub weekdays: Vec<u32>,
+    pub target: AlarmTarget,
+    pub volume: i32,
+    pub ramp_secs: u32,
+    pub enabled: bool,
+    #[serde(default)]
+    pub last_fired: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Schedule {
+    pub sleep_timers: Vec<SleepTimer>,
+    pub alarms: Vec<Alarm>,
+}
@@ -0,0 +1,51 @@
+use std::fs::File;
+
+use anyhow::Result;
+use camino::Utf8PathBuf;
+use serde::{Deserialize, Serialize};
+
+use crate::workspace::Workspace;
+use super::play_queue::PlayQueueSnapshot;
+use super::zone::ZoneId;
+
+static SNAPSHOT_FILE_NAME: &str = "playback_state";
+
+#[derive(Serialize, Deserialize)]
+pub struct PlaybackSnapshot {
+    pub queue: PlayQueueSnapshot,
+    pub progress: f32,
+}
+
+impl PlaybackSnapshot {
+    fn get_path(zone_id: ZoneId) -> Utf8PathBuf {
+        let workspace = crate::context().get_service::<Workspace>();
+        let file_name = match zone_id {
+            super::DEFAULT_ZONE_ID => format!("{}.yaml", SNAPSHOT_FILE_NAME),
+            zone_id => format!("{}_{}.yaml", SNAPSHOT_FILE_NAME, zone_id),
+        };
+        workspace.get_workspace_dir().join(file_name)
+    }
+
+    pub fn save(&self, zone_id: ZoneId) -> Result<()> {
+        let file = File::create(Self::get_path(zone_id))?;
+        serde_yaml::to_writer(file, self)?;
+        Ok(())
+    }
+
+    pub fn load(zone_id: ZoneId) -> Result<Option<Self>> {
+        let path = Self::get_path(zone_id);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let snapshot = serde_yaml::from_reader(File::open(path)?)?;
+        Ok(Some(snapshot))
+    }
+
+    pub fn clear(zone_id: ZoneId) -> Result<()> {
+        let path = Self::get_path(zone_id);
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
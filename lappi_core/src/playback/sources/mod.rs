@@ -2,15 +2,38 @@ use std::fmt::Debug;
 
 use anyhow::Result;
 use camino::Utf8PathBuf;
+use serde::{Deserialize, Serialize};
 
 use crate::collection::internal_files::InternalFiles;
 use crate::collection::music::{MusicCollection, MusicItemId};
-use crate::collection::music_sources::MusicSourcesCollection;
+use crate::collection::music_sources::{MusicLinkType, MusicSourcesCollection};
 use crate::collection::pictures::PictureId;
+use crate::playback::replay_gain;
 
-#[derive(Clone, Debug)]
+// Adjacently tagged, as YAML enum tags can not be read inside the internally tagged queue snapshot
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", content = "location")]
 pub enum SourceType {
     LocalFile(Utf8PathBuf),
+    ExternalFile(Utf8PathBuf),
+    Url(String),
+}
+
+// Sources of music items are resolved again on restore, as their files may have changed
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum PlaybackSourceSnapshot {
+    MusicItem { music_item_id: MusicItemId },
+    Source { name: String, source_type: SourceType },
+}
+
+impl PlaybackSourceSnapshot {
+    pub fn restore(&self) -> Result<Option<Box<PlaybackSource>>> {
+        match self {
+            Self::MusicItem { music_item_id } => PlaybackSource::default_from_music_item(*music_item_id),
+            Self::Source { name, source_type } => Ok(Some(PlaybackSource::new(name.clone(), source_type.clone()))),
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -18,24 +41,56 @@ pub struct PlaybackSource {
     name: String,
     source_type: SourceType,
     cover_picture: Option<PictureId>,
+    music_item_id: Option<MusicItemId>,
 }
 
 impl PlaybackSource {
     pub fn local_file(name: String, path: Utf8PathBuf) -> Box<PlaybackSource> {
+        Self::new(name, SourceType::LocalFile(path))
+    }
+
+    fn new(name: String, source_type: SourceType) -> Box<PlaybackSource> {
         Box::new(Self {
             name,
-            source_type: SourceType::LocalFile(path),
+            source_type,
             cover_picture: Option::None,
+            music_item_id: Option::None,
         })
     }
 
-    pub fn default_from_music_item(music_item_id: MusicItemId) -> Result<Option<Box<PlaybackSource>>> {
-        let music = crate::context().get_service::<MusicCollection>();
+    // Prefer the file managed by the collection, then external files, then URLs
+    fn find_source_type(music_item_id: MusicItemId) -> Result<Option<SourceType>> {
         let music_sources = crate::context().get_service::<MusicSourcesCollection>();
         let internal_files = crate::context().get_service::<InternalFiles>();
 
-        match music_sources.get_music_file(music_item_id)? {
-            Some(file_desc) => {
+        if let Some(file_desc) = music_sources.get_music_file(music_item_id)? {
+            let path = internal_files.get_system_path(file_desc.internal_file_id)?;
+            return Ok(Some(SourceType::LocalFile(path)));
+        }
+
+        let links = music_sources.get_music_links(music_item_id)?;
+
+        for link in links.iter().filter(|link| link.link_type == MusicLinkType::ExternalFile) {
+            let path = Utf8PathBuf::from(&link.link);
+            if path.is_file() {
+                return Ok(Some(SourceType::ExternalFile(path)));
+            } else {
+                log::debug!("External file {} of music item {} not found", path, music_item_id);
+            }
+        }
+
+        if let Some(link) = links.iter().find(|link| link.link_type == MusicLinkType::Url) {
+            return Ok(Some(SourceType::Url(link.link.clone())));
+        }
+
+        Ok(None)
+    }
+
+    pub fn default_from_music_item(music_item_id: MusicItemId) -> Result<Option<Box<PlaybackSource>>> {
+        let music = crate::context().get_service::<MusicCollection>();
+
+        match Self::find_source_type(music_item_id)? {
+            Some(source_type) => {
                 let item_desc = music.get_item_description(music_item_id)?;
 
                 let artist_tag = music.get_tag(music_item_id, "artist")?;
@@ -45,10 +100,9 @@ impl PlaybackSource {
                     item_desc.name
                 };
 
-                let path = internal_files.get_system_path(file_desc.internal_file_id)?;
-
-                let mut playback_source = Self::local_file(name, path);
+                let mut playback_source = Self::new(name, source_type);
                 playback_source.cover_picture = music.get_item_cover(music_item_id)?;
+                playback_source.music_item_id = Some(music_item_id);
 
                 Ok(Some(playback_source))
             },
@@ -60,6 +114,16 @@ impl PlaybackSource {
         self.name.as_str()
     }
 
+    pub fn get_snapshot(&self) -> PlaybackSourceSnapshot {
+        match self.music_item_id {
+            Some(music_item_id) => PlaybackSourceSnapshot::MusicItem { music_item_id },
+            None => PlaybackSourceSnapshot::Source {
+                name: self.name.clone(),
+                source_type: self.source_type.clone(),
+            },
+        }
+    }
+
     pub fn get_source_type(&self) -> &SourceType {
         &self.source_type
     }
@@ -67,4 +131,22 @@ impl PlaybackSource {
     pub fn get_cover_picture(&self) -> Option<PictureId> {
         self.cover_picture
     }
+
+    pub fn get_music_item_id(&self) -> Option<MusicItemId> {
+        self.music_item_id
+    }
+
+    pub fn get_replay_gain(&self) -> f32 {
+        let music_item_id = match self.music_item_id {
+            Some(music_item_id) => music_item_id,
+            None => return 1.,
+        };
+        match replay_gain::get_gain_factor(music_item_id) {
+            Ok(gain) => gain,
+            Err(err) => {
+                log::error!("Failed to get replay gain for music item {}: {}", music_item_id, err);
+                1.
+            }
+        }
+    }
 }
@@ -0,0 +1,688 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use anyhow::Result;
+use camino::Utf8PathBuf;
+use serde::{Deserialize, Serialize};
+use amina_core::events::EventEmitter;
+use amina_core::settings::Property;
+use amina_core::service::Service;
+use amina_core::tasks::{TaskContext, TaskManager};
+
+use crate::collection::folders::FolderId;
+use crate::collection::music::MusicItemId;
+use crate::collection::playlists::types::{PlaylistId, PlaylistItemId};
+use crate::settings::Settings;
+use crate::workspace::Workspace;
+
+use super::events::OnStateUpdated;
+use super::play_queue::{PlayQueue, SingleSourceQueue, SourceListQueue};
+use super::play_queue::folder_queue::FolderQueue;
+use super::play_queue::playlist_queue::PlaylistQueue;
+use super::snapshot::PlaybackSnapshot;
+use super::sources::PlaybackSource;
+use super::players::unavailable::UnavailablePlayer;
+use super::{Player, PlayerEvent, PlayerEventSender, PlayerFactory, PlayerState};
+
+static ZONES_FILE_NAME: &str = "playback_zones.yaml";
+// Players that push their state are not polled, the zone only wakes up to check for interruption
+static IDLE_TIMEOUT: Duration = Duration::from_millis(500);
+
+pub type ZoneId = i64;
+pub type PlayerFactories = Arc<RwLock<HashMap<String, Box<dyn PlayerFactory>>>>;
+
+#[derive(Debug, Clone)]
+enum PlayerCommand {
+    SwitchPlayer(String),
+    Play(Box<PlaybackSource>),
+    Pause,
+    Resume,
+    Seek(f32),
+    SetVolume(f32),
+    SetTransientVolume(f32),
+    RestoreVolume,
+    Mute(bool),
+    SetNext(Option<Box<PlaybackSource>>),
+    Close,
+}
+
+enum ZoneMessage {
+    Command(PlayerCommand),
+    PlayerEvent(u64, PlayerEvent),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum StopAfter {
+    Track,
+    Queue,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ZoneDesc {
+    pub id: ZoneId,
+    pub name: String,
+    pub player_id: String,
+}
+
+impl ZoneDesc {
+    fn get_path() -> Utf8PathBuf {
+        let workspace = crate::context().get_service::<Workspace>();
+        workspace.get_workspace_dir().join(ZONES_FILE_NAME)
+    }
+
+    pub fn save_all(zones: &[ZoneDesc]) -> Result<()> {
+        let file = File::create(Self::get_path())?;
+        serde_yaml::to_writer(file, zones)?;
+        Ok(())
+    }
+
+    pub fn load_all() -> Result<Vec<ZoneDesc>> {
+        let path = Self::get_path();
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+        Ok(serde_yaml::from_reader(File::open(path)?)?)
+    }
+}
+
+pub struct PlaybackZone {
+    id: ZoneId,
+    name: RwLock<String>,
+    player_id: RwLock<String>,
+    event_emitter: Service<EventEmitter>,
+    settings: Service<Settings>,
+    player_factories: PlayerFactories,
+    sender: Sender<ZoneMessage>,
+    player_state: RwLock<PlayerState>,
+    volume: RwLock<i32>,
+    stop_after: Mutex<Option<StopAfter>>,
+    current_queue: Mutex<Option<Box<dyn PlayQueue>>>,
+    restored_progress: Mutex<Option<f32>>,
+}
+
+impl PlaybackZone {
+    pub fn create(desc: ZoneDesc, player_factories: PlayerFactories) -> Arc<Self> {
+        let context = crate::context();
+        let task_manager = context.get_service::<TaskManager>();
+
+        let (sender, receiver) = channel();
+        let zone = Arc::new(Self {
+            id: desc.id,
+            name: RwLock::new(desc.name),
+            player_id: RwLock::new(desc.player_id),
+            event_emitter: context.get_service::<EventEmitter>(),
+            settings: context.get_service::<Settings>(),
+            player_factories,
+            sender,
+            player_state: RwLock::new(PlayerState::Stopped),
+            volume: RwLock::new(100),
+            stop_after: Mutex::new(None),
+            current_queue: Mutex::new(None),
+            restored_progress: Mutex::new(None),
+        });
+
+        let zone_clone = zone.clone();
+        task_manager.run(move |task_context| {
+            zone_clone.run_task(&task_context, receiver);
+        });
+
+        zone
+    }
+
+    fn send_command(&self, command: PlayerCommand) {
+        if self.sender.send(ZoneMessage::Command(command)).is_err() {
+            log::debug!("Zone {} is closed", self.id);
+        }
+    }
+
+    pub fn get_id(&self) -> ZoneId {
+        self.id
+    }
+
+    pub fn get_desc(&self) -> ZoneDesc {
+        ZoneDesc {
+            id: self.id,
+            name: self.name.read().unwrap().clone(),
+            player_id: self.player_id.read().unwrap().clone(),
+        }
+    }
+
+    pub fn rename(&self, name: String) {
+        *self.name.write().unwrap() = name;
+    }
+
+    // The player is reserved at once, so another zone can't take it while this one switches
+    pub fn switch_player(&self, player_id: String) {
+        *self.player_id.write().unwrap() = player_id.clone();
+        self.send_command(PlayerCommand::SwitchPlayer(player_id));
+    }
+
+    pub fn play_item(&self, item_id: MusicItemId) -> Result<()> {
+        if let Some(source) = PlaybackSource::default_from_music_item(item_id)? {
+            self.play_queue(Box::new(SingleSourceQueue::new(source)));
+        } else {
+            log::debug!("No source files for music item {}", item_id);
+        }
+        Ok(())
+    }
+
+    pub fn play_playlist(&self, playlist_id: PlaylistId, playlist_item: PlaylistItemId) -> Result<()> {
+        let play_queue = PlaylistQueue::create(playlist_id, playlist_item)?;
+        self.play_queue(Box::new(play_queue));
+        Ok(())
+    }
+
+    pub fn play_folder(&self, folder_id: FolderId, start_item: Option<MusicItemId>) -> Result<()> {
+        let play_queue = FolderQueue::create(folder_id, start_item)?;
+        if play_queue.is_empty() {
+            log::debug!("No source files in folder {}", folder_id);
+        } else {
+            self.play_queue(Box::new(play_queue));
+        }
+        Ok(())
+    }
+
+    pub fn play_queue(&self, play_queue: Box<dyn PlayQueue>) {
+        let source = match play_queue.get_current_source() {
+            Some(source) => source,
+            None => return,
+        };
+        self.current_queue.lock().unwrap().replace(play_queue);
+        self.restored_progress.lock().unwrap().take();
+        self.send_command(PlayerCommand::Play(source));
+    }
+
+    // Appends to the current queue without interrupting the playback
+    pub fn add_to_queue(&self, sources: Vec<Box<PlaybackSource>>) {
+        {
+            let mut current_queue = self.current_queue.lock().unwrap();
+            let (mut queue_sources, current_index) = match current_queue.as_ref() {
+                Some(queue) => (queue.get_sources(), queue.get_current_index()),
+                None => (vec![], 0),
+            };
+            queue_sources.extend(sources);
+            if queue_sources.is_empty() {
+                return;
+            }
+            current_queue.replace(Box::new(SourceListQueue::new(queue_sources, current_index)));
+        }
+        self.send_command(PlayerCommand::SetNext(self.get_next_source()));
+    }
+
+    pub fn clear_queue(&self) {
+        self.current_queue.lock().unwrap().take();
+        self.restored_progress.lock().unwrap().take();
+        self.send_command(PlayerCommand::Pause);
+        self.send_command(PlayerCommand::SetNext(None));
+    }
+
+    pub fn resume(&self) {
+        let state = *self.player_state.read().unwrap();
+        if state == PlayerState::Stopped {
+            // A restored queue or a queue that was only filled is not loaded into the player yet
+            let progress = self.restored_progress.lock().unwrap().take().unwrap_or(0.);
+            self.play_current_source(progress);
+            return;
+        }
+        self.send_command(PlayerCommand::Resume);
+    }
+
+    fn play_current_source(&self, progress: f32) {
+        let source = match self.current_queue.lock().unwrap().as_ref() {
+            Some(queue) => match queue.get_current_source() {
+                Some(source) => source,
+                None => return,
+            },
+            None => return,
+        };
+        log::debug!("Playing current source of the queue from {}", progress);
+        self.send_command(PlayerCommand::Play(source));
+        if progress > 0. {
+            self.send_command(PlayerCommand::Seek(progress));
+        }
+    }
+
+    pub fn pause(&self) {
+        self.send_command(PlayerCommand::Pause);
+    }
+
+    pub fn toggle(&self) {
+        let state = *self.player_state.read().unwrap();
+        match state {
+            PlayerState::Playing(_) => {
+                self.pause();
+            },
+            PlayerState::Paused(_) => {
+                self.resume();
+            },
+            PlayerState::Stopped => {
+                self.resume();
+            },
+            PlayerState::PlaybackFinished => { },
+        }
+    }
+
+    pub fn seek(&self, progress: i32) {
+        self.send_command(PlayerCommand::Seek((progress as f32) / 1000.));
+    }
+
+    pub fn set_volume(&self, volume: i32) {
+        let volume = (volume as f32 / 100.).clamp(0., 1.);
+        self.send_command(PlayerCommand::SetVolume(volume));
+    }
+
+    // Changes the volume without saving it, fades use it to keep the volume chosen by the user
+    pub fn set_transient_volume(&self, volume: i32) {
+        let volume = (volume as f32 / 100.).clamp(0., 1.);
+        self.send_command(PlayerCommand::SetTransientVolume(volume));
+    }
+
+    pub fn restore_saved_volume(&self) {
+        self.send_command(PlayerCommand::RestoreVolume);
+    }
+
+    pub fn mute(&self, muted: bool) {
+        self.send_command(PlayerCommand::Mute(muted));
+    }
+
+    pub fn get_player_state(&self) -> PlayerState {
+        *self.player_state.read().unwrap()
+    }
+
+    pub fn get_volume(&self) -> i32 {
+        *self.volume.read().unwrap()
+    }
+
+    // Playback is stopped once the current track or the whole queue is finished
+    pub fn set_stop_after(&self, stop_after: Option<StopAfter>) {
+        *self.stop_after.lock().unwrap() = stop_after;
+        self.send_command(PlayerCommand::SetNext(self.get_next_source()));
+    }
+
+    pub fn get_stop_after(&self) -> Option<StopAfter> {
+        *self.stop_after.lock().unwrap()
+    }
+
+    pub fn get_queue_sources(&self) -> Option<(Vec<Box<PlaybackSource>>, usize)> {
+        let play_queue = self.current_queue.lock().unwrap();
+        play_queue.as_ref().map(|play_queue| (play_queue.get_sources(), play_queue.get_current_index()))
+    }
+
+    pub fn play_queue_position(&self, position: usize) {
+        let mut play_queue = self.current_queue.lock().unwrap();
+        if let Some(play_queue) = play_queue.as_mut() {
+            while play_queue.get_current_index() < position && play_queue.has_next() {
+                play_queue.switch_to_next();
+            }
+            while play_queue.get_current_index() > position && play_queue.has_previous() {
+                play_queue.switch_to_previous();
+            }
+            if let Some(source) = play_queue.get_current_source() {
+                self.send_command(PlayerCommand::Play(source));
+            }
+        }
+    }
+
+    pub fn play_next(&self) {
+        let mut play_queue = self.current_queue.lock().unwrap();
+        if let Some(play_queue) = play_queue.as_mut() {
+            if play_queue.has_next() {
+                play_queue.switch_to_next();
+                if let Some(source) = play_queue.get_current_source() {
+                    self.send_command(PlayerCommand::Play(source));
+                }
+            }
+        }
+    }
+
+    pub fn play_previous(&self) {
+        let mut play_queue = self.current_queue.lock().unwrap();
+        if let Some(play_queue) = play_queue.as_mut() {
+            if play_queue.has_previous() {
+                play_queue.switch_to_previous();
+                if let Some(source) = play_queue.get_current_source() {
+                    self.send_command(PlayerCommand::Play(source));
+                }
+            }
+        }
+    }
+
+    // Stops the zone and hands its queue with the current position over to the caller
+    pub fn take_playback(&self) -> Option<(Box<dyn PlayQueue>, f32)> {
+        let progress = match *self.player_state.read().unwrap() {
+            PlayerState::Playing(position) => position,
+            PlayerState::Paused(position) => position,
+            PlayerState::Stopped => self.restored_progress.lock().unwrap().unwrap_or(0.),
+            PlayerState::PlaybackFinished => 0.,
+        };
+        let queue = self.current_queue.lock().unwrap().take()?;
+        self.restored_progress.lock().unwrap().take();
+        self.send_command(PlayerCommand::Pause);
+        Some((queue, progress))
+    }
+
+    pub fn accept_playback(&self, play_queue: Box<dyn PlayQueue>, progress: f32) {
+        self.play_queue(play_queue);
+        if progress > 0. {
+            self.send_command(PlayerCommand::Seek(progress));
+        }
+    }
+
+    pub fn close(&self) {
+        self.send_command(PlayerCommand::Close);
+    }
+
+    fn get_next_source(&self) -> Option<Box<PlaybackSource>> {
+        if self.get_stop_after() == Some(StopAfter::Track) {
+            return None;
+        }
+        self.current_queue.lock().unwrap()
+            .as_ref()
+            .and_then(|queue| queue.get_next_source())
+    }
+
+    fn on_player_switched_to_next(&self, player: &dyn Player) {
+        let switched = match self.current_queue.lock().unwrap().as_mut() {
+            Some(play_queue) if play_queue.has_next() => {
+                play_queue.switch_to_next();
+                true
+            },
+            _ => false,
+        };
+        // The queue lock is released first, get_next_source takes it again
+        let next_source = if switched { self.get_next_source() } else { None };
+        log::debug!("Player switched to the next source");
+        player.set_next(next_source);
+    }
+
+    fn create_player(&self, player_id: &str) -> Result<Box<dyn Player>> {
+        let player_factories = self.player_factories.read().unwrap();
+        let factory = player_factories.get(player_id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown player {}", player_id))?;
+        let player = factory.create_player()?;
+        self.restore_volume(player_id, player.as_ref());
+        Ok(player)
+    }
+
+    // Renderers are found by discovery after zones are restored, so a zone waits for its player instead of failing
+    fn create_initial_player(&self) -> (String, Box<dyn Player>, bool) {
+        let player_id = self.player_id.read().unwrap().clone();
+        match self.create_player(&player_id) {
+            Ok(player) => (player_id, player, false),
+            Err(err) => {
+                log::warn!("Player {} of zone {} is not available yet: {}", player_id, self.id, err);
+                let player = Box::new(UnavailablePlayer::new(&player_id));
+                (player_id, player, true)
+            },
+        }
+    }
+
+    fn is_player_known(&self, player_id: &str) -> bool {
+        self.player_factories.read().unwrap().contains_key(player_id)
+    }
+
+    fn get_volume_property(&self, player_id: &str) -> Property<String> {
+        self.settings.get_string(format!("playback.{}.volume", player_id).as_str())
+    }
+
+    fn restore_volume(&self, player_id: &str, player: &dyn Player) {
+        let volume = self.get_volume_property(player_id).get().parse::<f32>().unwrap_or(1.);
+        player.set_volume(volume.clamp(0., 1.));
+    }
+
+    fn save_volume(&self, player_id: &str, volume: f32) {
+        self.get_volume_property(player_id).set(format!("{}", volume));
+    }
+
+    fn attach_player(&self, player: &dyn Player, generation: u64) {
+        let sender = self.sender.clone();
+        player.set_event_sender(PlayerEventSender::new(move |event| {
+            let _ = sender.send(ZoneMessage::PlayerEvent(generation, event));
+        }));
+    }
+
+    fn on_player_event(&self, player: &dyn Player, event: PlayerEvent) {
+        match event {
+            PlayerEvent::StateChanged(state) => {
+                *self.player_state.write().unwrap() = state;
+                if state == PlayerState::PlaybackFinished {
+                    self.on_playback_finished();
+                }
+            },
+            PlayerEvent::SwitchedToNext => {
+                self.on_player_switched_to_next(player);
+            },
+        }
+    }
+
+    // Emits OnStateUpdated only if something visible has changed since the last call
+    fn publish_state(&self, player: &dyn Player, last_published: &mut String) {
+        let state = *self.player_state.read().unwrap();
+
+        let mut event = OnStateUpdated::default();
+        let zone_name = self.name.read().unwrap();
+
+        event.zone_id = self.id;
+        event.zone_name = zone_name.as_str();
+        event.current_player_name = player.get_name();
+        event.volume = (player.get_volume() * 100.).round() as i32;
+        *self.volume.write().unwrap() = event.volume;
+        event.is_muted = player.is_muted();
+
+        let queue = self.current_queue.lock().unwrap();
+        if let Some(queue) = queue.as_ref() {
+            event.title = queue.get_current_title();
+            event.cover_picture = queue.get_current_cover();
+        } else {
+            event.title = "Playback stopped";
+        }
+
+        match state {
+            PlayerState::Playing(position) => {
+                event.is_playing = true;
+                event.progress = (position * 1000.) as i32;
+            },
+            PlayerState::Paused(position) => {
+                event.is_playing = false;
+                event.progress = (position * 1000.) as i32;
+            },
+            PlayerState::Stopped => {
+                event.is_playing = false;
+                event.progress = match *self.restored_progress.lock().unwrap() {
+                    Some(progress) => (progress * 1000.) as i32,
+                    None => 0,
+                };
+            }
+            PlayerState::PlaybackFinished => {
+                event.is_playing = true;
+                event.progress = 0;
+            },
+        }
+
+        let published = serde_json::to_string(&event).unwrap();
+        if *last_published != published {
+            self.event_emitter.emit_event(&event);
+            *last_published = published;
+        }
+    }
+
+    fn run_task(&self, task_context: &TaskContext, receiver: Receiver<ZoneMessage>) {
+        let (mut current_player_id, mut player, mut is_waiting_for_player) = self.create_initial_player();
+        let mut player_generation = 0;
+        let mut last_published = String::new();
+        self.attach_player(player.as_ref(), player_generation);
+
+        while !task_context.is_interrupted() {
+            // The player is created once, a failure leaves the zone unavailable until it switches to another player
+            if is_waiting_for_player && self.is_player_known(&current_player_id) {
+                is_waiting_for_player = false;
+                match self.create_player(&current_player_id) {
+                    Ok(new_player) => {
+                        player = new_player;
+                        player_generation += 1;
+                        self.attach_player(player.as_ref(), player_generation);
+                        log::debug!("Player {} of zone {} is available", current_player_id, self.id);
+                    },
+                    Err(err) => {
+                        log::error!("Failed to create player {} for zone {}: {}", current_player_id, self.id, err);
+                    },
+                }
+            }
+
+            let timeout = player.get_poll_interval().unwrap_or(IDLE_TIMEOUT);
+            match receiver.recv_timeout(timeout) {
+                Ok(ZoneMessage::Command(cmd)) => {
+                    match cmd {
+                        PlayerCommand::SwitchPlayer(player_id) => {
+                            match self.create_player(&player_id) {
+                                Ok(new_player) => {
+                                    player.pause();
+                                    player = new_player;
+                                    player_generation += 1;
+                                    *self.player_state.write().unwrap() = PlayerState::Stopped;
+                                    self.attach_player(player.as_ref(), player_generation);
+                                    current_player_id = player_id.clone();
+                                    is_waiting_for_player = false;
+                                    log::debug!("Zone {} switched to player {}", self.id, player_id);
+                                },
+                                Err(err) => {
+                                    log::error!("Failed to create player {}: {}", player_id, err);
+                                    *self.player_id.write().unwrap() = current_player_id.clone();
+                                }
+                            }
+                        },
+                        PlayerCommand::Play(source) => {
+                            log::debug!("Playing source {:?}", source);
+                            player.play(source);
+                            player.set_next(self.get_next_source());
+                        },
+                        PlayerCommand::Pause => {
+                            log::debug!("Pausing playback");
+                            player.pause();
+                        },
+                        PlayerCommand::Resume => {
+                            log::debug!("Resuming playback");
+                            player.resume();
+                        }
+                        PlayerCommand::Seek(progress) => {
+                            log::debug!("Seeking to {}", progress);
+                            player.seek(progress);
+                        }
+                        PlayerCommand::SetVolume(volume) => {
+                            log::debug!("Setting volume to {}", volume);
+                            player.set_volume(volume);
+                            self.save_volume(&current_player_id, volume);
+                        }
+                        PlayerCommand::SetTransientVolume(volume) => {
+                            player.set_volume(volume);
+                        }
+                        PlayerCommand::RestoreVolume => {
+                            self.restore_volume(&current_player_id, player.as_ref());
+                        }
+                        PlayerCommand::Mute(muted) => {
+                            log::debug!("Setting muted to {}", muted);
+                            player.mute(muted);
+                        }
+                        PlayerCommand::SetNext(source) => {
+                            player.set_next(source);
+                        }
+                        PlayerCommand::Close => {
+                            log::debug!("Closing zone {}", self.id);
+                            player.pause();
+                            break;
+                        }
+                    }
+                    player.poll();
+                },
+                Ok(ZoneMessage::PlayerEvent(generation, event)) => {
+                    // Events of a player that was already replaced are dropped
+                    if generation == player_generation {
+                        self.on_player_event(player.as_ref(), event);
+                    }
+                },
+                Err(RecvTimeoutError::Timeout) => {
+                    player.poll();
+                },
+                Err(RecvTimeoutError::Disconnected) => {
+                    log::debug!("Zone {} channel disconnected", self.id);
+                    break;
+                }
+            };
+
+            self.publish_state(player.as_ref(), &mut last_published);
+        }
+    }
+
+    fn on_playback_finished(&self) {
+        let has_next = self.current_queue.lock().unwrap()
+            .as_ref()
+            .map(|queue| queue.has_next())
+            .unwrap_or(false);
+
+        let mut stop_after = self.stop_after.lock().unwrap();
+        if *stop_after == Some(StopAfter::Track) || !has_next {
+            if stop_after.take().is_some() {
+                log::debug!("Zone {} stopped by the sleep timer", self.id);
+            }
+            return;
+        }
+        drop(stop_after);
+
+        self.play_next();
+    }
+
+    pub fn save_snapshot(&self) -> Result<()> {
+        let queue_snapshot = self.current_queue.lock().unwrap()
+            .as_ref()
+            .and_then(|queue| queue.get_snapshot());
+
+        match queue_snapshot {
+            Some(queue_snapshot) => {
+                let progress = match *self.player_state.read().unwrap() {
+                    PlayerState::Playing(position) => position,
+                    PlayerState::Paused(position) => position,
+                    PlayerState::Stopped => self.restored_progress.lock().unwrap().unwrap_or(0.),
+                    PlayerState::PlaybackFinished => 0.,
+                };
+                let snapshot = PlaybackSnapshot {
+                    queue: queue_snapshot,
+                    progress,
+                };
+                snapshot.save(self.id)
+            },
+            None => PlaybackSnapshot::clear(self.id),
+        }
+    }
+
+    pub fn restore_snapshot(&self) -> Result<()> {
+        if let Some(snapshot) = PlaybackSnapshot::load(self.id)? {
+            if let Some(queue) = snapshot.queue.restore()? {
+                log::debug!("Restored play queue {:?} of zone {}", snapshot.queue, self.id);
+                self.current_queue.lock().unwrap().replace(queue);
+                self.restored_progress.lock().unwrap().replace(snapshot.progress);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn on_collection_updated(&self) {
+        let is_empty = match self.current_queue.lock().unwrap().as_mut() {
+            Some(queue) => {
+                if let Err(err) = queue.refresh() {
+                    log::error!("Failed to refresh queue: {}", err);
+                }
+                queue.get_current_source().is_none()
+            },
+            None => return,
+        };
+        // Every item of the queue was removed from the collection
+        if is_empty {
+            self.clear_queue();
+            return;
+        }
+        self.send_command(PlayerCommand::SetNext(self.get_next_source()));
+    }
+}
@@ -3,7 +3,7 @@ use std::io::{Read, Write};
 use anyhow::{Context, Result};
 use camino::{Utf8Path, Utf8PathBuf};
 
-use crate::storage::remote::adapter::{RemoteStorageAdapter, RemoteStorageFactory, RemoteStorageSettings};
+use crate::storage::remote::adapter::{FileNotFound, RemoteStorageAdapter, RemoteStorageFactory, RemoteStorageSettings};
 
 pub struct FileSystemAdapter {
     base_path: Utf8PathBuf,
@@ -22,18 +22,23 @@ impl FileSystemAdapter {
 impl RemoteStorageAdapter for FileSystemAdapter {
     fn list_dir(&mut self, path: &Utf8Path) -> Result<Vec<String>> {
         let full_path = self.base_path.join(path);
-        std::fs::read_dir(full_path)
-            .context(format!("Failed to list directory {:?}", path))
-            .map(|read_dir| {
-                read_dir.filter_map(|entry| entry.ok())
-                        .map(|entry| entry.file_name().to_string_lossy().into_owned())
-                        .collect()
-            })
+        let read_dir = match std::fs::read_dir(full_path) {
+            Ok(read_dir) => read_dir,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Err(FileNotFound(path.to_path_buf()).into()),
+            Err(err) => return Err(anyhow::Error::new(err).context(format!("Failed to list directory {:?}", path))),
+        };
+        Ok(read_dir.filter_map(|entry| entry.ok())
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect())
     }
 
     fn read_file(&mut self, path: &Utf8Path, dest: &mut dyn Write) -> Result<()> {
         let full_path = self.base_path.join(path);
-        let mut file = std::fs::File::open(full_path).context(format!("Failed to open file {:?}", path))?;
+        let mut file = match std::fs::File::open(full_path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Err(FileNotFound(path.to_path_buf()).into()),
+            Err(err) => return Err(anyhow::Error::new(err).context(format!("Failed to open file {:?}", path))),
+        };
         std::io::copy(&mut file, dest).context("Failed to read file")?;
         Ok(())
     }
@@ -51,6 +56,19 @@ impl RemoteStorageAdapter for FileSystemAdapter {
         std::fs::remove_file(&full_path).context(format!("Failed to remove file {:?}", full_path))?;
         Ok(())
     }
+
+    fn get_file_size(&mut self, path: &Utf8Path) -> Result<u64> {
+        let full_path = self.base_path.join(path);
+        let metadata = std::fs::metadata(&full_path).context(format!("Failed to get metadata of {:?}", full_path))?;
+        Ok(metadata.len())
+    }
+
+    fn rename_file(&mut self, from: &Utf8Path, to: &Utf8Path) -> Result<()> {
+        let from_path = self.base_path.join(from);
+        let to_path = self.base_path.join(to);
+        std::fs::rename(&from_path, &to_path).context(format!("Failed to rename {:?} to {:?}", from_path, to_path))?;
+        Ok(())
+    }
 }
 pub struct FileSystemFactory {
 
@@ -74,5 +92,9 @@ impl RemoteStorageFactory for FileSystemFactory {
             base_path: Utf8PathBuf::from(&settings.url),
         }))   
     }
+
+    fn get_max_connections(&self) -> usize {
+        8
+    }
 }
 
@@ -4,39 +4,90 @@ use anyhow::{Context, Result};
 use camino::Utf8Path;
 use suppaftp::FtpStream;
 
-use crate::storage::remote::adapter::{RemoteStorageAdapter, RemoteStorageFactory, RemoteStorageSettings};
+use crate::storage::remote::adapter::{FileNotFound, RemoteStorageAdapter, RemoteStorageFactory, RemoteStorageSettings};
 
 pub struct FtpAdapter {
     stream: Cell<FtpStream>,
+    // Working directory after login, paths are relative to it
+    root: String,
+}
+
+impl FtpAdapter {
+    // Runs the operation in the parent folder of the path and returns to the root after it
+    fn in_parent_dir<T>(&mut self, path: &Utf8Path, operation: impl FnOnce(&mut FtpStream, &str) -> Result<T>) -> Result<T> {
+        let parent = path.parent().context(format!("Path has no parent folder '{:?}'", path))?;
+        let file_name = path.file_name().context(format!("Path has no file name '{:?}'", path))?;
+        let stream = self.stream.get_mut();
+        if !parent.as_str().is_empty() {
+            if let Err(err) = stream.cwd(parent.as_str()) {
+                if Self::is_missing(stream, parent) {
+                    return Err(FileNotFound(path.to_path_buf()).into());
+                }
+                return Err(anyhow::Error::new(err).context(format!("Failed to open folder {:?}", parent)));
+            }
+        }
+        let result = operation(stream, file_name);
+        let restored = stream.cwd(&self.root).context(format!("Failed to return to '{}'", self.root));
+        let value = result?;
+        restored?;
+        Ok(value)
+    }
+
+    // Servers answer 550 for a missing file and for a denied one alike, so the parent folder is listed to tell them apart
+    fn is_missing(stream: &mut FtpStream, path: &Utf8Path) -> bool {
+        let parent = path.parent().map(|parent| parent.as_str()).filter(|parent| !parent.is_empty());
+        match (stream.nlst(parent), path.file_name()) {
+            (Ok(names), Some(file_name)) => !names.iter().any(|name| name.rsplit('/').next() == Some(file_name)),
+            _ => false,
+        }
+    }
 }
 
 impl RemoteStorageAdapter for FtpAdapter {
     fn list_dir(&mut self, path: &Utf8Path) -> Result<Vec<String>> {
         let stream = self.stream.get_mut();
-        let list = stream.list(Some(path.as_str()))?;
-        Ok(list)
+        let dir = if path.as_str().is_empty() { None } else { Some(path.as_str()) };
+        let names = match stream.nlst(dir) {
+            Ok(names) => names,
+            Err(err) => {
+                if dir.is_some() && Self::is_missing(stream, path) {
+                    return Err(FileNotFound(path.to_path_buf()).into());
+                }
+                return Err(anyhow::Error::new(err).context(format!("Failed to list directory {:?}", path)));
+            },
+        };
+        // Some servers answer NLST with full paths
+        Ok(names.iter()
+            .filter_map(|name| name.rsplit('/').next())
+            .filter(|name| !name.is_empty() && *name != "." && *name != "..")
+            .map(|name| name.to_string())
+            .collect())
     }
 
     fn read_file(&mut self, path: &Utf8Path, dest: &mut dyn Write) -> Result<()> {
-        let stream = self.stream.get_mut();
-        let parent = path.parent().context(format!("Path has no parent folder '{:?}'", path))?;
-        let file_name = path.file_name().context(format!("Path has no file name '{:?}'", path))?;
-        stream.cwd(parent)?;
-        let mut reader = stream.retr_as_stream(file_name)?;
-        std::io::copy(&mut reader, dest)?;
-        stream.finalize_retr_stream(reader)?;
-        Ok(())
+        self.in_parent_dir(path, |stream, file_name| {
+            let mut reader = match stream.retr_as_stream(file_name) {
+                Ok(reader) => reader,
+                Err(err) => {
+                    if Self::is_missing(stream, Utf8Path::new(file_name)) {
+                        return Err(FileNotFound(path.to_path_buf()).into());
+                    }
+                    return Err(anyhow::Error::new(err).context(format!("Failed to open file {:?}", path)));
+                },
+            };
+            std::io::copy(&mut reader, dest)?;
+            stream.finalize_retr_stream(reader)?;
+            Ok(())
+        })
     }
 
     fn write_file(&mut self, path: &Utf8Path, src: &mut dyn Read) -> Result<()> {
-        let stream = self.stream.get_mut();
-        let parent = path.parent().context(format!("Path has no parent folder '{:?}'", path))?;
-        let filename = path.file_name().context(format!("Path has no file name '{:?}'", path))?;
-        stream.cwd(parent)?;
-        let mut writer = stream.put_with_stream(filename)?;
-        std::io::copy(src, &mut writer)?;
-        stream.finalize_retr_stream(writer)?;
-        Ok(())
+        self.in_parent_dir(path, |stream, file_name| {
+            let mut writer = stream.put_with_stream(file_name)?;
+            std::io::copy(src, &mut writer)?;
+            stream.finalize_put_stream(writer)?;
+            Ok(())
+        })
     }
 
     fn remove_file(&mut self, path: &Utf8Path) -> Result<()> {
@@ -44,6 +95,24 @@ impl RemoteStorageAdapter for FtpAdapter {
         let file_path = path.as_str();
         stream.rm(file_path).context(format!("Failed to delete file '{}'", file_path))
     }
+
+    fn get_file_size(&mut self, path: &Utf8Path) -> Result<u64> {
+        let stream = self.stream.get_mut();
+        let size = stream.size(path.as_str()).context(format!("Failed to get size of '{}'", path))?;
+        Ok(size as u64)
+    }
+
+    fn rename_file(&mut self, from: &Utf8Path, to: &Utf8Path) -> Result<()> {
+        let stream = self.stream.get_mut();
+        // Many servers refuse to rename onto an existing file, it is removed first then
+        if let Err(err) = stream.rename(from.as_str(), to.as_str()) {
+            if stream.rm(to.as_str()).is_err() {
+                return Err(anyhow::Error::new(err).context(format!("Failed to rename '{}' to '{}'", from, to)));
+            }
+            stream.rename(from.as_str(), to.as_str()).context(format!("Failed to rename '{}' to '{}'", from, to))?;
+        }
+        Ok(())
+    }
 }
 
 impl Drop for FtpAdapter {
@@ -72,9 +141,16 @@ impl RemoteStorageFactory for FtpStorageFactory {
     fn connect(&self, settings: &RemoteStorageSettings) -> Result<Box<dyn RemoteStorageAdapter>> {
         let mut ftp_stream = FtpStream::connect(&settings.url)?;
         ftp_stream.login(&settings.user, &settings.password)?;
+        let root = ftp_stream.pwd()?;
         Ok(Box::new(FtpAdapter {
-            stream: Cell::new(ftp_stream)
-        }))   
+            stream: Cell::new(ftp_stream),
+            root,
+        }))
+    }
+
+    // FTP servers usually limit the number of sessions per user
+    fn get_max_connections(&self) -> usize {
+        2
     }
 }
 
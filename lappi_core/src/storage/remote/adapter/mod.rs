@@ -1,17 +1,35 @@
 pub mod file_system;
 pub mod ftp;
+pub mod s3;
+pub mod sftp;
+pub mod webdav;
+
+#[cfg(test)]
+mod tests;
 
 use std::io::{Read, Write};
 
 use anyhow::{Result, anyhow};
 use serde::{Serialize, Deserialize};
-use camino::Utf8Path;
+use camino::{Utf8Path, Utf8PathBuf};
 
 pub trait RemoteStorageAdapter {
     fn list_dir(&mut self, path: &Utf8Path) -> Result<Vec<String>>;
     fn read_file(&mut self, path: &Utf8Path, dest: &mut dyn Write) -> Result<()>;
     fn write_file(&mut self, path: &Utf8Path, src: &mut dyn Read) -> Result<()>;
     fn remove_file(&mut self, path: &Utf8Path) -> Result<()>;
+    fn get_file_size(&mut self, path: &Utf8Path) -> Result<u64>;
+    // Replaces the destination if it exists
+    fn rename_file(&mut self, from: &Utf8Path, to: &Utf8Path) -> Result<()>;
+}
+
+// Returned only when the server definitely reports that the file does not exist
+#[derive(Debug, thiserror::Error)]
+#[error("File {0:?} does not exist")]
+pub struct FileNotFound(pub Utf8PathBuf);
+
+pub fn is_not_found(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<FileNotFound>().is_some()
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -20,11 +38,18 @@ pub struct RemoteStorageSettings {
     pub user: String,
     pub password: String,
     pub path: String,
+    #[serde(default)]
+    pub region: String,
+    // SHA256 fingerprint of the SFTP host key, known_hosts is used when it is empty
+    #[serde(default)]
+    pub host_key: String,
 }
 
-pub trait RemoteStorageFactory {
+pub trait RemoteStorageFactory: Send + Sync {
     fn get_name(&self) -> &'static str;
     fn connect(&self, settings: &RemoteStorageSettings) -> Result<Box<dyn RemoteStorageAdapter>>;
+    // How many connections can be opened at once for parallel transfers
+    fn get_max_connections(&self) -> usize;
 }
 
 pub struct DummyRemoteStorage {
@@ -53,4 +78,12 @@ impl RemoteStorageAdapter for DummyRemoteStorage {
     fn remove_file(&mut self, _: &Utf8Path) -> Result<()> {
         Err(anyhow!("Remote storage is not available"))
     }
+
+    fn get_file_size(&mut self, _: &Utf8Path) -> Result<u64> {
+        Err(anyhow!("Remote storage is not available"))
+    }
+
+    fn rename_file(&mut self, _: &Utf8Path, _: &Utf8Path) -> Result<()> {
+        Err(anyhow!("Remote storage is not available"))
+    }
 }
\ No newline at end of file
@@ -0,0 +1,315 @@
+use std::io::{Read, Write};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use reqwest::blocking::{Client, Response};
+use reqwest::{Method, StatusCode};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::storage::remote::adapter::{FileNotFound, RemoteStorageAdapter, RemoteStorageFactory, RemoteStorageSettings};
+use crate::utils::xml;
+
+static DEFAULT_REGION: &str = "us-east-1";
+static UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+// Files larger than one part are sent with a multipart upload, S3 needs at least 5 MB for all parts but the last
+static PART_SIZE: usize = 8 * 1024 * 1024;
+static CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Characters that are left as is by the AWS signature URI encoding
+const URI_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+const URI_PATH_ENCODE_SET: &AsciiSet = &URI_ENCODE_SET.remove(b'/');
+
+// Talks to S3-compatible services with path-style requests signed by AWS Signature Version 4
+pub struct S3Adapter {
+    client: Client,
+    endpoint: Url,
+    bucket: String,
+    prefix: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+}
+
+impl S3Adapter {
+    fn get_key(&self, path: &Utf8Path) -> String {
+        let path = path.as_str().trim_start_matches('/');
+        if self.prefix.is_empty() {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.prefix, path)
+        }
+    }
+
+    fn send(&self, method: Method, key: &str, query: &[(&str, &str)], amz_headers: &[(&str, String)], body: Option<Vec<u8>>) -> Result<Response> {
+        let host = match self.endpoint.port() {
+            Some(port) => format!("{}:{}", self.endpoint.host_str().unwrap_or(""), port),
+            None => self.endpoint.host_str().unwrap_or("").to_string(),
+        };
+        let uri = format!("/{}/{}", self.bucket, utf8_percent_encode(key, URI_PATH_ENCODE_SET));
+        let mut query: Vec<String> = query.iter()
+            .map(|(name, value)| format!("{}={}", utf8_percent_encode(name, URI_ENCODE_SET), utf8_percent_encode(value, URI_ENCODE_SET)))
+            .collect();
+        query.sort();
+        let query = query.join("&");
+
+        let now = chrono::Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let mut headers = vec![
+            ("x-amz-content-sha256", UNSIGNED_PAYLOAD.to_string()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        headers.extend(amz_headers.iter().cloned());
+        headers.sort();
+        let mut canonical_headers = format!("host:{}\n", host);
+        for (name, value) in &headers {
+            canonical_headers.push_str(&format!("{}:{}\n", name, value.trim()));
+        }
+        let signed_headers = std::iter::once("host")
+            .chain(headers.iter().map(|(name, _)| *name))
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method, uri, query, canonical_headers, signed_headers, UNSIGNED_PAYLOAD,
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date, scope, to_hex(&Sha256::digest(canonical_request.as_bytes())),
+        );
+
+        let mut signing_key = hmac(format!("AWS4{}", self.secret_key).as_bytes(), date.as_bytes());
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            signing_key = hmac(&signing_key, part.as_bytes());
+        }
+        let signature = to_hex(&hmac(&signing_key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, signed_headers, signature,
+        );
+
+        let mut url = format!("{}://{}{}", self.endpoint.scheme(), host, uri);
+        if !query.is_empty() {
+            url.push('?');
+            url.push_str(&query);
+        }
+        let mut request = self.client.request(method, url)
+            .header("Authorization", authorization);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        if let Some(body) = body {
+            request = request.body(body);
+        }
+
+        let response = request.send()?;
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text().unwrap_or_default();
+            // HEAD responses have no body to tell a missing key from a missing bucket
+            let code = xml::find_element(&text, "Code");
+            if status == StatusCode::NOT_FOUND && (code == Some("NoSuchKey") || (text.is_empty() && !key.is_empty())) {
+                return Err(FileNotFound(Utf8PathBuf::from(key)).into());
+            }
+            let message = xml::find_element(&text, "Message").unwrap_or(status.as_str()).to_string();
+            anyhow::bail!("S3 request for '{}' failed: {}", key, message);
+        }
+        Ok(response)
+    }
+
+    fn start_multipart_upload(&self, key: &str) -> Result<String> {
+        let body = self.send(Method::POST, key, &[("uploads", "")], &[], None)?.text()?;
+        xml::find_element(&body, "UploadId")
+            .map(xml::unescape)
+            .ok_or_else(|| anyhow::anyhow!("Server did not return an upload id for '{}'", key))
+    }
+
+    fn upload_parts(&self, key: &str, upload_id: &str, first_part: Vec<u8>, src: &mut dyn Read) -> Result<()> {
+        let mut etags = Vec::new();
+        let mut part = first_part;
+        while !part.is_empty() {
+            let part_number = (etags.len() + 1).to_string();
+            let response = self.send(Method::PUT, key, &[("partNumber", &part_number), ("uploadId", upload_id)], &[], Some(part))?;
+            let etag = response.headers().get(reqwest::header::ETAG)
+                .and_then(|etag| etag.to_str().ok())
+                .ok_or_else(|| anyhow::anyhow!("Server did not return ETag of part {}", part_number))?;
+            etags.push(etag.to_string());
+            part = read_part(src)?;
+        }
+
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for (index, etag) in etags.iter().enumerate() {
+            body.push_str(&format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", index + 1, xml::escape(etag)));
+        }
+        body.push_str("</CompleteMultipartUpload>");
+        let text = self.send(Method::POST, key, &[("uploadId", upload_id)], &[], Some(body.into_bytes()))?.text()?;
+        // Completion can fail after the status is sent, the error is in the body then
+        if xml::find_element(&text, "Code").is_some() {
+            let message = xml::find_element(&text, "Message").unwrap_or("unknown error");
+            anyhow::bail!("S3 upload of '{}' failed: {}", key, message);
+        }
+        Ok(())
+    }
+}
+
+impl RemoteStorageAdapter for S3Adapter {
+    fn list_dir(&mut self, path: &Utf8Path) -> Result<Vec<String>> {
+        let mut prefix = self.get_key(path);
+        if !prefix.is_empty() && !prefix.ends_with('/') {
+            prefix.push('/');
+        }
+
+        let mut names = Vec::new();
+        let mut continuation_token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("delimiter", "/"), ("prefix", prefix.as_str())];
+            if let Some(token) = continuation_token.as_deref() {
+                query.push(("continuation-token", token));
+            }
+            let body = self.send(Method::GET, "", &query, &[], None)
+                .context(format!("Failed to list directory {:?}", path))?
+                .text()?;
+
+            let keys = xml::find_elements(&body, "CommonPrefixes").into_iter()
+                .filter_map(|common_prefix| xml::find_element(common_prefix, "Prefix"))
+                .chain(xml::find_elements(&body, "Contents").into_iter()
+                    .filter_map(|content| xml::find_element(content, "Key")));
+            for key in keys {
+                let key = xml::unescape(key);
+                let name = key.strip_prefix(prefix.as_str()).unwrap_or(&key).trim_end_matches('/');
+                if !name.is_empty() {
+                    names.push(name.to_string());
+                }
+            }
+
+            continuation_token = match xml::find_element(&body, "IsTruncated") {
+                Some("true") => xml::find_element(&body, "NextContinuationToken").map(xml::unescape),
+                _ => None,
+            };
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+        Ok(names)
+    }
+
+    fn read_file(&mut self, path: &Utf8Path, dest: &mut dyn Write) -> Result<()> {
+        let key = self.get_key(path);
+        let mut response = self.send(Method::GET, &key, &[], &[], None).context(format!("Failed to open file {:?}", path))?;
+        response.copy_to(dest).context("Failed to read file")?;
+        Ok(())
+    }
+
+    // Only one part of the file is kept in memory
+    fn write_file(&mut self, path: &Utf8Path, src: &mut dyn Read) -> Result<()> {
+        let key = self.get_key(path);
+        let first_part = read_part(src).context("Failed to read source")?;
+        if first_part.len() < PART_SIZE {
+            self.send(Method::PUT, &key, &[], &[], Some(first_part)).context(format!("Failed to create file {:?}", path))?;
+            return Ok(());
+        }
+
+        let upload_id = self.start_multipart_upload(&key).context(format!("Failed to create file {:?}", path))?;
+        if let Err(err) = self.upload_parts(&key, &upload_id, first_part, src) {
+            if let Err(abort_err) = self.send(Method::DELETE, &key, &[("uploadId", &upload_id)], &[], None) {
+                log::warn!("Failed to abort upload of {:?}: {}", path, abort_err);
+            }
+            return Err(err.context(format!("Failed to create file {:?}", path)));
+        }
+        Ok(())
+    }
+
+    fn remove_file(&mut self, path: &Utf8Path) -> Result<()> {
+        let key = self.get_key(path);
+        self.send(Method::DELETE, &key, &[], &[], None).context(format!("Failed to remove file {:?}", path))?;
+        Ok(())
+    }
+
+    fn get_file_size(&mut self, path: &Utf8Path) -> Result<u64> {
+        let key = self.get_key(path);
+        let response = self.send(Method::HEAD, &key, &[], &[], None).context(format!("Failed to get size of {:?}", path))?;
+        // The body of a HEAD response is empty, so the size is taken from the header
+        response.headers().get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse().ok())
+            .ok_or_else(|| anyhow::anyhow!("Server did not report size of {:?}", path))
+    }
+
+    // S3 has no rename, the object is copied on the server side and the source is removed
+    fn rename_file(&mut self, from: &Utf8Path, to: &Utf8Path) -> Result<()> {
+        let from_key = self.get_key(from);
+        let copy_source = format!("/{}/{}", self.bucket, utf8_percent_encode(&from_key, URI_PATH_ENCODE_SET));
+        self.send(Method::PUT, &self.get_key(to), &[], &[("x-amz-copy-source", copy_source)], None)
+            .context(format!("Failed to copy {:?} to {:?}", from, to))?;
+        self.send(Method::DELETE, &from_key, &[], &[], None).context(format!("Failed to remove file {:?}", from))?;
+        Ok(())
+    }
+}
+
+pub struct S3StorageFactory {
+
+}
+
+impl S3StorageFactory {
+    pub fn new() -> Self {
+        Self {
+
+        }
+    }
+}
+
+impl RemoteStorageFactory for S3StorageFactory {
+    fn get_name(&self) -> &'static str {
+        "S3-compatible"
+    }
+
+    // The path setting holds the bucket name optionally followed by a key prefix
+    fn connect(&self, settings: &RemoteStorageSettings) -> Result<Box<dyn RemoteStorageAdapter>> {
+        let endpoint = Url::parse(&settings.url).context(format!("Invalid S3 endpoint {}", settings.url))?;
+        let path = settings.path.trim_matches('/');
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+        if bucket.is_empty() {
+            anyhow::bail!("S3 bucket is not set");
+        }
+        let region = if settings.region.is_empty() { DEFAULT_REGION } else { settings.region.as_str() };
+
+        // The default timeout covers the whole request, which large files do not fit in
+        let client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(None)
+            .build()?;
+        Ok(Box::new(S3Adapter {
+            client,
+            endpoint,
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+            region: region.to_string(),
+            access_key: settings.user.clone(),
+            secret_key: settings.password.clone(),
+        }))
+    }
+
+    fn get_max_connections(&self) -> usize {
+        8
+    }
+}
+
+fn read_part(src: &mut dyn Read) -> Result<Vec<u8>> {
+    let mut part = Vec::new();
+    Read::take(&mut *src, PART_SIZE as u64).read_to_end(&mut part)?;
+    Ok(part)
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
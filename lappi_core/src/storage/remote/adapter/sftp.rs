@@ -0,0 +1,202 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use base64::{Engine as _, engine::general_purpose};
+use camino::{Utf8Path, Utf8PathBuf};
+use ssh2::{CheckResult, ErrorCode, HashType, KnownHostFileKind, RenameFlags, Session, Sftp};
+use url::Url;
+
+use crate::storage::remote::adapter::{FileNotFound, RemoteStorageAdapter, RemoteStorageFactory, RemoteStorageSettings};
+
+static DEFAULT_SFTP_PORT: u16 = 22;
+static CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// LIBSSH2_FX_NO_SUCH_FILE
+static SFTP_NO_SUCH_FILE: i32 = 2;
+
+pub struct SftpAdapter {
+    sftp: Sftp,
+    base_path: Utf8PathBuf,
+}
+
+impl SftpAdapter {
+    fn ensure_directory_exists(&self, path: &Utf8Path) -> Result<()> {
+        let mut current = Utf8PathBuf::new();
+        for component in path.parent().into_iter().flat_map(|parent| parent.components()) {
+            current.push(component);
+            if self.sftp.stat(current.as_std_path()).is_err() {
+                self.sftp.mkdir(current.as_std_path(), 0o755)
+                    .context(format!("Failed to create directory {:?}", current))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RemoteStorageAdapter for SftpAdapter {
+    fn list_dir(&mut self, path: &Utf8Path) -> Result<Vec<String>> {
+        let full_path = self.base_path.join(path);
+        let entries = match self.sftp.readdir(full_path.as_std_path()) {
+            Ok(entries) => entries,
+            Err(err) if err.code() == ErrorCode::SFTP(SFTP_NO_SUCH_FILE) => return Err(FileNotFound(path.to_path_buf()).into()),
+            Err(err) => return Err(anyhow::Error::new(err).context(format!("Failed to list directory {:?}", path))),
+        };
+        Ok(entries.into_iter()
+            .filter_map(|(entry_path, _)| entry_path.file_name().map(|name| name.to_string_lossy().into_owned()))
+            .collect())
+    }
+
+    fn read_file(&mut self, path: &Utf8Path, dest: &mut dyn Write) -> Result<()> {
+        let full_path = self.base_path.join(path);
+        let mut file = match self.sftp.open(full_path.as_std_path()) {
+            Ok(file) => file,
+            Err(err) if err.code() == ErrorCode::SFTP(SFTP_NO_SUCH_FILE) => return Err(FileNotFound(path.to_path_buf()).into()),
+            Err(err) => return Err(anyhow::Error::new(err).context(format!("Failed to open file {:?}", path))),
+        };
+        std::io::copy(&mut file, dest).context("Failed to read file")?;
+        Ok(())
+    }
+
+    fn write_file(&mut self, path: &Utf8Path, src: &mut dyn Read) -> Result<()> {
+        let full_path = self.base_path.join(path);
+        self.ensure_directory_exists(&full_path)?;
+        let mut file = self.sftp.create(full_path.as_std_path()).context(format!("Failed to create file {:?}", path))?;
+        std::io::copy(src, &mut file).context("Failed to write file")?;
+        Ok(())
+    }
+
+    fn remove_file(&mut self, path: &Utf8Path) -> Result<()> {
+        let full_path = self.base_path.join(path);
+        self.sftp.unlink(full_path.as_std_path()).context(format!("Failed to remove file {:?}", full_path))
+    }
+
+    fn get_file_size(&mut self, path: &Utf8Path) -> Result<u64> {
+        let full_path = self.base_path.join(path);
+        let stat = self.sftp.stat(full_path.as_std_path()).context(format!("Failed to get metadata of {:?}", full_path))?;
+        stat.size.ok_or_else(|| anyhow::anyhow!("Server did not report size of {:?}", full_path))
+    }
+
+    fn rename_file(&mut self, from: &Utf8Path, to: &Utf8Path) -> Result<()> {
+        let from_path = self.base_path.join(from);
+        let to_path = self.base_path.join(to);
+        let flags = RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE;
+        // Servers of SFTP version 3, like OpenSSH, ignore the flags and refuse to replace a file
+        if let Err(err) = self.sftp.rename(from_path.as_std_path(), to_path.as_std_path(), Some(flags)) {
+            if self.sftp.unlink(to_path.as_std_path()).is_err() {
+                return Err(anyhow::Error::new(err).context(format!("Failed to rename {:?} to {:?}", from_path, to_path)));
+            }
+            self.sftp.rename(from_path.as_std_path(), to_path.as_std_path(), Some(flags))
+                .context(format!("Failed to rename {:?} to {:?}", from_path, to_path))?;
+        }
+        Ok(())
+    }
+}
+
+pub struct SftpStorageFactory {
+
+}
+
+impl SftpStorageFactory {
+    pub fn new() -> Self {
+        Self {
+
+        }
+    }
+}
+
+impl RemoteStorageFactory for SftpStorageFactory {
+    fn get_name(&self) -> &'static str {
+        "SFTP"
+    }
+
+    fn connect(&self, settings: &RemoteStorageSettings) -> Result<Box<dyn RemoteStorageAdapter>> {
+        let (host, port) = parse_address(&settings.url)?;
+        let tcp = connect_tcp(&host, port)?;
+
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.handshake()?;
+        // The password must not be sent before the server is known
+        verify_host_key(&session, &host, port, &settings.host_key)?;
+        if settings.password.is_empty() {
+            session.userauth_agent(&settings.user)?;
+        } else {
+            session.userauth_password(&settings.user, &settings.password)?;
+        }
+        let sftp = session.sftp()?;
+
+        Ok(Box::new(SftpAdapter {
+            sftp,
+            base_path: Utf8PathBuf::from(&settings.path),
+        }))
+    }
+
+    fn get_max_connections(&self) -> usize {
+        4
+    }
+}
+
+// Accepts sftp://host:port URLs as well as plain host or host:port addresses
+fn parse_address(address: &str) -> Result<(String, u16)> {
+    let address = address.trim();
+    let url_text = if address.contains("://") { address.to_string() } else { format!("sftp://{}", address) };
+    let url = Url::parse(&url_text).context(format!("Invalid SFTP address {}", address))?;
+    if url.scheme() != "sftp" && url.scheme() != "ssh" {
+        anyhow::bail!("Unsupported scheme {} in SFTP address {}", url.scheme(), address);
+    }
+    let host = url.host_str()
+        .ok_or_else(|| anyhow::anyhow!("SFTP address {} has no host", address))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    Ok((host, url.port().unwrap_or(DEFAULT_SFTP_PORT)))
+}
+
+fn connect_tcp(host: &str, port: u16) -> Result<TcpStream> {
+    let addresses = (host, port).to_socket_addrs().context(format!("Failed to resolve {}", host))?;
+    let mut last_error = None;
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(tcp) => return Ok(tcp),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    match last_error {
+        Some(err) => Err(anyhow::Error::new(err).context(format!("Failed to connect to {}:{}", host, port))),
+        None => Err(anyhow::anyhow!("No addresses found for {}", host)),
+    }
+}
+
+// The key is checked against the configured fingerprint, or against known_hosts when none is configured
+fn verify_host_key(session: &Session, host: &str, port: u16, expected_fingerprint: &str) -> Result<()> {
+    let (key, _) = session.host_key().ok_or_else(|| anyhow::anyhow!("Server {} did not send a host key", host))?;
+    let hash = session.host_key_hash(HashType::Sha256)
+        .ok_or_else(|| anyhow::anyhow!("Failed to hash the host key of {}", host))?;
+    // Same format as ssh-keygen -l prints
+    let fingerprint = format!("SHA256:{}", general_purpose::STANDARD_NO_PAD.encode(hash));
+
+    let expected_fingerprint = expected_fingerprint.trim();
+    if !expected_fingerprint.is_empty() {
+        let expected = expected_fingerprint.strip_prefix("SHA256:").unwrap_or(expected_fingerprint).trim_end_matches('=');
+        if expected != fingerprint.trim_start_matches("SHA256:") {
+            anyhow::bail!("Host key of {} is {}, which does not match the configured fingerprint", host, fingerprint);
+        }
+        return Ok(());
+    }
+
+    let mut known_hosts = session.known_hosts()?;
+    let known_hosts_path = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| std::path::PathBuf::from(home).join(".ssh").join("known_hosts"));
+    if let Some(path) = known_hosts_path.filter(|path| path.exists()) {
+        known_hosts.read_file(&path, KnownHostFileKind::OpenSSH)
+            .context(format!("Failed to read {}", path.display()))?;
+    }
+    match known_hosts.check_port(host, port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => anyhow::bail!("Host key of {} is {}, which does not match known_hosts", host, fingerprint),
+        CheckResult::NotFound => anyhow::bail!("Host {} is not in known_hosts, set remote_storage.sftp.host_key to {} to trust it", host, fingerprint),
+        CheckResult::Failure => anyhow::bail!("Failed to check the host key of {}", host),
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Cursor, Error, ErrorKind, Read};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use percent_encoding::percent_decode_str;
+use tiny_http::{Header, Method, Request, Response, Server};
+use url::Url;
+
+use super::file_system::FileSystemFactory;
+use super::s3::S3StorageFactory;
+use super::sftp::SftpStorageFactory;
+use super::webdav::WebDavStorageFactory;
+use super::{is_not_found, RemoteStorageAdapter, RemoteStorageFactory, RemoteStorageSettings};
+
+// S3 splits uploads into 8 MB parts, so the large file takes three of them
+static LARGE_FILE_SIZE: usize = 17 * 1024 * 1024;
+
+type Files = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+fn gen_content(size: usize) -> Vec<u8> {
+    (0..size).map(|index| (index * 31 % 251) as u8).collect()
+}
+
+fn read_file(adapter: &mut dyn RemoteStorageAdapter, path: &str) -> anyhow::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    adapter.read_file(Utf8Path::new(path), &mut content)?;
+    Ok(content)
+}
+
+// The same scenario runs against every adapter
+fn check_adapter(adapter: &mut dyn RemoteStorageAdapter) {
+    let small = gen_content(1000);
+    adapter.write_file(Utf8Path::new("dir/sub/small file.bin"), &mut small.as_slice()).unwrap();
+    assert_eq!(read_file(adapter, "dir/sub/small file.bin").unwrap(), small);
+    assert_eq!(adapter.get_file_size(Utf8Path::new("dir/sub/small file.bin")).unwrap(), 1000);
+
+    let large = gen_content(LARGE_FILE_SIZE);
+    adapter.write_file(Utf8Path::new("dir/large.bin"), &mut large.as_slice()).unwrap();
+    assert!(read_file(adapter, "dir/large.bin").unwrap() == large);
+
+    let mut names = adapter.list_dir(Utf8Path::new("dir")).unwrap();
+    names.sort();
+    assert_eq!(names, vec!["large.bin", "sub"]);
+
+    // Storages without folders list a missing one as empty, the others report it as not found
+    match adapter.list_dir(Utf8Path::new("missing")) {
+        Ok(names) => assert!(names.is_empty()),
+        Err(err) => assert!(is_not_found(&err), "{:#}", err),
+    }
+
+    adapter.rename_file(Utf8Path::new("dir/sub/small file.bin"), Utf8Path::new("dir/large.bin")).unwrap();
+    assert_eq!(read_file(adapter, "dir/large.bin").unwrap(), small);
+    assert!(adapter.get_file_size(Utf8Path::new("dir/sub/small file.bin")).is_err());
+
+    adapter.remove_file(Utf8Path::new("dir/large.bin")).unwrap();
+    assert!(is_not_found(&read_file(adapter, "dir/large.bin").unwrap_err()));
+}
+
+// Gives some data and then fails like a file on a broken disk
+struct FailingReader {
+    remaining: usize,
+}
+
+impl Read for FailingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.remaining == 0 {
+            return Err(Error::new(ErrorKind::Other, "Disk is broken"));
+        }
+        let size = buf.len().min(self.remaining);
+        buf[..size].fill(1);
+        self.remaining -= size;
+        Ok(size)
+    }
+}
+
+fn get_settings(url: String, path: &str) -> RemoteStorageSettings {
+    RemoteStorageSettings {
+        url,
+        user: "user".to_string(),
+        password: "password".to_string(),
+        path: path.to_string(),
+        region: String::new(),
+        host_key: String::new(),
+    }
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value).unwrap()
+}
+
+fn status(code: u16) -> Response<Cursor<Vec<u8>>> {
+    Response::from_data(vec![]).with_status_code(code)
+}
+
+fn find_header(request: &Request, name: &str) -> Option<String> {
+    request.headers().iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.to_string())
+}
+
+fn parse_url(request: &Request) -> Url {
+    Url::parse(&format!("http://stand-in{}", request.url())).unwrap()
+}
+
+fn decode_path(path: &str) -> String {
+    percent_decode_str(path).decode_utf8_lossy().trim_matches('/').to_string()
+}
+
+// Names of files and directories right under the directory
+fn list_children(files: &BTreeMap<String, Vec<u8>>, dir: &str) -> (BTreeSet<String>, BTreeSet<String>) {
+    let prefix = if dir.is_empty() { String::new() } else { format!("{}/", dir) };
+    let mut dirs = BTreeSet::new();
+    let mut names = BTreeSet::new();
+    for path in files.keys().filter_map(|path| path.strip_prefix(prefix.as_str())) {
+        match path.split_once('/') {
+            Some((dir_name, _)) => dirs.insert(dir_name.to_string()),
+            None => names.insert(path.to_string()),
+        };
+    }
+    (dirs, names)
+}
+
+fn start_server(handler: impl Fn(Request, &Files) + Send + 'static) -> (SocketAddr, Files) {
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let address = server.server_addr().to_ip().unwrap();
+    let files = Files::default();
+
+    let server_files = files.clone();
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            handler(request, &server_files);
+        }
+    });
+    (address, files)
+}
+
+// A WebDAV server that keeps files in memory, like a minimal Apache mod_dav
+fn handle_webdav_request(mut request: Request, files: &Files) {
+    let path = decode_path(parse_url(&request).path());
+    let mut body = Vec::new();
+    if request.as_reader().read_to_end(&mut body).is_err() {
+        let _ = request.respond(status(400));
+        return;
+    }
+
+    let mut files = files.lock().unwrap();
+    let response = match request.method().as_str() {
+        "PUT" => {
+            files.insert(path, body);
+            status(201)
+        },
+        "GET" | "HEAD" => match files.get(&path) {
+            Some(content) => Response::from_data(content.clone()).with_status_code(200),
+            None => status(404),
+        },
+        "DELETE" => match files.remove(&path) {
+            Some(_) => status(204),
+            None => status(404),
+        },
+        // Directories exist as long as they have files, so creating one always succeeds
+        "MKCOL" => status(201),
+        "MOVE" => {
+            let destination = find_header(&request, "Destination").unwrap();
+            let destination = decode_path(Url::parse(&destination).unwrap().path());
+            match files.remove(&path) {
+                Some(content) => {
+                    files.insert(destination, content);
+                    status(204)
+                },
+                None => status(404),
+            }
+        },
+        "PROPFIND" => {
+            let (dirs, names) = list_children(&files, &path);
+            // Directories exist as long as they have files
+            if dirs.is_empty() && names.is_empty() && !path.is_empty() {
+                status(404)
+            } else {
+                let mut body = format!("<D:multistatus xmlns:D=\"DAV:\"><D:response><D:href>/{}/</D:href></D:response>", path);
+                for dir in dirs {
+                    body.push_str(&format!("<D:response><D:href>/{}/{}/</D:href></D:response>", path, dir));
+                }
+                for name in names {
+                    body.push_str(&format!("<D:response><D:href>/{}/{}</D:href></D:response>", path, name.replace(' ', "%20")));
+                }
+                body.push_str("</D:multistatus>");
+                Response::from_string(body).with_status_code(207)
+            }
+        },
+        _ => status(405),
+    };
+    let _ = request.respond(response);
+}
+
+// An S3 service with a single bucket, like MinIO with path-style requests
+fn handle_s3_request(mut request: Request, files: &Files, uploads: &Mutex<BTreeMap<usize, Vec<u8>>>) {
+    let url = parse_url(&request);
+    let query: BTreeMap<String, String> = url.query_pairs().into_owned().collect();
+    let key = decode_path(url.path()).trim_start_matches("bucket").trim_start_matches('/').to_string();
+    let mut body = Vec::new();
+    if request.as_reader().read_to_end(&mut body).is_err() {
+        let _ = request.respond(status(400));
+        return;
+    }
+
+    let not_found = || Response::from_string("<Error><Code>NoSuchKey</Code><Message>Key not found</Message></Error>").with_status_code(404);
+    let mut files = files.lock().unwrap();
+    let response = match (request.method(), query.get("uploadId")) {
+        (Method::Get, None) if query.get("list-type").map(String::as_str) == Some("2") => {
+            let prefix = query.get("prefix").cloned().unwrap_or_default();
+            let (dirs, names) = list_children(&files, prefix.trim_end_matches('/'));
+            let mut body = String::from("<ListBucketResult><IsTruncated>false</IsTruncated>");
+            for dir in dirs {
+                body.push_str(&format!("<CommonPrefixes><Prefix>{}{}/</Prefix></CommonPrefixes>", prefix, dir));
+            }
+            for name in names {
+                body.push_str(&format!("<Contents><Key>{}{}</Key></Contents>", prefix, name));
+            }
+            body.push_str("</ListBucketResult>");
+            Response::from_string(body)
+        },
+        (Method::Get, None) | (Method::Head, None) => match files.get(&key) {
+            Some(content) => Response::from_data(content.clone()),
+            None => not_found(),
+        },
+        (Method::Put, Some(_)) => {
+            let part_number: usize = query["partNumber"].parse().unwrap();
+            uploads.lock().unwrap().insert(part_number, body);
+            status(200).with_header(header("ETag", &format!("\"part-{}\"", part_number)))
+        },
+        (Method::Put, None) => match find_header(&request, "x-amz-copy-source") {
+            Some(source) => match files.get(decode_path(&source).trim_start_matches("bucket/")).cloned() {
+                Some(content) => {
+                    files.insert(key, content);
+                    Response::from_string("<CopyObjectResult/>")
+                },
+                None => not_found(),
+            },
+            None => {
+                files.insert(key, body);
+                status(200)
+            },
+        },
+        (Method::Post, None) if query.contains_key("uploads") => {
+            uploads.lock().unwrap().clear();
+            Response::from_string("<InitiateMultipartUploadResult><UploadId>upload-1</UploadId></InitiateMultipartUploadResult>")
+        },
+        (Method::Post, Some(_)) => {
+            let content = uploads.lock().unwrap().values().flatten().copied().collect();
+            files.insert(key, content);
+            Response::from_string("<CompleteMultipartUploadResult><Location>here</Location></CompleteMultipartUploadResult>")
+        },
+        (Method::Delete, Some(_)) => {
+            uploads.lock().unwrap().clear();
+            status(204)
+        },
+        (Method::Delete, None) => {
+            files.remove(&key);
+            status(204)
+        },
+        _ => status(405),
+    };
+    let _ = request.respond(response);
+}
+
+#[test]
+fn file_system_adapter() {
+    let base_path = Utf8PathBuf::from_path_buf(std::env::temp_dir()).unwrap()
+        .join(format!("lappi-storage-test-{:08x}", rand::random::<u32>()));
+    let mut adapter = FileSystemFactory::new().connect(&get_settings(base_path.to_string(), "")).unwrap();
+    check_adapter(adapter.as_mut());
+    std::fs::remove_dir_all(&base_path).unwrap();
+}
+
+#[test]
+fn webdav_adapter() {
+    let (address, _) = start_server(handle_webdav_request);
+    let settings = get_settings(format!("http://{}/dav", address), "music");
+    let mut adapter = WebDavStorageFactory::new().connect(&settings).unwrap();
+    check_adapter(adapter.as_mut());
+}
+
+#[test]
+fn webdav_upload_fails_with_source() {
+    let (address, files) = start_server(handle_webdav_request);
+    let settings = get_settings(format!("http://{}/dav", address), "");
+    let mut adapter = WebDavStorageFactory::new().connect(&settings).unwrap();
+
+    let mut src = FailingReader { remaining: 1024 * 1024 };
+    let err = adapter.write_file(Utf8Path::new("broken.bin"), &mut src).unwrap_err();
+    assert_eq!(err.to_string(), "Failed to read source");
+    assert!(files.lock().unwrap().is_empty());
+}
+
+#[test]
+fn webdav_denied_file_is_not_missing() {
+    let (address, _) = start_server(|request, _| {
+        let _ = request.respond(status(403));
+    });
+    let settings = get_settings(format!("http://{}/dav", address), "");
+    let mut adapter = WebDavStorageFactory::new().connect(&settings).unwrap();
+    assert!(!is_not_found(&read_file(adapter.as_mut(), "meta/tags.pb").unwrap_err()));
+}
+
+#[test]
+fn s3_adapter() {
+    let uploads = Mutex::new(BTreeMap::new());
+    let (address, _) = start_server(move |request, files| handle_s3_request(request, files, &uploads));
+    let settings = get_settings(format!("http://{}", address), "bucket/backups");
+    let mut adapter = S3StorageFactory::new().connect(&settings).unwrap();
+    check_adapter(adapter.as_mut());
+}
+
+#[test]
+fn s3_upload_is_aborted_with_source() {
+    let uploads = Mutex::new(BTreeMap::new());
+    let (address, files) = start_server(move |request, files| handle_s3_request(request, files, &uploads));
+    let settings = get_settings(format!("http://{}", address), "bucket");
+    let mut adapter = S3StorageFactory::new().connect(&settings).unwrap();
+
+    let mut src = FailingReader { remaining: LARGE_FILE_SIZE };
+    assert!(adapter.write_file(Utf8Path::new("broken.bin"), &mut src).is_err());
+    assert!(files.lock().unwrap().is_empty());
+}
+
+// Needs an sshd, e.g. a linuxserver/openssh-server container:
+// LAPPI_TEST_SFTP_URL=localhost:2222 LAPPI_TEST_SFTP_USER=lappi LAPPI_TEST_SFTP_PASSWORD=lappi \
+// LAPPI_TEST_SFTP_PATH=/config cargo test sftp_adapter -- --ignored
+// The host must be in known_hosts, or its fingerprint given in LAPPI_TEST_SFTP_HOST_KEY
+#[test]
+#[ignore]
+fn sftp_adapter() {
+    let get_env = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{} is not set", name));
+    let settings = RemoteStorageSettings {
+        url: get_env("LAPPI_TEST_SFTP_URL"),
+        user: get_env("LAPPI_TEST_SFTP_USER"),
+        password: get_env("LAPPI_TEST_SFTP_PASSWORD"),
+        path: format!("{}/lappi-storage-test-{:08x}", get_env("LAPPI_TEST_SFTP_PATH"), rand::random::<u32>()),
+        region: String::new(),
+        host_key: std::env::var("LAPPI_TEST_SFTP_HOST_KEY").unwrap_or_default(),
+    };
+    let mut adapter = SftpStorageFactory::new().connect(&settings).unwrap();
+    check_adapter(adapter.as_mut());
+}
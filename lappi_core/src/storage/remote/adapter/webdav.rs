@@ -0,0 +1,258 @@
+use std::io::{ErrorKind, Read, Write};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+use percent_encoding::percent_decode_str;
+use reqwest::blocking::{Body, Client, RequestBuilder, Response};
+use reqwest::{Method, StatusCode};
+use url::Url;
+
+use crate::storage::remote::adapter::{FileNotFound, RemoteStorageAdapter, RemoteStorageFactory, RemoteStorageSettings};
+use crate::utils::xml;
+
+static PROPFIND_BODY: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?><propfind xmlns=\"DAV:\"><prop><resourcetype/></prop></propfind>";
+static UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+static UPLOAD_BUFFERED_CHUNKS: usize = 16;
+static CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Body of an upload that is read from the caller's thread, a read error aborts the request
+struct UploadBody {
+    receiver: Receiver<std::io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl Read for UploadBody {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.position = 0;
+                },
+                Err(_) => return Ok(0),
+            }
+        }
+        let size = buf.len().min(self.chunk.len() - self.position);
+        buf[..size].copy_from_slice(&self.chunk[self.position..self.position + size]);
+        self.position += size;
+        Ok(size)
+    }
+}
+
+pub struct WebDavAdapter {
+    client: Client,
+    base_url: Url,
+    user: String,
+    password: String,
+}
+
+impl WebDavAdapter {
+    fn get_url(&self, path: &Utf8Path, is_dir: bool) -> Result<Url> {
+        let mut url = self.base_url.clone();
+        {
+            let mut segments = url.path_segments_mut()
+                .map_err(|_| anyhow::anyhow!("Invalid WebDAV url {}", self.base_url))?;
+            segments.pop_if_empty();
+            segments.extend(path.iter().filter(|segment| *segment != "/"));
+            if is_dir {
+                segments.push("");
+            }
+        }
+        Ok(url)
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        if self.user.is_empty() {
+            request
+        } else {
+            request.basic_auth(&self.user, Some(&self.password))
+        }
+    }
+
+    fn check_response(response: Response, action: &str, path: &Utf8Path) -> Result<Response> {
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!("Failed to {} {:?}: {}", action, path, status);
+        }
+        Ok(response)
+    }
+
+    fn ensure_directory_exists(&self, path: &Utf8Path) -> Result<()> {
+        let parent = match path.parent() {
+            Some(parent) => parent,
+            None => return Ok(()),
+        };
+        let mut current = Utf8PathBuf::new();
+        for component in parent.iter() {
+            current.push(component);
+            let url = self.get_url(&current, true)?;
+            let status = self.request(Method::from_bytes(b"MKCOL")?, url).send()?.status();
+            // 405 means the collection already exists
+            if !status.is_success() && status != StatusCode::METHOD_NOT_ALLOWED {
+                anyhow::bail!("Failed to create directory {:?}: {}", current, status);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RemoteStorageAdapter for WebDavAdapter {
+    fn list_dir(&mut self, path: &Utf8Path) -> Result<Vec<String>> {
+        let url = self.get_url(path, true)?;
+        let response = self.request(Method::from_bytes(b"PROPFIND")?, url.clone())
+            .header("Depth", "1")
+            .header("Content-Type", "application/xml")
+            .body(PROPFIND_BODY)
+            .send()
+            .context(format!("Failed to list directory {:?}", path))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(FileNotFound(path.to_path_buf()).into());
+        }
+        let body = Self::check_response(response, "list directory", path)?.text()?;
+
+        let dir_path = percent_decode_str(url.path()).decode_utf8_lossy().trim_end_matches('/').to_string();
+        let mut names = Vec::new();
+        for href in xml::find_elements(&body, "href") {
+            let href = xml::unescape(href);
+            let href_path = match Url::parse(&href) {
+                Ok(href_url) => href_url.path().to_string(),
+                Err(_) => href,
+            };
+            let href_path = percent_decode_str(&href_path).decode_utf8_lossy().trim_end_matches('/').to_string();
+            if href_path == dir_path {
+                continue;
+            }
+            if let Some(name) = href_path.rsplit('/').next().filter(|name| !name.is_empty()) {
+                names.push(name.to_string());
+            }
+        }
+        Ok(names)
+    }
+
+    fn read_file(&mut self, path: &Utf8Path, dest: &mut dyn Write) -> Result<()> {
+        let url = self.get_url(path, false)?;
+        let response = self.request(Method::GET, url).send().context(format!("Failed to open file {:?}", path))?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(FileNotFound(path.to_path_buf()).into());
+        }
+        let mut response = Self::check_response(response, "read file", path)?;
+        response.copy_to(dest).context("Failed to read file")?;
+        Ok(())
+    }
+
+    // The file is sent with chunked encoding while it is read, so it is never held in memory
+    fn write_file(&mut self, path: &Utf8Path, src: &mut dyn Read) -> Result<()> {
+        self.ensure_directory_exists(path)?;
+        let url = self.get_url(path, false)?;
+
+        let (sender, receiver) = sync_channel(UPLOAD_BUFFERED_CHUNKS);
+        let body = UploadBody { receiver, chunk: Vec::new(), position: 0 };
+        let request = self.request(Method::PUT, url).body(Body::new(body));
+        let upload = std::thread::spawn(move || request.send());
+
+        let mut read_error = None;
+        let mut buffer = vec![0; UPLOAD_CHUNK_SIZE];
+        loop {
+            let chunk = match src.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(size) => Ok(buffer[..size].to_vec()),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    let chunk_error = std::io::Error::new(err.kind(), err.to_string());
+                    read_error = Some(err);
+                    Err(chunk_error)
+                },
+            };
+            // Sending fails if the request has already ended, its own error is reported then
+            if sender.send(chunk).is_err() || read_error.is_some() {
+                break;
+            }
+        }
+        drop(sender);
+
+        let response = upload.join()
+            .map_err(|_| anyhow::anyhow!("Upload of {:?} panicked", path))?;
+        if let Some(err) = read_error {
+            return Err(anyhow::Error::new(err).context("Failed to read source"));
+        }
+        let response = response.context(format!("Failed to create file {:?}", path))?;
+        Self::check_response(response, "write file", path)?;
+        Ok(())
+    }
+
+    fn remove_file(&mut self, path: &Utf8Path) -> Result<()> {
+        let url = self.get_url(path, false)?;
+        let response = self.request(Method::DELETE, url).send().context(format!("Failed to remove file {:?}", path))?;
+        Self::check_response(response, "remove file", path)?;
+        Ok(())
+    }
+
+    fn get_file_size(&mut self, path: &Utf8Path) -> Result<u64> {
+        let url = self.get_url(path, false)?;
+        let response = self.request(Method::HEAD, url).send().context(format!("Failed to get size of {:?}", path))?;
+        let response = Self::check_response(response, "get size of", path)?;
+        response.headers().get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|length| length.to_str().ok())
+            .and_then(|length| length.parse().ok())
+            .ok_or_else(|| anyhow::anyhow!("Server did not report size of {:?}", path))
+    }
+
+    fn rename_file(&mut self, from: &Utf8Path, to: &Utf8Path) -> Result<()> {
+        let from_url = self.get_url(from, false)?;
+        let to_url = self.get_url(to, false)?;
+        let response = self.request(Method::from_bytes(b"MOVE")?, from_url)
+            .header("Destination", to_url.as_str())
+            .header("Overwrite", "T")
+            .send()
+            .context(format!("Failed to rename {:?}", from))?;
+        Self::check_response(response, "rename file", from)?;
+        Ok(())
+    }
+}
+
+pub struct WebDavStorageFactory {
+
+}
+
+impl WebDavStorageFactory {
+    pub fn new() -> Self {
+        Self {
+
+        }
+    }
+}
+
+impl RemoteStorageFactory for WebDavStorageFactory {
+    fn get_name(&self) -> &'static str {
+        "WebDAV"
+    }
+
+    fn connect(&self, settings: &RemoteStorageSettings) -> Result<Box<dyn RemoteStorageAdapter>> {
+        let mut base_url = Url::parse(&settings.url).context(format!("Invalid WebDAV url {}", settings.url))?;
+        if !settings.path.is_empty() {
+            base_url.path_segments_mut()
+                .map_err(|_| anyhow::anyhow!("Invalid WebDAV url {}", settings.url))?
+                .pop_if_empty()
+                .extend(settings.path.split('/').filter(|segment| !segment.is_empty()));
+        }
+
+        let client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(None)
+            .build()?;
+        Ok(Box::new(WebDavAdapter {
+            client,
+            base_url,
+            user: settings.user.clone(),
+            password: settings.password.clone(),
+        }))
+    }
+
+    fn get_max_connections(&self) -> usize {
+        4
+    }
+}
@@ -1,18 +1,37 @@
 pub mod adapter;
 
-use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
 
 use anyhow::Result;
+use amina_core::register_rpc_handler;
+use amina_core::rpc::Rpc;
 use amina_core::service::{Context, Service, ServiceApi, ServiceInitializer};
+use serde::Serialize;
 
 use crate::app_config::AppConfig;
 use crate::settings::Settings;
 use crate::storage::remote::adapter::file_system::FileSystemFactory;
+use crate::storage::remote::adapter::ftp::FtpStorageFactory;
+use crate::storage::remote::adapter::s3::S3StorageFactory;
+use crate::storage::remote::adapter::sftp::SftpStorageFactory;
+use crate::storage::remote::adapter::webdav::WebDavStorageFactory;
 use crate::storage::remote::adapter::{RemoteStorageAdapter, RemoteStorageFactory, RemoteStorageSettings};
 
+static DEFAULT_STORAGE_TYPE: &str = "file_system";
+static DEFAULT_PARALLEL_TRANSFERS: usize = 4;
+static DEFAULT_TRANSFER_RETRIES: u32 = 3;
+
+#[derive(Serialize, Clone)]
+pub struct RemoteStorageType {
+    pub id: String,
+    pub name: String,
+}
+
 pub struct RemoteStorage {
     storage_available: bool,
     settings: Service<Settings>,
+    factories: RwLock<BTreeMap<String, Box<dyn RemoteStorageFactory>>>,
 }
 
 impl RemoteStorage {
@@ -20,20 +39,101 @@ impl RemoteStorage {
         return self.storage_available;
     }
 
+    pub fn register_factory(&self, storage_type: &str, factory: Box<dyn RemoteStorageFactory>) {
+        self.register_settings(storage_type);
+        self.factories.write().unwrap().insert(storage_type.to_string(), factory);
+    }
+
+    pub fn get_storage_types(&self) -> Vec<RemoteStorageType> {
+        self.factories.read().unwrap().iter()
+            .map(|(id, factory)| RemoteStorageType {
+                id: id.clone(),
+                name: factory.get_name().to_string(),
+            })
+            .collect()
+    }
+
     pub fn connect(&self) -> Result<Box<dyn RemoteStorageAdapter>> {
-        let factory = FileSystemFactory::new();
-        let ftp_url = self.settings.get_string("remote_storage.ftp.url");
-        let ftp_user = self.settings.get_string("remote_storage.ftp.user");
-        let ftp_password = self.settings.get_string("remote_storage.ftp.password");
-
-        let ftp_settings = RemoteStorageSettings {
-            url: ftp_url.get(),
-            user: ftp_user.get(),
-            password: ftp_password.get(),
-            path: "".to_string(),
+        let storage_type = self.get_storage_type();
+        let factories = self.factories.read().unwrap();
+        let factory = factories.get(&storage_type)
+            .ok_or_else(|| anyhow::anyhow!("Unknown remote storage type '{}'", storage_type))?;
+
+        let url = self.get_setting(&storage_type, "url");
+        if url.trim().is_empty() {
+            anyhow::bail!("Remote storage is not configured, remote_storage.{}.url is empty", storage_type);
+        }
+
+        let settings = RemoteStorageSettings {
+            url,
+            user: self.get_setting(&storage_type, "user"),
+            password: self.get_setting(&storage_type, "password"),
+            path: self.get_setting(&storage_type, "path"),
+            region: self.get_setting(&storage_type, "region"),
+            host_key: self.get_setting(&storage_type, "host_key"),
         };
 
-        factory.connect(&ftp_settings)
+        log::debug!("Connecting to {} remote storage", factory.get_name());
+        factory.connect(&settings)
+    }
+
+    // Tells remote storages apart, so state kept for one of them is not applied to another
+    pub fn get_identity(&self) -> String {
+        let storage_type = self.get_storage_type();
+        let url = self.get_setting(&storage_type, "url");
+        let user = self.get_setting(&storage_type, "user");
+        let path = self.get_setting(&storage_type, "path");
+        format!("{} {} {} {}", storage_type, url.trim(), user, path.trim_matches('/'))
+    }
+
+    // Number of connections for parallel transfers, limited by what the storage type allows
+    pub fn get_parallel_transfers(&self) -> usize {
+        let parallel_transfers = self.settings.get_string("remote_storage.parallel_transfers").get()
+            .parse()
+            .unwrap_or(DEFAULT_PARALLEL_TRANSFERS);
+        let max_connections = self.factories.read().unwrap()
+            .get(&self.get_storage_type())
+            .map_or(1, |factory| factory.get_max_connections());
+        parallel_transfers.clamp(1, max_connections.max(1))
+    }
+
+    pub fn get_transfer_retries(&self) -> u32 {
+        self.settings.get_string("remote_storage.transfer_retries").get()
+            .parse()
+            .unwrap_or(DEFAULT_TRANSFER_RETRIES)
+    }
+
+    fn get_storage_type(&self) -> String {
+        let storage_type = self.settings.get_string("remote_storage.type").get();
+        if storage_type.is_empty() { DEFAULT_STORAGE_TYPE.to_string() } else { storage_type }
+    }
+
+    fn get_setting(&self, storage_type: &str, name: &str) -> String {
+        self.settings.get_string(&format!("remote_storage.{}.{}", storage_type, name)).get()
+    }
+
+    // The file system storage used to read its path from the FTP url, from the time it was the only storage
+    fn migrate_legacy_settings(&self) {
+        let storage_type = self.settings.get_string("remote_storage.type");
+        let legacy_url = self.settings.get_string("remote_storage.ftp.url");
+        let file_system_url = self.settings.get_string("remote_storage.file_system.url");
+        if !storage_type.get().is_empty() || legacy_url.get().is_empty() || !file_system_url.get().is_empty() {
+            return;
+        }
+
+        log::info!("Move remote_storage.ftp settings to the {} storage type", DEFAULT_STORAGE_TYPE);
+        for name in ["url", "user", "password"] {
+            let legacy_setting = self.settings.get_string(&format!("remote_storage.ftp.{}", name));
+            self.settings.get_string(&format!("remote_storage.file_system.{}", name)).set(legacy_setting.get());
+            legacy_setting.set(String::new());
+        }
+        storage_type.set(DEFAULT_STORAGE_TYPE.to_string());
+    }
+
+    fn register_settings(&self, storage_type: &str) {
+        for name in ["url", "user", "password", "path"] {
+            let _ = self.get_setting(storage_type, name);
+        }
     }
 }
 
@@ -44,20 +144,32 @@ impl ServiceApi for RemoteStorage {
 impl ServiceInitializer for RemoteStorage {
     fn initialize(context: &Context) -> Arc<Self> {
         let app_config = context.get_service::<AppConfig>();
+        let rpc = context.get_service::<Rpc>();
 
         let settings = context.get_service::<Settings>();
-        let _ = settings.get_string("remote_storage.ftp.url");
-        let _ = settings.get_string("remote_storage.ftp.user");
-        let _ = settings.get_string("remote_storage.ftp.password");
+        let _ = settings.get_string("remote_storage.type");
+        let _ = settings.get_string("remote_storage.s3.region");
+        let _ = settings.get_string("remote_storage.sftp.host_key");
+        let _ = settings.get_string("remote_storage.parallel_transfers");
+        let _ = settings.get_string("remote_storage.transfer_retries");
 
         let storage_available = app_config.collection.storage;
 
         let storage = Arc::new(Self {
             storage_available,
-            settings
+            settings,
+            factories: RwLock::new(BTreeMap::new()),
         });
 
+        storage.register_factory("file_system", Box::new(FileSystemFactory::new()));
+        storage.register_factory("ftp", Box::new(FtpStorageFactory::new()));
+        storage.register_factory("sftp", Box::new(SftpStorageFactory::new()));
+        storage.register_factory("webdav", Box::new(WebDavStorageFactory::new()));
+        storage.register_factory("s3", Box::new(S3StorageFactory::new()));
+        storage.migrate_legacy_settings();
+
+        register_rpc_handler!(rpc, storage, "lappi.remote_storage.get_storage_types", get_storage_types());
+
         return storage;
     }
 }
-
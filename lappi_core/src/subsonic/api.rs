@@ -0,0 +1,513 @@
+use camino::Utf8PathBuf;
+use serde_json::{json, Map, Value};
+use amina_core::service::Service;
+
+use crate::collection::folders::{AlbumId, ArtistId, FolderDesc, FolderType, FoldersCollection};
+use crate::collection::internal_files::InternalFiles;
+use crate::collection::music::{MusicCollection, MusicItemId};
+use crate::collection::pictures::{PictureId, PicturesCollection};
+use crate::collection::playlists::PlaylistsCollection;
+use crate::collection::playlists::types::{PlaylistDesc, PlaylistId};
+use crate::collection::tags::{Tag, TagValue};
+use crate::metadata::audio;
+use crate::playback::sources::{PlaybackSource, SourceType};
+use crate::transcoding::{TranscodedAudio, Transcoder, TranscodingProfile, TranscodingStream};
+use crate::utils::http_file::get_content_type;
+
+use super::request::SubsonicRequest;
+use super::response::SubsonicError;
+
+static ARTIST_PREFIX: &str = "ar-";
+static ALBUM_PREFIX: &str = "al-";
+static SONG_PREFIX: &str = "so-";
+static PLAYLIST_PREFIX: &str = "pl-";
+static PICTURE_PREFIX: &str = "pic-";
+static MUSIC_FOLDER_ID: i32 = 0;
+
+pub enum ApiResponse {
+    Body(Map<String, Value>),
+    File { path: Utf8PathBuf, content_type: &'static str },
+    Stream { stream: TranscodingStream, content_type: String },
+    Redirect(String),
+}
+
+type ApiResult = Result<ApiResponse, SubsonicError>;
+
+fn body(key: &str, value: Value) -> ApiResponse {
+    let mut map = Map::new();
+    map.insert(key.to_string(), value);
+    ApiResponse::Body(map)
+}
+
+fn empty_body() -> ApiResponse {
+    ApiResponse::Body(Map::new())
+}
+
+fn parse_id(id: &str, prefix: &str) -> Result<i64, SubsonicError> {
+    id.strip_prefix(prefix)
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| SubsonicError::not_found(&format!("Item {}", id)))
+}
+
+fn tag_to_number(tag: Option<Tag>) -> Option<i32> {
+    match tag?.get_value() {
+        TagValue::Number(value) => Some(*value),
+        TagValue::String(value) => value.parse().ok(),
+        TagValue::Bool => None,
+    }
+}
+
+pub struct SubsonicApi {
+    folders: Service<FoldersCollection>,
+    music: Service<MusicCollection>,
+    pictures: Service<PicturesCollection>,
+    playlists: Service<PlaylistsCollection>,
+    internal_files: Service<InternalFiles>,
+    transcoder: Service<Transcoder>,
+    user: String,
+}
+
+impl SubsonicApi {
+    pub fn new(user: String) -> Self {
+        let context = crate::context();
+        Self {
+            folders: context.get_service::<FoldersCollection>(),
+            music: context.get_service::<MusicCollection>(),
+            pictures: context.get_service::<PicturesCollection>(),
+            playlists: context.get_service::<PlaylistsCollection>(),
+            internal_files: context.get_service::<InternalFiles>(),
+            transcoder: context.get_service::<Transcoder>(),
+            user,
+        }
+    }
+
+    pub fn handle(&self, method: &str, request: &SubsonicRequest) -> ApiResult {
+        match method {
+            "ping" => Ok(empty_body()),
+            "getLicense" => Ok(body("license", json!({ "valid": true }))),
+            "getMusicFolders" => self.get_music_folders(),
+            "getArtists" => self.get_artists("artists"),
+            // The older folder based listing has the same shape under its own key
+            "getIndexes" => self.get_artists("indexes"),
+            "getArtist" => self.get_artist(request),
+            "getAlbum" => self.get_album(request),
+            "getSong" => self.get_song(request),
+            "stream" | "download" => self.stream(request),
+            "getCoverArt" => self.get_cover_art(request),
+            "search3" => self.search3(request),
+            "getPlaylists" => self.get_playlists(),
+            "getPlaylist" => self.get_playlist(request),
+            "createPlaylist" => self.create_playlist(request),
+            "updatePlaylist" => self.update_playlist(request),
+            "deletePlaylist" => self.delete_playlist(request),
+            "scrobble" => self.scrobble(request),
+            _ => Err(SubsonicError::not_found(&format!("Method {}", method))),
+        }
+    }
+
+    fn get_folders_of_type(&self, folder_type: FolderType) -> anyhow::Result<Vec<FolderDesc>> {
+        let mut result = vec![];
+        for folder_id in self.folders.get_all_folders()? {
+            let description = self.folders.get_folder_description(folder_id)?;
+            if description.folder_type == folder_type {
+                result.push(description);
+            }
+        }
+        Ok(result)
+    }
+
+    fn get_albums_of_artist(&self, artist_id: ArtistId) -> anyhow::Result<Vec<FolderDesc>> {
+        Ok(self.folders.get_folders_in_folder(artist_id)?
+            .into_iter()
+            .filter(|folder| folder.folder_type == FolderType::Album)
+            .collect())
+    }
+
+    fn get_album_songs(&self, album_id: AlbumId) -> anyhow::Result<Vec<MusicItemId>> {
+        let mut songs = vec![];
+        for music_item_id in self.folders.get_music_items_in_folder(album_id)? {
+            let track = tag_to_number(self.music.get_tag(music_item_id, "track")?).unwrap_or(i32::MAX);
+            songs.push((track, music_item_id));
+        }
+        songs.sort();
+        Ok(songs.into_iter().map(|(_, music_item_id)| music_item_id).collect())
+    }
+
+    fn cover_art_id(picture_id: Option<PictureId>) -> Value {
+        match picture_id {
+            Some(picture_id) => json!(format!("{}{}", PICTURE_PREFIX, picture_id)),
+            None => Value::Null,
+        }
+    }
+
+    fn artist_to_json(&self, artist: &FolderDesc) -> anyhow::Result<Value> {
+        Ok(json!({
+            "id": format!("{}{}", ARTIST_PREFIX, artist.folder_id),
+            "name": artist.name,
+            "albumCount": self.get_albums_of_artist(artist.folder_id)?.len(),
+            "coverArt": Self::cover_art_id(artist.avatar_picture_id),
+        }))
+    }
+
+    fn album_to_json(&self, album: &FolderDesc) -> anyhow::Result<Value> {
+        let artist = self.folders.find_parent_node(album.folder_id, FolderType::Artist)?;
+        let songs = self.folders.get_music_items_in_folder(album.folder_id)?;
+        let year = tag_to_number(self.folders.get_tag(album.folder_id, "year", true)?);
+
+        let mut value = json!({
+            "id": format!("{}{}", ALBUM_PREFIX, album.folder_id),
+            "name": album.name,
+            "songCount": songs.len(),
+            "duration": 0,
+            "coverArt": Self::cover_art_id(self.folders.find_folder_cover(album.folder_id)?),
+            "year": year,
+        });
+        if let Some(artist) = artist {
+            value["artist"] = json!(artist.name);
+            value["artistId"] = json!(format!("{}{}", ARTIST_PREFIX, artist.folder_id));
+        }
+        Ok(value)
+    }
+
+    fn get_song_file(&self, music_item_id: MusicItemId) -> anyhow::Result<Option<Utf8PathBuf>> {
+        let source = PlaybackSource::default_from_music_item(music_item_id)?;
+        Ok(source.and_then(|source| match source.get_source_type() {
+            SourceType::LocalFile(path) | SourceType::ExternalFile(path) => Some(path.clone()),
+            SourceType::Url(_) => None,
+        }))
+    }
+
+    fn song_to_json(&self, music_item_id: MusicItemId) -> anyhow::Result<Value> {
+        let description = self.music.get_item_description(music_item_id)?;
+        let folder = self.folders.get_folder_description(description.folder_id)?;
+        let get_string = |tag_name: &str| -> anyhow::Result<Option<String>> {
+            Ok(self.music.get_tag(music_item_id, tag_name)?.map(|tag| tag.to_string()))
+        };
+
+        let mut value = json!({
+            "id": format!("{}{}", SONG_PREFIX, music_item_id),
+            "isDir": false,
+            "type": "music",
+            "title": description.name,
+            "artist": get_string("artist")?,
+            "album": get_string("album")?,
+            "track": tag_to_number(self.music.get_tag(music_item_id, "track")?),
+            "year": tag_to_number(self.music.get_tag(music_item_id, "year")?),
+            "coverArt": Self::cover_art_id(self.music.get_item_cover(music_item_id)?),
+        });
+
+        if folder.folder_type == FolderType::Album {
+            value["parent"] = json!(format!("{}{}", ALBUM_PREFIX, folder.folder_id));
+            value["albumId"] = value["parent"].clone();
+            if let Some(artist) = self.folders.find_parent_node(folder.folder_id, FolderType::Artist)? {
+                value["artistId"] = json!(format!("{}{}", ARTIST_PREFIX, artist.folder_id));
+            }
+        }
+
+        if let Some(path) = self.get_song_file(music_item_id)? {
+            let suffix = path.extension().unwrap_or("").to_lowercase();
+            value["contentType"] = json!(get_content_type(&suffix));
+            value["suffix"] = json!(suffix);
+            value["path"] = json!(path.file_name().unwrap_or(""));
+            if let Ok(metadata) = std::fs::metadata(&path) {
+                value["size"] = json!(metadata.len());
+            }
+            if let Some(duration) = audio::get_duration(&path) {
+                value["duration"] = json!(duration as i32);
+            }
+        }
+
+        Ok(value)
+    }
+
+    fn songs_to_json(&self, songs: &[MusicItemId]) -> anyhow::Result<Vec<Value>> {
+        songs.iter().map(|music_item_id| self.song_to_json(*music_item_id)).collect()
+    }
+
+    fn get_music_folders(&self) -> ApiResult {
+        Ok(body("musicFolders", json!({
+            "musicFolder": [{ "id": MUSIC_FOLDER_ID, "name": "Music" }],
+        })))
+    }
+
+    fn get_artists(&self, key: &str) -> ApiResult {
+        let mut artists = self.get_folders_of_type(FolderType::Artist)?;
+        artists.sort_by_key(|artist| artist.name.to_lowercase());
+
+        let mut indexes: Vec<(String, Vec<Value>)> = vec![];
+        for artist in &artists {
+            let index_name = artist.name.chars().next()
+                .filter(|ch| ch.is_alphabetic())
+                .map(|ch| ch.to_uppercase().to_string())
+                .unwrap_or_else(|| "#".to_string());
+            let artist_value = self.artist_to_json(artist)?;
+            match indexes.last_mut() {
+                Some((name, values)) if *name == index_name => values.push(artist_value),
+                _ => indexes.push((index_name, vec![artist_value])),
+            }
+        }
+
+        let index: Vec<Value> = indexes.into_iter()
+            .map(|(name, artists)| json!({ "name": name, "artist": artists }))
+            .collect();
+
+        let mut value = json!({ "ignoredArticles": "", "index": index });
+        if key == "indexes" {
+            value["lastModified"] = json!(0);
+        }
+        Ok(body(key, value))
+    }
+
+    fn get_artist(&self, request: &SubsonicRequest) -> ApiResult {
+        let artist_id = parse_id(request.require("id")?, ARTIST_PREFIX)?;
+        let artist = self.folders.get_folder_description(artist_id)?;
+
+        let mut value = self.artist_to_json(&artist)?;
+        let albums: anyhow::Result<Vec<Value>> = self.get_albums_of_artist(artist_id)?
+            .iter()
+            .map(|album| self.album_to_json(album))
+            .collect();
+        value["album"] = json!(albums?);
+
+        Ok(body("artist", value))
+    }
+
+    fn get_album(&self, request: &SubsonicRequest) -> ApiResult {
+        let album_id = parse_id(request.require("id")?, ALBUM_PREFIX)?;
+        let album = self.folders.get_folder_description(album_id)?;
+
+        let mut value = self.album_to_json(&album)?;
+        let songs = self.songs_to_json(&self.get_album_songs(album_id)?)?;
+        let duration: i64 = songs.iter().filter_map(|song| song["duration"].as_i64()).sum();
+        value["duration"] = json!(duration);
+        value["song"] = json!(songs);
+
+        Ok(body("album", value))
+    }
+
+    fn get_song(&self, request: &SubsonicRequest) -> ApiResult {
+        let music_item_id = parse_id(request.require("id")?, SONG_PREFIX)?;
+        Ok(body("song", self.song_to_json(music_item_id)?))
+    }
+
+    fn stream(&self, request: &SubsonicRequest) -> ApiResult {
+        let music_item_id = parse_id(request.require("id")?, SONG_PREFIX)?;
+        let source = PlaybackSource::default_from_music_item(music_item_id)?
+            .ok_or_else(|| SubsonicError::not_found("Song source"))?;
+
+        if let Some(profile) = self.find_transcoding_profile(request) {
+            return match self.transcoder.transcode(&source, &profile.name)? {
+                TranscodedAudio::Cached(path) => {
+                    let content_type = get_content_type(&profile.extension);
+                    Ok(ApiResponse::File { path, content_type })
+                },
+                TranscodedAudio::Stream(stream) => {
+                    Ok(ApiResponse::Stream { stream, content_type: profile.content_type })
+                },
+            };
+        }
+
+        match source.get_source_type() {
+            SourceType::LocalFile(path) | SourceType::ExternalFile(path) => {
+                let content_type = get_content_type(path.extension().unwrap_or(""));
+                Ok(ApiResponse::File { path: path.clone(), content_type })
+            },
+            SourceType::Url(url) => Ok(ApiResponse::Redirect(url.clone())),
+        }
+    }
+
+    // Clients ask for a format by its extension and/or limit the bitrate in kbps
+    fn find_transcoding_profile(&self, request: &SubsonicRequest) -> Option<TranscodingProfile> {
+        let format = request.get("format").unwrap_or("");
+        let max_bitrate = request.get_number("maxBitRate", 0u32);
+        if format == "raw" {
+            return None;
+        }
+
+        if format.is_empty() {
+            if max_bitrate == 0 {
+                return None;
+            }
+            return self.transcoder.find_profile_for_bitrate(max_bitrate).cloned();
+        }
+
+        self.transcoder.get_profiles()
+            .into_iter()
+            .filter(|profile| profile.extension == format || profile.name == format)
+            .filter(|profile| max_bitrate == 0 || profile.bitrate <= max_bitrate)
+            .max_by_key(|profile| profile.bitrate)
+    }
+
+    fn get_cover_art(&self, request: &SubsonicRequest) -> ApiResult {
+        let id = request.require("id")?;
+
+        let picture_id = if id.starts_with(PICTURE_PREFIX) {
+            Some(parse_id(id, PICTURE_PREFIX)?)
+        } else if id.starts_with(ALBUM_PREFIX) {
+            self.folders.find_folder_cover(parse_id(id, ALBUM_PREFIX)?)?
+        } else if id.starts_with(ARTIST_PREFIX) {
+            self.folders.find_folder_cover(parse_id(id, ARTIST_PREFIX)?)?
+        } else {
+            self.music.get_item_cover(parse_id(id, SONG_PREFIX)?)?
+        };
+
+        let picture_id = picture_id.ok_or_else(|| SubsonicError::not_found("Cover art"))?;
+        let descriptor = self.pictures.get_picture_descriptor(picture_id)?;
+        let path = self.internal_files.get_system_path(descriptor.internal_file_id)?;
+        let content_type = get_content_type(path.extension().unwrap_or(""));
+
+        Ok(ApiResponse::File { path, content_type })
+    }
+
+    fn page<T>(items: Vec<T>, request: &SubsonicRequest, count_param: &str, offset_param: &str) -> Vec<T> {
+        let count = request.get_number(count_param, 20usize);
+        let offset = request.get_number(offset_param, 0usize);
+        items.into_iter().skip(offset).take(count).collect()
+    }
+
+    fn search3(&self, request: &SubsonicRequest) -> ApiResult {
+        let query = request.get("query").unwrap_or("").trim_matches('"').to_lowercase();
+        let matches = |name: &str| query.is_empty() || name.to_lowercase().contains(&query);
+
+        let artists: Vec<FolderDesc> = self.get_folders_of_type(FolderType::Artist)?
+            .into_iter()
+            .filter(|artist| matches(&artist.name))
+            .collect();
+        let albums: Vec<FolderDesc> = self.get_folders_of_type(FolderType::Album)?
+            .into_iter()
+            .filter(|album| matches(&album.name))
+            .collect();
+        let mut songs = vec![];
+        for music_item_id in self.music.get_all_music_items()? {
+            if matches(&self.music.get_item_description(music_item_id)?.name) {
+                songs.push(music_item_id);
+            }
+        }
+
+        let artists: anyhow::Result<Vec<Value>> = Self::page(artists, request, "artistCount", "artistOffset")
+            .iter()
+            .map(|artist| self.artist_to_json(artist))
+            .collect();
+        let albums: anyhow::Result<Vec<Value>> = Self::page(albums, request, "albumCount", "albumOffset")
+            .iter()
+            .map(|album| self.album_to_json(album))
+            .collect();
+        let songs = self.songs_to_json(&Self::page(songs, request, "songCount", "songOffset"))?;
+
+        Ok(body("searchResult3", json!({
+            "artist": artists?,
+            "album": albums?,
+            "song": songs,
+        })))
+    }
+
+    fn playlist_to_json(&self, playlist: &PlaylistDesc, with_entries: bool) -> anyhow::Result<Value> {
+        let items = self.playlists.get_playlist_items(playlist.id)?;
+        let mut value = json!({
+            "id": format!("{}{}", PLAYLIST_PREFIX, playlist.id),
+            "name": playlist.name,
+            "owner": self.user,
+            "public": false,
+            "songCount": items.len(),
+            "duration": 0,
+            "coverArt": Self::cover_art_id(playlist.avatar_picture_id),
+        });
+        if with_entries {
+            let songs: Vec<MusicItemId> = items.iter().map(|item| item.music_item_id).collect();
+            let entries = self.songs_to_json(&songs)?;
+            let duration: i64 = entries.iter().filter_map(|song| song["duration"].as_i64()).sum();
+            value["duration"] = json!(duration);
+            value["entry"] = json!(entries);
+        }
+        Ok(value)
+    }
+
+    fn get_playlists(&self) -> ApiResult {
+        let playlists: anyhow::Result<Vec<Value>> = self.playlists.get_playlists()?
+            .iter()
+            .map(|playlist| self.playlist_to_json(playlist, false))
+            .collect();
+        Ok(body("playlists", json!({ "playlist": playlists? })))
+    }
+
+    fn get_playlist_response(&self, playlist_id: PlaylistId) -> ApiResult {
+        let playlist = self.playlists.get_playlist_description(playlist_id)?;
+        Ok(body("playlist", self.playlist_to_json(&playlist, true)?))
+    }
+
+    fn get_playlist(&self, request: &SubsonicRequest) -> ApiResult {
+        let playlist_id = parse_id(request.require("id")?, PLAYLIST_PREFIX)?;
+        self.get_playlist_response(playlist_id)
+    }
+
+    fn get_song_ids(request: &SubsonicRequest, name: &str) -> Result<Vec<MusicItemId>, SubsonicError> {
+        request.get_all(name).into_iter()
+            .map(|id| parse_id(id, SONG_PREFIX))
+            .collect()
+    }
+
+    fn create_playlist(&self, request: &SubsonicRequest) -> ApiResult {
+        let songs = Self::get_song_ids(request, "songId")?;
+
+        let playlist_id = match request.get("playlistId") {
+            Some(playlist_id) => {
+                let playlist_id = parse_id(playlist_id, PLAYLIST_PREFIX)?;
+                for item in self.playlists.get_playlist_items(playlist_id)? {
+                    self.playlists.delete_playlist_item(item.id)?;
+                }
+                playlist_id
+            },
+            None => self.playlists.create_playlist(request.require("name")?.to_string())?,
+        };
+
+        for music_item_id in songs {
+            self.playlists.add_item_to_playlist(playlist_id, music_item_id)?;
+        }
+
+        self.get_playlist_response(playlist_id)
+    }
+
+    fn update_playlist(&self, request: &SubsonicRequest) -> ApiResult {
+        let playlist_id = parse_id(request.require("playlistId")?, PLAYLIST_PREFIX)?;
+
+        if let Some(name) = request.get("name") {
+            self.playlists.set_playlist_name(playlist_id, name.to_string())?;
+        }
+
+        // Indexes refer to the playlist as it was before the update
+        let items = self.playlists.get_playlist_items(playlist_id)?;
+        for index in request.get_all("songIndexToRemove") {
+            let item = index.parse::<usize>().ok().and_then(|index| items.get(index));
+            if let Some(item) = item {
+                self.playlists.delete_playlist_item(item.id)?;
+            }
+        }
+
+        for music_item_id in Self::get_song_ids(request, "songIdToAdd")? {
+            self.playlists.add_item_to_playlist(playlist_id, music_item_id)?;
+        }
+
+        Ok(empty_body())
+    }
+
+    fn delete_playlist(&self, request: &SubsonicRequest) -> ApiResult {
+        let playlist_id = parse_id(request.require("id")?, PLAYLIST_PREFIX)?;
+        self.playlists.delete_playlist(playlist_id)?;
+        Ok(empty_body())
+    }
+
+    fn scrobble(&self, request: &SubsonicRequest) -> ApiResult {
+        if request.get("submission") == Some("false") {
+            return Ok(empty_body());
+        }
+        for music_item_id in Self::get_song_ids(request, "id")? {
+            let own_tags = self.music.get_tags(music_item_id)?;
+            let play_count = own_tags.into_iter()
+                .find(|tag| tag.get_key() == "play_count")
+                .and_then(|tag| tag_to_number(Some(tag)))
+                .unwrap_or(0);
+            self.music.set_tag(music_item_id, "play_count".to_string(), TagValue::Number(play_count + 1))?;
+        }
+        Ok(empty_body())
+    }
+}
@@ -0,0 +1,45 @@
+use super::request::SubsonicRequest;
+use super::response::{SubsonicError, ERROR_WRONG_CREDENTIALS};
+
+fn decode_hex(hex: &str) -> Option<String> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let bytes: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect();
+    String::from_utf8(bytes?).ok()
+}
+
+pub fn check_credentials(request: &SubsonicRequest, user: &str, password: &str) -> Result<(), SubsonicError> {
+    let wrong_credentials = || SubsonicError::new(ERROR_WRONG_CREDENTIALS, "Wrong username or password");
+
+    if user.is_empty() {
+        return Err(SubsonicError::new(ERROR_WRONG_CREDENTIALS, "Subsonic user is not configured"));
+    }
+    // An empty password would let anyone in, with an empty p or a token of the salt alone
+    if password.is_empty() {
+        return Err(SubsonicError::new(ERROR_WRONG_CREDENTIALS, "Subsonic password is not configured"));
+    }
+    if request.require("u")? != user {
+        return Err(wrong_credentials());
+    }
+
+    if let Some(request_password) = request.get("p") {
+        let request_password = match request_password.strip_prefix("enc:") {
+            Some(hex) => decode_hex(hex).ok_or_else(wrong_credentials)?,
+            None => request_password.to_string(),
+        };
+        return if request_password == password { Ok(()) } else { Err(wrong_credentials()) };
+    }
+
+    let token = request.require("t")?;
+    let salt = request.require("s")?;
+    let expected_token = format!("{:x}", md5::compute(format!("{}{}", password, salt)));
+    if token.to_lowercase() == expected_token {
+        Ok(())
+    } else {
+        Err(wrong_credentials())
+    }
+}
@@ -0,0 +1,121 @@
+mod api;
+mod auth;
+mod request;
+mod response;
+
+use std::io::Read;
+use std::sync::Arc;
+
+use anyhow::Result;
+use amina_core::service::{Context, ServiceApi, ServiceInitializer};
+use amina_core::tasks::{TaskContext, TaskManager};
+use tiny_http::{Method, Request, Response, Server};
+
+use crate::settings::Settings;
+use crate::utils::{http_file, http_server};
+
+use api::{ApiResponse, SubsonicApi};
+use request::SubsonicRequest;
+use response::Format;
+
+static DEFAULT_ADDRESS: &str = "0.0.0.0:4040";
+static WORKERS_COUNT: usize = 4;
+static FILE_CACHE_MAX_AGE: u32 = 86400;
+// Forms only carry request parameters, playlists with thousands of songs still fit
+static MAX_FORM_SIZE: u64 = 1024 * 1024;
+
+struct Credentials {
+    user: String,
+    password: String,
+}
+
+pub struct SubsonicServer {
+
+}
+
+impl SubsonicServer {
+    fn run_task(task_context: &TaskContext, address: &str, credentials: Arc<Credentials>) -> Result<()> {
+        let server = Server::http(address).map_err(|err| anyhow::anyhow!("{}", err))?;
+        log::info!("Subsonic API is listening on {}", address);
+
+        http_server::serve_requests(task_context, server, WORKERS_COUNT, "Subsonic", move |request| {
+            Self::handle_request(request, &credentials)
+        })
+    }
+
+    fn handle_request(mut request: Request, credentials: &Credentials) -> Result<()> {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+
+        let mut params = SubsonicRequest::new();
+        params.add_query(query.as_bytes());
+        if *request.method() == Method::Post {
+            let mut form = vec![];
+            request.as_reader().take(MAX_FORM_SIZE + 1).read_to_end(&mut form)?;
+            if form.len() as u64 > MAX_FORM_SIZE {
+                request.respond(Response::empty(413))?;
+                return Ok(());
+            }
+            params.add_query(&form);
+        }
+
+        let method = path.trim_start_matches("/rest/").trim_end_matches(".view");
+        let format = Format::from_param(params.get("f"));
+
+        let result = auth::check_credentials(&params, &credentials.user, &credentials.password)
+            .and_then(|_| SubsonicApi::new(credentials.user.clone()).handle(method, &params));
+
+        match result {
+            Ok(ApiResponse::Body(body)) => {
+                let data = response::render_ok(format, body);
+                request.respond(Response::from_data(data).with_header(http_file::header("Content-Type", format.get_content_type())))?;
+            },
+            Ok(ApiResponse::File { path, content_type: file_content_type }) => {
+                http_file::respond_file(request, &path, file_content_type, FILE_CACHE_MAX_AGE)?;
+            },
+            Ok(ApiResponse::Stream { stream, content_type }) => {
+                http_file::respond_stream(request, stream, &content_type)?;
+            },
+            Ok(ApiResponse::Redirect(location)) => {
+                request.respond(Response::empty(302).with_header(http_file::header("Location", &location)))?;
+            },
+            Err(err) => {
+                let data = response::render_error(format, &err);
+                request.respond(Response::from_data(data).with_header(http_file::header("Content-Type", format.get_content_type())))?;
+            },
+        }
+
+        Ok(())
+    }
+}
+
+impl ServiceApi for SubsonicServer {
+
+}
+
+impl ServiceInitializer for SubsonicServer {
+    fn initialize(context: &Context) -> Arc<Self> {
+        let settings = context.get_service::<Settings>();
+        let task_manager = context.get_service::<TaskManager>();
+
+        let enabled = settings.get_string("subsonic.enabled").get() == "true";
+        let address = settings.get_string("subsonic.address").get();
+        let address = if address.is_empty() { DEFAULT_ADDRESS.to_string() } else { address };
+        let credentials = Arc::new(Credentials {
+            user: settings.get_string("subsonic.user").get(),
+            password: settings.get_string("subsonic.password").get(),
+        });
+
+        if enabled {
+            task_manager.run(move |task_context| {
+                if let Err(err) = Self::run_task(&task_context, &address, credentials) {
+                    log::error!("Subsonic API server failed: {}", err);
+                }
+            });
+        }
+
+        Arc::new(Self {
+
+        })
+    }
+}
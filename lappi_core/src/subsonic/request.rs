@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+use url::form_urlencoded;
+
+use super::response::SubsonicError;
+
+pub struct SubsonicRequest {
+    params: HashMap<String, Vec<String>>,
+}
+
+impl SubsonicRequest {
+    pub fn new() -> Self {
+        Self {
+            params: HashMap::new(),
+        }
+    }
+
+    pub fn add_query(&mut self, query: &[u8]) {
+        for (key, value) in form_urlencoded::parse(query) {
+            self.params.entry(key.into_owned()).or_default().push(value.into_owned());
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params.get(name)
+            .and_then(|values| values.first())
+            .map(String::as_str)
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.params.get(name)
+            .map(|values| values.iter().map(String::as_str).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn require(&self, name: &str) -> Result<&str, SubsonicError> {
+        self.get(name).ok_or_else(|| SubsonicError::missing_parameter(name))
+    }
+
+    pub fn get_number<T: std::str::FromStr>(&self, name: &str, default: T) -> T {
+        self.get(name)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    }
+}
@@ -0,0 +1,149 @@
+use serde_json::{json, Map, Value};
+
+static API_VERSION: &str = "1.16.1";
+static SERVER_TYPE: &str = "lappi";
+
+pub static ERROR_GENERIC: i32 = 0;
+pub static ERROR_MISSING_PARAMETER: i32 = 10;
+pub static ERROR_WRONG_CREDENTIALS: i32 = 40;
+pub static ERROR_NOT_FOUND: i32 = 70;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Xml,
+}
+
+impl Format {
+    pub fn from_param(param: Option<&str>) -> Self {
+        match param {
+            Some("json") => Self::Json,
+            _ => Self::Xml,
+        }
+    }
+
+    pub fn get_content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Xml => "text/xml; charset=UTF-8",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SubsonicError {
+    pub code: i32,
+    pub message: String,
+}
+
+impl SubsonicError {
+    pub fn new(code: i32, message: &str) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn missing_parameter(name: &str) -> Self {
+        Self::new(ERROR_MISSING_PARAMETER, &format!("Required parameter '{}' is missing", name))
+    }
+
+    pub fn not_found(what: &str) -> Self {
+        Self::new(ERROR_NOT_FOUND, &format!("{} not found", what))
+    }
+}
+
+impl From<anyhow::Error> for SubsonicError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::new(ERROR_GENERIC, &err.to_string())
+    }
+}
+
+fn wrap(status: &str, body: Map<String, Value>) -> Map<String, Value> {
+    let mut response = Map::new();
+    response.insert("status".to_string(), json!(status));
+    response.insert("version".to_string(), json!(API_VERSION));
+    response.insert("type".to_string(), json!(SERVER_TYPE));
+    response.insert("openSubsonic".to_string(), json!(true));
+    response.extend(body);
+    response
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn scalar_to_string(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+// Scalars become attributes, objects and arrays become nested elements
+fn write_xml_element(out: &mut String, name: &str, value: &Value, extra_attributes: &str) {
+    out.push('<');
+    out.push_str(name);
+    out.push_str(extra_attributes);
+
+    let object = match value {
+        Value::Object(object) => object,
+        scalar => {
+            out.push('>');
+            out.push_str(&escape_xml(&scalar_to_string(scalar)));
+            out.push_str(&format!("</{}>", name));
+            return;
+        }
+    };
+
+    for (key, value) in object {
+        if !value.is_object() && !value.is_array() && !value.is_null() {
+            out.push_str(&format!(" {}=\"{}\"", key, escape_xml(&scalar_to_string(value))));
+        }
+    }
+    out.push('>');
+
+    for (key, value) in object {
+        match value {
+            Value::Array(items) => {
+                for item in items {
+                    write_xml_element(out, key, item, "");
+                }
+            },
+            Value::Object(_) => write_xml_element(out, key, value, ""),
+            _ => { },
+        }
+    }
+
+    out.push_str(&format!("</{}>", name));
+}
+
+fn render(format: Format, response: Map<String, Value>) -> Vec<u8> {
+    match format {
+        Format::Json => {
+            json!({ "subsonic-response": Value::Object(response) }).to_string().into_bytes()
+        },
+        Format::Xml => {
+            let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+            write_xml_element(&mut out, "subsonic-response", &Value::Object(response), " xmlns=\"http://subsonic.org/restapi\"");
+            out.into_bytes()
+        },
+    }
+}
+
+pub fn render_ok(format: Format, body: Map<String, Value>) -> Vec<u8> {
+    render(format, wrap("ok", body))
+}
+
+pub fn render_error(format: Format, error: &SubsonicError) -> Vec<u8> {
+    let mut body = Map::new();
+    body.insert("error".to_string(), json!({
+        "code": error.code,
+        "message": error.message,
+    }));
+    render(format, wrap("failed", body))
+}
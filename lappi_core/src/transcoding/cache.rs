@@ -0,0 +1,72 @@
+use std::fs::{self, File};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::Result;
+use camino::{Utf8Path, Utf8PathBuf};
+
+// Files are evicted by the last access time, which is kept in the file modification time
+pub struct TranscodingCache {
+    cache_dir: Utf8PathBuf,
+    max_size: u64,
+    lock: Mutex<()>,
+}
+
+impl TranscodingCache {
+    pub fn new(cache_dir: Utf8PathBuf, max_size: u64) -> Result<Self> {
+        fs::create_dir_all(&cache_dir)?;
+        Ok(Self {
+            cache_dir,
+            max_size,
+            lock: Mutex::new(()),
+        })
+    }
+
+    pub fn get_path(&self, key: &str, extension: &str) -> Utf8PathBuf {
+        self.cache_dir.join(format!("{}.{}", key, extension))
+    }
+
+    pub fn get_temp_path(&self, key: &str) -> Utf8PathBuf {
+        self.cache_dir.join(format!("{}.{:08x}.part", key, rand::random::<u32>()))
+    }
+
+    pub fn touch(&self, path: &Utf8Path) -> Result<()> {
+        File::options().append(true).open(path)?.set_modified(SystemTime::now())?;
+        Ok(())
+    }
+
+    pub fn insert(&self, temp_path: &Utf8Path, path: &Utf8Path) -> Result<()> {
+        let _lock = self.lock.lock().unwrap();
+        fs::rename(temp_path, path)?;
+        self.evict(path)
+    }
+
+    fn evict(&self, keep_path: &Utf8Path) -> Result<()> {
+        let mut entries = vec![];
+        let mut total_size = 0;
+        for entry in self.cache_dir.read_dir_utf8()? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() || entry.path().extension() == Some("part") {
+                continue;
+            }
+            total_size += metadata.len();
+            entries.push((metadata.modified()?, metadata.len(), entry.path().to_path_buf()));
+        }
+
+        entries.sort_by_key(|(modified, _, _)| *modified);
+        for (_, size, path) in entries {
+            if total_size <= self.max_size {
+                break;
+            }
+            if path == keep_path {
+                continue;
+            }
+            log::debug!("Evict transcoded file {}", path);
+            fs::remove_file(&path)?;
+            total_size -= size;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+mod cache;
+mod stream;
+
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context as _, Result};
+use amina_core::register_rpc_handler;
+use amina_core::rpc::Rpc;
+use amina_core::service::{Context, ServiceApi, ServiceInitializer};
+use camino::Utf8PathBuf;
+use serde::Serialize;
+
+use crate::playback::sources::{PlaybackSource, SourceType};
+use crate::settings::Settings;
+use crate::workspace::Workspace;
+
+use cache::TranscodingCache;
+
+pub use stream::TranscodingStream;
+
+static CACHE_DIR_NAME: &str = "transcoding";
+static DEFAULT_FFMPEG_PATH: &str = "ffmpeg";
+static DEFAULT_CACHE_SIZE_MB: u64 = 1024;
+
+#[derive(Clone, Serialize)]
+pub struct TranscodingProfile {
+    pub name: String,
+    pub codec: String,
+    pub format: String,
+    pub extension: String,
+    pub content_type: String,
+    pub bitrate: u32,
+}
+
+impl TranscodingProfile {
+    fn new(name: &str, codec: &str, format: &str, extension: &str, content_type: &str, bitrate: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            codec: codec.to_string(),
+            format: format.to_string(),
+            extension: extension.to_string(),
+            content_type: content_type.to_string(),
+            bitrate,
+        }
+    }
+}
+
+pub enum TranscodedAudio {
+    Cached(Utf8PathBuf),
+    Stream(TranscodingStream),
+}
+
+pub struct Transcoder {
+    profiles: Vec<TranscodingProfile>,
+    ffmpeg_path: String,
+    // Transcoding is disabled when there is no place for the cache
+    cache: Option<Arc<TranscodingCache>>,
+}
+
+impl Transcoder {
+    pub fn get_profiles(&self) -> Vec<TranscodingProfile> {
+        self.profiles.clone()
+    }
+
+    pub fn find_profile(&self, name: &str) -> Option<&TranscodingProfile> {
+        self.profiles.iter().find(|profile| profile.name == name)
+    }
+
+    // Picks the best profile that fits into the bitrate limit in kbps
+    pub fn find_profile_for_bitrate(&self, max_bitrate: u32) -> Option<&TranscodingProfile> {
+        self.profiles.iter()
+            .filter(|profile| profile.bitrate <= max_bitrate)
+            .max_by_key(|profile| profile.bitrate)
+    }
+
+    // A file transcoded before is given from the cache, otherwise ffmpeg output is streamed while it runs
+    pub fn transcode(&self, source: &PlaybackSource, profile_name: &str) -> Result<TranscodedAudio> {
+        let cache = self.cache.as_ref()
+            .context("Transcoding is disabled, the cache directory is not available")?;
+        let profile = self.find_profile(profile_name)
+            .with_context(|| format!("Unknown transcoding profile '{}'", profile_name))?;
+
+        let input = match source.get_source_type() {
+            SourceType::LocalFile(path) | SourceType::ExternalFile(path) => path.to_string(),
+            SourceType::Url(url) => url.clone(),
+        };
+
+        let key = Self::get_cache_key(source, profile)?;
+        let output_path = cache.get_path(&key, &profile.extension);
+        if output_path.exists() {
+            cache.touch(&output_path)?;
+            return Ok(TranscodedAudio::Cached(output_path));
+        }
+
+        log::debug!("Transcode {} with profile {}", input, profile.name);
+        let child = Command::new(&self.ffmpeg_path)
+            .args(["-v", "error", "-nostdin", "-i", &input])
+            .args(["-vn", "-map_metadata", "-1"])
+            .args(["-c:a", &profile.codec, "-b:a", &format!("{}k", profile.bitrate)])
+            .args(["-f", &profile.format, "pipe:1"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Can't run '{}'", self.ffmpeg_path))?;
+
+        let temp_path = cache.get_temp_path(&key);
+        let stream = TranscodingStream::start(child, temp_path, output_path, cache.clone())
+            .with_context(|| format!("Transcoding of {} failed", input))?;
+        Ok(TranscodedAudio::Stream(stream))
+    }
+
+    fn get_cache_key(source: &PlaybackSource, profile: &TranscodingProfile) -> Result<String> {
+        let mut hasher = blake3::Hasher::new();
+        match source.get_source_type() {
+            SourceType::LocalFile(path) | SourceType::ExternalFile(path) => {
+                let metadata = std::fs::metadata(path)?;
+                let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+                hasher.update(path.as_str().as_bytes());
+                hasher.update(&metadata.len().to_le_bytes());
+                hasher.update(&modified.to_le_bytes());
+            },
+            SourceType::Url(url) => {
+                hasher.update(url.as_bytes());
+            },
+        }
+        hasher.update(profile.name.as_bytes());
+        Ok(hasher.finalize().to_hex()[..32].to_string())
+    }
+}
+
+impl ServiceApi for Transcoder {
+
+}
+
+impl ServiceInitializer for Transcoder {
+    fn initialize(context: &Context) -> Arc<Self> {
+        let rpc = context.get_service::<Rpc>();
+        let settings = context.get_service::<Settings>();
+        let workspace = context.get_service::<Workspace>();
+
+        let ffmpeg_path = settings.get_string("transcoding.ffmpeg_path").get();
+        let ffmpeg_path = if ffmpeg_path.is_empty() { DEFAULT_FFMPEG_PATH.to_string() } else { ffmpeg_path };
+        let cache_size_mb = settings.get_string("transcoding.cache_size_mb").get()
+            .parse()
+            .unwrap_or(DEFAULT_CACHE_SIZE_MB);
+
+        let cache_dir = workspace.get_temp_dir().join(CACHE_DIR_NAME);
+        let cache = match TranscodingCache::new(cache_dir.clone(), cache_size_mb * 1024 * 1024) {
+            Ok(cache) => Some(Arc::new(cache)),
+            Err(err) => {
+                log::error!("Can't create transcoding cache in {}, transcoding is disabled: {}", cache_dir, err);
+                None
+            },
+        };
+
+        let transcoder = Arc::new(Self {
+            profiles: vec![
+                TranscodingProfile::new("opus_96", "libopus", "ogg", "opus", "audio/ogg", 96),
+                TranscodingProfile::new("mp3_192", "libmp3lame", "mp3", "mp3", "audio/mpeg", 192),
+            ],
+            ffmpeg_path,
+            cache,
+        });
+
+        register_rpc_handler!(rpc, transcoder, "lappi.transcoding.get_profiles", get_profiles());
+
+        transcoder
+    }
+}
@@ -0,0 +1,128 @@
+use std::fs::{self, File};
+use std::io::{Error, ErrorKind, Read, Write};
+use std::process::{Child, ChildStderr, ChildStdout};
+use std::sync::Arc;
+
+use anyhow::{Context as _, Result};
+use camino::Utf8PathBuf;
+
+use super::cache::TranscodingCache;
+
+static HEAD_SIZE: usize = 64 * 1024;
+
+// Gives the output of ffmpeg to the client while it is produced and keeps a copy of it.
+// The copy gets into the cache only if the whole file was transcoded.
+pub struct TranscodingStream {
+    child: Child,
+    stdout: ChildStdout,
+    stderr: Option<ChildStderr>,
+    head: Vec<u8>,
+    head_position: usize,
+    temp_file: Option<File>,
+    temp_path: Utf8PathBuf,
+    output_path: Utf8PathBuf,
+    cache: Arc<TranscodingCache>,
+    is_finished: bool,
+}
+
+impl TranscodingStream {
+    pub fn start(mut child: Child, temp_path: Utf8PathBuf, output_path: Utf8PathBuf, cache: Arc<TranscodingCache>) -> Result<Self> {
+        let stdout = child.stdout.take().context("ffmpeg output is not piped")?;
+        let stderr = child.stderr.take();
+        let temp_file = File::create(&temp_path)
+            .map_err(|err| log::warn!("Transcoded file won't be cached: {}", err))
+            .ok();
+
+        let mut stream = Self {
+            child,
+            stdout,
+            stderr,
+            head: vec![],
+            head_position: 0,
+            temp_file,
+            temp_path,
+            output_path,
+            cache,
+            is_finished: false,
+        };
+
+        // ffmpeg fails on a bad input before it writes anything, so the caller learns about it before responding
+        let mut head = vec![0; HEAD_SIZE];
+        let size = stream.read_output(&mut head)?;
+        head.truncate(size);
+        stream.head = head;
+        Ok(stream)
+    }
+
+    fn read_output(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.is_finished {
+            return Ok(0);
+        }
+
+        let size = self.stdout.read(buf)?;
+        if size == 0 {
+            self.finish()?;
+            return Ok(0);
+        }
+
+        if let Some(temp_file) = self.temp_file.as_mut() {
+            if let Err(err) = temp_file.write_all(&buf[..size]) {
+                log::warn!("Transcoded file won't be cached: {}", err);
+                self.temp_file = None;
+            }
+        }
+        Ok(size)
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        self.is_finished = true;
+
+        let mut errors = String::new();
+        if let Some(mut stderr) = self.stderr.take() {
+            stderr.read_to_string(&mut errors)?;
+        }
+        let status = self.child.wait()?;
+        if !status.success() {
+            let _ = fs::remove_file(&self.temp_path);
+            let message = format!("ffmpeg failed: {}", errors.trim());
+            return Err(Error::new(ErrorKind::Other, message));
+        }
+
+        match self.temp_file.take() {
+            Some(temp_file) => {
+                drop(temp_file);
+                if let Err(err) = self.cache.insert(&self.temp_path, &self.output_path) {
+                    log::warn!("Can't cache transcoded file {}: {}", self.output_path, err);
+                    let _ = fs::remove_file(&self.temp_path);
+                }
+            },
+            None => {
+                let _ = fs::remove_file(&self.temp_path);
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Read for TranscodingStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.head_position < self.head.len() {
+            let size = buf.len().min(self.head.len() - self.head_position);
+            buf[..size].copy_from_slice(&self.head[self.head_position..self.head_position + size]);
+            self.head_position += size;
+            return Ok(size);
+        }
+        self.read_output(buf)
+    }
+}
+
+impl Drop for TranscodingStream {
+    // The client went away before the end, a partial file must not get into the cache
+    fn drop(&mut self) {
+        if !self.is_finished {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
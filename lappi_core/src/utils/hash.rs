@@ -24,6 +24,10 @@ pub fn calc_file_hash(path: &Utf8Path) -> Result<Vec<u8>> {
     calc_hash(&mut file)
 }
 
+pub fn to_hex(hash: &[u8]) -> String {
+    hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
 pub fn create_hash_file(path: &Utf8Path) -> Result<Vec<u8>> {
     let hash = calc_file_hash(path)?;
 
@@ -0,0 +1,91 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use camino::Utf8Path;
+use tiny_http::{Header, Request, Response, StatusCode};
+
+use super::http_range::{self, RangeRequest};
+
+pub fn get_content_type(extension: &str) -> &'static str {
+    match extension.to_lowercase().as_str() {
+        "mp3" => "audio/mpeg",
+        "flac" => "audio/flac",
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "m4a" | "mp4" | "aac" => "audio/mp4",
+        "wav" => "audio/wav",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "webp" => "image/webp",
+        _ => "application/octet-stream",
+    }
+}
+
+pub fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value).unwrap()
+}
+
+fn find_header(request: &Request, name: &str) -> Option<String> {
+    request.headers().iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.to_string())
+}
+
+// Streams the file with support of single byte ranges and ETag revalidation
+pub fn respond_file(request: Request, path: &Utf8Path, content_type: &str, cache_max_age: u32) -> Result<()> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let size = metadata.len();
+    let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs();
+    let etag = format!("\"{:x}-{:x}\"", size, modified);
+
+    let mut headers = vec![
+        header("Content-Type", content_type),
+        header("Accept-Ranges", "bytes"),
+        header("ETag", &etag),
+        header("Cache-Control", &format!("private, max-age={}", cache_max_age)),
+    ];
+
+    if find_header(&request, "If-None-Match").as_deref() == Some(etag.as_str()) {
+        request.respond(Response::empty(304).with_header(headers.remove(2)))?;
+        return Ok(());
+    }
+
+    // A range is only honored if the client's copy is still the same file
+    let if_range_matches = find_header(&request, "If-Range")
+        .map(|if_range| if_range == etag)
+        .unwrap_or(true);
+    let range = find_header(&request, "Range")
+        .filter(|_| if_range_matches)
+        .and_then(|range| http_range::parse_range(&range, size));
+
+    match range {
+        Some(RangeRequest::Unsatisfiable) => {
+            headers.push(header("Content-Range", &format!("bytes */{}", size)));
+            request.respond(Response::new(StatusCode(416), headers, std::io::empty(), Some(0), None))?;
+        },
+        Some(RangeRequest::Range(range)) => {
+            file.seek(SeekFrom::Start(range.start))?;
+            headers.push(header("Content-Range", &range.to_content_range(size)));
+            let reader = file.take(range.len());
+            request.respond(Response::new(StatusCode(206), headers, reader, Some(range.len() as usize), None))?;
+        },
+        None => {
+            request.respond(Response::new(StatusCode(200), headers, file, Some(size as usize), None))?;
+        },
+    }
+
+    Ok(())
+}
+
+// Streams data of unknown length, which is sent chunked and can't be requested by ranges
+pub fn respond_stream<R: Read>(request: Request, reader: R, content_type: &str) -> Result<()> {
+    let headers = vec![
+        header("Content-Type", content_type),
+        header("Accept-Ranges", "none"),
+        header("Cache-Control", "no-store"),
+    ];
+    request.respond(Response::new(StatusCode(200), headers, reader, None, None))?;
+    Ok(())
+}
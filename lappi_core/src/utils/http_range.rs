@@ -0,0 +1,91 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn to_content_range(&self, size: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, size)
+    }
+}
+
+pub enum RangeRequest {
+    Range(ByteRange),
+    Unsatisfiable,
+}
+
+// Parses a single "bytes=start-end" range. Malformed and multi-part ranges are ignored, so the
+// whole file is sent, while ranges outside of the file are reported as unsatisfiable.
+pub fn parse_range(header: &str, size: u64) -> Option<RangeRequest> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            if suffix == 0 || size == 0 {
+                return Some(RangeRequest::Unsatisfiable);
+            }
+            (size.saturating_sub(suffix), size - 1)
+        },
+        (start, "") => (start.parse().ok()?, size.saturating_sub(1)),
+        (start, end) => {
+            let (start, end): (u64, u64) = (start.parse().ok()?, end.parse().ok()?);
+            if start > end {
+                return None;
+            }
+            (start, end.min(size.saturating_sub(1)))
+        },
+    };
+
+    if start >= size {
+        return Some(RangeRequest::Unsatisfiable);
+    }
+
+    Some(RangeRequest::Range(ByteRange { start, end }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(header: &str, size: u64) -> Option<Result<(u64, u64), ()>> {
+        parse_range(header, size).map(|range| match range {
+            RangeRequest::Range(range) => Ok((range.start, range.end)),
+            RangeRequest::Unsatisfiable => Err(()),
+        })
+    }
+
+    #[test]
+    fn satisfiable_ranges() {
+        assert_eq!(parse("bytes=0-99", 1000), Some(Ok((0, 99))));
+        assert_eq!(parse("bytes=500-", 1000), Some(Ok((500, 999))));
+        assert_eq!(parse("bytes=-100", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse("bytes=900-2000", 1000), Some(Ok((900, 999))));
+        assert_eq!(parse("bytes=-2000", 1000), Some(Ok((0, 999))));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(parse("bytes=1000-", 1000), Some(Err(())));
+        assert_eq!(parse("bytes=1000-1100", 1000), Some(Err(())));
+        assert_eq!(parse("bytes=-0", 1000), Some(Err(())));
+        assert_eq!(parse("bytes=0-", 0), Some(Err(())));
+    }
+
+    #[test]
+    fn ignored_ranges() {
+        assert_eq!(parse("bytes=0-1,5-9", 1000), None);
+        assert_eq!(parse("bytes=9-5", 1000), None);
+        assert_eq!(parse("items=0-5", 1000), None);
+        assert_eq!(parse("bytes=a-b", 1000), None);
+    }
+}
@@ -0,0 +1,29 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Result;
+use amina_core::tasks::TaskContext;
+use threadpool::ThreadPool;
+use tiny_http::{Request, Server};
+
+static RECV_TIMEOUT: Duration = Duration::from_millis(200);
+
+// Handles requests on a pool of workers until the task is interrupted
+pub fn serve_requests<F>(task_context: &TaskContext, server: Server, workers_count: usize, name: &'static str, handler: F) -> Result<()>
+where
+    F: Fn(Request) -> Result<()> + Send + Sync + 'static
+{
+    let pool = ThreadPool::new(workers_count);
+    let handler = Arc::new(handler);
+    while !task_context.is_interrupted() {
+        if let Some(request) = server.recv_timeout(RECV_TIMEOUT)? {
+            let handler = handler.clone();
+            pool.execute(move || {
+                if let Err(err) = handler(request) {
+                    log::debug!("{} request failed: {}", name, err);
+                }
+            });
+        }
+    }
+    Ok(())
+}
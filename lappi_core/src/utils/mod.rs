@@ -1 +1,5 @@
-pub mod hash;
\ No newline at end of file
+pub mod hash;
+pub mod http_file;
+pub mod http_range;
+pub mod http_server;
+pub mod xml;
@@ -0,0 +1,48 @@
+// Minimal helpers for the simple XML documents of network protocols, namespace prefixes are ignored
+
+// Returns the raw text of the first element with the given local name
+pub fn find_element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    find_elements(xml, name).into_iter().next()
+}
+
+pub fn find_elements<'a>(xml: &'a str, name: &str) -> Vec<&'a str> {
+    let mut elements = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let tag_end = match rest.find('>') {
+            Some(tag_end) => tag_end,
+            None => break,
+        };
+        let tag = &rest[..tag_end];
+        let tag_name = tag.split_whitespace().next().unwrap_or("");
+        let local_name = tag_name.rsplit(':').next().unwrap_or(tag_name);
+        if local_name != name || tag.ends_with('/') {
+            continue;
+        }
+
+        let content = &rest[tag_end + 1..];
+        let closing = format!("</{}>", tag_name);
+        if let Some(content_end) = content.find(&closing) {
+            elements.push(content[..content_end].trim());
+            rest = &content[content_end + closing.len()..];
+        }
+    }
+    elements
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
@@ -7,12 +7,15 @@ use amina_core::service::{AppContext, Service, ServiceApi, ServiceInitializer};
 use crate::platform_api::PlatformApi;
 
 pub struct Workspace {
-    #[allow(dead_code)]
     platform_api: Service<PlatformApi>,
     temp_folder_path: Utf8PathBuf,
 }
 
 impl Workspace {
+    pub fn get_workspace_dir(&self) -> Utf8PathBuf {
+        self.platform_api.file_system.get_workspace_dir()
+    }
+
     pub fn get_temp_dir(&self) -> Utf8PathBuf {
         self.temp_folder_path.clone()
     }
@@ -0,0 +1,5 @@
+import { ref } from 'vue'
+
+// Zone controlled by the player pane, shared with the players switch
+export const defaultZoneId = 0
+export const currentZoneId = ref(defaultZoneId)
@@ -0,0 +1,161 @@
+use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::time::Duration;
+
+use anyhow::Result;
+use reqwest::StatusCode;
+use reqwest::blocking::{Client, Response};
+use reqwest::header::RANGE;
+
+static CHUNK_SIZE: usize = 16 * 1024;
+static BUFFERED_CHUNKS: usize = 64;
+// Decoders probe the beginning of the stream several times, so it stays available for seeking back
+static HEAD_SIZE: usize = 512 * 1024;
+static CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Reads an HTTP response into the decoder while it is downloaded. At most BUFFERED_CHUNKS are
+// read ahead, so endless streams are played with bounded memory.
+pub struct HttpStream {
+    url: String,
+    client: Client,
+    chunks: Mutex<Receiver<std::io::Result<Vec<u8>>>>,
+    head: Vec<u8>,
+    chunk: Vec<u8>,
+    chunk_start: u64,
+    position: u64,
+}
+
+impl HttpStream {
+    pub fn open(url: &str) -> Result<Self> {
+        let client = reqwest::blocking::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(None)
+            .build()?;
+        let response = client.get(url).send()?.error_for_status()?;
+
+        Ok(Self {
+            url: url.to_string(),
+            client,
+            chunks: Mutex::new(Self::start_download(response)),
+            head: Vec::new(),
+            chunk: Vec::new(),
+            chunk_start: 0,
+            position: 0,
+        })
+    }
+
+    fn start_download(mut response: Response) -> Receiver<std::io::Result<Vec<u8>>> {
+        let (sender, receiver) = sync_channel(BUFFERED_CHUNKS);
+        std::thread::spawn(move || {
+            loop {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                let result = match response.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(size) => {
+                        chunk.truncate(size);
+                        Ok(chunk)
+                    },
+                    Err(err) => Err(err),
+                };
+                let is_error = result.is_err();
+                // Sending fails when the stream is dropped, which stops the download
+                if sender.send(result).is_err() || is_error {
+                    break;
+                }
+            }
+        });
+        receiver
+    }
+
+    // Restarts the download at the offset, for positions that are no longer buffered
+    fn fetch_from(&mut self, offset: u64) -> std::io::Result<()> {
+        let response = self.client.get(&self.url)
+            .header(RANGE, format!("bytes={}-", offset))
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map_err(|err| Error::new(ErrorKind::Other, err))?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(Error::new(ErrorKind::Unsupported, "The server does not support range requests"));
+        }
+
+        // The old download thread stops when its receiver is dropped here
+        *self.chunks.get_mut().unwrap() = Self::start_download(response);
+        self.chunk = Vec::new();
+        self.chunk_start = offset;
+        self.position = offset;
+        Ok(())
+    }
+
+    fn get_downloaded_size(&self) -> u64 {
+        self.chunk_start + self.chunk.len() as u64
+    }
+
+    fn next_chunk(&mut self) -> std::io::Result<bool> {
+        let chunk = match self.chunks.get_mut().unwrap().recv() {
+            Ok(chunk) => chunk?,
+            Err(_) => return Ok(false),
+        };
+        // Chunks fetched after a seek do not continue the head
+        if self.chunk_start + (self.chunk.len() as u64) == self.head.len() as u64 && self.head.len() < HEAD_SIZE {
+            let size = chunk.len().min(HEAD_SIZE - self.head.len());
+            self.head.extend_from_slice(&chunk[..size]);
+        }
+        self.chunk_start = self.get_downloaded_size();
+        self.chunk = chunk;
+        Ok(true)
+    }
+}
+
+impl Read for HttpStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.head.len() as u64 && self.position < self.chunk_start {
+            self.fetch_from(self.position)?;
+        }
+        while self.position == self.get_downloaded_size() {
+            if !self.next_chunk()? {
+                return Ok(0);
+            }
+        }
+
+        let (data, offset) = if self.position >= self.chunk_start {
+            (&self.chunk, (self.position - self.chunk_start) as usize)
+        } else {
+            (&self.head, self.position as usize)
+        };
+        let size = buf.len().min(data.len() - offset);
+        buf[..size].copy_from_slice(&data[offset..offset + size]);
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+impl Seek for HttpStream {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::Current(offset) => self.position as i64 + offset,
+            SeekFrom::End(_) => return Err(Error::new(ErrorKind::Unsupported, "The length of the stream is unknown")),
+        };
+        if target < 0 {
+            return Err(Error::new(ErrorKind::InvalidInput, "Seek before the start of the stream"));
+        }
+
+        let target = target as u64;
+        if target < self.head.len() as u64 || target >= self.chunk_start {
+            self.position = target.min(self.get_downloaded_size());
+        } else {
+            self.fetch_from(target)?;
+        }
+
+        // Seeking forward reads through the stream
+        let mut buffer = vec![0; CHUNK_SIZE];
+        while self.position < target {
+            let size = (target - self.position).min(CHUNK_SIZE as u64) as usize;
+            if self.read(&mut buffer[..size])? == 0 {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "Seek beyond the end of the stream"));
+            }
+        }
+        Ok(self.position)
+    }
+}
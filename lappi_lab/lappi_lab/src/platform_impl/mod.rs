@@ -1,2 +1,3 @@
 pub mod file_system;
+pub mod http_stream;
 pub mod playback;
@@ -1,81 +1,210 @@
 use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
-use std::io::BufReader;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
 use anyhow::Result;
-use rodio::{OutputStream, OutputStreamHandle, Sink, Source};
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Sample, Sink, Source};
+use rodio::source::{Amplify, SeekError};
 
+use amina_core::settings::Property;
 use lappi_core::platform_api::PlaybackApi;
-use lappi_core::playback::{Player, PlayerFactory, PlayerState};
+use lappi_core::playback::{Player, PlayerEventSender, PlayerFactory, PlayerState};
+use lappi_core::playback::players::polling::PollingAdapter;
 use lappi_core::playback::sources::{SourceType, PlaybackSource};
+use lappi_core::settings::Settings;
+
+use super::http_stream::HttpStream;
 
 static NATIVE_PLAYER_NAME: &str = "Current device";
+static GAPLESS_PREBUFFER_TIME: Duration = Duration::from_secs(1);
+static NATIVE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+struct FadeOut<S> {
+    input: S,
+    triggered: Arc<AtomicBool>,
+    duration: Duration,
+    total_samples: u64,
+    remaining_samples: u64,
+}
+
+impl<S> FadeOut<S> where S: Source, S::Item: Sample {
+    fn new(input: S, triggered: Arc<AtomicBool>, duration: Duration) -> Self {
+        Self {
+            input,
+            triggered,
+            duration,
+            total_samples: 0,
+            remaining_samples: 0,
+        }
+    }
+}
+
+impl<S> Iterator for FadeOut<S> where S: Source, S::Item: Sample {
+    type Item = S::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.triggered.load(Ordering::Relaxed) {
+            return self.input.next();
+        }
+
+        if self.total_samples == 0 {
+            let samples_per_second = self.input.sample_rate() as u64 * self.input.channels() as u64;
+            self.total_samples = (self.duration.as_secs_f64() * samples_per_second as f64) as u64 + 1;
+            self.remaining_samples = self.total_samples;
+        }
+
+        if self.remaining_samples == 0 {
+            return None;
+        }
+
+        let factor = self.remaining_samples as f32 / self.total_samples as f32;
+        self.remaining_samples -= 1;
+        self.input.next().map(|sample| sample.amplify(factor))
+    }
+}
+
+impl<S> Source for FadeOut<S> where S: Source, S::Item: Sample {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.input.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.input.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.input.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.input.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.input.try_seek(pos)
+    }
+}
+
+trait MediaStream: Read + Seek + Send + Sync {}
+
+impl<T: Read + Seek + Send + Sync> MediaStream for T {}
+
+struct LoadedSource {
+    decoder: FadeOut<Amplify<Decoder<Box<dyn MediaStream>>>>,
+    duration: Option<Duration>,
+    fade_out: Arc<AtomicBool>,
+}
+
+impl LoadedSource {
+    fn open_stream(source: &PlaybackSource) -> Result<Box<dyn MediaStream>> {
+        match source.get_source_type() {
+            SourceType::LocalFile(path) | SourceType::ExternalFile(path) => {
+                let file = File::open(path)?;
+                Ok(Box::new(BufReader::new(file)))
+            },
+            SourceType::Url(url) => {
+                Ok(Box::new(HttpStream::open(url)?))
+            },
+        }
+    }
+
+    fn load(source: &PlaybackSource, fade_out_duration: Duration) -> Result<Self> {
+        let decoder = Decoder::new(Self::open_stream(source)?)?;
+        let duration = decoder.total_duration();
+        let decoder = decoder.amplify(source.get_replay_gain());
+        let fade_out = Arc::new(AtomicBool::new(false));
+        Ok(Self {
+            decoder: FadeOut::new(decoder, fade_out.clone(), fade_out_duration),
+            duration,
+            fade_out,
+        })
+    }
+}
 
 pub struct NativePlayer {
     _stream: OutputStream,
-    _stream_handle: OutputStreamHandle,
+    stream_handle: OutputStreamHandle,
     sink: RefCell<Sink>,
+    fading_sink: RefCell<Option<Sink>>,
+    crossfade_ms: Property<String>,
     current_duration: Cell<Option<Duration>>,
+    current_fade_out: RefCell<Option<Arc<AtomicBool>>>,
+    next_source: RefCell<Option<LoadedSource>>,
+    queued_duration: Cell<Option<Option<Duration>>>,
     is_playing: Cell<bool>,
+    volume: Cell<f32>,
+    is_muted: Cell<bool>,
+    polling: PollingAdapter,
 }
 
 impl NativePlayer {
     pub fn create() -> Result<Self> {
         let (stream, stream_handle) = OutputStream::try_default()?;
         let sink: Sink = Sink::try_new(&stream_handle).unwrap();
+        let settings = lappi_core::context().get_service::<Settings>();
 
         Ok(Self {
             _stream: stream,
-            _stream_handle: stream_handle,
+            stream_handle,
             sink: RefCell::new(sink),
+            fading_sink: RefCell::new(None),
+            crossfade_ms: settings.get_string("playback.native.crossfade_ms"),
             current_duration: Cell::new(None),
+            current_fade_out: RefCell::new(None),
+            next_source: RefCell::new(None),
+            queued_duration: Cell::new(None),
             is_playing: Cell::new(false),
+            volume: Cell::new(1.),
+            is_muted: Cell::new(false),
+            polling: PollingAdapter::new(),
         })
     }
-}
 
-impl Player for NativePlayer{
-    fn get_name(&self) -> &str {
-        NATIVE_PLAYER_NAME
+    fn get_effective_volume(&self) -> f32 {
+        if self.is_muted.get() { 0. } else { self.volume.get() }
     }
 
-    fn play(&self, source: Box<PlaybackSource>) {
-        match source.get_source_type() {
-            SourceType::LocalFile(path) => {
-                let file = std::fs::File::open(path).unwrap();
-                let decoder = rodio::Decoder::new(BufReader::new(file)).unwrap();
-                let duration = decoder.total_duration();
-                let sink = &self.sink.borrow();
-                sink.clear();
-                sink.append(decoder);
-                sink.play();
-                self.current_duration.set(duration);
-                self.is_playing.set(true);
-            },
+    fn apply_volume(&self) {
+        let volume = self.get_effective_volume();
+        self.sink.borrow().set_volume(volume);
+        if let Some(sink) = self.fading_sink.borrow().as_ref() {
+            sink.set_volume(volume);
         }
     }
 
-    fn resume(&self) {
-        self.sink.borrow().play();
-        self.is_playing.set(true);
+    fn get_crossfade(&self) -> Duration {
+        Duration::from_millis(self.crossfade_ms.get().parse().unwrap_or(0))
     }
 
-    fn pause(&self) {
-        self.sink.borrow().pause();
-        self.is_playing.set(false);
+    fn get_remaining_time(&self) -> Option<Duration> {
+        let duration = self.current_duration.get()?;
+        Some(duration.saturating_sub(self.sink.borrow().get_pos()))
     }
 
-    fn seek(&self, progress: f32) {
-        if let Some(duration) = self.current_duration.get() {
-            let sink = self.sink.borrow();
-            let position = duration.mul_f32(progress);
-            sink.try_seek(position).unwrap();
+    fn start_loaded(&self, sink: &Sink, loaded: LoadedSource, fade_in: Duration) {
+        if fade_in.is_zero() {
+            sink.append(loaded.decoder);
+        } else {
+            sink.append(loaded.decoder.fade_in(fade_in));
         }
+        self.current_duration.set(loaded.duration);
+        self.current_fade_out.replace(Some(loaded.fade_out));
     }
 
-    fn get_state(&self) -> PlayerState {
+    fn fetch_state(&self) -> PlayerState {
+        if self.is_playing.get() {
+            self.advance_to_next_source();
+        }
+
+        let finished_fading = self.fading_sink.borrow().as_ref().map_or(false, |sink| sink.empty());
+        if finished_fading {
+            self.fading_sink.replace(None);
+        }
+
         let sink = self.sink.borrow();
         if sink.empty() {
             if self.is_playing.get() == true {
@@ -100,6 +229,162 @@ impl Player for NativePlayer{
         }
 
     }
+
+    fn advance_to_next_source(&self) {
+        let sink = self.sink.borrow();
+
+        if let Some(duration) = self.queued_duration.get() {
+            // The pre-buffered source was appended to the sink and became current once the previous one finished
+            if sink.len() <= 1 {
+                self.queued_duration.set(None);
+                self.current_duration.set(duration);
+                self.polling.notify_switched_to_next();
+            }
+            return;
+        }
+
+        if self.next_source.borrow().is_none() || sink.is_paused() {
+            return;
+        }
+
+        if sink.empty() {
+            let loaded = self.next_source.take().unwrap();
+            self.start_loaded(&sink, loaded, Duration::ZERO);
+            self.polling.notify_switched_to_next();
+            return;
+        }
+
+        let remaining = match self.get_remaining_time() {
+            Some(remaining) => remaining,
+            None => return,
+        };
+        let crossfade = self.get_crossfade();
+
+        if crossfade.is_zero() {
+            if remaining <= GAPLESS_PREBUFFER_TIME {
+                let loaded = self.next_source.take().unwrap();
+                self.queued_duration.set(Some(loaded.duration));
+                sink.append(loaded.decoder);
+                self.current_fade_out.replace(Some(loaded.fade_out));
+            }
+        } else if remaining <= crossfade {
+            if let Some(fade_out) = self.current_fade_out.borrow().as_ref() {
+                fade_out.store(true, Ordering::Relaxed);
+            }
+            let new_sink = match Sink::try_new(&self.stream_handle) {
+                Ok(new_sink) => new_sink,
+                Err(err) => {
+                    log::error!("Failed to create sink for crossfade: {}", err);
+                    return;
+                }
+            };
+            new_sink.set_volume(self.get_effective_volume());
+            let loaded = self.next_source.take().unwrap();
+            self.start_loaded(&new_sink, loaded, crossfade);
+            drop(sink);
+            let old_sink = self.sink.replace(new_sink);
+            self.fading_sink.replace(Some(old_sink));
+            self.polling.notify_switched_to_next();
+        }
+    }
+}
+
+impl Player for NativePlayer{
+    fn get_name(&self) -> &str {
+        NATIVE_PLAYER_NAME
+    }
+
+    fn play(&self, source: Box<PlaybackSource>) {
+        self.fading_sink.replace(None);
+        self.next_source.replace(None);
+        self.queued_duration.set(None);
+
+        match LoadedSource::load(&source, self.get_crossfade()) {
+            Ok(loaded) => {
+                let sink = &self.sink.borrow();
+                sink.clear();
+                self.start_loaded(sink, loaded, Duration::ZERO);
+                sink.play();
+                self.is_playing.set(true);
+            },
+            Err(err) => {
+                log::error!("Failed to play {:?}: {}", source, err);
+            }
+        }
+    }
+
+    fn resume(&self) {
+        self.sink.borrow().play();
+        if let Some(sink) = self.fading_sink.borrow().as_ref() {
+            sink.play();
+        }
+        self.is_playing.set(true);
+    }
+
+    fn pause(&self) {
+        self.sink.borrow().pause();
+        if let Some(sink) = self.fading_sink.borrow().as_ref() {
+            sink.pause();
+        }
+        self.is_playing.set(false);
+    }
+
+    fn seek(&self, progress: f32) {
+        if let Some(duration) = self.current_duration.get() {
+            let sink = self.sink.borrow();
+            let position = duration.mul_f32(progress);
+            if let Err(err) = sink.try_seek(position) {
+                log::error!("Failed to seek to {:?}: {}", position, err);
+            }
+        }
+    }
+
+    fn set_next(&self, source: Option<Box<PlaybackSource>>) {
+        if self.queued_duration.get().is_some() {
+            return;
+        }
+
+        let loaded = source.and_then(|source| {
+            match LoadedSource::load(&source, self.get_crossfade()) {
+                Ok(loaded) => Some(loaded),
+                Err(err) => {
+                    log::error!("Failed to pre-buffer {:?}: {}", source, err);
+                    None
+                }
+            }
+        });
+        self.next_source.replace(loaded);
+    }
+
+    fn set_volume(&self, volume: f32) {
+        self.volume.set(volume);
+        self.apply_volume();
+    }
+
+    fn get_volume(&self) -> f32 {
+        self.volume.get()
+    }
+
+    fn mute(&self, muted: bool) {
+        self.is_muted.set(muted);
+        self.apply_volume();
+    }
+
+    fn is_muted(&self) -> bool {
+        self.is_muted.get()
+    }
+
+    fn set_event_sender(&self, events: PlayerEventSender) {
+        self.polling.set_event_sender(events);
+    }
+
+    fn get_poll_interval(&self) -> Option<Duration> {
+        Some(NATIVE_POLL_INTERVAL)
+    }
+
+    fn poll(&self) {
+        self.polling.update_state(self.fetch_state());
+    }
 }
 
 pub struct NativePlayerFactory {